@@ -0,0 +1,48 @@
+#ifndef OPENBOOKV2_DECODER_H
+#define OPENBOOKV2_DECODER_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define OB_OK 0
+#define OB_ERR_NULL -1
+#define OB_ERR_NOT_FILL_LOG -2
+
+typedef struct {
+    uint8_t market[32];
+    uint8_t taker_side;
+    uint8_t maker_slot;
+    bool maker_out;
+    uint64_t timestamp;
+    uint64_t seq_num;
+    uint8_t maker[32];
+    uint64_t maker_client_order_id;
+    uint64_t maker_fee;
+    uint64_t maker_timestamp;
+    uint8_t taker[32];
+    uint64_t taker_client_order_id;
+    uint64_t taker_fee_ceil;
+    int64_t price;
+    int64_t quantity;
+} ObFillLog;
+
+typedef struct {
+    uint8_t base_decimals;
+    uint8_t quote_decimals;
+    int64_t base_lot_size;
+    int64_t quote_lot_size;
+} ObMarketParams;
+
+/* data: raw event bytes (8 byte discriminator + body), already base64 decoded */
+int32_t decode_fill_log(const uint8_t *data, size_t len, ObFillLog *out);
+
+/* returns NULL on error, release the result with free_trade_json */
+char *fill_to_trade_json(const ObFillLog *fill_log,
+                         const ObMarketParams *market,
+                         const char *market_name,
+                         const char *signature);
+
+void free_trade_json(char *json);
+
+#endif
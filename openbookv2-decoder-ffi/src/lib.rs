@@ -0,0 +1,153 @@
+// C ABI over openbookv2-decoder, see include/openbookv2_decoder.h
+use anchor_lang::prelude::Pubkey;
+use openbookv2_decoder::{FillLog, MarketParams, Trade};
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+pub const OB_OK: i32 = 0;
+pub const OB_ERR_NULL: i32 = -1;
+pub const OB_ERR_NOT_FILL_LOG: i32 = -2;
+
+#[repr(C)]
+pub struct ObFillLog {
+    pub market: [u8; 32],
+    pub taker_side: u8,
+    pub maker_slot: u8,
+    pub maker_out: bool,
+    pub timestamp: u64,
+    pub seq_num: u64,
+    pub maker: [u8; 32],
+    pub maker_client_order_id: u64,
+    pub maker_fee: u64,
+    pub maker_timestamp: u64,
+    pub taker: [u8; 32],
+    pub taker_client_order_id: u64,
+    pub taker_fee_ceil: u64,
+    pub price: i64,
+    pub quantity: i64,
+}
+
+#[repr(C)]
+pub struct ObMarketParams {
+    pub base_decimals: u8,
+    pub quote_decimals: u8,
+    pub base_lot_size: i64,
+    pub quote_lot_size: i64,
+}
+
+impl From<&FillLog> for ObFillLog {
+    fn from(fill_log: &FillLog) -> Self {
+        ObFillLog {
+            market: fill_log.market.to_bytes(),
+            taker_side: fill_log.taker_side,
+            maker_slot: fill_log.maker_slot,
+            maker_out: fill_log.maker_out,
+            timestamp: fill_log.timestamp,
+            seq_num: fill_log.seq_num,
+            maker: fill_log.maker.to_bytes(),
+            maker_client_order_id: fill_log.maker_client_order_id,
+            maker_fee: fill_log.maker_fee,
+            maker_timestamp: fill_log.maker_timestamp,
+            taker: fill_log.taker.to_bytes(),
+            taker_client_order_id: fill_log.taker_client_order_id,
+            taker_fee_ceil: fill_log.taker_fee_ceil,
+            price: fill_log.price,
+            quantity: fill_log.quantity,
+        }
+    }
+}
+
+impl From<&ObFillLog> for FillLog {
+    fn from(fill_log: &ObFillLog) -> Self {
+        FillLog {
+            market: Pubkey::new_from_array(fill_log.market),
+            taker_side: fill_log.taker_side,
+            maker_slot: fill_log.maker_slot,
+            maker_out: fill_log.maker_out,
+            timestamp: fill_log.timestamp,
+            seq_num: fill_log.seq_num,
+            maker: Pubkey::new_from_array(fill_log.maker),
+            maker_client_order_id: fill_log.maker_client_order_id,
+            maker_fee: fill_log.maker_fee,
+            maker_timestamp: fill_log.maker_timestamp,
+            taker: Pubkey::new_from_array(fill_log.taker),
+            taker_client_order_id: fill_log.taker_client_order_id,
+            taker_fee_ceil: fill_log.taker_fee_ceil,
+            price: fill_log.price,
+            quantity: fill_log.quantity,
+        }
+    }
+}
+
+unsafe fn optional_str(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(s).to_string_lossy().into_owned()
+}
+
+/// Decodes a raw event (8 byte discriminator + borsh body) into `out`.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `out` to a writable `ObFillLog`.
+#[no_mangle]
+pub unsafe extern "C" fn decode_fill_log(data: *const u8, len: usize, out: *mut ObFillLog) -> i32 {
+    if data.is_null() || out.is_null() {
+        return OB_ERR_NULL;
+    }
+    let bytes = std::slice::from_raw_parts(data, len);
+    match openbookv2_decoder::decode_fill_log(bytes) {
+        Some(fill_log) => {
+            ptr::write(out, ObFillLog::from(&fill_log));
+            OB_OK
+        }
+        None => OB_ERR_NOT_FILL_LOG,
+    }
+}
+
+/// Converts a decoded fill into the printer's Trade JSON. Returns NULL on bad
+/// input, otherwise a string that must be released with `free_trade_json`.
+///
+/// # Safety
+/// `fill_log` and `market` must be valid pointers, `market_name` and
+/// `signature` NULL or NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn fill_to_trade_json(
+    fill_log: *const ObFillLog,
+    market: *const ObMarketParams,
+    market_name: *const c_char,
+    signature: *const c_char,
+) -> *mut c_char {
+    if fill_log.is_null() || market.is_null() {
+        return ptr::null_mut();
+    }
+    let market = &*market;
+    if market.base_lot_size == 0 {
+        return ptr::null_mut();
+    }
+    let params = MarketParams {
+        base_decimals: market.base_decimals,
+        quote_decimals: market.quote_decimals,
+        base_lot_size: market.base_lot_size,
+        quote_lot_size: market.quote_lot_size,
+    };
+    let trade = Trade::new(
+        &FillLog::from(&*fill_log),
+        &params,
+        optional_str(market_name),
+        optional_str(signature),
+    );
+    match serde_json::to_string(&trade).map(CString::new) {
+        Ok(Ok(json)) => json.into_raw(),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+/// `json` must come from `fill_to_trade_json` and not be freed twice.
+#[no_mangle]
+pub unsafe extern "C" fn free_trade_json(json: *mut c_char) {
+    if !json.is_null() {
+        drop(CString::from_raw(json));
+    }
+}
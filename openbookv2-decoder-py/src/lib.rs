@@ -0,0 +1,216 @@
+// Python bindings over openbookv2-decoder, so research code runs the exact same
+// conversion math as the live feed. Build with `maturin develop`.
+use openbookv2_decoder as decoder;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+#[pyclass(name = "MarketParams", get_all, set_all)]
+#[derive(Clone)]
+pub struct PyMarketParams {
+    base_decimals: u8,
+    quote_decimals: u8,
+    base_lot_size: i64,
+    quote_lot_size: i64,
+}
+
+#[pymethods]
+impl PyMarketParams {
+    #[new]
+    fn new(base_decimals: u8, quote_decimals: u8, base_lot_size: i64, quote_lot_size: i64) -> Self {
+        PyMarketParams {
+            base_decimals,
+            quote_decimals,
+            base_lot_size,
+            quote_lot_size,
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MarketParams(base_decimals={}, quote_decimals={}, base_lot_size={}, quote_lot_size={})",
+            self.base_decimals, self.quote_decimals, self.base_lot_size, self.quote_lot_size
+        )
+    }
+}
+
+impl From<&PyMarketParams> for decoder::MarketParams {
+    fn from(params: &PyMarketParams) -> Self {
+        decoder::MarketParams {
+            base_decimals: params.base_decimals,
+            quote_decimals: params.quote_decimals,
+            base_lot_size: params.base_lot_size,
+            quote_lot_size: params.quote_lot_size,
+        }
+    }
+}
+
+#[pyclass(name = "FillLog")]
+pub struct PyFillLog {
+    inner: decoder::FillLog,
+}
+
+#[pymethods]
+impl PyFillLog {
+    #[getter]
+    fn market(&self) -> String {
+        self.inner.market.to_string()
+    }
+    #[getter]
+    fn taker_side(&self) -> u8 {
+        self.inner.taker_side
+    }
+    #[getter]
+    fn maker_slot(&self) -> u8 {
+        self.inner.maker_slot
+    }
+    #[getter]
+    fn maker_out(&self) -> bool {
+        self.inner.maker_out
+    }
+    #[getter]
+    fn timestamp(&self) -> u64 {
+        self.inner.timestamp
+    }
+    #[getter]
+    fn seq_num(&self) -> u64 {
+        self.inner.seq_num
+    }
+    #[getter]
+    fn maker(&self) -> String {
+        self.inner.maker.to_string()
+    }
+    #[getter]
+    fn maker_client_order_id(&self) -> u64 {
+        self.inner.maker_client_order_id
+    }
+    #[getter]
+    fn maker_fee(&self) -> u64 {
+        self.inner.maker_fee
+    }
+    #[getter]
+    fn maker_timestamp(&self) -> u64 {
+        self.inner.maker_timestamp
+    }
+    #[getter]
+    fn taker(&self) -> String {
+        self.inner.taker.to_string()
+    }
+    #[getter]
+    fn taker_client_order_id(&self) -> u64 {
+        self.inner.taker_client_order_id
+    }
+    #[getter]
+    fn taker_fee_ceil(&self) -> u64 {
+        self.inner.taker_fee_ceil
+    }
+    #[getter]
+    fn price(&self) -> i64 {
+        self.inner.price
+    }
+    #[getter]
+    fn quantity(&self) -> i64 {
+        self.inner.quantity
+    }
+
+    fn to_json(&self) -> String {
+        self.inner.to_json().to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.inner)
+    }
+}
+
+#[pyclass(name = "Trade", get_all)]
+pub struct PyTrade {
+    time_stamp: u64,
+    maker_owner: String,
+    taker_owner: String,
+    price_double: f64,
+    quantity_double: f64,
+    market_id: String,
+    taker_side: u8,
+    market_name: String,
+    signature: String,
+    json: String,
+}
+
+#[pymethods]
+impl PyTrade {
+    // Same camelCase payload the printer publishes
+    fn to_json(&self) -> String {
+        self.json.clone()
+    }
+
+    fn __repr__(&self) -> String {
+        self.json.clone()
+    }
+}
+
+impl From<decoder::Trade> for PyTrade {
+    fn from(trade: decoder::Trade) -> Self {
+        let json = serde_json::to_string(&trade).unwrap_or_default();
+        PyTrade {
+            time_stamp: trade.time_stamp,
+            maker_owner: trade.maker_owner,
+            taker_owner: trade.taker_owner,
+            price_double: trade.price_double,
+            quantity_double: trade.quantity_double,
+            market_id: trade.market_id,
+            taker_side: trade.taker_side,
+            market_name: trade.market_name,
+            signature: trade.signature,
+            json,
+        }
+    }
+}
+
+// Raw event bytes including the 8 byte discriminator
+#[pyfunction]
+fn decode_fill_log(data: &[u8]) -> Option<PyFillLog> {
+    decoder::decode_fill_log(data).map(|inner| PyFillLog { inner })
+}
+
+// A full `Program data: <base64>` log line
+#[pyfunction]
+fn decode_program_data_log(log: &str) -> Option<PyFillLog> {
+    decoder::decode_program_data_log(log).map(|inner| PyFillLog { inner })
+}
+
+#[pyfunction]
+fn price_lots_to_ui(price: i64, market: &PyMarketParams) -> f64 {
+    decoder::price_lots_to_ui(price, &market.into())
+}
+
+#[pyfunction]
+fn base_lots_to_ui(quantity: i64, market: &PyMarketParams) -> f64 {
+    decoder::base_lots_to_ui(quantity, &market.into())
+}
+
+#[pyfunction]
+#[pyo3(signature = (fill_log, market, market_name, signature=String::new()))]
+fn trade_from_fill_log(
+    fill_log: &PyFillLog,
+    market: &PyMarketParams,
+    market_name: String,
+    signature: String,
+) -> PyResult<PyTrade> {
+    if market.base_lot_size == 0 {
+        return Err(PyValueError::new_err("base_lot_size must not be zero"));
+    }
+    let trade = decoder::Trade::new(&fill_log.inner, &market.into(), market_name, signature);
+    Ok(trade.into())
+}
+
+#[pymodule]
+fn openbookv2_decoder_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyMarketParams>()?;
+    m.add_class::<PyFillLog>()?;
+    m.add_class::<PyTrade>()?;
+    m.add_function(wrap_pyfunction!(decode_fill_log, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_program_data_log, m)?)?;
+    m.add_function(wrap_pyfunction!(price_lots_to_ui, m)?)?;
+    m.add_function(wrap_pyfunction!(base_lots_to_ui, m)?)?;
+    m.add_function(wrap_pyfunction!(trade_from_fill_log, m)?)?;
+    Ok(())
+}
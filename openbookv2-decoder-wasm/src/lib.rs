@@ -0,0 +1,71 @@
+// wasm-bindgen exports of openbookv2-decoder, built without anchor-lang
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use openbookv2_decoder::utils::{base_lots_to_ui, price_lots_to_ui, MarketParams};
+use openbookv2_decoder::{decode_fill_log, FillLog, Trade};
+use wasm_bindgen::prelude::*;
+
+fn params(base_decimals: u8, quote_decimals: u8, base_lot_size: i64, quote_lot_size: i64) -> MarketParams {
+    MarketParams {
+        base_decimals,
+        quote_decimals,
+        base_lot_size,
+        quote_lot_size,
+    }
+}
+
+fn decode_base64(data: &str) -> Result<FillLog, JsError> {
+    let data = STANDARD.decode(data.trim())?;
+    decode_fill_log(&data).ok_or_else(|| JsError::new("not a FillLog event"))
+}
+
+// Takes the base64 payload of a `Program data: ` log, returns the FillLog as JSON
+#[wasm_bindgen(js_name = decodeFillLog)]
+pub fn decode_fill_log_json(data: &str) -> Result<String, JsError> {
+    Ok(decode_base64(data)?.to_json().to_string())
+}
+
+// Same as the printer output for a fill, market params come from the market account
+#[wasm_bindgen(js_name = fillLogToTrade)]
+pub fn fill_log_to_trade_json(
+    data: &str,
+    base_decimals: u8,
+    quote_decimals: u8,
+    base_lot_size: i64,
+    quote_lot_size: i64,
+    market_name: String,
+    signature: String,
+) -> Result<String, JsError> {
+    let fill_log = decode_base64(data)?;
+    let market = params(base_decimals, quote_decimals, base_lot_size, quote_lot_size);
+    let trade = Trade::new(&fill_log, &market, market_name, signature);
+    Ok(serde_json::to_string(&trade)?)
+}
+
+#[wasm_bindgen(js_name = priceLotsToUi)]
+pub fn price_lots_to_ui_js(
+    price: i64,
+    base_decimals: u8,
+    quote_decimals: u8,
+    base_lot_size: i64,
+    quote_lot_size: i64,
+) -> f64 {
+    price_lots_to_ui(
+        price,
+        &params(base_decimals, quote_decimals, base_lot_size, quote_lot_size),
+    )
+}
+
+#[wasm_bindgen(js_name = baseLotsToUi)]
+pub fn base_lots_to_ui_js(
+    quantity: i64,
+    base_decimals: u8,
+    quote_decimals: u8,
+    base_lot_size: i64,
+    quote_lot_size: i64,
+) -> f64 {
+    base_lots_to_ui(
+        quantity,
+        &params(base_decimals, quote_decimals, base_lot_size, quote_lot_size),
+    )
+}
@@ -0,0 +1,4 @@
+// cargo run -p openbookv2-decoder --example frame_layout > openbookv2-decoder/schemas/trade_frame.md
+fn main() {
+    print!("{}", openbookv2_decoder::frame::frame_layout_spec());
+}
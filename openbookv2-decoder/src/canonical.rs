@@ -0,0 +1,99 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::Write;
+
+// Canonical JSON of feed messages, byte for byte the same for the same message across
+// versions and platforms: no whitespace, object keys sorted by their UTF-8 bytes, integers
+// as they are and other numbers as the shortest decimal that parses back to the same
+// f64, without exponent (1e-7 is 0.0000001, 2.0 is 2). Strings are escaped like
+// serde_json does. Hash or sign this form, not the default output.
+pub fn to_canonical_string<T: Serialize>(value: &T) -> serde_json::Result<String> {
+    Ok(canonical_value(&serde_json::to_value(value)?))
+}
+
+// A JSON document in its canonical form
+pub fn canonicalize(json: &str) -> serde_json::Result<String> {
+    Ok(canonical_value(&serde_json::from_str(json)?))
+}
+
+fn canonical_value(value: &Value) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value);
+    out
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+        Value::Number(number) => match (number.as_u64(), number.as_i64(), number.as_f64()) {
+            (Some(value), _, _) => write!(out, "{}", value).unwrap(),
+            (_, Some(value), _) => write!(out, "{}", value).unwrap(),
+            (_, _, Some(value)) if value.is_finite() => write!(out, "{}", value).unwrap(),
+            _ => out.push_str("null"),
+        },
+        Value::String(value) => out.push_str(&serde_json::to_string(value).unwrap()),
+        Value::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(out, value);
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.as_bytes().cmp(b.as_bytes()));
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(key).unwrap());
+                out.push(':');
+                write_value(out, value);
+            }
+            out.push('}');
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_keys_by_bytes_without_whitespace() {
+        let json = r#"{ "b": [1, {"z": null, "a": true}], "a": "x", "B": false, "é": {} }"#;
+        assert_eq!(canonicalize(json).unwrap(), r#"{"B":false,"a":"x","b":[1,{"a":true,"z":null}],"é":{}}"#);
+    }
+
+    #[test]
+    fn numbers_are_the_shortest_decimal_without_exponent() {
+        let json = "[2.0, 1e-7, 1.5e3, 0.1, -3, 18446744073709551615]";
+        assert_eq!(canonicalize(json).unwrap(), "[2,0.0000001,1500,0.1,-3,18446744073709551615]");
+        assert_eq!(canonicalize("1e21").unwrap(), "1000000000000000000000");
+    }
+
+    #[test]
+    fn strings_are_escaped_like_serde_json() {
+        let json = r#"{"a\"b": "line\nbreak\u0001 ü"}"#;
+        assert_eq!(canonicalize(json).unwrap(), r#"{"a\"b":"line\nbreak\u0001 ü"}"#);
+    }
+
+    #[test]
+    fn the_same_value_has_the_same_form_however_it_is_written() {
+        #[derive(Serialize)]
+        struct Message {
+            second: f64,
+            first: u64,
+        }
+        let message = Message { second: 0.5, first: 7 };
+        let canonical = to_canonical_string(&message).unwrap();
+        assert_eq!(canonical, r#"{"first":7,"second":0.5}"#);
+        assert_eq!(canonicalize(&serde_json::to_string_pretty(&message).unwrap()).unwrap(), canonical);
+        assert!(canonicalize("{\"a\":").is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use crate::logs::{FillLog, SettleFundsLog, FILL_LOG_DISCRIMINATOR, SETTLE_FUNDS_LOG_DISCRIMINATOR};
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use borsh::BorshDeserialize;
+
+pub const PROGRAM_DATA_PREFIX: &str = "Program data: ";
+// `Program return: <program id> <base64>`, used by some simulators for events
+pub const PROGRAM_RETURN_PREFIX: &str = "Program return: ";
+
+// `data` is the raw event including the 8 byte discriminator
+pub fn decode_fill_log(data: &[u8]) -> Option<FillLog> {
+    if data.len() < 8 || data[..8] != FILL_LOG_DISCRIMINATOR {
+        return None;
+    }
+    FillLog::deserialize(&mut &data[8..]).ok()
+}
+
+pub fn decode_settle_funds_log(data: &[u8]) -> Option<SettleFundsLog> {
+    if data.len() < 8 || data[..8] != SETTLE_FUNDS_LOG_DISCRIMINATOR {
+        return None;
+    }
+    SettleFundsLog::deserialize(&mut &data[8..]).ok()
+}
+
+fn program_data(log: &str) -> Option<Vec<u8>> {
+    let encoded = log.strip_prefix(PROGRAM_DATA_PREFIX)?;
+    STANDARD.decode(encoded.trim()).ok()
+}
+
+fn is_program_id(word: &str) -> bool {
+    bs58::decode(word).into_vec().is_ok_and(|bytes| bytes.len() == 32)
+}
+
+// Which log lines carry events. Validators log `Program data: <base64>`, test validators
+// and simulators used in CI also log events under other prefixes, with a program id in
+// front of the payload or without base64 padding.
+#[derive(Clone, Debug)]
+pub struct LogFormat {
+    prefixes: Vec<String>,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat {
+            prefixes: vec![PROGRAM_DATA_PREFIX.to_string()],
+        }
+    }
+}
+
+impl LogFormat {
+    // Prefixes are matched at the start of the line, leading whitespace ignored
+    pub fn new(prefixes: Vec<String>) -> Self {
+        LogFormat { prefixes }
+    }
+
+    pub fn prefixes(&self) -> &[String] {
+        &self.prefixes
+    }
+
+    // The payload follows the prefix, after a program id where there is one, as base64
+    // with or without padding. A line of several chunks (sol_log_data of more than one
+    // slice) is not an event, as with the plain `Program data: ` decoding.
+    fn payload(&self, log: &str) -> Option<Vec<u8>> {
+        let log = log.trim_start();
+        let rest = self.prefixes.iter().find_map(|prefix| log.strip_prefix(prefix.as_str()))?;
+        let words: Vec<&str> = rest.split_whitespace().collect();
+        let encoded = match words[..] {
+            [encoded] => encoded,
+            [program_id, encoded] if is_program_id(program_id) => encoded,
+            _ => return None,
+        };
+        STANDARD
+            .decode(encoded)
+            .or_else(|_| STANDARD_NO_PAD.decode(encoded))
+            .ok()
+    }
+
+    pub fn decode_fill_log(&self, log: &str) -> Option<FillLog> {
+        decode_fill_log(&self.payload(log)?)
+    }
+
+    pub fn decode_settle_funds_log(&self, log: &str) -> Option<SettleFundsLog> {
+        decode_settle_funds_log(&self.payload(log)?)
+    }
+}
+
+// Decodes a `Program data: <base64>` log line, anything else returns None
+pub fn decode_program_data_log(log: &str) -> Option<FillLog> {
+    decode_fill_log(&program_data(log)?)
+}
+
+pub fn decode_program_data_settle_funds_log(log: &str) -> Option<SettleFundsLog> {
+    decode_settle_funds_log(&program_data(log)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pubkey;
+    use borsh::BorshSerialize;
+
+    fn fill_log_data() -> Vec<u8> {
+        let fill_log = FillLog {
+            market: Pubkey::new_from_array([1; 32]),
+            taker_side: 1,
+            maker_slot: 0,
+            maker_out: false,
+            timestamp: 1_700_000_000,
+            seq_num: 42,
+            maker: Pubkey::new_from_array([2; 32]),
+            maker_client_order_id: 0,
+            maker_fee: 0,
+            maker_timestamp: 0,
+            taker: Pubkey::new_from_array([3; 32]),
+            taker_client_order_id: 0,
+            taker_fee_ceil: 0,
+            price: 1_500,
+            quantity: 7,
+        };
+        let mut data = FILL_LOG_DISCRIMINATOR.to_vec();
+        data.extend(fill_log.try_to_vec().unwrap());
+        data
+    }
+
+    fn log_format() -> LogFormat {
+        LogFormat::new(vec![PROGRAM_DATA_PREFIX.to_string(), PROGRAM_RETURN_PREFIX.to_string()])
+    }
+
+    #[test]
+    fn decodes_a_single_payload_with_or_without_padding_and_program_id() {
+        let data = fill_log_data();
+        let program_id = Pubkey::new_from_array([9; 32]);
+        for log in [
+            format!("Program data: {}", STANDARD.encode(&data)),
+            format!("  Program data: {}", STANDARD_NO_PAD.encode(&data)),
+            format!("Program return: {} {}", program_id, STANDARD.encode(&data)),
+        ] {
+            let fill_log = log_format().decode_fill_log(&log).unwrap();
+            assert_eq!((fill_log.seq_num, fill_log.price, fill_log.quantity), (42, 1_500, 7));
+        }
+        assert!(decode_program_data_log(&format!("Program data: {}", STANDARD.encode(&data))).is_some());
+    }
+
+    #[test]
+    fn lines_of_several_chunks_are_not_events() {
+        let data = STANDARD.encode(fill_log_data());
+        let log = format!("Program data: {} {}", STANDARD.encode([1, 2, 3]), data);
+        assert!(log_format().decode_fill_log(&log).is_none());
+        assert!(decode_program_data_log(&log).is_none());
+        let log = format!("Program data: {} {}", data, data);
+        assert!(log_format().decode_fill_log(&log).is_none());
+    }
+
+    #[test]
+    fn other_events_and_prefixes_are_skipped() {
+        let mut data = fill_log_data();
+        assert!(log_format().decode_settle_funds_log(&format!("Program data: {}", STANDARD.encode(&data))).is_none());
+        assert!(log_format().decode_fill_log(&format!("Program log: {}", STANDARD.encode(&data))).is_none());
+        data[0] ^= 1;
+        assert!(log_format().decode_fill_log(&format!("Program data: {}", STANDARD.encode(&data))).is_none());
+    }
+
+    #[cfg(feature = "anchor")]
+    #[test]
+    fn discriminators_are_the_ones_anchor_derives() {
+        use anchor_lang::Discriminator;
+        assert_eq!(FillLog::discriminator(), FILL_LOG_DISCRIMINATOR);
+        assert_eq!(SettleFundsLog::discriminator(), SETTLE_FUNDS_LOG_DISCRIMINATOR);
+    }
+}
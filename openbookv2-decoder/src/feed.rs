@@ -0,0 +1,402 @@
+use crate::logs::Trade;
+use crate::utils::MarketParams;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct Volume {
+    pub base: f64,
+    pub quote: f64,
+}
+
+impl Volume {
+    pub fn add(&mut self, other: &Volume) {
+        self.base += other.base;
+        self.quote += other.quote;
+    }
+}
+
+// Fee revenue of a market in quote UI units: fees paid by takers, fees paid by makers and
+// rebates paid to makers, net is what the market keeps (taker_fees + maker_fees - maker_rebates)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeTotals {
+    pub taker_fees: f64,
+    pub maker_fees: f64,
+    pub maker_rebates: f64,
+    pub net: f64,
+    pub fills: u64,
+}
+
+impl FeeTotals {
+    pub fn add(&mut self, other: &FeeTotals) {
+        self.taker_fees += other.taker_fees;
+        self.maker_fees += other.maker_fees;
+        self.maker_rebates += other.maker_rebates;
+        self.net = self.taker_fees + self.maker_fees - self.maker_rebates;
+        self.fills += other.fills;
+    }
+}
+
+// Version of the JSON messages, bumped on incompatible changes only
+pub const FEED_SCHEMA_VERSION: u32 = 1;
+
+// Sent by websocket clients after connecting to pick a schema version, encoding
+// ("json" or "binary") and topics, missing fields keep the defaults
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(tag = "type", rename = "hello", rename_all = "camelCase")]
+pub struct ClientHello {
+    #[serde(default)]
+    pub schema_version: Option<u32>,
+    #[serde(default)]
+    pub encoding: Option<String>,
+    // empty for all topics
+    #[serde(default)]
+    pub topics: Vec<String>,
+}
+
+// Sent by websocket clients to get the archived trades of a market between two unix
+// seconds (inclusive) on their connection only, e.g. to rebuild state after downtime.
+// speed 1 paces them as they happened, 10 ten times faster, missing or 0 sends them
+// as fast as the connection takes them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(tag = "type", rename = "replay", rename_all = "camelCase")]
+pub struct ClientReplay {
+    // market id or name
+    pub market: String,
+    pub from: u64,
+    pub to: u64,
+    #[serde(default)]
+    pub speed: Option<f64>,
+}
+
+// Sent by consumers every few seconds, over the websocket or the control socket, with the
+// last feedSeq they processed per market id, so the publisher can show how far behind
+// each consumer is (GET /consumers on the admin port)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(tag = "type", rename = "heartbeat", rename_all = "camelCase")]
+pub struct ClientHeartbeat {
+    pub consumer: String,
+    pub feed_seqs: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OraclePrice {
+    pub account: String,
+    pub price: f64,
+    pub confidence: f64,
+    pub slot: Option<u64>,
+    // unix seconds
+    pub publish_time: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MakerInventory {
+    pub owner: String,
+    // base bought minus base sold as maker in the period, quote the opposite way
+    pub base_delta: f64,
+    pub quote_delta: f64,
+    pub fills: u32,
+    // cumulative base_delta since the publisher started tracking
+    pub position: f64,
+}
+
+// Fields of a published trade which were resolved after it was published (owners of a
+// trade with ownersUnresolved, USD prices once the quote currency has one), only the
+// resolved fields are set
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TradeCorrection {
+    pub market_id: String,
+    pub trade_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maker_owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maker_delegate: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taker_owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taker_delegate: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_usd: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notional_usd: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote_usd: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usd_source: Option<String>,
+}
+
+// Non-trade messages published on the same feed as trades, tagged by `type`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case", rename_all_fields = "camelCase")]
+pub enum ControlMessage {
+    // first message on a websocket connection, what the server can send
+    Hello {
+        schema_versions: Vec<u32>,
+        encodings: Vec<String>,
+        topics: Vec<String>,
+    },
+    // answer to a ClientHello, the connection uses these from now on
+    Welcome {
+        schema_version: u32,
+        encoding: String,
+        topics: Vec<String>,
+    },
+    // the ClientHello could not be satisfied, the server closes the connection, or a
+    // ClientReplay was rejected and the connection stays open
+    Error {
+        message: String,
+    },
+    // an archived trade of a ClientReplay, binary connections get it as frame with the
+    // replayed flag instead
+    Replayed {
+        trade: Box<Trade>,
+    },
+    // the last message of a ClientReplay, `truncated` when it had more trades than the
+    // server sends per request
+    ReplayEnd {
+        market: String,
+        from: u64,
+        to: u64,
+        trades: u64,
+        truncated: bool,
+    },
+    // trades with feed_seq in from_seq..=to_seq were dropped by the publisher
+    DataLoss {
+        market_id: String,
+        from_seq: u64,
+        to_seq: u64,
+    },
+    // the market was closed or expired on-chain and is no longer watched
+    MarketClosed {
+        market_id: String,
+        market_name: String,
+        reason: String,
+        signature: String,
+    },
+    // a market of the listing filters (--listing-mint, --listing-name) was created, closed,
+    // expired or changed parameters, with its state after the change
+    MarketListing {
+        // "created", "closed", "expired" or "params_changed"
+        event: String,
+        market_id: String,
+        market_name: String,
+        base_mint: String,
+        quote_mint: String,
+        #[serde(flatten)]
+        params: MarketParams,
+        maker_fee_bps: f64,
+        taker_fee_bps: f64,
+        time_expiry: i64,
+        // fields which differ from the last poll for params_changed, e.g. ["takerFeeBps"]
+        changed: Vec<String>,
+    },
+    // periodic per market summary, volumes in UI units
+    Ticker {
+        market_id: String,
+        market_name: String,
+        last_price: Option<f64>,
+        // rolling window ending now
+        cum_volume_24h: Volume,
+        // since 00:00 UTC
+        cum_volume_day: Volume,
+    },
+    // parameters used to convert the market's trades, trades carry the matching paramsVersion
+    MarketInfo {
+        market_id: String,
+        market_name: String,
+        // bumped whenever any of the parameters below changes
+        version: u32,
+        #[serde(flatten)]
+        params: MarketParams,
+        maker_fee_bps: f64,
+        taker_fee_bps: f64,
+        // 0 when the market does not expire
+        time_expiry: i64,
+    },
+    // 1 second OHLCV bar, start is the unix second by block time
+    Bar {
+        market_id: String,
+        market_name: String,
+        start: i64,
+        open: f64,
+        high: f64,
+        low: f64,
+        close: f64,
+        volume: Volume,
+        trades: u32,
+    },
+    // oracle prices of the market, price is oracle_a / oracle_b (base in quote)
+    Price {
+        market_id: String,
+        market_name: String,
+        price: f64,
+        oracle_a: OraclePrice,
+        oracle_b: Option<OraclePrice>,
+    },
+    // USD price of a quote currency from the oracle of its --quote-conversion
+    ConversionPrice {
+        currency: String,
+        price: f64,
+        oracle: OraclePrice,
+    },
+    // maker inventory changes between start and end (unix seconds)
+    Inventory {
+        market_id: String,
+        market_name: String,
+        start: i64,
+        end: i64,
+        makers: Vec<MakerInventory>,
+    },
+    // settleFunds paid a referrer, amounts are native quote since the log names no market
+    ReferrerRebate {
+        open_orders_account: String,
+        referrer: String,
+        referrer_rebate: u64,
+        signature: String,
+    },
+    // a trade matched the alert rule (an expression, see --alert)
+    Alert {
+        rule: String,
+        market_id: String,
+        market_name: String,
+        trade_id: String,
+        signature: String,
+        price: f64,
+        size: f64,
+    },
+    // fields of a published trade which were resolved after it was published
+    TradeCorrection(TradeCorrection),
+    // the owner of an OpenOrders account of a fill can never be resolved (closed,
+    // not_open_orders or other_market), on the diagnostics topic once per account
+    ResolutionFailed {
+        market_id: String,
+        account: String,
+        // maker or taker
+        side: String,
+        reason: String,
+        // the first trade it was seen in
+        trade_id: String,
+    },
+    // the source delivered a transaction of `slot` on a subscription of `requested`
+    // commitment but the slot had only reached `observed` after the grace of
+    // --commitment-check-ms ("none" when the slot never got a status, e.g. a dropped fork),
+    // on the diagnostics topic
+    CommitmentDowngrade {
+        slot: u64,
+        requested: String,
+        observed: String,
+    },
+    // the OpenBook program was deployed again at `slot`, with the reason of each market
+    // whose account no longer has the layout this version decodes, on the diagnostics topic
+    ProgramUpgrade {
+        program_id: String,
+        slot: u64,
+        previous_slot: u64,
+        markets: u32,
+        incompatible: BTreeMap<String, String>,
+    },
+    // the health score of the market fell below --health-min-score (breached) or is above
+    // it again, lag in ms from block time to publishing of the last sample
+    LagSlo {
+        market_id: String,
+        market_name: String,
+        breached: bool,
+        lag_ms: i64,
+        limit_ms: u64,
+        // consecutive lag samples over the limit
+        trades: u32,
+        // health score of the market, 0-100
+        #[serde(default)]
+        score: u8,
+    },
+    // a fill whose on-chain seq_num is not above the last published one of the market,
+    // with dropped its trade (and feed_seq) was not published
+    SeqRegression {
+        market_id: String,
+        market_name: String,
+        seq_num: u64,
+        last_seq_num: u64,
+        feed_seq: u64,
+        signature: String,
+        dropped: bool,
+    },
+    // a plugin of the publisher filtered out the trade with this feed_seq, it is not missing
+    TradeFiltered {
+        market_id: String,
+        market_name: String,
+        feed_seq: u64,
+        trade_id: String,
+    },
+    // a trading session of --session began, start and end are unix seconds
+    SessionOpen {
+        market_id: String,
+        market_name: String,
+        session: String,
+        timezone: String,
+        start: i64,
+        end: i64,
+    },
+    // OHLCV of a trading session which ended, prices are null without trades
+    SessionClose {
+        market_id: String,
+        market_name: String,
+        session: String,
+        timezone: String,
+        start: i64,
+        end: i64,
+        open: Option<f64>,
+        high: Option<f64>,
+        low: Option<f64>,
+        close: Option<f64>,
+        vwap: Option<f64>,
+        volume: Volume,
+        trades: u32,
+    },
+    // fee revenue of the market over the rolling windows ending now (--fees-interval)
+    Fees {
+        market_id: String,
+        market_name: String,
+        last_1h: FeeTotals,
+        last_24h: FeeTotals,
+    },
+    // the trades of a market published in one --checksum-interval period: root is the
+    // merkle root of the sha256 of every trade payload in feed_seq order, chain the sha256
+    // of the previous chain and root, both hex
+    Checksum {
+        market_id: String,
+        market_name: String,
+        from_seq: u64,
+        to_seq: u64,
+        // block times of the first and last trade
+        from_time: u64,
+        to_time: u64,
+        trades: u64,
+        root: String,
+        chain: String,
+    },
+    // any other message, sealed for one consumer group (--encrypted-sinks). data is the
+    // XChaCha20-Poly1305 ciphertext of the payload with group and key_id as associated
+    // data, nonce and data are base64
+    Encrypted {
+        group: String,
+        key_id: String,
+        nonce: String,
+        data: String,
+    },
+}
+
+// Answer to `replay <market_id> <seq_from> <seq_to>` on the publisher's control socket
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayResponse {
+    pub market_id: String,
+    pub from_seq: u64,
+    pub to_seq: u64,
+    pub trades: Vec<Trade>,
+    // inclusive feed_seq ranges the publisher does not have anymore
+    pub missing: Vec<(u64, u64)>,
+}
@@ -0,0 +1,218 @@
+use crate::logs::Trade;
+use crate::Pubkey;
+use std::str::FromStr;
+
+pub const FRAME_VERSION: u8 = 1;
+pub const FRAME_SIZE: usize = 224;
+
+// bit 0 of flags
+pub const FLAG_MAKER_REBATE: u8 = 1;
+// bit 1, an archived trade sent for a replay request of the connection
+pub const FLAG_REPLAYED: u8 = 2;
+
+// (name, offset, size, type) of every field of a trade frame, little-endian, no padding
+// besides `reserved`. The published layout (schemas/trade_frame.md) is generated from
+// this table with `cargo run -p openbookv2-decoder --example frame_layout`.
+pub const FRAME_LAYOUT: &[(&str, usize, usize, &str)] = &[
+    ("version", 0, 1, "u8"),
+    ("takerSide", 1, 1, "u8"),
+    ("flags", 2, 1, "u8"),
+    ("reserved", 3, 1, "u8"),
+    ("paramsVersion", 4, 4, "u32"),
+    ("timeStamp", 8, 8, "u64"),
+    ("slot", 16, 8, "u64"),
+    ("feedSeq", 24, 8, "u64"),
+    ("priceDouble", 32, 8, "f64"),
+    ("quantityDouble", 40, 8, "f64"),
+    ("makerFee", 48, 8, "f64"),
+    ("takerFee", 56, 8, "f64"),
+    ("marketId", 64, 32, "pubkey"),
+    ("makerOwner", 96, 32, "pubkey"),
+    ("takerOwner", 128, 32, "pubkey"),
+    ("signature", 160, 64, "signature"),
+];
+
+// Fixed layout trade for latency critical consumers. Market names, trade ids and
+// delegates are not part of it, they can be derived or looked up from market_info.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TradeFrame {
+    pub taker_side: u8,
+    pub flags: u8,
+    pub params_version: u32,
+    pub time_stamp: u64,
+    pub slot: u64,
+    pub feed_seq: u64,
+    pub price_double: f64,
+    pub quantity_double: f64,
+    pub maker_fee: f64,
+    pub taker_fee: f64,
+    pub market_id: [u8; 32],
+    pub maker_owner: [u8; 32],
+    pub taker_owner: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+fn pubkey_bytes(key: &str) -> Option<[u8; 32]> {
+    Pubkey::from_str(key).ok().map(|key| key.to_bytes())
+}
+
+fn signature_bytes(signature: &str) -> Option<[u8; 64]> {
+    bs58::decode(signature).into_vec().ok()?.try_into().ok()
+}
+
+impl TradeFrame {
+    // None when an account or the signature is not valid base58
+    pub fn from_trade(trade: &Trade) -> Option<Self> {
+        Some(TradeFrame {
+            taker_side: trade.taker_side,
+            flags: if trade.maker_rebate { FLAG_MAKER_REBATE } else { 0 },
+            params_version: trade.params_version,
+            time_stamp: trade.time_stamp,
+            slot: trade.slot,
+            feed_seq: trade.feed_seq,
+            price_double: trade.price_double,
+            quantity_double: trade.quantity_double,
+            maker_fee: trade.maker_fee,
+            taker_fee: trade.taker_fee,
+            market_id: pubkey_bytes(&trade.market_id)?,
+            maker_owner: pubkey_bytes(&trade.maker_owner)?,
+            taker_owner: pubkey_bytes(&trade.taker_owner)?,
+            signature: signature_bytes(&trade.signature)?,
+        })
+    }
+
+    // fields in FRAME_LAYOUT order
+    pub fn encode(&self) -> [u8; FRAME_SIZE] {
+        let mut frame = [0u8; FRAME_SIZE];
+        frame[0] = FRAME_VERSION;
+        frame[1] = self.taker_side;
+        frame[2] = self.flags;
+        frame[4..8].copy_from_slice(&self.params_version.to_le_bytes());
+        frame[8..16].copy_from_slice(&self.time_stamp.to_le_bytes());
+        frame[16..24].copy_from_slice(&self.slot.to_le_bytes());
+        frame[24..32].copy_from_slice(&self.feed_seq.to_le_bytes());
+        frame[32..40].copy_from_slice(&self.price_double.to_le_bytes());
+        frame[40..48].copy_from_slice(&self.quantity_double.to_le_bytes());
+        frame[48..56].copy_from_slice(&self.maker_fee.to_le_bytes());
+        frame[56..64].copy_from_slice(&self.taker_fee.to_le_bytes());
+        frame[64..96].copy_from_slice(&self.market_id);
+        frame[96..128].copy_from_slice(&self.maker_owner);
+        frame[128..160].copy_from_slice(&self.taker_owner);
+        frame[160..224].copy_from_slice(&self.signature);
+        frame
+    }
+
+    // None for frames of another size or version
+    pub fn decode(frame: &[u8]) -> Option<Self> {
+        if frame.len() != FRAME_SIZE || frame[0] != FRAME_VERSION {
+            return None;
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(frame[offset..offset + 8].try_into().unwrap());
+        let f64_at = |offset: usize| f64::from_le_bytes(frame[offset..offset + 8].try_into().unwrap());
+        Some(TradeFrame {
+            taker_side: frame[1],
+            flags: frame[2],
+            params_version: u32::from_le_bytes(frame[4..8].try_into().unwrap()),
+            time_stamp: u64_at(8),
+            slot: u64_at(16),
+            feed_seq: u64_at(24),
+            price_double: f64_at(32),
+            quantity_double: f64_at(40),
+            maker_fee: f64_at(48),
+            taker_fee: f64_at(56),
+            market_id: frame[64..96].try_into().unwrap(),
+            maker_owner: frame[96..128].try_into().unwrap(),
+            taker_owner: frame[128..160].try_into().unwrap(),
+            signature: frame[160..224].try_into().unwrap(),
+        })
+    }
+}
+
+// Markdown table of FRAME_LAYOUT
+pub fn frame_layout_spec() -> String {
+    let mut spec = format!(
+        "# trade frame v{}\n\n{} bytes, little-endian, pubkeys and the signature as raw bytes.\n\n| offset | size | type | field |\n|---|---|---|---|\n",
+        FRAME_VERSION, FRAME_SIZE
+    );
+    for (name, offset, size, kind) in FRAME_LAYOUT {
+        spec.push_str(&format!("| {} | {} | {} | {} |\n", offset, size, kind, name));
+    }
+    spec.push_str(&format!(
+        "\nflags: bit 0 maker rebate ({}), bit 1 replayed ({})\n",
+        FLAG_MAKER_REBATE, FLAG_REPLAYED
+    ));
+    spec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn trade() -> Trade {
+        serde_json::from_value(json!({
+            "timeStamp": 1_700_000_000u64,
+            "makerOwner": Pubkey::new_from_array([2; 32]).to_string(),
+            "takerOwner": Pubkey::new_from_array([3; 32]).to_string(),
+            "priceDouble": 101.25,
+            "quantityDouble": 0.5,
+            "marketId": Pubkey::new_from_array([1; 32]).to_string(),
+            "takerSide": 1,
+            "marketName": "SOL-USDC",
+            "signature": bs58::encode([4u8; 64]).into_string(),
+            "feedSeq": 9,
+            "slot": 250_000_000u64,
+            "paramsVersion": 2,
+            "makerFee": -0.01,
+            "makerRebate": true,
+            "takerFee": 0.02,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let frame = TradeFrame::from_trade(&trade()).unwrap();
+        assert_eq!(frame.flags, FLAG_MAKER_REBATE);
+        assert_eq!(frame.market_id, [1; 32]);
+        assert_eq!(frame.signature, [4; 64]);
+        let encoded = frame.encode();
+        assert_eq!(encoded[0], FRAME_VERSION);
+        assert_eq!(TradeFrame::decode(&encoded), Some(frame));
+    }
+
+    #[test]
+    fn fields_sit_where_the_layout_says() {
+        let encoded = TradeFrame::from_trade(&trade()).unwrap().encode();
+        let field = |name: &str| {
+            let (_, offset, size, _) = FRAME_LAYOUT.iter().find(|(field, ..)| *field == name).unwrap();
+            &encoded[*offset..offset + size]
+        };
+        assert_eq!(field("takerSide"), [1]);
+        assert_eq!(field("paramsVersion"), 2u32.to_le_bytes());
+        assert_eq!(field("feedSeq"), 9u64.to_le_bytes());
+        assert_eq!(field("priceDouble"), 101.25f64.to_le_bytes());
+        assert_eq!(field("takerOwner"), [3; 32]);
+        // the fields cover the frame without gaps or overlaps
+        let mut next = 0;
+        for (name, offset, size, _) in FRAME_LAYOUT {
+            assert_eq!(*offset, next, "{} is not where the previous field ends", name);
+            next += size;
+        }
+        assert_eq!(next, FRAME_SIZE);
+    }
+
+    #[test]
+    fn invalid_trades_and_frames_are_refused() {
+        let mut invalid = trade();
+        invalid.signature = "not base58!".to_string();
+        assert_eq!(TradeFrame::from_trade(&invalid), None);
+        let mut invalid = trade();
+        invalid.market_id = "SOL-USDC".to_string();
+        assert_eq!(TradeFrame::from_trade(&invalid), None);
+        let mut encoded = TradeFrame::from_trade(&trade()).unwrap().encode();
+        assert_eq!(TradeFrame::decode(&encoded[..FRAME_SIZE - 1]), None);
+        encoded[0] = FRAME_VERSION + 1;
+        assert_eq!(TradeFrame::decode(&encoded), None);
+    }
+}
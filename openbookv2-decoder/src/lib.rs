@@ -0,0 +1,29 @@
+// Decoding core shared by the printer and other consumers. Keep this crate free
+// of tokio / solana-client so it also builds for wasm32, without default features
+// (see openbookv2-decoder-wasm).
+pub mod canonical;
+pub mod decode;
+pub mod feed;
+pub mod frame;
+pub mod logs;
+pub mod name;
+#[cfg(not(feature = "anchor"))]
+pub mod pubkey;
+pub mod schema;
+pub mod trade_id;
+pub mod utils;
+
+pub use decode::{
+    decode_fill_log, decode_program_data_log, decode_program_data_settle_funds_log, decode_settle_funds_log,
+    LogFormat, PROGRAM_DATA_PREFIX, PROGRAM_RETURN_PREFIX,
+};
+pub use feed::{ClientHeartbeat, ClientHello, ClientReplay, ControlMessage, ReplayResponse, TradeCorrection, FEED_SCHEMA_VERSION};
+pub use frame::{TradeFrame, FLAG_REPLAYED, FRAME_SIZE};
+pub use logs::{FillLog, SettleFundsLog, Trade};
+#[cfg(not(feature = "anchor"))]
+pub use pubkey::Pubkey;
+#[cfg(feature = "anchor")]
+pub use anchor_lang::prelude::Pubkey;
+pub use name::parse_name;
+pub use trade_id::{FillPosition, TradeIdScheme};
+pub use utils::{base_lots_to_ui, price_lots_to_ui, to_native, to_ui_decimals, MarketParams};
@@ -0,0 +1,260 @@
+use crate::utils::{base_lots_to_ui, price_lots_to_ui, to_ui_decimals, MarketParams};
+use crate::Pubkey;
+#[cfg(feature = "anchor")]
+use anchor_lang::prelude::borsh;
+#[cfg(feature = "anchor")]
+use anchor_lang::{event, AnchorDeserialize, AnchorSerialize};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Trade {
+    pub time_stamp: u64,
+    pub maker_owner: String,
+    pub taker_owner: String,
+    pub price_double: f64,
+    pub quantity_double: f64,
+    pub market_id: String,
+    pub taker_side: u8,
+    pub market_name: String,
+    pub signature: String,
+    // per market sequence assigned by the publisher, contiguous so consumers can detect gaps
+    #[serde(default)]
+    pub feed_seq: u64,
+    // unix ms the publisher started numbering feed_seq at, a new epoch restarts the sequence
+    #[serde(default)]
+    pub feed_epoch: u64,
+    // stable id for idempotent stores, format depends on the publisher's --trade-id
+    #[serde(default)]
+    pub trade_id: String,
+    #[serde(default)]
+    pub slot: u64,
+    // version of the market_info whose parameters converted this trade
+    #[serde(default)]
+    pub params_version: u32,
+    // position of the fill among the fills of its transaction (all markets) and their
+    // number, fill_count 0 when unknown
+    #[serde(default)]
+    pub fill_index: u32,
+    #[serde(default)]
+    pub fill_count: u32,
+    // makerOwner/takerOwner still name OpenOrders accounts because owner lookups were over
+    // budget, trade_correction control messages follow
+    #[serde(default)]
+    pub owners_unresolved: bool,
+    // an OpenOrders account of the fill belongs to another market, it was kept as owner
+    #[serde(default)]
+    pub owner_market_mismatch: bool,
+    // delegates of the maker/taker OpenOrders accounts, owners stay in makerOwner/takerOwner
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maker_delegate: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taker_delegate: Option<String>,
+    // fees in quote UI units, maker_fee is paid to the maker instead when maker_rebate is set
+    #[serde(default)]
+    pub maker_fee: f64,
+    #[serde(default)]
+    pub maker_rebate: bool,
+    #[serde(default)]
+    pub taker_fee: f64,
+    // seconds the maker order rested on the book before this fill, by on-chain clock
+    #[serde(default)]
+    pub maker_order_age: u64,
+    // time in force of the maker order in seconds, 0 when it does not expire, and the unix
+    // time it expires at; only from the tif enricher of the printer, which reads them from
+    // the order book as the fill log has neither
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maker_time_in_force: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maker_expiry: Option<u64>,
+    // only on the merged per pair feed (--pair-feed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pair: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pair_seq: Option<u64>,
+    // commitment of the subscription which delivered the fill, see --market-commitment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commitment: Option<String>,
+    // compute units consumed by the transaction of the fill and the fee it paid above the
+    // base fee in lamports, both for the whole transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compute_units: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority_fee: Option<u64>,
+    // set by the optional enrichers of the printer (--enrichers usd,labels,spread)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_usd: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notional_usd: Option<f64>,
+    // USD per unit of the quote currency the USD fields were converted with and where that
+    // rate came from: usd, fixed, market, oracle or trades (see --quote-conversion)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote_usd: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usd_source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maker_label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taker_label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oracle_spread_bps: Option<f64>,
+    // fields set by the annotation plugins of the printer (--plugins)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, Value>>,
+    // enrichers which timed out on this trade, their fields are missing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub enrichment_pending: Vec<String>,
+    // seq_num of the fill is not above the last one published for its market, e.g. a
+    // replay after a failover (--seq-guard flag)
+    #[serde(default)]
+    pub seq_regressed: bool,
+    // the market is on the printer's quarantine list, published on the quarantine topic only
+    #[serde(default)]
+    pub quarantined: bool,
+    // from a transaction simulated before it was executed (the printer's shredstream
+    // source), the fill can still differ or not happen at all
+    #[serde(default)]
+    pub pre_confirmed: bool,
+    // block time of the fill in ms, estimated from the slot (or the on-chain clock) where
+    // the source of the publisher had none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_time_ms: Option<i64>,
+    #[serde(default)]
+    pub block_time_estimated: bool,
+    // publish time minus block time, only with --embed-lag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lag_ms: Option<i64>,
+    // time the printer received the fill minus block time, only with --embed-lag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub received_lag_ms: Option<i64>,
+}
+
+impl Trade {
+    pub fn new(
+        fill_log: &FillLog,
+        market: &MarketParams,
+        market_name: String,
+        signature: String,
+    ) -> Trade {
+        let price_hr = price_lots_to_ui(fill_log.price, market);
+        // this is correct
+        let quantity = base_lots_to_ui(fill_log.quantity, market);
+        Trade {
+            time_stamp: fill_log.timestamp,
+            maker_owner: fill_log.maker.to_string(),
+            taker_owner: fill_log.taker.to_string(),
+            price_double: price_hr,
+            quantity_double: quantity,
+            market_id: fill_log.market.to_string(),
+            taker_side: fill_log.taker_side,
+            market_name,
+            signature,
+            feed_seq: 0,
+            feed_epoch: 0,
+            trade_id: String::new(),
+            slot: 0,
+            params_version: 0,
+            fill_index: 0,
+            fill_count: 0,
+            owners_unresolved: false,
+            owner_market_mismatch: false,
+            maker_delegate: None,
+            taker_delegate: None,
+            maker_fee: to_ui_decimals(fill_log.maker_fee as f64, market.quote_decimals as f64),
+            maker_rebate: false,
+            taker_fee: to_ui_decimals(fill_log.taker_fee_ceil as f64, market.quote_decimals as f64),
+            maker_order_age: fill_log.timestamp.saturating_sub(fill_log.maker_timestamp),
+            maker_time_in_force: None,
+            maker_expiry: None,
+            pair: None,
+            pair_seq: None,
+            commitment: None,
+            compute_units: None,
+            priority_fee: None,
+            price_usd: None,
+            notional_usd: None,
+            quote_usd: None,
+            usd_source: None,
+            maker_label: None,
+            taker_label: None,
+            oracle_spread_bps: None,
+            annotations: None,
+            enrichment_pending: Vec::new(),
+            seq_regressed: false,
+            quarantined: false,
+            pre_confirmed: false,
+            block_time_ms: None,
+            block_time_estimated: false,
+            lag_ms: None,
+            received_lag_ms: None,
+        }
+    }
+}
+
+// What #[event] derives as discriminators, sha256("event:<name>")[..8], for builds
+// without anchor-lang
+pub const FILL_LOG_DISCRIMINATOR: [u8; 8] = [150, 23, 41, 148, 152, 162, 215, 64];
+pub const SETTLE_FUNDS_LOG_DISCRIMINATOR: [u8; 8] = [10, 50, 240, 117, 237, 67, 230, 233];
+
+#[derive(Debug)]
+#[cfg_attr(feature = "anchor", event)]
+#[cfg_attr(not(feature = "anchor"), derive(borsh::BorshSerialize, borsh::BorshDeserialize))]
+pub struct FillLog {
+    pub market: Pubkey,
+    pub taker_side: u8, // side from the taker's POV
+    pub maker_slot: u8,
+    pub maker_out: bool, // true if maker order quantity == 0
+    pub timestamp: u64,
+    pub seq_num: u64, // note: usize same as u64
+
+    pub maker: Pubkey,
+    pub maker_client_order_id: u64,
+    pub maker_fee: u64, // native quote
+
+    // Timestamp of when the maker order was placed; copied over from the LeafNode
+    pub maker_timestamp: u64,
+
+    pub taker: Pubkey,
+    pub taker_client_order_id: u64,
+    pub taker_fee_ceil: u64, // native quote
+
+    pub price: i64,
+    pub quantity: i64, // number of base lots
+}
+
+// Emitted by settleFunds, the only event naming the referrer
+#[derive(Debug)]
+#[cfg_attr(feature = "anchor", event)]
+#[cfg_attr(not(feature = "anchor"), derive(borsh::BorshSerialize, borsh::BorshDeserialize))]
+pub struct SettleFundsLog {
+    pub open_orders_account: Pubkey,
+    pub base_native: u64,
+    pub quote_native: u64,
+    pub referrer_rebate: u64,
+    pub referrer: Option<Pubkey>,
+}
+
+impl FillLog {
+    // Pubkeys as base58 strings, the borsh layout is not useful outside of rust
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "market": self.market.to_string(),
+            "takerSide": self.taker_side,
+            "makerSlot": self.maker_slot,
+            "makerOut": self.maker_out,
+            "timestamp": self.timestamp,
+            "seqNum": self.seq_num,
+            "maker": self.maker.to_string(),
+            "makerClientOrderId": self.maker_client_order_id,
+            "makerFee": self.maker_fee,
+            "makerTimestamp": self.maker_timestamp,
+            "taker": self.taker.to_string(),
+            "takerClientOrderId": self.taker_client_order_id,
+            "takerFeeCeil": self.taker_fee_ceil,
+            "price": self.price,
+            "quantity": self.quantity,
+        })
+    }
+}
@@ -9,7 +9,6 @@ pub fn parse_name(name: &[u8; 16]) -> String {
     // utf16
     let u16_bytes: Vec<u16> = name
         .chunks_exact(2)
-        .into_iter()
         .map(|a| u16::from_ne_bytes([a[0], a[1]]))
         .collect();
     let result_name = String::from_utf16(&u16_bytes);
@@ -17,5 +16,5 @@ pub fn parse_name(name: &[u8; 16]) -> String {
         return name;
     }
     // utf8 with errors
-    return String::from_utf8_lossy(name).parse().unwrap();
+    String::from_utf8_lossy(name).parse().unwrap()
 }
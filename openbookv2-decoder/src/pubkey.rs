@@ -0,0 +1,46 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use std::fmt;
+use std::str::FromStr;
+
+// The 32 bytes of an account, for builds without anchor-lang (see the anchor feature).
+// Prints and parses as base58 like solana_program::pubkey::Pubkey.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, BorshSerialize, BorshDeserialize)]
+pub struct Pubkey([u8; 32]);
+
+impl Pubkey {
+    pub const fn new_from_array(bytes: [u8; 32]) -> Self {
+        Pubkey(bytes)
+    }
+
+    pub fn to_bytes(self) -> [u8; 32] {
+        self.0
+    }
+}
+
+impl AsRef<[u8]> for Pubkey {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Pubkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", bs58::encode(self.0).into_string())
+    }
+}
+
+impl fmt::Debug for Pubkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for Pubkey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = bs58::decode(s).into_vec().map_err(|err| format!("{} is not base58: {}", s, err))?;
+        let bytes: [u8; 32] = bytes.try_into().map_err(|_| format!("{} is not 32 bytes", s))?;
+        Ok(Pubkey(bytes))
+    }
+}
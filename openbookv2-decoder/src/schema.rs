@@ -0,0 +1,32 @@
+use crate::feed::FEED_SCHEMA_VERSION;
+use crate::logs::Trade;
+use serde_json::Value;
+
+// Rewrites a JSON trade of version `version + 1` into `version`. Every bump of
+// FEED_SCHEMA_VERSION adds the step back to the previous version here, so publishers can
+// keep serving it while consumers migrate (--legacy-schema-version).
+type Downgrade = fn(&mut Value);
+
+// (version produced, step), newest last
+const DOWNGRADES: &[(u32, Downgrade)] = &[];
+
+// Oldest trade schema version a publisher can produce
+pub fn oldest_schema_version() -> u32 {
+    DOWNGRADES.first().map_or(FEED_SCHEMA_VERSION, |(version, _)| *version)
+}
+
+pub fn supports_schema_version(version: u32) -> bool {
+    (oldest_schema_version()..=FEED_SCHEMA_VERSION).contains(&version)
+}
+
+// The trade as JSON of an older schema version, None for versions without a downgrade
+pub fn trade_payload(trade: &Trade, version: u32) -> Option<String> {
+    if !supports_schema_version(version) {
+        return None;
+    }
+    let mut value = serde_json::to_value(trade).ok()?;
+    for (_, downgrade) in DOWNGRADES.iter().rev().filter(|(produced, _)| *produced >= version) {
+        downgrade(&mut value);
+    }
+    serde_json::to_string(&value).ok()
+}
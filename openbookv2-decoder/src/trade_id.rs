@@ -0,0 +1,131 @@
+use crate::logs::FillLog;
+use std::fmt;
+use std::str::FromStr;
+
+// Where a fill was found, enough to identify it across replays and backfills
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FillPosition {
+    pub slot: u64,
+    // index of the transaction in its block
+    pub tx_index: u64,
+    // index of the log line in the transaction logs
+    pub log_index: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TradeIdScheme {
+    // {market}:{slot}:{tx_index}:{log_index}
+    #[default]
+    Slot,
+    // {market}:{seq_num}:{log_index}, the market order sequence number
+    Seq,
+    // {signature}:{log_index}
+    Signature,
+}
+
+impl TradeIdScheme {
+    pub fn trade_id(&self, fill_log: &FillLog, signature: &str, position: &FillPosition) -> String {
+        match self {
+            TradeIdScheme::Slot => format!(
+                "{}:{}:{}:{}",
+                fill_log.market, position.slot, position.tx_index, position.log_index
+            ),
+            TradeIdScheme::Seq => format!(
+                "{}:{}:{}",
+                fill_log.market, fill_log.seq_num, position.log_index
+            ),
+            TradeIdScheme::Signature => format!("{}:{}", signature, position.log_index),
+        }
+    }
+}
+
+impl FromStr for TradeIdScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "slot" => Ok(TradeIdScheme::Slot),
+            "seq" => Ok(TradeIdScheme::Seq),
+            "signature" => Ok(TradeIdScheme::Signature),
+            _ => Err(format!("unknown trade id scheme {}, expected slot, seq or signature", s)),
+        }
+    }
+}
+
+impl fmt::Display for TradeIdScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TradeIdScheme::Slot => "slot",
+            TradeIdScheme::Seq => "seq",
+            TradeIdScheme::Signature => "signature",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pubkey;
+
+    fn fill_log(market: Pubkey, seq_num: u64) -> FillLog {
+        FillLog {
+            market,
+            taker_side: 0,
+            maker_slot: 0,
+            maker_out: false,
+            timestamp: 0,
+            seq_num,
+            maker: Pubkey::default(),
+            maker_client_order_id: 0,
+            maker_fee: 0,
+            maker_timestamp: 0,
+            taker: Pubkey::default(),
+            taker_client_order_id: 0,
+            taker_fee_ceil: 0,
+            price: 1,
+            quantity: 1,
+        }
+    }
+
+    #[test]
+    fn ids_of_each_scheme() {
+        let market = Pubkey::new_from_array([1; 32]);
+        let fill = fill_log(market, 42);
+        let position = FillPosition {
+            slot: 250_000_000,
+            tx_index: 7,
+            log_index: 3,
+        };
+        assert_eq!(
+            TradeIdScheme::Slot.trade_id(&fill, "sig", &position),
+            format!("{}:250000000:7:3", market)
+        );
+        assert_eq!(TradeIdScheme::Seq.trade_id(&fill, "sig", &position), format!("{}:42:3", market));
+        assert_eq!(TradeIdScheme::Signature.trade_id(&fill, "sig", &position), "sig:3");
+    }
+
+    #[test]
+    fn ids_tell_fills_of_one_transaction_apart() {
+        let fill = fill_log(Pubkey::new_from_array([1; 32]), 42);
+        let first = FillPosition {
+            slot: 1,
+            tx_index: 0,
+            log_index: 3,
+        };
+        let second = FillPosition { log_index: 5, ..first };
+        for scheme in [TradeIdScheme::Slot, TradeIdScheme::Seq, TradeIdScheme::Signature] {
+            assert_ne!(scheme.trade_id(&fill, "sig", &first), scheme.trade_id(&fill, "sig", &second));
+        }
+    }
+
+    #[test]
+    fn schemes_parse_and_print_by_name() {
+        for scheme in [TradeIdScheme::Slot, TradeIdScheme::Seq, TradeIdScheme::Signature] {
+            assert_eq!(scheme.to_string().parse::<TradeIdScheme>(), Ok(scheme));
+        }
+        assert_eq!("SEQ".parse::<TradeIdScheme>(), Ok(TradeIdScheme::Seq));
+        assert!("hash".parse::<TradeIdScheme>().is_err());
+        assert_eq!(TradeIdScheme::default(), TradeIdScheme::Slot);
+    }
+}
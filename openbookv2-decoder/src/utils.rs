@@ -0,0 +1,49 @@
+#[cfg(feature = "anchor")]
+use openbookv2_generated::Market;
+use serde::{Deserialize, Serialize};
+
+// The subset of market state needed to convert lots into UI units
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketParams {
+    pub base_decimals: u8,
+    pub quote_decimals: u8,
+    pub base_lot_size: i64,
+    pub quote_lot_size: i64,
+}
+
+#[cfg(feature = "anchor")]
+impl From<&Market> for MarketParams {
+    fn from(market: &Market) -> Self {
+        MarketParams {
+            base_decimals: market.base_decimals,
+            quote_decimals: market.quote_decimals,
+            base_lot_size: market.base_lot_size,
+            quote_lot_size: market.quote_lot_size,
+        }
+    }
+}
+
+pub fn to_native(value: f64, decimals: f64) -> f64 {
+    let d = 10_f64.powf(decimals);
+    value * d
+}
+
+pub fn to_ui_decimals(value: f64, decimals: f64) -> f64 {
+    let d = 10_f64.powf(decimals);
+    value / d
+}
+
+pub fn price_lots_to_ui(price: i64, market: &MarketParams) -> f64 {
+    let coeff = (10_f64.powf((market.base_decimals as i8 - market.quote_decimals as i8) as f64)
+        * market.quote_lot_size as f64)
+        / market.base_lot_size as f64;
+    price as f64 * coeff
+}
+
+pub fn base_lots_to_ui(quantity: i64, market: &MarketParams) -> f64 {
+    to_ui_decimals(
+        quantity as f64 * market.base_lot_size as f64,
+        market.base_decimals as f64,
+    )
+}
@@ -0,0 +1,216 @@
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use clap::Parser;
+use futures::stream::{self, StreamExt};
+use openbookv2_decoder::{decode_program_data_log, parse_name, FillLog, FillPosition, MarketParams, Trade, TradeIdScheme};
+use openbookv2_generated::{Market, OpenOrdersAccount};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::time::{interval, Interval, MissedTickBehavior};
+
+// Fetches past fills of a market from RPC and prints them as JSON lines, oldest first:
+// cargo run --example backfill -- --rpc-url <url> --market <pubkey> --limit 5000 --parallelism 16 --rps 40 > trades.jsonl
+#[derive(Parser)]
+struct Cli {
+    #[arg(short, long, default_value = "https://api.mainnet-beta.solana.com")]
+    rpc_url: String,
+    #[arg(short, long)]
+    market: String,
+    // start below this signature, default is the newest
+    #[arg(long)]
+    before: Option<String>,
+    // stop at this signature
+    #[arg(long)]
+    until: Option<String>,
+    // max signatures to scan
+    #[arg(long, default_value_t = 1000)]
+    limit: usize,
+    // getTransaction requests in flight
+    #[arg(long, default_value_t = 8)]
+    parallelism: usize,
+    // rpc requests per second over all requests
+    #[arg(long, default_value_t = 20)]
+    rps: u32,
+    // slot ids use tx index 0, getTransaction does not return the position in the block
+    #[arg(long, default_value = "signature")]
+    trade_id: TradeIdScheme,
+}
+
+// Shared request budget, every rpc call waits for a tick
+#[derive(Clone)]
+struct RateLimit(Arc<Mutex<Interval>>);
+
+impl RateLimit {
+    fn new(rps: u32) -> Self {
+        let mut ticker = interval(Duration::from_secs_f64(1.0 / rps.max(1) as f64));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        RateLimit(Arc::new(Mutex::new(ticker)))
+    }
+
+    async fn wait(&self) {
+        self.0.lock().await.tick().await;
+    }
+}
+
+async fn signatures(client: &RpcClient, cli: &Cli, market: &Pubkey, limit: &RateLimit) -> Vec<Signature> {
+    let mut signatures = Vec::new();
+    let mut before = cli.before.as_deref().map(|s| Signature::from_str(s).unwrap());
+    let until = cli.until.as_deref().map(|s| Signature::from_str(s).unwrap());
+    while signatures.len() < cli.limit {
+        limit.wait().await;
+        let config = GetConfirmedSignaturesForAddress2Config {
+            before,
+            until,
+            limit: Some((cli.limit - signatures.len()).min(1000)),
+            commitment: Some(CommitmentConfig::confirmed()),
+        };
+        let page = client
+            .get_signatures_for_address_with_config(market, config)
+            .await
+            .unwrap();
+        let Some(last) = page.last() else {
+            break;
+        };
+        before = Some(Signature::from_str(&last.signature).unwrap());
+        signatures.extend(
+            page.iter()
+                .filter(|status| status.err.is_none())
+                .map(|status| Signature::from_str(&status.signature).unwrap()),
+        );
+        eprintln!("{} signatures", signatures.len());
+    }
+    // oldest first
+    signatures.reverse();
+    signatures
+}
+
+async fn transaction(
+    client: &RpcClient,
+    signature: Signature,
+    limit: &RateLimit,
+) -> Option<EncodedConfirmedTransactionWithStatusMeta> {
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(CommitmentConfig::confirmed()),
+        max_supported_transaction_version: Some(0),
+    };
+    for attempt in 0..3 {
+        limit.wait().await;
+        match client.get_transaction_with_config(&signature, config).await {
+            Ok(tx) => return Some(tx),
+            Err(err) => eprintln!("getTransaction {} failed (attempt {}): {}", signature, attempt + 1, err),
+        }
+    }
+    None
+}
+
+// Same as the live feed, OpenOrders accounts are replaced by their owner
+async fn owner(
+    client: &RpcClient,
+    owners: &mut HashMap<Pubkey, Pubkey>,
+    key: Pubkey,
+    limit: &RateLimit,
+) -> Pubkey {
+    if let Some(owner) = owners.get(&key) {
+        return *owner;
+    }
+    limit.wait().await;
+    let owner = match client.get_account_data(&key).await {
+        Ok(data) if data.len() >= 40 && data[0..8] == OpenOrdersAccount::discriminator() => {
+            Pubkey::try_from(&data[8..40]).unwrap()
+        }
+        _ => key,
+    };
+    owners.insert(key, owner);
+    owner
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let market_key = Pubkey::from_str(&cli.market).unwrap();
+    let client = Arc::new(RpcClient::new(cli.rpc_url.clone()));
+    let limit = RateLimit::new(cli.rps);
+
+    let data = client.get_account_data(&market_key).await.unwrap();
+    let market = Market::deserialize(&mut &data[8..]).unwrap();
+    let params = MarketParams::from(&market);
+    let market_name = parse_name(&market.name).replace('\0', "");
+
+    let signatures = signatures(&client, &cli, &market_key, &limit).await;
+    let total = signatures.len();
+    let started = Instant::now();
+    let mut owners = HashMap::new();
+    let mut fills = 0;
+    // buffered keeps the order, so trades come out oldest first
+    let mut transactions = stream::iter(signatures)
+        .map(|signature| {
+            let client = client.clone();
+            let limit = limit.clone();
+            async move { (signature, transaction(&client, signature, &limit).await) }
+        })
+        .buffered(cli.parallelism.max(1))
+        .enumerate();
+    while let Some((done, (signature, tx))) = transactions.next().await {
+        if (done + 1) % 100 == 0 || done + 1 == total {
+            let elapsed = started.elapsed().as_secs_f64();
+            eprintln!(
+                "{}/{} transactions, {} fills, {:.1} tx/s",
+                done + 1,
+                total,
+                fills,
+                (done + 1) as f64 / elapsed.max(0.001)
+            );
+        }
+        let Some(tx) = tx else {
+            eprintln!("skipping {}", signature);
+            continue;
+        };
+        let Some(meta) = tx.transaction.meta else {
+            continue;
+        };
+        let OptionSerializer::Some(logs) = meta.log_messages else {
+            continue;
+        };
+        // numbered like the live feed, over the deduplicated fills of all markets in the transaction
+        let mut seen = HashSet::new();
+        let tx_fills: Vec<(usize, FillLog)> = logs
+            .iter()
+            .enumerate()
+            .filter_map(|(log_index, log)| decode_program_data_log(log).map(|fill_log| (log_index, fill_log)))
+            .filter(|(_, fill_log)| seen.insert((fill_log.market, fill_log.seq_num)))
+            .collect();
+        let fill_count = tx_fills.len() as u32;
+        for (fill_index, (log_index, mut fill_log)) in tx_fills.into_iter().enumerate() {
+            if fill_log.market != market_key {
+                continue;
+            }
+            let position = FillPosition {
+                slot: tx.slot,
+                tx_index: 0,
+                log_index: log_index as u32,
+            };
+            let trade_id = cli.trade_id.trade_id(&fill_log, &signature.to_string(), &position);
+            fill_log.maker = owner(&client, &mut owners, fill_log.maker, &limit).await;
+            fill_log.taker = owner(&client, &mut owners, fill_log.taker, &limit).await;
+            let mut trade = Trade::new(&fill_log, &params, market_name.clone(), signature.to_string());
+            trade.trade_id = trade_id;
+            trade.slot = tx.slot;
+            trade.fill_index = fill_index as u32;
+            trade.fill_count = fill_count;
+            trade.maker_rebate = market.maker_fee < 0;
+            println!("{}", serde_json::to_string(&trade).unwrap());
+            fills += 1;
+        }
+    }
+}
@@ -1,12 +1,11 @@
 use anchor_lang::{AnchorDeserialize, Discriminator};
 use clap::Parser;
+use openbookv2_decoder::parse_name;
 use openbookv2_generated::{id, Market};
-use serde_json;
 use solana_account_decoder::UiAccountEncoding;
 use solana_client::rpc_client::RpcClient;
 use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
 use solana_client::rpc_filter::{Memcmp, RpcFilterType};
-use std::str::from_utf8;
 
 #[derive(Parser)]
 struct Cli {
@@ -14,26 +13,6 @@ struct Cli {
     rpc_url: String,
 }
 
-pub fn parse_name(name: &[u8; 16]) -> String {
-    let result_name = from_utf8(name);
-    // utf8
-    if result_name.is_ok() {
-        return result_name.unwrap().to_string();
-    }
-    // utf16
-    let u16_bytes: Vec<u16> = name
-        .chunks_exact(2)
-        .into_iter()
-        .map(|a| u16::from_ne_bytes([a[0], a[1]]))
-        .collect();
-    let result_name = String::from_utf16(&u16_bytes);
-    if result_name.is_ok() {
-        return result_name.unwrap();
-    }
-    // utf8 with errors
-    return String::from_utf8_lossy(name).parse().unwrap();
-}
-
 fn main() {
     let cli = Cli::parse();
     let market_filter =
@@ -0,0 +1,335 @@
+use log::{error, info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::spawn;
+
+use crate::auth::ApiKeys;
+use crate::consumers::ConsumerRegistry;
+use crate::features::FeatureFlags;
+use crate::metrics::metrics;
+use crate::sink::{HealthRegistry, SinkControl};
+use crate::slo::{health_score, SloRegistry};
+#[cfg(feature = "profiling")]
+use crate::profiling;
+
+const MAX_REQUEST_SIZE: usize = 8192;
+
+#[derive(Clone)]
+pub struct AdminState {
+    // only read by the profiling endpoints
+    #[cfg_attr(not(feature = "profiling"), allow(dead_code))]
+    pub profiling: bool,
+    pub sinks: HealthRegistry,
+    pub lag_slos: SloRegistry,
+    // /readyz fails below this health score
+    pub min_score: u8,
+    // effective configuration and market table, set once the markets are loaded
+    pub startup: Arc<Mutex<Option<Value>>>,
+    pub features: FeatureFlags,
+    // heartbeats of the consumers, None without --consumer-heartbeats
+    pub consumers: Option<ConsumerRegistry>,
+    // adds and removes sinks at runtime, set once the startup sinks are open
+    pub sink_control: Arc<Mutex<Option<SinkControl>>>,
+    // keys of --api-keys, set once the markets are loaded, None without --api-keys. The
+    // endpoints which change anything need a key with "admin": true.
+    pub api_keys: Option<Arc<Mutex<Option<Arc<ApiKeys>>>>>,
+}
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    // x-api-key header or api_key query parameter
+    pub api_key: Option<String>,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Response {
+        Response {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn text(status: u16, body: impl Into<String>) -> Response {
+        Response::new(status, "text/plain; charset=utf-8", body.into().into_bytes())
+    }
+
+    pub fn json(body: String) -> Response {
+        Response::new(200, "application/json", body.into_bytes())
+    }
+
+    pub fn not_found() -> Response {
+        Response::text(404, "not found\n")
+    }
+}
+
+fn status_text(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+// Small HTTP/1.1 server for operational endpoints, one request per connection
+pub async fn serve(addr: String, state: AdminState) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("admin server failed to bind {}: {}", addr, err);
+            return;
+        }
+    };
+    info!("admin server listening on {}", addr);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let state = state.clone();
+                spawn(async move {
+                    if let Err(err) = handle_connection(stream, state).await {
+                        warn!("admin connection error: {}", err);
+                    }
+                });
+            }
+            Err(err) => {
+                warn!("admin server accept failed: {}", err);
+            }
+        }
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, state: AdminState) -> std::io::Result<()> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if buf.len() > MAX_REQUEST_SIZE {
+            break;
+        }
+    }
+
+    let response = match parse_request(&buf) {
+        Some(request) if request.method == "GET" => route(request, &state).await,
+        // the only endpoints which change anything
+        Some(request) if request.method == "POST" && request.path == "/features" => match authorize(&request, &state) {
+            Ok(()) => update_features(request, &state),
+            Err(response) => response,
+        },
+        Some(request) if request.path == "/sinks" && matches!(request.method.as_str(), "POST" | "DELETE") => {
+            match authorize(&request, &state) {
+                Ok(()) => update_sinks(request, &state),
+                Err(response) => response,
+            }
+        }
+        Some(_) => Response::text(405, "method not allowed\n"),
+        None => Response::text(400, "bad request\n"),
+    };
+
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        status_text(response.status),
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await
+}
+
+fn parse_request(buf: &[u8]) -> Option<Request> {
+    let text = String::from_utf8_lossy(buf);
+    let mut lines = text.lines();
+    let line = lines.next()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?;
+    let (path, query_str) = target.split_once('?').unwrap_or((target, ""));
+    let mut query: HashMap<String, String> = query_str
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(k), percent_decode(v))
+        })
+        .collect();
+    let header = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("x-api-key"))
+        .map(|(_, value)| value.trim().to_string());
+    // not a parameter of the endpoint
+    let api_key = header.or(query.remove("api_key"));
+    Some(Request {
+        method,
+        path: path.to_string(),
+        query,
+        api_key,
+    })
+}
+
+// The endpoints which change the printer take an api key with "admin": true
+fn authorize(request: &Request, state: &AdminState) -> Result<(), Response> {
+    let Some(api_keys) = state.api_keys.as_ref() else {
+        return Err(Response::text(403, "changes need --api-keys with an admin key\n"));
+    };
+    let api_keys = api_keys.lock().unwrap();
+    let Some(api_keys) = api_keys.as_ref() else {
+        return Err(Response::text(503, "api keys are still loading\n"));
+    };
+    match request.api_key.as_deref().and_then(|key| api_keys.lookup(key)) {
+        Some(key) if key.admin => Ok(()),
+        Some(key) => Err(Response::text(403, format!("api key {} is not an admin key\n", key.name))),
+        None => Err(Response::text(401, "unknown or missing api key\n")),
+    }
+}
+
+// Query values may be percent-encoded, e.g. filter=side%3Dbuy%3Bmin_size%3D100
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match (bytes[i], hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+async fn route(request: Request, state: &AdminState) -> Response {
+    match request.path.as_str() {
+        "/metrics" => Response::new(
+            200,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            metrics().encode().into_bytes(),
+        ),
+        "/readyz" => {
+            // not ready while the lowest score of the sinks and markets is below
+            // --health-min-score
+            let score = health_score(&state.sinks, &state.lag_slos);
+            let status = if score >= state.min_score { 200 } else { 503 };
+            let body = serde_json::json!({
+                "score": score,
+                "sinks": &*state.sinks.lock().unwrap(),
+                "lagSlo": &*state.lag_slos.lock().unwrap(),
+            });
+            Response::new(status, "application/json", body.to_string().into_bytes())
+        }
+        "/features" => Response::json(serde_json::to_string(&state.features.snapshot()).unwrap()),
+        "/consumers" => match state.consumers.as_ref() {
+            Some(consumers) => Response::json(consumers.report().to_string()),
+            None => Response::text(404, "consumer heartbeats are off, start with --consumer-heartbeats\n"),
+        },
+        "/sinks" => {
+            let runtime = state
+                .sink_control
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(SinkControl::runtime)
+                .unwrap_or_default();
+            let body = serde_json::json!({
+                "sinks": &*state.sinks.lock().unwrap(),
+                "runtime": runtime,
+            });
+            Response::json(body.to_string())
+        }
+        "/startup" => match state.startup.lock().unwrap().as_ref() {
+            Some(startup) => Response::json(startup.to_string()),
+            None => Response::text(503, "markets are still loading\n"),
+        },
+        "/debug/pprof/profile" | "/debug/pprof/flamegraph" | "/debug/heap" => {
+            profiling_route(request, state).await
+        }
+        _ => Response::not_found(),
+    }
+}
+
+// POST /features?bars=false&owners=false, answers with all flags
+fn update_features(request: Request, state: &AdminState) -> Response {
+    match state.features.update(request.query.iter()) {
+        Ok(()) => Response::json(serde_json::to_string(&state.features.snapshot()).unwrap()),
+        Err(message) => Response::text(400, format!("{}\n", message)),
+    }
+}
+
+// POST /sinks?name=capture&path=capture.jsonl&topics=trades.SOL.*&filter=.., the path is
+// relative to --capture-dir
+// adds a file sink, DELETE /sinks?name=capture removes a sink added this way
+fn update_sinks(request: Request, state: &AdminState) -> Response {
+    let control = state.sink_control.lock().unwrap();
+    let Some(control) = control.as_ref() else {
+        return Response::text(503, "sinks are still starting\n");
+    };
+    if request.method == "POST" {
+        return match control.open(&request.query) {
+            Ok(name) => Response::text(200, format!("sink {} added\n", name)),
+            Err(message) => Response::text(400, format!("{}\n", message)),
+        };
+    }
+    let name = request.query.get("name").map(String::as_str).unwrap_or_default();
+    match control.remove(name) {
+        Ok(()) => Response::text(200, format!("sink {} removed\n", name)),
+        Err(message) => Response::text(404, format!("{}\n", message)),
+    }
+}
+
+#[cfg(feature = "profiling")]
+async fn profiling_route(request: Request, state: &AdminState) -> Response {
+    if !state.profiling {
+        return Response::text(404, "profiling is disabled, start with --profiling\n");
+    }
+    match request.path.as_str() {
+        "/debug/heap" => profiling::heap_stats(),
+        path => {
+            let seconds = request
+                .query
+                .get("seconds")
+                .and_then(|s| s.parse::<u64>().ok())
+                .unwrap_or(30);
+            profiling::cpu_profile(seconds, path == "/debug/pprof/flamegraph").await
+        }
+    }
+}
+
+#[cfg(not(feature = "profiling"))]
+async fn profiling_route(_request: Request, _state: &AdminState) -> Response {
+    Response::text(
+        404,
+        "profiling support not compiled in, build with --features profiling\n",
+    )
+}
@@ -0,0 +1,33 @@
+use log::{error, info, warn};
+use std::future::Future;
+use std::thread::JoinHandle;
+
+// Pins the calling thread, a core which does not exist only logs a warning
+pub fn pin_current_thread(name: &str, core: usize) {
+    let cores = core_affinity::get_core_ids().unwrap_or_default();
+    match cores.into_iter().find(|id| id.id == core) {
+        Some(id) if core_affinity::set_for_current(id) => info!("{} pinned to core {}", name, core),
+        _ => warn!("could not pin {} to core {}", name, core),
+    }
+}
+
+// Runs the future on its own thread and current-thread runtime, away from the
+// scheduling of the main runtime
+pub fn spawn_dedicated<F>(name: &str, core: Option<usize>, future: F) -> JoinHandle<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let thread_name = name.to_string();
+    std::thread::Builder::new()
+        .name(thread_name.clone())
+        .spawn(move || {
+            if let Some(core) = core {
+                pin_current_thread(&thread_name, core);
+            }
+            match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+                Ok(runtime) => runtime.block_on(future),
+                Err(err) => error!("could not start the {} runtime: {}", thread_name, err),
+            }
+        })
+        .unwrap()
+}
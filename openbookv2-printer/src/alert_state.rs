@@ -0,0 +1,39 @@
+use crate::state;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// When every alert last went out, by "<rule>|<market id>" (and "lag_slo|<market id>" for
+// lag breaches). Saved on every notification, so a restart in the middle of an incident
+// stays quiet until the cool-down of each alert is over.
+#[derive(Serialize, Deserialize, Default)]
+pub struct AlertState {
+    fired: BTreeMap<String, i64>,
+    #[serde(skip)]
+    cooldown_secs: i64,
+}
+
+impl AlertState {
+    pub fn load(path: &Path, cooldown_secs: u64) -> Self {
+        let mut alerts: AlertState = if cooldown_secs > 0 { state::load(path) } else { AlertState::default() };
+        alerts.cooldown_secs = cooldown_secs as i64;
+        alerts
+    }
+
+    // Whether the alert goes out at `now` (unix seconds), it is remembered if so. Always
+    // true without a cool-down.
+    pub fn fire(&mut self, key: &str, now: i64, path: &Path) -> bool {
+        if self.cooldown_secs == 0 {
+            return true;
+        }
+        if self.fired.get(key).is_some_and(|last| now - last < self.cooldown_secs) {
+            return false;
+        }
+        self.fired.insert(key.to_string(), now);
+        // alerts long past their cool-down are not needed anymore
+        let cooldown_secs = self.cooldown_secs;
+        self.fired.retain(|_, last| now - *last < cooldown_secs);
+        state::save(path, self);
+        true
+    }
+}
@@ -0,0 +1,61 @@
+// Allocation counting for the zero-copy work, with the alloc-audit feature every
+// thread's allocations are counted and `measure` reports them per stage
+#[cfg(feature = "alloc-audit")]
+mod counting {
+    use crate::metrics::{metrics, Metrics};
+    use std::alloc::{GlobalAlloc, Layout};
+    use std::cell::Cell;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    }
+
+    // Wraps the allocator of the binary, counts allocations and reallocations
+    pub struct CountingAllocator<A>(pub A);
+
+    fn count() {
+        // not available while the thread shuts down
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+    }
+
+    unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count();
+            self.0.alloc(layout)
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            count();
+            self.0.alloc_zeroed(layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count();
+            self.0.realloc(ptr, layout, new_size)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            self.0.dealloc(ptr, layout)
+        }
+    }
+
+    // Runs `f` and records the allocations it made on this thread in openbook_allocations
+    pub fn measure<T>(stage: &'static str, f: impl FnOnce() -> T) -> T {
+        let before = ALLOCATIONS.with(Cell::get);
+        let result = f();
+        let allocations = ALLOCATIONS.with(Cell::get) - before;
+        metrics()
+            .allocations
+            .get_or_create(&Metrics::stage(stage))
+            .observe(allocations as f64);
+        result
+    }
+}
+
+#[cfg(feature = "alloc-audit")]
+pub use counting::{measure, CountingAllocator};
+
+#[cfg(not(feature = "alloc-audit"))]
+pub fn measure<T>(_stage: &'static str, f: impl FnOnce() -> T) -> T {
+    f()
+}
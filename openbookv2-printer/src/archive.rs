@@ -0,0 +1,424 @@
+use crate::clock::now_ms;
+use crate::error::SinkError;
+use crate::metrics::metrics;
+use crate::sink::{topic_matches, Sink, TOPIC_CHECKSUMS, TOPIC_TRADES};
+use crate::state;
+use chrono::{Datelike, TimeZone, Utc};
+use openbookv2_decoder::{ControlMessage, Trade};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use log::{debug, info, warn};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const MANIFEST_INTERVAL: Duration = Duration::from_secs(1);
+// how often day partitions past the retention are looked for
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    pub min_slot: u64,
+    pub max_slot: u64,
+    pub min_time: u64,
+    pub max_time: u64,
+    pub count: u64,
+}
+
+// Index of all archive files by path relative to the archive dir
+pub type Manifest = BTreeMap<String, ManifestEntry>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ArchivePolicy {
+    // trades held before the complete slots among them are written
+    pub batch: usize,
+    // the newest slot is written once no trade arrived for this long
+    pub flush_after: Duration,
+    // day partitions older than this are deleted, None keeps them forever
+    pub retention: Option<Duration>,
+}
+
+// A trade waiting for its slot to complete
+struct Pending {
+    partition: String,
+    slot: u64,
+    time: u64,
+    line: String,
+}
+
+// Trades as JSON lines in <dir>/year=YYYY/month=MM/day=DD/market=<id>/trades.jsonl
+// by block time, with manifest.json describing every file so readers can pick
+// files by slot range without opening them. Trades are written a slot at a time,
+// once a later slot arrives, so readers never see part of a slot. Checksum manifests
+// go next to the trades of their market, in checksums.jsonl of the day of their last trade.
+// Writes are idempotent: a trade whose tradeId is in its partition already is skipped, so
+// replays and backfills never archive a trade twice.
+pub struct ArchiveSink {
+    dir: PathBuf,
+    files: BTreeMap<String, File>,
+    // tradeIds per partition, written or pending, loaded from the file on first use
+    trade_ids: BTreeMap<String, HashSet<String>>,
+    manifest: Manifest,
+    manifest_saved: Instant,
+    manifest_dirty: bool,
+    policy: ArchivePolicy,
+    pending: Vec<Pending>,
+    newest_slot: u64,
+    last_trade: Instant,
+    last_prune: Instant,
+}
+
+impl ArchiveSink {
+    pub fn new(dir: PathBuf, policy: ArchivePolicy) -> Result<Self, SinkError> {
+        std::fs::create_dir_all(&dir).map_err(|err| SinkError::Open {
+            target: dir.display().to_string(),
+            reason: err.to_string(),
+        })?;
+        let manifest = state::load_plain(&dir.join("manifest.json"));
+        let mut sink = ArchiveSink {
+            dir,
+            files: BTreeMap::new(),
+            trade_ids: BTreeMap::new(),
+            manifest,
+            manifest_saved: Instant::now(),
+            manifest_dirty: false,
+            policy,
+            pending: Vec::new(),
+            newest_slot: 0,
+            last_trade: Instant::now(),
+            last_prune: Instant::now(),
+        };
+        sink.prune();
+        Ok(sink)
+    }
+
+    // Deletes the day partitions (trades and checksums of every market) whose day ended
+    // more than the retention ago, and their manifest entries. Partition paths start with
+    // the zero padded date, so they compare by day as strings.
+    fn prune(&mut self) {
+        self.last_prune = Instant::now();
+        let Some(retention) = self.policy.retention else {
+            return;
+        };
+        let cutoff = ((now_ms() / 1000) as u64).saturating_sub(retention.as_secs());
+        let cutoff = Self::day_file(cutoff, "", "");
+        let cutoff = cutoff.split("/market=").next().unwrap_or_default();
+        let mut removed = 0;
+        for year in subdirs(&self.dir, "year=") {
+            for month in subdirs(&year, "month=") {
+                for day in subdirs(&month, "day=") {
+                    let Ok(partition) = day.strip_prefix(&self.dir) else {
+                        continue;
+                    };
+                    if &*partition.to_string_lossy() >= cutoff {
+                        continue;
+                    }
+                    match std::fs::remove_dir_all(&day) {
+                        Ok(()) => removed += 1,
+                        Err(err) => warn!("could not delete {}: {}", day.display(), err),
+                    }
+                }
+                // only removes it when empty
+                let _ = std::fs::remove_dir(&month);
+            }
+            let _ = std::fs::remove_dir(&year);
+        }
+        let before = self.manifest.len();
+        self.manifest.retain(|partition, _| partition.as_str() >= cutoff);
+        self.files.retain(|partition, _| partition.as_str() >= cutoff);
+        self.trade_ids.retain(|partition, _| partition.as_str() >= cutoff);
+        if self.manifest.len() != before {
+            state::save_plain(&self.dir.join("manifest.json"), &self.manifest);
+        }
+        if removed > 0 {
+            info!("archive: deleted {} day partitions before {}", removed, cutoff);
+        }
+    }
+
+    fn partition(trade: &Trade) -> String {
+        Self::day_file(trade.time_stamp, &trade.market_id, "trades.jsonl")
+    }
+
+    fn day_file(time_stamp: u64, market_id: &str, name: &str) -> String {
+        let time = Utc.timestamp_opt(time_stamp as i64, 0).single().unwrap_or_default();
+        format!(
+            "year={}/month={:02}/day={:02}/market={}/{}",
+            time.year(),
+            time.month(),
+            time.day(),
+            market_id,
+            name
+        )
+    }
+
+    // false when the partition holds the trade id already, otherwise it does from now on.
+    // Only the ids of the newest partition per market and of partitions with pending
+    // trades are kept, an older partition is read again when a trade of it comes back.
+    fn first_write(&mut self, partition: &str, trade_id: &str) -> bool {
+        if !self.trade_ids.contains_key(partition) {
+            let market = partition.rsplit('/').nth(1).unwrap_or_default().to_string();
+            let pending: HashSet<&str> = self.pending.iter().map(|pending| pending.partition.as_str()).collect();
+            self.trade_ids.retain(|kept, _| !kept.contains(&market) || pending.contains(kept.as_str()));
+            let ids = match File::open(self.dir.join(partition)) {
+                Ok(file) => BufReader::new(file)
+                    .lines()
+                    .map_while(Result::ok)
+                    .filter_map(|line| serde_json::from_str::<Trade>(&line).ok())
+                    .map(|trade| trade.trade_id)
+                    .collect(),
+                Err(_) => HashSet::new(),
+            };
+            self.trade_ids.insert(partition.to_string(), ids);
+        }
+        self.trade_ids.get_mut(partition).unwrap().insert(trade_id.to_string())
+    }
+
+    // Appended right away, manifests are rare and not part of manifest.json
+    fn write_checksum(&self, payload: &str) -> Result<(), SinkError> {
+        let ControlMessage::Checksum { market_id, to_time, .. } = serde_json::from_str(payload)? else {
+            return Ok(());
+        };
+        let path = self.dir.join(Self::day_file(to_time, &market_id, "checksums.jsonl"));
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|err| SinkError::Open {
+                target: path.display().to_string(),
+                reason: err.to_string(),
+            })?;
+        file.write_all(format!("{}\n", payload).as_bytes())?;
+        Ok(())
+    }
+
+    fn file(&mut self, partition: &str) -> Result<&mut File, SinkError> {
+        if !self.files.contains_key(partition) {
+            let path = self.dir.join(partition);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|err| SinkError::Open {
+                    target: path.display().to_string(),
+                    reason: err.to_string(),
+                })?;
+            // only the partitions of the current day per market stay open
+            let market = partition.rsplit('/').nth(1).unwrap_or_default().to_string();
+            self.files.retain(|open, _| !open.ends_with(&format!("{}/trades.jsonl", market)));
+            self.files.insert(partition.to_string(), file);
+        }
+        Ok(self.files.get_mut(partition).unwrap())
+    }
+
+    // Writes the pending trades of the slots before `slot`, one write per file. A failed
+    // file keeps its trades pending for the next flush.
+    fn flush_before(&mut self, slot: u64) -> Result<(), SinkError> {
+        let (ready, waiting): (Vec<Pending>, Vec<Pending>) =
+            std::mem::take(&mut self.pending).into_iter().partition(|pending| pending.slot < slot);
+        self.pending = waiting;
+        let mut partitions: BTreeMap<String, Vec<Pending>> = BTreeMap::new();
+        for pending in ready {
+            partitions.entry(pending.partition.clone()).or_default().push(pending);
+        }
+        let mut result = Ok(());
+        for (partition, trades) in partitions {
+            let lines: String = trades.iter().map(|pending| format!("{}\n", pending.line)).collect();
+            let written = match self.file(&partition) {
+                Ok(file) => file.write_all(lines.as_bytes()).map_err(SinkError::from),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = written {
+                self.pending.extend(trades);
+                result = Err(err);
+                continue;
+            }
+            for pending in trades {
+                let entry = self.manifest.entry(partition.clone()).or_insert(ManifestEntry {
+                    min_slot: pending.slot,
+                    max_slot: pending.slot,
+                    min_time: pending.time,
+                    max_time: pending.time,
+                    count: 0,
+                });
+                entry.min_slot = entry.min_slot.min(pending.slot);
+                entry.max_slot = entry.max_slot.max(pending.slot);
+                entry.min_time = entry.min_time.min(pending.time);
+                entry.max_time = entry.max_time.max(pending.time);
+                entry.count += 1;
+                self.manifest_dirty = true;
+            }
+        }
+        self.pending.sort_by_key(|pending| pending.slot);
+        if self.manifest_dirty && self.manifest_saved.elapsed() >= MANIFEST_INTERVAL {
+            state::save_plain(&self.dir.join("manifest.json"), &self.manifest);
+            self.manifest_saved = Instant::now();
+            self.manifest_dirty = false;
+        }
+        result
+    }
+}
+
+impl Sink for ArchiveSink {
+    fn name(&self) -> &str {
+        "archive"
+    }
+
+    fn send(&mut self, topic: &str, payload: &str) -> Result<(), SinkError> {
+        if self.last_prune.elapsed() >= PRUNE_INTERVAL {
+            self.prune();
+        }
+        if topic_matches(TOPIC_CHECKSUMS, topic) {
+            return self.write_checksum(payload);
+        }
+        // other messages only give a quiet feed the chance to write its last slot
+        if !topic_matches(TOPIC_TRADES, topic) {
+            if !self.pending.is_empty() && self.last_trade.elapsed() >= self.policy.flush_after {
+                return self.flush_before(u64::MAX);
+            }
+            return Ok(());
+        }
+        // a retry of a message whose flush failed is already pending
+        if self.pending.last().is_some_and(|pending| pending.line == payload) {
+            return self.flush_before(self.newest_slot);
+        }
+        let trade: Trade = serde_json::from_str(payload)?;
+        let partition = Self::partition(&trade);
+        // trades of publishers without ids are always written
+        if !trade.trade_id.is_empty() && !self.first_write(&partition, &trade.trade_id) {
+            debug!("trade {} is archived already", trade.trade_id);
+            metrics().archive_duplicates.inc();
+            return Ok(());
+        }
+        let slot_done = trade.slot > self.newest_slot;
+        self.newest_slot = self.newest_slot.max(trade.slot);
+        self.last_trade = Instant::now();
+        self.pending.push(Pending {
+            partition,
+            slot: trade.slot,
+            time: trade.time_stamp,
+            line: payload.to_string(),
+        });
+        let one_slot = self.pending.iter().all(|pending| pending.slot == self.newest_slot);
+        if self.pending.len() >= self.policy.batch && one_slot {
+            // one slot larger than the batch
+            self.flush_before(u64::MAX)
+        } else if slot_done || self.pending.len() >= self.policy.batch {
+            self.flush_before(self.newest_slot)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Calls `f` with the archived trades of every file of `markets` (ids, all when empty) which
+// reaches `since` (unix seconds), files in path order, so the trades of one market come
+// oldest first and in publishing order
+pub fn replay(dir: &Path, markets: &[String], since: u64, mut f: impl FnMut(Trade)) {
+    let manifest: Manifest = state::load_plain(&dir.join("manifest.json"));
+    let wanted = |partition: &str| {
+        markets.is_empty() || markets.iter().any(|market| partition.contains(&format!("/market={}/", market)))
+    };
+    for (partition, _) in manifest.iter().filter(|(partition, entry)| entry.max_time >= since && wanted(partition)) {
+        let path = dir.join(partition);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) => {
+                warn!("could not open {}: {}", path.display(), err);
+                continue;
+            }
+        };
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            // the last line can be cut off by a crash
+            if let Ok(trade) = serde_json::from_str::<Trade>(&line) {
+                if trade.time_stamp >= since {
+                    f(trade);
+                }
+            }
+        }
+    }
+}
+
+// The directories `<prefix>...` of `dir`
+fn subdirs(dir: &Path, prefix: &str) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(prefix) && entry.path().is_dir())
+        .map(|entry| entry.path())
+        .collect()
+}
+
+impl Drop for ArchiveSink {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush_before(u64::MAX) {
+            warn!("archive lost {} trades at shutdown: {}", self.pending.len(), err);
+        }
+        if self.manifest_dirty {
+            state::save_plain(&self.dir.join("manifest.json"), &self.manifest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn payload(trade_id: &str, slot: u64) -> String {
+        json!({
+            "timeStamp": 1_700_000_000,
+            "makerOwner": "maker",
+            "takerOwner": "taker",
+            "priceDouble": 1.0,
+            "quantityDouble": 1.0,
+            "marketId": "a",
+            "takerSide": 0,
+            "marketName": "SOL-USDC",
+            "signature": "signature",
+            "tradeId": trade_id,
+            "slot": slot,
+        })
+        .to_string()
+    }
+
+    fn archived(dir: &Path) -> Vec<String> {
+        let mut ids = Vec::new();
+        replay(dir, &[], 0, |trade| ids.push(trade.trade_id));
+        ids
+    }
+
+    #[test]
+    fn trades_are_archived_once_per_trade_id() {
+        let dir = std::env::temp_dir().join(format!("openbookv2-archive-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let policy = ArchivePolicy {
+            batch: 1000,
+            flush_after: Duration::from_secs(1),
+            retention: None,
+        };
+        let mut sink = ArchiveSink::new(dir.clone(), policy).unwrap();
+        for (trade_id, slot) in [("1", 1), ("2", 1), ("1", 2), ("3", 2)] {
+            sink.send(TOPIC_TRADES, &payload(trade_id, slot)).unwrap();
+        }
+        drop(sink);
+        assert_eq!(archived(&dir), ["1", "2", "3"]);
+        // a replay after a restart finds the ids in the file
+        let mut sink = ArchiveSink::new(dir.clone(), policy).unwrap();
+        for (trade_id, slot) in [("2", 1), ("3", 2), ("4", 3)] {
+            sink.send(TOPIC_TRADES, &payload(trade_id, slot)).unwrap();
+        }
+        drop(sink);
+        assert_eq!(archived(&dir), ["1", "2", "3", "4"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
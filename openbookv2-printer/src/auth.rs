@@ -0,0 +1,218 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiKeyConfig {
+    key: String,
+    name: String,
+    // market ids or names, empty for all markets
+    #[serde(default)]
+    markets: Vec<String>,
+    // messages per second over all connections of the key
+    #[serde(default)]
+    rate_limit: Option<u32>,
+    // consumer groups (--feed-key) whose encrypted envelopes the key gets
+    #[serde(default)]
+    groups: Vec<String>,
+    // may use the admin endpoints which change the printer
+    #[serde(default)]
+    admin: bool,
+}
+
+// Message types which concern no market, sent to market restricted keys and consumer groups
+pub const GLOBAL_TYPES: [&str; 3] = ["conversion_price", "commitment_downgrade", "program_upgrade"];
+
+pub struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u32) -> Self {
+        TokenBucket {
+            rate: rate as f64,
+            tokens: rate as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    pub fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub struct ApiKey {
+    pub name: String,
+    pub admin: bool,
+    markets: Vec<String>,
+    groups: Vec<String>,
+    limiter: Option<Mutex<TokenBucket>>,
+}
+
+impl ApiKey {
+    // A key restricted to some markets only gets messages without a market id when their
+    // `type` is one of the global ones, e.g. not referrer rebates, and encrypted envelopes,
+    // which name no market, of its groups only
+    pub fn entitled(&self, market_id: Option<&str>, kind: Option<&str>, group: Option<&str>) -> bool {
+        if self.markets.is_empty() {
+            return true;
+        }
+        match (market_id, kind) {
+            (Some(market_id), _) => self.markets.iter().any(|market| market == market_id),
+            (None, Some("encrypted")) => group.is_some_and(|group| self.groups.iter().any(|own| own == group)),
+            (None, kind) => kind.is_some_and(|kind| GLOBAL_TYPES.contains(&kind)),
+        }
+    }
+
+    pub fn allow(&self) -> bool {
+        self.limiter
+            .as_ref()
+            .is_none_or(|limiter| limiter.lock().unwrap().take())
+    }
+}
+
+// Keys for partners on the websocket feed, loaded from --api-keys:
+// [{"key": "...", "name": "partner-a", "markets": ["SOL-USDC"], "rateLimit": 100, "groups": ["desk-a"]}],
+// keys with "admin": true may also change the printer on the admin port
+pub struct ApiKeys {
+    keys: HashMap<String, Arc<ApiKey>>,
+}
+
+impl ApiKeys {
+    // market names are resolved to ids here, messages are matched by id only. `markets` are
+    // the ids and names of the published markets; a key naming any other market is an error,
+    // it would silently get nothing of it. The same goes for groups which are not in `groups`.
+    pub fn load(path: &str, markets: &[(String, String)], groups: &[String]) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|err| format!("could not read {}: {}", path, err))?;
+        let configs: Vec<ApiKeyConfig> =
+            serde_json::from_slice(&data).map_err(|err| format!("invalid api keys in {}: {}", path, err))?;
+        let mut keys = HashMap::new();
+        for config in configs {
+            let entitled = config
+                .markets
+                .iter()
+                .map(|market| {
+                    let market = market.trim();
+                    markets
+                        .iter()
+                        .find(|(id, name)| id == market || name == market)
+                        .map(|(id, _)| id.clone())
+                        .ok_or(format!("api key {} names market {}, which is not published", config.name, market))
+                })
+                .collect::<Result<Vec<String>, String>>()?;
+            if let Some(group) = config.groups.iter().find(|group| !groups.contains(group)) {
+                return Err(format!("api key {} names group {}, which has no --feed-key", config.name, group));
+            }
+            let key = ApiKey {
+                name: config.name,
+                admin: config.admin,
+                markets: entitled,
+                groups: config.groups,
+                limiter: config.rate_limit.map(|rate| Mutex::new(TokenBucket::new(rate))),
+            };
+            keys.insert(config.key, Arc::new(key));
+        }
+        Ok(ApiKeys { keys })
+    }
+
+    pub fn lookup(&self, key: &str) -> Option<Arc<ApiKey>> {
+        self.keys.get(key).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::WsMessage;
+    use openbookv2_decoder::ControlMessage;
+    use serde_json::json;
+
+    fn key(markets: &[&str]) -> ApiKey {
+        ApiKey {
+            name: "partner".to_string(),
+            admin: false,
+            markets: markets.iter().map(|market| market.to_string()).collect(),
+            groups: vec!["desk-a".to_string()],
+            limiter: None,
+        }
+    }
+
+    // Entitlement as the websocket checks it, from the published payload
+    fn receives(key: &ApiKey, message: &ControlMessage) -> bool {
+        let message = WsMessage::new("control", &serde_json::to_string(message).unwrap());
+        key.entitled(message.market_id.as_deref(), message.kind.as_deref(), message.group.as_deref())
+    }
+
+    #[test]
+    fn global_messages_go_to_restricted_keys() {
+        let restricted = key(&["market-a"]);
+        let oracle = json!({"account": "oracle", "price": 1.0, "confidence": 0.0, "slot": null, "publishTime": null});
+        let globals = [
+            json!({"type": "conversion_price", "currency": "USDT", "price": 1.0, "oracle": oracle}),
+            json!({"type": "commitment_downgrade", "slot": 1, "requested": "confirmed", "observed": "processed"}),
+            json!({"type": "program_upgrade", "programId": "program", "slot": 2, "previousSlot": 1, "markets": 0,
+                "incompatible": {}}),
+        ];
+        assert_eq!(globals.len(), GLOBAL_TYPES.len());
+        for global in globals {
+            let message: ControlMessage = serde_json::from_value(global).unwrap();
+            assert!(receives(&restricted, &message), "{:?} not sent to a restricted key", message);
+        }
+        for kind in GLOBAL_TYPES {
+            assert!(restricted.entitled(None, Some(kind), None), "{}", kind);
+        }
+    }
+
+    #[test]
+    fn restricted_keys_do_not_get_control_messages_of_other_markets() {
+        let restricted = key(&["market-a"]);
+        let data_loss = |market_id: &str| ControlMessage::DataLoss {
+            market_id: market_id.to_string(),
+            from_seq: 1,
+            to_seq: 2,
+        };
+        assert!(receives(&restricted, &data_loss("market-a")));
+        assert!(!receives(&restricted, &data_loss("market-b")));
+        let rebate = ControlMessage::ReferrerRebate {
+            open_orders_account: "account".to_string(),
+            referrer: "referrer".to_string(),
+            referrer_rebate: 1,
+            signature: "signature".to_string(),
+        };
+        assert!(!receives(&restricted, &rebate));
+        assert!(!restricted.entitled(None, None, None));
+    }
+
+    #[test]
+    fn keys_of_all_markets_get_everything() {
+        let all = key(&[]);
+        assert!(all.entitled(Some("market-b"), None, None));
+        assert!(all.entitled(None, Some("referrer_rebate"), None));
+        assert!(all.entitled(None, None, None));
+    }
+
+    #[test]
+    fn restricted_keys_get_the_envelopes_of_their_groups() {
+        let restricted = key(&["market-a"]);
+        assert!(restricted.entitled(None, Some("encrypted"), Some("desk-a")));
+        assert!(!restricted.entitled(None, Some("encrypted"), Some("desk-b")));
+        assert!(!restricted.entitled(None, Some("encrypted"), None));
+    }
+}
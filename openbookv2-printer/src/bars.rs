@@ -0,0 +1,172 @@
+use openbookv2_decoder::feed::Volume;
+use openbookv2_decoder::{ControlMessage, Trade};
+use std::collections::BTreeMap;
+
+struct Bar {
+    market_name: String,
+    start: i64,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: Volume,
+    trades: u32,
+}
+
+impl Bar {
+    fn new(trade: &Trade, start: i64) -> Self {
+        Bar {
+            market_name: trade.market_name.clone(),
+            start,
+            open: trade.price_double,
+            high: trade.price_double,
+            low: trade.price_double,
+            close: trade.price_double,
+            volume: Volume::default(),
+            trades: 0,
+        }
+    }
+
+    fn add(&mut self, trade: &Trade) {
+        self.high = self.high.max(trade.price_double);
+        self.low = self.low.min(trade.price_double);
+        self.close = trade.price_double;
+        self.volume.add(&Volume {
+            base: trade.quantity_double,
+            quote: trade.quantity_double * trade.price_double,
+        });
+        self.trades += 1;
+    }
+
+    fn into_message(self, market_id: String) -> ControlMessage {
+        ControlMessage::Bar {
+            market_id,
+            market_name: self.market_name,
+            start: self.start,
+            open: self.open,
+            high: self.high,
+            low: self.low,
+            close: self.close,
+            volume: self.volume,
+            trades: self.trades,
+        }
+    }
+}
+
+// 1 second OHLCV bars by block time. Seconds without trades produce no bar, trades
+// for a second whose bar was already published are not added to any bar.
+#[derive(Default)]
+pub struct BarAggregator {
+    current: BTreeMap<String, Bar>,
+}
+
+impl BarAggregator {
+    // Returns the previous bar of the market once a trade of a later second arrives
+    pub fn record(&mut self, trade: &Trade) -> Option<ControlMessage> {
+        let second = trade.time_stamp as i64;
+        match self.current.get_mut(&trade.market_id) {
+            Some(bar) if bar.start == second => {
+                bar.add(trade);
+                None
+            }
+            Some(bar) if bar.start > second => None,
+            _ => {
+                let mut bar = Bar::new(trade, second);
+                bar.add(trade);
+                self.current
+                    .insert(trade.market_id.clone(), bar)
+                    .map(|done| done.into_message(trade.market_id.clone()))
+            }
+        }
+    }
+
+    // Bars which can not receive trades anymore, one second of grace for late transactions
+    pub fn flush(&mut self, now_secs: i64) -> Vec<ControlMessage> {
+        let done: Vec<String> = self
+            .current
+            .iter()
+            .filter(|(_, bar)| bar.start < now_secs - 1)
+            .map(|(market_id, _)| market_id.clone())
+            .collect();
+        done.into_iter()
+            .filter_map(|market_id| {
+                let bar = self.current.remove(&market_id)?;
+                Some(bar.into_message(market_id))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn trade(market_id: &str, time_stamp: i64, price: f64, quantity: f64) -> Trade {
+        serde_json::from_value(json!({
+            "timeStamp": time_stamp,
+            "makerOwner": "maker",
+            "takerOwner": "taker",
+            "priceDouble": price,
+            "quantityDouble": quantity,
+            "marketId": market_id,
+            "takerSide": 0,
+            "marketName": "SOL-USDC",
+            "signature": "signature",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn a_later_second_publishes_the_bar_of_the_previous_one() {
+        let mut bars = BarAggregator::default();
+        assert!(bars.record(&trade("a", NOW, 2.0, 1.0)).is_none());
+        assert!(bars.record(&trade("a", NOW, 5.0, 1.0)).is_none());
+        assert!(bars.record(&trade("a", NOW, 1.0, 2.0)).is_none());
+        // another market has its own bar
+        assert!(bars.record(&trade("b", NOW + 1, 9.0, 1.0)).is_none());
+        match bars.record(&trade("a", NOW + 3, 3.0, 1.0)) {
+            Some(ControlMessage::Bar {
+                market_id,
+                start,
+                open,
+                high,
+                low,
+                close,
+                volume,
+                trades,
+                ..
+            }) => {
+                assert_eq!((market_id.as_str(), start), ("a", NOW));
+                assert_eq!((open, high, low, close), (2.0, 5.0, 1.0, 1.0));
+                assert_eq!((volume.base, volume.quote), (4.0, 9.0));
+                assert_eq!(trades, 3);
+            }
+            message => panic!("expected a bar, got {:?}", message),
+        }
+    }
+
+    #[test]
+    fn trades_of_a_published_second_are_dropped() {
+        let mut bars = BarAggregator::default();
+        bars.record(&trade("a", NOW, 1.0, 1.0));
+        assert!(bars.record(&trade("a", NOW + 1, 2.0, 1.0)).is_some());
+        assert!(bars.record(&trade("a", NOW, 3.0, 1.0)).is_none());
+        let flushed = bars.flush(NOW + 3);
+        assert!(matches!(flushed[..], [ControlMessage::Bar { open, trades: 1, .. }] if open == 2.0));
+    }
+
+    #[test]
+    fn flushes_bars_after_a_second_of_grace() {
+        let mut bars = BarAggregator::default();
+        bars.record(&trade("a", NOW, 1.0, 1.0));
+        bars.record(&trade("b", NOW + 1, 1.0, 1.0));
+        assert!(bars.flush(NOW + 1).is_empty());
+        let flushed = bars.flush(NOW + 2);
+        assert!(matches!(&flushed[..], [ControlMessage::Bar { market_id, .. }] if market_id == "a"));
+        assert_eq!(bars.flush(NOW + 3).len(), 1);
+        assert!(bars.flush(NOW + 10).is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+const MAX_SLOTS: usize = 4096;
+const SLOT_MS: i64 = 400;
+// recent blocks the slot clock is fitted over, about 3 minutes
+const FIT_POINTS: usize = 512;
+
+// Block times by slot, fed from the geyser blocks_meta stream
+#[derive(Default)]
+pub struct BlockTimes {
+    times: BTreeMap<u64, i64>,
+}
+
+impl BlockTimes {
+    pub fn insert(&mut self, slot: u64, block_time: i64) {
+        observe(slot, block_time * 1000);
+        self.times.insert(slot, block_time);
+        while self.times.len() > MAX_SLOTS {
+            self.times.pop_first();
+        }
+    }
+
+    // Block time in ms. Transactions usually arrive before the meta of their own
+    // block, in that case the closest earlier block is extrapolated by slot time.
+    pub fn estimate_ms(&self, slot: u64) -> Option<i64> {
+        let (&known_slot, &block_time) = self.times.range(..=slot).next_back()?;
+        Some(block_time * 1000 + (slot - known_slot) as i64 * SLOT_MS)
+    }
+}
+
+// Block time as a linear function of the slot, least squares over the last FIT_POINTS
+// block times any source saw. Block times are whole seconds and slots do not take exactly
+// SLOT_MS, the fit smooths both. Shared by all sources, so one with block times (geyser
+// blocks_meta, poll) also covers fills of one without (helius).
+struct SlotClock {
+    points: VecDeque<(u64, i64)>,
+    // (slot, ms) the fit goes through and ms per slot
+    fit: Option<(u64, f64, f64)>,
+}
+
+static SLOT_CLOCK: Mutex<SlotClock> = Mutex::new(SlotClock {
+    points: VecDeque::new(),
+    fit: None,
+});
+
+impl SlotClock {
+    fn refit(&mut self) {
+        let Some(&(origin, _)) = self.points.front() else {
+            self.fit = None;
+            return;
+        };
+        let n = self.points.len() as f64;
+        let (mut sum_x, mut sum_y) = (0.0, 0.0);
+        for (slot, ms) in self.points.iter() {
+            sum_x += (slot - origin) as f64;
+            sum_y += *ms as f64;
+        }
+        let (mean_x, mean_y) = (sum_x / n, sum_y / n);
+        let (mut covariance, mut variance) = (0.0, 0.0);
+        for (slot, ms) in self.points.iter() {
+            let dx = (slot - origin) as f64 - mean_x;
+            covariance += dx * (*ms as f64 - mean_y);
+            variance += dx * dx;
+        }
+        // a single slot (or a fit going backwards) says nothing about the slot time
+        let slope = match covariance / variance {
+            slope if variance > 0.0 && slope > 0.0 => slope,
+            _ => SLOT_MS as f64,
+        };
+        self.fit = Some((origin + mean_x.round() as u64, mean_y, slope));
+    }
+}
+
+pub fn observe(slot: u64, block_time_ms: i64) {
+    let mut clock = SLOT_CLOCK.lock().unwrap();
+    // sources deliver blocks about in order, older ones than the window are of no use
+    if clock.points.front().is_some_and(|(first, _)| slot < *first) {
+        return;
+    }
+    if let Some(point) = clock.points.iter_mut().find(|(known, _)| *known == slot) {
+        point.1 = block_time_ms;
+    } else {
+        let at = clock.points.partition_point(|(known, _)| *known < slot);
+        clock.points.insert(at, (slot, block_time_ms));
+        if clock.points.len() > FIT_POINTS {
+            clock.points.pop_front();
+        }
+    }
+    clock.refit();
+}
+
+// Estimated block time in ms, None until a source saw a block time
+pub fn fitted_ms(slot: u64) -> Option<i64> {
+    let (at, ms, slope) = SLOT_CLOCK.lock().unwrap().fit?;
+    Some((ms + (slot as f64 - at as f64) * slope).round() as i64)
+}
@@ -0,0 +1,128 @@
+use crate::utils::{parse_ooa_owner, OoaOwner};
+use anchor_lang::Discriminator;
+use futures::stream::{self, StreamExt};
+use log::{info, warn};
+use openbookv2_generated::OpenOrdersAccount;
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_client::client_error::ClientError;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::account::Account;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Instant;
+
+// getMultipleAccounts takes at most 100 keys
+const ACCOUNTS_PER_REQUEST: usize = 100;
+// market of an OpenOrders account, after the discriminator and the owner
+const OOA_MARKET_OFFSET: usize = 40;
+// discriminator, owner, market, name and delegate, all the owner lookup reads
+const OOA_OWNER_LEN: usize = 136;
+
+// The accounts of `keys` in their order, with at most `concurrency` requests of 100 keys
+// in flight. Logs the progress when it takes more than one request.
+pub async fn fetch_accounts(
+    client: &RpcClient,
+    keys: &[Pubkey],
+    concurrency: usize,
+    what: &str,
+) -> Result<Vec<Option<Account>>, ClientError> {
+    let chunks: Vec<&[Pubkey]> = keys.chunks(ACCOUNTS_PER_REQUEST).collect();
+    let total = chunks.len();
+    let started = Instant::now();
+    let mut results = stream::iter(chunks)
+        .map(|chunk| client.get_multiple_accounts(chunk))
+        .buffered(concurrency.max(1));
+    let mut accounts = Vec::with_capacity(keys.len());
+    while let Some(chunk) = results.next().await {
+        accounts.extend(chunk?);
+        if total > 1 {
+            info!("loaded {} of {} {}", accounts.len(), keys.len(), what);
+        }
+    }
+    if total > 1 {
+        info!("loaded {} {} in {} ms", keys.len(), what, started.elapsed().as_millis());
+    }
+    Ok(accounts)
+}
+
+// The data of `keys` from a {"<pubkey>": "<base64 account data>"} file (--market-accounts),
+// None for keys the file does not have
+pub fn load_accounts(path: &Path, keys: &[Pubkey]) -> Result<Vec<Option<Vec<u8>>>, String> {
+    let file = std::fs::read_to_string(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    let accounts: HashMap<String, String> =
+        serde_json::from_str(&file).map_err(|err| format!("invalid {}: {}", path.display(), err))?;
+    keys.iter()
+        .map(|key| {
+            accounts
+                .get(&key.to_string())
+                .map(|data| STANDARD.decode(data.trim()))
+                .transpose()
+                .map_err(|err| format!("account {} in {}: {}", key, path.display(), err))
+        })
+        .collect()
+}
+
+// Owners of the OpenOrders accounts of every market, one getProgramAccounts per market with at
+// most `concurrency` in flight, so the first trades of a market don't wait for lookups. A market
+// whose accounts can't be listed (not every RPC serves getProgramAccounts) is skipped.
+pub async fn prewarm_owners(client: &RpcClient, markets: &[Pubkey], concurrency: usize) -> HashMap<Pubkey, OoaOwner> {
+    let started = Instant::now();
+    let mut results = stream::iter(markets)
+        .map(|market| async move { (market, open_orders_owners(client, market).await) })
+        .buffer_unordered(concurrency.max(1));
+    let mut owners = HashMap::new();
+    let mut done = 0;
+    while let Some((market, result)) = results.next().await {
+        done += 1;
+        match result {
+            Ok(market_owners) => {
+                info!(
+                    "pre-warmed {} owners of market {} ({} of {} markets)",
+                    market_owners.len(),
+                    market,
+                    done,
+                    markets.len()
+                );
+                owners.extend(market_owners);
+            }
+            Err(err) => warn!("could not list the OpenOrders accounts of market {}: {}", market, err),
+        }
+    }
+    info!(
+        "pre-warmed {} owners of {} markets in {} ms",
+        owners.len(),
+        markets.len(),
+        started.elapsed().as_millis()
+    );
+    owners
+}
+
+async fn open_orders_owners(client: &RpcClient, market: &Pubkey) -> Result<Vec<(Pubkey, OoaOwner)>, ClientError> {
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(0, OpenOrdersAccount::discriminator().to_vec())),
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(OOA_MARKET_OFFSET, market.to_bytes().to_vec())),
+        ]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            data_slice: Some(UiDataSliceConfig {
+                offset: 0,
+                length: OOA_OWNER_LEN,
+            }),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let accounts = client
+        .get_program_accounts_with_config(&openbookv2_generated::id(), config)
+        .await?;
+    Ok(accounts
+        .into_iter()
+        .filter_map(|(key, account)| parse_ooa_owner(&account.data).map(|owner| (key, owner)))
+        .collect())
+}
@@ -0,0 +1,71 @@
+use openbookv2_decoder::Trade;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, watch};
+
+// One published message as the network sinks get it, with the trade of trade messages
+// so in-process consumers need not parse the payload again
+#[derive(Clone, Debug)]
+pub struct BusMessage {
+    pub topic: String,
+    pub payload: String,
+    pub trade: Option<Trade>,
+}
+
+// The feed for consumers in the same process as an embedded pipeline, without a socket in
+// between. subscribe() gets every message of the complete path (trades, control messages,
+// bars and the other topics, not the fast path of --fast-sinks) from the time it is
+// called; a receiver which falls more than the capacity behind gets RecvError::Lagged
+// with the number of messages it missed and continues after them, the pipeline never
+// waits for it. last_trade() watches the last trade of one market. Create it before
+// spawn() to miss nothing:
+//
+//   let bus = Bus::new(4096);
+//   let mut messages = bus.subscribe();
+//   let handle = TradesStreamer::builder().bus(bus.clone()).spawn().await?;
+#[derive(Clone)]
+pub struct Bus {
+    messages: broadcast::Sender<Arc<BusMessage>>,
+    last_trades: Arc<Mutex<HashMap<String, watch::Sender<Option<Arc<Trade>>>>>>,
+}
+
+impl Bus {
+    pub fn new(capacity: usize) -> Self {
+        let (messages, _) = broadcast::channel(capacity.max(1));
+        Bus {
+            messages,
+            last_trades: Arc::default(),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<BusMessage>> {
+        self.messages.subscribe()
+    }
+
+    // None until the market trades
+    pub fn last_trade(&self, market_id: &str) -> watch::Receiver<Option<Arc<Trade>>> {
+        let mut last_trades = self.last_trades.lock().unwrap();
+        last_trades
+            .entry(market_id.to_string())
+            .or_insert_with(|| watch::channel(None).0)
+            .subscribe()
+    }
+
+    // Called by the publish stage after the sinks, takes the message to avoid copies
+    pub(crate) fn publish(&self, message: BusMessage, trade_topic: bool) {
+        if let Some(trade) = message.trade.as_ref().filter(|_| trade_topic) {
+            let mut last_trades = self.last_trades.lock().unwrap();
+            let last_trade = Some(Arc::new(trade.clone()));
+            match last_trades.get(&trade.market_id) {
+                Some(sender) => {
+                    sender.send_replace(last_trade);
+                }
+                None => {
+                    last_trades.insert(trade.market_id.clone(), watch::channel(last_trade).0);
+                }
+            }
+        }
+        // no receivers is not an error
+        let _ = self.messages.send(Arc::new(message));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::clock::now_ms;
+use crate::intake::{TxCost, Update};
+use futures::stream::{self, StreamExt};
+use openbookv2_decoder::{FillPosition, LogFormat};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::{RpcBlockConfig, RpcTransactionConfig};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::{TransactionDetails, UiTransactionEncoding};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+// getTransaction requests in flight per market
+const PARALLELISM: usize = 8;
+
+// The index of a transaction in its block, which getTransaction does not return, so fills
+// read again get the trade ids the live sources gave them. One getBlock with signatures
+// only per slot; transactions come newest first, so only the block of the last slot is kept.
+#[derive(Default)]
+pub struct TxIndexes {
+    slot: Option<u64>,
+    signatures: HashMap<String, u64>,
+}
+
+impl TxIndexes {
+    pub async fn get(
+        &mut self,
+        client: &RpcClient,
+        slot: u64,
+        signature: &str,
+        commitment: CommitmentConfig,
+    ) -> Result<u64, String> {
+        if self.slot != Some(slot) {
+            let config = RpcBlockConfig {
+                encoding: None,
+                transaction_details: Some(TransactionDetails::Signatures),
+                rewards: Some(false),
+                commitment: Some(commitment),
+                max_supported_transaction_version: Some(0),
+            };
+            let block = client
+                .get_block_with_config(slot, config)
+                .await
+                .map_err(|err| format!("getBlock {}: {}", slot, err))?;
+            // failed transactions are in the list too, like in the blocks of the poll source
+            self.signatures = block
+                .signatures
+                .unwrap_or_default()
+                .into_iter()
+                .enumerate()
+                .map(|(index, signature)| (signature, index as u64))
+                .collect();
+            self.slot = Some(slot);
+        }
+        self.signatures
+            .get(signature)
+            .copied()
+            .ok_or(format!("transaction {} is not in block {}", signature, slot))
+    }
+}
+
+pub struct CaughtUp {
+    // oldest first, without feed_seq
+    pub fills: Vec<Update>,
+    // false when the scan ended before a fill at or below `after`, the fills in between are missing
+    pub complete: bool,
+    pub scanned: usize,
+}
+
+// The fills of `market` after seq_num `after` (the last one published before the restart),
+// read from the newest `limit` transactions of the market
+pub async fn missed_fills(
+    client: &RpcClient,
+    market: Pubkey,
+    after: u64,
+    limit: usize,
+    log_format: &LogFormat,
+) -> Result<CaughtUp, String> {
+    let transaction_config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(CommitmentConfig::confirmed()),
+        max_supported_transaction_version: Some(0),
+    };
+    let mut fills = Vec::new();
+    let mut tx_indexes = TxIndexes::default();
+    let mut scanned = 0;
+    let mut before = None;
+    while scanned < limit {
+        let config = GetConfirmedSignaturesForAddress2Config {
+            before,
+            until: None,
+            limit: Some((limit - scanned).min(1000)),
+            commitment: Some(CommitmentConfig::confirmed()),
+        };
+        let page = client
+            .get_signatures_for_address_with_config(&market, config)
+            .await
+            .map_err(|err| err.to_string())?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        before = Some(Signature::from_str(&last.signature).map_err(|err| err.to_string())?);
+        scanned += page.len();
+        // newest first, buffered keeps the order so the scan stops at the first old fill
+        let mut transactions = stream::iter(page.into_iter().filter(|status| status.err.is_none()))
+            .map(|status| async move {
+                #[cfg(feature = "chaos")]
+                crate::chaos::rpc_delay().await;
+                let signature = Signature::from_str(&status.signature).map_err(|err| err.to_string())?;
+                let transaction = client
+                    .get_transaction_with_config(&signature, transaction_config)
+                    .await
+                    .map_err(|err| format!("getTransaction {}: {}", signature, err))?;
+                Ok::<_, String>((status.signature, transaction))
+            })
+            .buffered(PARALLELISM);
+        while let Some(result) = transactions.next().await {
+            let (signature, transaction) = result?;
+            let Some(meta) = transaction.transaction.meta else {
+                continue;
+            };
+            let OptionSerializer::Some(logs) = meta.log_messages else {
+                continue;
+            };
+            // numbered like the live feed, over the deduplicated fills of all markets
+            let mut seen = HashSet::new();
+            let tx_fills: Vec<_> = logs
+                .iter()
+                .enumerate()
+                .filter_map(|(log_index, log)| log_format.decode_fill_log(log).map(|fill_log| (log_index, fill_log)))
+                .filter(|(_, fill_log)| seen.insert((fill_log.market, fill_log.seq_num)))
+                .collect();
+            let fill_count = tx_fills.len() as u32;
+            let tx_index = match tx_fills.iter().any(|(_, fill_log)| fill_log.market == market) {
+                true => tx_indexes.get(client, transaction.slot, &signature, CommitmentConfig::confirmed()).await?,
+                false => 0,
+            };
+            let mut reached = false;
+            for (fill_index, (log_index, fill_log)) in tx_fills.into_iter().enumerate() {
+                if fill_log.market != market {
+                    continue;
+                }
+                if fill_log.seq_num <= after {
+                    reached = true;
+                    continue;
+                }
+                fills.push(Update::Fill {
+                    fill_log,
+                    signature: signature.clone(),
+                    position: FillPosition {
+                        slot: transaction.slot,
+                        tx_index,
+                        log_index: log_index as u32,
+                    },
+                    fill_index: (fill_index as u32, fill_count),
+                    feed_seq: 0,
+                    block_time_ms: transaction.block_time.map(|block_time| block_time * 1000),
+                    block_time_estimated: false,
+                    cost: TxCost::default(),
+                    received_ms: now_ms(),
+                    pre_confirmed: false,
+                });
+            }
+            if reached {
+                fills.sort_by_key(seq_num);
+                return Ok(CaughtUp {
+                    fills,
+                    complete: true,
+                    scanned,
+                });
+            }
+        }
+    }
+    fills.sort_by_key(seq_num);
+    Ok(CaughtUp {
+        fills,
+        complete: false,
+        scanned,
+    })
+}
+
+pub fn seq_num(update: &Update) -> u64 {
+    match update {
+        Update::Fill { fill_log, .. } => fill_log.seq_num,
+        _ => 0,
+    }
+}
@@ -0,0 +1,111 @@
+use crate::error::SinkError;
+use log::warn;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+// Faults injected by builds with the chaos feature, for exercising the reconnect, breaker
+// and backfill paths without a misbehaving node or sink at hand. Release builds do not
+// contain the hooks.
+#[derive(Clone, Debug, Default)]
+pub struct Faults {
+    // fraction of source transactions dropped before their fills are read
+    pub drop_messages: f64,
+    // added before every RPC request of the owners enricher, the poll source and catch up
+    pub rpc_delay: Duration,
+    // added before every update the enrich stage takes, so fills back up in the intake queue
+    pub enrich_delay: Duration,
+    // fraction of sink sends which fail, per attempt
+    pub sink_failures: f64,
+    // only these sinks fail, all when empty
+    pub failing_sinks: Vec<String>,
+}
+
+static FAULTS: RwLock<Option<Faults>> = RwLock::new(None);
+// xorshift state, fixed so a run can be repeated with CHAOS_SEED
+static STATE: AtomicU64 = AtomicU64::new(0x9e37_79b9_7f4a_7c15);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+// Replaces the faults, None stops injecting
+pub fn set(faults: Option<Faults>) {
+    if let Some(faults) = faults.as_ref() {
+        warn!("injecting faults: {:?}", faults);
+    }
+    *FAULTS.write().unwrap() = faults;
+}
+
+pub fn seed(seed: u64) {
+    STATE.store(seed.max(1), Ordering::Relaxed);
+}
+
+// CHAOS="drop=0.1,rpc_delay_ms=500,enrich_delay_ms=20,sink_fail=0.2,sinks=zmq|ws" and CHAOS_SEED,
+// read at startup
+pub fn from_env() {
+    if let Ok(seed) = std::env::var("CHAOS_SEED") {
+        self::seed(seed.parse().unwrap());
+    }
+    let Ok(spec) = std::env::var("CHAOS") else {
+        return;
+    };
+    let mut faults = Faults::default();
+    for part in spec.split(',').filter(|part| !part.trim().is_empty()) {
+        let (name, value) = part
+            .split_once('=')
+            .unwrap_or_else(|| panic!("invalid CHAOS entry {}, expected <fault>=<value>", part));
+        match name.trim() {
+            "drop" => faults.drop_messages = value.trim().parse().unwrap(),
+            "rpc_delay_ms" => faults.rpc_delay = Duration::from_millis(value.trim().parse().unwrap()),
+            "enrich_delay_ms" => faults.enrich_delay = Duration::from_millis(value.trim().parse().unwrap()),
+            "sink_fail" => faults.sink_failures = value.trim().parse().unwrap(),
+            "sinks" => faults.failing_sinks = value.split('|').map(|sink| sink.trim().to_string()).collect(),
+            name => panic!("unknown CHAOS fault {}", name),
+        }
+    }
+    set(Some(faults));
+}
+
+// Uniform in [0, 1)
+fn roll() -> f64 {
+    let mut x = STATE.load(Ordering::Relaxed);
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    STATE.store(x, Ordering::Relaxed);
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn faults() -> Option<Faults> {
+    FAULTS.read().unwrap().clone()
+}
+
+pub fn drop_message() -> bool {
+    let dropped = faults().is_some_and(|faults| faults.drop_messages > 0.0 && roll() < faults.drop_messages);
+    if dropped {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+    dropped
+}
+
+// Transactions dropped so far
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+pub async fn rpc_delay() {
+    if let Some(delay) = faults().map(|faults| faults.rpc_delay).filter(|delay| !delay.is_zero()) {
+        tokio::time::sleep(delay).await;
+    }
+}
+
+pub async fn enrich_delay() {
+    if let Some(delay) = faults().map(|faults| faults.enrich_delay).filter(|delay| !delay.is_zero()) {
+        tokio::time::sleep(delay).await;
+    }
+}
+
+pub fn sink_failure(sink: &str) -> Option<SinkError> {
+    let faults = faults()?;
+    let targeted = faults.failing_sinks.is_empty() || faults.failing_sinks.iter().any(|name| name == sink);
+    (targeted && faults.sink_failures > 0.0 && roll() < faults.sink_failures)
+        .then_some(SinkError::Closed("injected failure"))
+}
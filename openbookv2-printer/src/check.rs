@@ -0,0 +1,338 @@
+use crate::config::{Config, DecimalsCheck};
+use crate::{bootstrap, decimals, exit, source};
+use anchor_lang::AnchorDeserialize;
+use openbookv2_decoder::parse_name;
+use openbookv2_generated::state::Market;
+use serde_json::{json, Value};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_program::pubkey::Pubkey;
+use std::collections::BTreeMap;
+
+// Credentials end up in query strings (helius api-key) and headers
+fn redact_url(url: &str) -> String {
+    match url.split_once('?') {
+        Some((base, _)) => format!("{}?***", base),
+        None => url.to_string(),
+    }
+}
+
+fn redact(secret: &str) -> &str {
+    if secret.is_empty() {
+        ""
+    } else {
+        "***"
+    }
+}
+
+pub fn effective(config: &Config) -> Value {
+    json!({
+        "envFile": config.env_file,
+        "rpcUrl": redact_url(&config.rpc_url),
+        "markets": config.market_keys.iter().map(|key| key.to_string()).collect::<Vec<_>>(),
+        "host": config.host,
+        "port": config.port,
+        "connect": config.connect,
+        "grpc": redact_url(&config.grpc),
+        "xToken": redact(&config.x_token),
+        "tokenRefreshCommand": config.token_refresh_command.as_deref().map(redact),
+        "commitmentCheckMs": config.commitment_check_ms,
+        "grpcHeaders": config.grpc_headers.iter().map(|(name, value)| (name.clone(), redact(value).to_string())).collect::<Vec<_>>(),
+        "commitment": format!("{:?}", config.commitment),
+        "source": format!("{:?}", config.source),
+        "sourceCommitment": format!("{:?}", config.source_commitment()),
+        "marketCommitments": config
+            .market_commitments
+            .iter()
+            .map(|(market, commitment)| (market.to_string(), commitment.as_str()))
+            .collect::<Vec<_>>(),
+        "pollIntervalMs": config.poll_interval_ms,
+        "staleFeedSecs": config.stale_feed_secs,
+        "pruneIdleDays": config.prune_idle_days,
+        "heliusUrl": redact_url(&config.helius_url),
+        "shredstreamUrl": redact_url(&config.shredstream_url),
+        "clockSync": format!("{:?}", config.clock_sync),
+        "ntpServer": config.ntp_server,
+        "embedLag": config.embed_lag,
+        "canonicalJson": config.canonical_json,
+        "admin": config.admin,
+        "disableFeatures": config.disable_features.iter().map(|feature| feature.as_str()).collect::<Vec<_>>(),
+        "profiling": config.profiling,
+        "ws": config.ws,
+        "apiKeys": config.api_keys,
+        "wsClientRate": config.ws_client_rate,
+        "wsSendTimeoutMs": config.ws_send_timeout_ms,
+        "wsReplayLimit": config.ws_replay_limit,
+        "legacySchemaVersion": config.legacy_schema_version,
+        "legacySchemaUntil": config.legacy_schema_until,
+        "wsSlowClient": format!("{:?}", config.ws_slow_client),
+        "queueSize": config.queue_size,
+        "shards": config.shards,
+        "fillDedup": config.fill_dedup,
+        "signatureDedup": config.signature_dedup,
+        "logPrefixes": config.log_prefixes,
+        "stateDir": config.state_dir,
+        "persistMetrics": config.persist_metrics,
+        "archiveDir": config.archive_dir,
+        "captureDir": config.capture_dir,
+        "blacklist": config.blacklist.iter().map(|key| key.to_string()).collect::<Vec<_>>(),
+        "quarantine": config.quarantine.iter().map(|key| key.to_string()).collect::<Vec<_>>(),
+        "priorityMarkets": config.priority_markets.iter().map(|key| key.to_string()).collect::<Vec<_>>(),
+        "mockFile": config.mock_file,
+        "mockSpeed": config.mock_speed,
+        "marketAccounts": config.market_accounts,
+        "archiveBatch": config.archive_batch,
+        "archiveFlushMs": config.archive_flush_ms,
+        "archiveRetentionDays": config.archive_retention_days,
+        "stateInterval": config.state_interval,
+        "repairFromArchive": config.repair_from_archive,
+        "tickerInterval": config.ticker_interval,
+        "summaryInterval": config.summary_interval,
+        "marketInfoInterval": config.market_info_interval,
+        "inventoryInterval": config.inventory_interval,
+        "checksumInterval": config.checksum_interval,
+        "feesInterval": config.fees_interval,
+        "sessions": config.sessions.iter().map(|session| session.to_string()).collect::<Vec<_>>(),
+        "oracleInterval": config.oracle_interval,
+        "listingInterval": config.listing_interval,
+        "listingMints": config.listing_mints.iter().map(|key| key.to_string()).collect::<Vec<_>>(),
+        "listingNames": config.listing_names,
+        "upgradeCheckSecs": config.upgrade_check_secs,
+        "tradeId": config.trade_id.to_string(),
+        "sinkRetries": config.sink_retries,
+        "sinkBackoffMs": config.sink_backoff_ms,
+        "sinkBackoffMaxMs": config.sink_backoff_max_ms,
+        "breakerThreshold": config.breaker_threshold,
+        "breakerOpenSecs": config.breaker_open_secs,
+        "bars": config.bars,
+        "pairFeed": config.pair_feed,
+        "zmqTopics": config.zmq_topics,
+        "zmqBinary": config.zmq_binary,
+        "quoteFeeds": config
+            .quote_feeds
+            .iter()
+            .map(|(mint, addr)| (mint.to_string(), addr.clone()))
+            .collect::<Vec<_>>(),
+        "zmqFilter": config.zmq_filter.as_ref().map(|filter| format!("{:?}", filter)),
+        "wsFilter": config.ws_filter.as_ref().map(|filter| format!("{:?}", filter)),
+        "stdout": config.stdout,
+        "stdoutTopics": config.stdout_topics,
+        "stdoutFilter": config.stdout_filter.as_ref().map(|filter| format!("{:?}", filter)),
+        "noZmq": config.no_zmq,
+        "failFast": config.fail_fast,
+        "panicPolicy": format!("{:?}", config.panic_policy),
+        "diagnosticsDir": config.diagnostics_dir,
+        "lagSloMs": config.lag_slo_ms,
+        "marketLagSlos": config
+            .market_lag_slos
+            .iter()
+            .map(|(market, ms)| (market.to_string(), *ms))
+            .collect::<Vec<_>>(),
+        "healthMinIntervalMs": config.health_min_interval_ms,
+        "healthMaxIntervalMs": config.health_max_interval_ms,
+        "healthMinScore": config.health_min_score,
+        "shadow": config.shadow.as_deref().map(redact_url),
+        "shadowWindowSecs": config.shadow_window_secs,
+        "kafkaBrokers": config.kafka_brokers,
+        "kafkaTopic": config.kafka_topic,
+        "schemaRegistry": config.schema_registry.as_deref().map(redact_url),
+        "seqGuard": format!("{:?}", config.seq_guard),
+        "catchUpSignatures": config.catch_up_signatures,
+        "fastSinks": config.fast_sinks,
+        "encryptedSinks": config.encrypted_sinks,
+        "sinkWorkers": config
+            .sink_workers
+            .iter()
+            .map(|(sink, threads)| format!("{}={}", sink, threads))
+            .collect::<Vec<_>>(),
+        "feedKeys": config.feed_keys.iter().map(|key| format!("{:?}", key)).collect::<Vec<_>>(),
+        "feedGroupMarkets": config
+            .feed_group_markets
+            .iter()
+            .map(|(group, market)| format!("{}={}", group, market))
+            .collect::<Vec<_>>(),
+        "alerts": config.alerts.iter().map(|rule| rule.to_string()).collect::<Vec<_>>(),
+        "alertCooldownSecs": config.alert_cooldown_secs,
+        "control": config.control,
+        "replayBuffer": config.replay_buffer,
+        "consumerHeartbeats": config.consumer_heartbeats,
+        "consumerStaleSecs": config.consumer_stale_secs,
+        "intakeRuntime": config.intake_runtime,
+        "intakeCore": config.intake_core,
+        "publishCore": config.publish_core,
+        "yieldEvery": config.yield_every,
+        "ownerLookups": config.owner_lookups,
+        "ownerWaitMs": config.owner_wait_ms,
+        "bookPollMs": config.book_poll_ms,
+        "ownerSnapshotInterval": config.owner_snapshot_interval,
+        "ownersFromArchive": config.owners_from_archive,
+        "enrichers": config.enrichers.iter().map(|enricher| enricher.as_str()).collect::<Vec<_>>(),
+        "labelsFile": config.labels_file,
+        "plugins": config.plugins,
+        "quoteConversions": config
+            .quote_conversions
+            .iter()
+            .map(|(currency, conversion)| (currency.as_str(), format!("{:?}", conversion)))
+            .collect::<Vec<_>>(),
+        "enricherTimeoutMs": config.enricher_timeout_ms,
+        "enricherTimeouts": config
+            .enricher_timeouts
+            .iter()
+            .map(|(enricher, ms)| (enricher.as_str(), *ms))
+            .collect::<Vec<_>>(),
+        "enricherFilters": config
+            .enricher_filters
+            .iter()
+            .map(|(enricher, filter)| (enricher.as_str(), format!("{:?}", filter)))
+            .collect::<Vec<_>>(),
+        "decimalsCheck": format!("{:?}", config.decimals_check),
+        "bootstrapConcurrency": config.bootstrap_concurrency,
+        "prewarmOwners": config.prewarm_owners,
+    })
+}
+
+// The settings of `effective` which differ from the defaults, for the startup log
+pub fn non_default(config: &Config) -> Value {
+    match (effective(config), effective(&Config::defaults())) {
+        (Value::Object(settings), Value::Object(defaults)) => settings
+            .into_iter()
+            .filter(|(key, value)| defaults.get(key) != Some(value))
+            .collect(),
+        (settings, _) => settings,
+    }
+}
+
+// What the printer watches, for deployment tooling (`/startup` on the admin port)
+pub fn market_table(config: &Config, markets: &BTreeMap<Pubkey, Market>) -> Value {
+    markets
+        .iter()
+        .map(|(key, market)| {
+            json!({
+                "marketId": key.to_string(),
+                "marketName": parse_name(&market.name).replace('\0', ""),
+                "baseMint": market.base_mint.to_string(),
+                "quoteMint": market.quote_mint.to_string(),
+                "baseDecimals": market.base_decimals,
+                "quoteDecimals": market.quote_decimals,
+                "baseLotSize": market.base_lot_size,
+                "quoteLotSize": market.quote_lot_size,
+                "makerFee": market.maker_fee,
+                "takerFee": market.taker_fee,
+                "commitment": config.market_commitment(key).as_str(),
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn outcome(name: &str, result: Result<String, String>) -> Value {
+    match result {
+        Ok(detail) => json!({"check": name, "ok": true, "detail": detail}),
+        Err(error) => json!({"check": name, "ok": false, "error": error}),
+    }
+}
+
+pub(crate) async fn markets(client: &RpcClient, config: &Config) -> (Vec<Value>, BTreeMap<Pubkey, Market>) {
+    let mut markets = BTreeMap::new();
+    let accounts = match bootstrap::fetch_accounts(client, &config.market_keys, config.bootstrap_concurrency, "markets").await
+    {
+        Ok(accounts) => accounts,
+        Err(err) => {
+            let checks = config
+                .market_keys
+                .iter()
+                .map(|key| outcome(&format!("market {}", key), Err(err.to_string())))
+                .collect();
+            return (checks, markets);
+        }
+    };
+    let mut checks = Vec::new();
+    for (key, account) in config.market_keys.iter().zip(accounts) {
+        let result = match account {
+            None => Err("account not found".to_string()),
+            Some(account) if account.owner != openbookv2_generated::id() => {
+                Err(format!("owned by {}, not openbook v2", account.owner))
+            }
+            Some(account) if account.data.len() < 8 => {
+                Err(format!("{} bytes, too few for a market", account.data.len()))
+            }
+            Some(account) => match Market::deserialize(&mut &account.data[8..]) {
+                Ok(market) => {
+                    let name = parse_name(&market.name).replace('\0', "");
+                    markets.insert(*key, market);
+                    Ok(name)
+                }
+                Err(err) => Err(format!("not a market: {}", err)),
+            },
+        };
+        checks.push(outcome(&format!("market {}", key), result));
+    }
+    (checks, markets)
+}
+
+// Binds (or connects) a socket like the printer would, so a taken port or a bad
+// address fails here instead of at startup
+fn zmq_endpoint(ctx: &zmq::Context, kind: zmq::SocketType, addr: &str, connect: bool) -> Result<String, String> {
+    let socket = ctx.socket(kind).map_err(|err| err.to_string())?;
+    let result = if connect {
+        socket.connect(addr)
+    } else {
+        socket.bind(addr)
+    };
+    result.map(|()| addr.to_string()).map_err(|err| format!("{}: {}", addr, err))
+}
+
+// `config check`: prints {"config":...,"markets":[...],"checks":[...],"ok":...} and returns the exit code
+pub async fn run(config: &Config) -> i32 {
+    let client = RpcClient::new(config.rpc_url.clone());
+    let mut checks = vec![outcome(
+        "rpc",
+        client
+            .get_version()
+            .await
+            .map(|version| format!("solana-core {}", version.solana_core))
+            .map_err(|err| err.to_string()),
+    )];
+    let (market_checks, markets) = markets(&client, config).await;
+    checks.extend(market_checks);
+    if config.decimals_check != DecimalsCheck::Off {
+        let result = match decimals::verify(&client, &markets, config.bootstrap_concurrency).await {
+            Ok(mismatches) if mismatches.is_empty() => Ok(format!("{} markets", markets.len())),
+            Ok(mismatches) => Err(mismatches.join("; ")),
+            Err(err) => Err(err),
+        };
+        checks.push(outcome("decimals", result));
+    }
+    for (commitment, markets) in config.commitment_groups(&config.market_keys) {
+        let source = source::from_config(config, &markets, commitment);
+        checks.push(outcome(
+            &format!("source {} {}", source.name(), commitment.as_str()),
+            source.check().await.map(|()| format!("connected, {} markets", markets.len())),
+        ));
+    }
+    let ctx = zmq::Context::new();
+    if !config.no_zmq {
+        let zero_url = format!("tcp://{}:{}", config.host, config.port);
+        checks.push(outcome("zmq", zmq_endpoint(&ctx, zmq::PUB, &zero_url, config.connect)));
+    }
+    if let Some(addr) = config.zmq_binary.as_deref() {
+        checks.push(outcome("zmq binary", zmq_endpoint(&ctx, zmq::PUB, addr, false)));
+    }
+    for (mint, addr) in config.quote_feeds.iter() {
+        checks.push(outcome(&format!("zmq quote {}", mint), zmq_endpoint(&ctx, zmq::PUB, addr, false)));
+    }
+    if let Some(addr) = config.control.as_deref() {
+        checks.push(outcome("zmq control", zmq_endpoint(&ctx, zmq::REP, addr, false)));
+    }
+    let ok = checks.iter().all(|check| check["ok"] == true);
+    let report = json!({
+        "config": effective(config),
+        "markets": market_table(config, &markets),
+        "checks": checks,
+        "ok": ok,
+    });
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    if ok {
+        exit::OK
+    } else {
+        exit::CHECK_FAILED
+    }
+}
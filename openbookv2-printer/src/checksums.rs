@@ -0,0 +1,198 @@
+use crate::state;
+use openbookv2_decoder::{ControlMessage, Trade};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Default)]
+struct MarketChecksum {
+    market_name: String,
+    // hex chain of the last published period, empty before the first one
+    chain: String,
+    // sha256 of every trade payload of the current period
+    #[serde(skip)]
+    leaves: Vec<[u8; 32]>,
+    #[serde(skip)]
+    from_seq: u64,
+    #[serde(skip)]
+    to_seq: u64,
+    #[serde(skip)]
+    from_time: u64,
+    #[serde(skip)]
+    to_time: u64,
+}
+
+// Checksums of the published trades per market and period, so auditors can verify they
+// received every trade of a period unmodified. The chain links the periods and is kept in
+// the state dir, a period missing on the consumer side breaks it.
+#[derive(Serialize, Deserialize, Default)]
+pub struct ChecksumTracker {
+    markets: BTreeMap<String, MarketChecksum>,
+}
+
+impl ChecksumTracker {
+    pub fn load(path: &Path) -> Self {
+        state::load(path)
+    }
+
+    pub fn save(&self, path: &Path) {
+        state::save(path, self)
+    }
+
+    // `payload` exactly as published on the trades topic
+    pub fn record(&mut self, trade: &Trade, payload: &str) {
+        let market = self.markets.entry(trade.market_id.clone()).or_default();
+        market.market_name = trade.market_name.clone();
+        if market.leaves.is_empty() {
+            market.from_seq = trade.feed_seq;
+            market.from_time = trade.time_stamp;
+        }
+        market.to_seq = trade.feed_seq;
+        market.to_time = trade.time_stamp;
+        market.leaves.push(Sha256::digest(payload.as_bytes()).into());
+    }
+
+    // One checksum message per market with trades since the last call
+    pub fn flush(&mut self) -> Vec<ControlMessage> {
+        let mut messages = Vec::new();
+        for (market_id, market) in self.markets.iter_mut() {
+            if market.leaves.is_empty() {
+                continue;
+            }
+            let trades = market.leaves.len() as u64;
+            let root = merkle_root(std::mem::take(&mut market.leaves));
+            let previous = from_hex(&market.chain);
+            let chain: [u8; 32] = Sha256::new().chain_update(previous).chain_update(root).finalize().into();
+            market.chain = to_hex(&chain);
+            messages.push(ControlMessage::Checksum {
+                market_id: market_id.clone(),
+                market_name: market.market_name.clone(),
+                from_seq: market.from_seq,
+                to_seq: market.to_seq,
+                from_time: market.from_time,
+                to_time: market.to_time,
+                trades,
+                root: to_hex(&root),
+                chain: market.chain.clone(),
+            });
+        }
+        messages
+    }
+}
+
+// Pairs of nodes hashed level by level, the odd node of a level moves up unchanged
+fn merkle_root(mut level: Vec<[u8; 32]>) -> [u8; 32] {
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => Sha256::new().chain_update(left).chain_update(right).finalize().into(),
+                [odd] => *odd,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// 32 zero bytes for the empty chain of a market's first period
+fn from_hex(hex: &str) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = hex.get(i * 2..i * 2 + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()).unwrap_or(0);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn trade(market_id: &str, feed_seq: u64) -> Trade {
+        serde_json::from_value(json!({
+            "timeStamp": 1_700_000_000 + feed_seq,
+            "makerOwner": "maker",
+            "takerOwner": "taker",
+            "priceDouble": 1.0,
+            "quantityDouble": 1.0,
+            "marketId": market_id,
+            "takerSide": 0,
+            "marketName": "SOL-USDC",
+            "signature": "signature",
+            "feedSeq": feed_seq,
+        }))
+        .unwrap()
+    }
+
+    fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+        parts.iter().fold(Sha256::new(), |hasher, part| hasher.chain_update(part)).finalize().into()
+    }
+
+    // (trades, root, chain) of the checksum message
+    fn checksum(message: &ControlMessage) -> (u64, String, String) {
+        let ControlMessage::Checksum { trades, root, chain, .. } = message else {
+            panic!("not a checksum message");
+        };
+        (*trades, root.clone(), chain.clone())
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        let bytes = sha256(&[b"payload"]);
+        assert_eq!(to_hex(&[0x00, 0x0f, 0xa0, 0xff]), "000fa0ff");
+        assert_eq!(to_hex(&bytes).len(), 64);
+        assert_eq!(from_hex(&to_hex(&bytes)), bytes);
+        assert_eq!(from_hex(""), [0u8; 32]);
+    }
+
+    #[test]
+    fn merkle_root_moves_the_odd_node_up() {
+        let leaves: Vec<[u8; 32]> = [b"a", b"b", b"c"].iter().map(|leaf| sha256(&[*leaf])).collect();
+        assert_eq!(merkle_root(leaves[..1].to_vec()), leaves[0]);
+        let pair = sha256(&[&leaves[0], &leaves[1]]);
+        assert_eq!(merkle_root(leaves[..2].to_vec()), pair);
+        assert_eq!(merkle_root(leaves.clone()), sha256(&[&pair, &leaves[2]]));
+    }
+
+    #[test]
+    fn periods_are_chained_per_market() {
+        let mut tracker = ChecksumTracker::default();
+        tracker.record(&trade("a", 1), "one");
+        tracker.record(&trade("a", 2), "two");
+        tracker.record(&trade("b", 9), "nine");
+        let messages = tracker.flush();
+        assert_eq!(messages.len(), 2);
+        let ControlMessage::Checksum {
+            market_id,
+            from_seq,
+            to_seq,
+            from_time,
+            to_time,
+            ..
+        } = &messages[0]
+        else {
+            panic!("not a checksum message");
+        };
+        assert_eq!((market_id.as_str(), *from_seq, *to_seq), ("a", 1, 2));
+        assert_eq!((*from_time, *to_time), (1_700_000_001, 1_700_000_002));
+        let root = sha256(&[&sha256(&[b"one"]), &sha256(&[b"two"])]);
+        let chain = sha256(&[&[0u8; 32], &root]);
+        assert_eq!(checksum(&messages[0]), (2, to_hex(&root), to_hex(&chain)));
+        let root_b = sha256(&[b"nine"]);
+        assert_eq!(checksum(&messages[1]), (1, to_hex(&root_b), to_hex(&sha256(&[&[0u8; 32], &root_b]))));
+
+        // nothing recorded, nothing to publish
+        assert!(tracker.flush().is_empty());
+
+        tracker.record(&trade("a", 3), "three");
+        let messages = tracker.flush();
+        let root = sha256(&[b"three"]);
+        assert_eq!(checksum(&messages[0]), (1, to_hex(&root), to_hex(&sha256(&[&chain, &root]))));
+    }
+}
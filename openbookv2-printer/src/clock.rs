@@ -0,0 +1,101 @@
+use crate::config::ClockSync;
+use crate::metrics::metrics;
+use log::{info, warn};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+// seconds between 1900 (ntp epoch) and 1970
+const NTP_UNIX_OFFSET_SECS: i64 = 2_208_988_800;
+// block times have second resolution and are about a slot old, smaller offsets are noise
+const RPC_MIN_SKEW_MS: i64 = 2000;
+
+// true time minus local time
+static OFFSET_MS: AtomicI64 = AtomicI64::new(0);
+
+fn system_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+// Local time corrected by the offset found at startup, use it for everything compared to block times
+pub fn now_ms() -> i64 {
+    system_ms() + OFFSET_MS.load(Ordering::Relaxed)
+}
+
+pub async fn calibrate(mode: ClockSync, ntp_server: &str, client: &RpcClient) {
+    let offset = match mode {
+        ClockSync::Off => return,
+        ClockSync::Ntp => match ntp_offset_ms(ntp_server).await {
+            Ok(offset) => Some(offset),
+            Err(err) => {
+                warn!("ntp query to {} failed: {}, falling back to rpc block time", ntp_server, err);
+                rpc_offset_ms(client).await
+            }
+        },
+        ClockSync::Rpc => rpc_offset_ms(client).await,
+    };
+    if let Some(offset) = offset {
+        info!("local clock offset is {} ms", offset);
+        OFFSET_MS.store(offset, Ordering::Relaxed);
+        metrics().clock_offset_seconds.set(offset as f64 / 1000.0);
+    }
+}
+
+fn ntp_timestamp_ms(bytes: &[u8]) -> i64 {
+    let secs = u32::from_be_bytes(bytes[0..4].try_into().unwrap()) as i64;
+    let fraction = u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as i64;
+    (secs - NTP_UNIX_OFFSET_SECS) * 1000 + ((fraction * 1000) >> 32)
+}
+
+// Single SNTP request, offset = ((t1 - t0) + (t2 - t3)) / 2
+async fn ntp_offset_ms(server: &str) -> Result<i64, String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|err| err.to_string())?;
+    socket.connect(server).await.map_err(|err| err.to_string())?;
+    let mut request = [0u8; 48];
+    // leap indicator 0, version 3, mode 3 (client)
+    request[0] = 0x1b;
+    let t0 = system_ms();
+    socket.send(&request).await.map_err(|err| err.to_string())?;
+    let mut response = [0u8; 48];
+    let received = timeout(Duration::from_secs(2), socket.recv(&mut response))
+        .await
+        .map_err(|_| "timed out".to_string())?
+        .map_err(|err| err.to_string())?;
+    let t3 = system_ms();
+    if received < 48 {
+        return Err(format!("short response of {} bytes", received));
+    }
+    let t1 = ntp_timestamp_ms(&response[32..40]);
+    let t2 = ntp_timestamp_ms(&response[40..48]);
+    Ok(((t1 - t0) + (t2 - t3)) / 2)
+}
+
+async fn rpc_offset_ms(client: &RpcClient) -> Option<i64> {
+    let result = async {
+        let slot = client
+            .get_slot_with_commitment(CommitmentConfig::confirmed())
+            .await?;
+        client.get_block_time(slot).await
+    }
+    .await;
+    match result {
+        Ok(block_time) => {
+            let offset = block_time * 1000 - system_ms();
+            if offset.abs() < RPC_MIN_SKEW_MS {
+                info!("local clock is within {} ms of the rpc block time", RPC_MIN_SKEW_MS);
+                return None;
+            }
+            Some(offset)
+        }
+        Err(err) => {
+            warn!("could not calibrate the local clock against rpc: {}", err);
+            None
+        }
+    }
+}
@@ -1,7 +1,17 @@
-use clap::Parser;
-use log::info;
+use clap::{Parser, ValueEnum};
+use crate::expr::Expr;
+use crate::enricher::usd::QuoteConversion;
+use crate::features::Feature;
+use crate::feed_crypto::FeedKey;
+use crate::filter::TradeFilter;
+use crate::legacy_schema::LegacySchema;
+use crate::sessions::Session;
+use log::{info, warn};
+use openbookv2_decoder::{TradeIdScheme, PROGRAM_DATA_PREFIX};
 use solana_program::pubkey::Pubkey;
+use std::collections::HashSet;
 use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Parser)]
 pub struct Cli {
@@ -22,16 +32,504 @@ pub struct Cli {
     #[arg(short, long)]
     pub x_token: Option<String>,
     #[arg(long)]
-    pub check: Option<u64>,
+    pub token_refresh_command: Option<String>,
+    #[arg(long)]
+    pub commitment_check_ms: Option<u64>,
+    #[arg(long)]
+    pub admin: Option<String>,
+    // comma separated, features off at startup unless the admin API saved otherwise
+    #[arg(long, value_enum, value_delimiter = ',', num_args = 0..)]
+    pub disable_features: Option<Vec<Feature>>,
+    #[arg(long, action)]
+    pub profiling: bool,
+    #[arg(long)]
+    pub ws: Option<String>,
+    // json file with api keys for the websocket feed
+    #[arg(long)]
+    pub api_keys: Option<String>,
+    #[arg(long)]
+    pub queue_size: Option<usize>,
+    // pipelines in this process, markets are partitioned by hash
+    #[arg(long)]
+    pub shards: Option<usize>,
+    #[arg(long)]
+    pub fill_dedup: Option<bool>,
+    #[arg(long)]
+    pub signature_dedup: Option<usize>,
+    // log line prefix of events, repeatable, replaces "Program data: "
+    #[arg(long)]
+    pub log_prefix: Vec<String>,
+    #[arg(long, action)]
+    pub embed_lag: bool,
+    #[arg(long, action)]
+    pub canonical_json: bool,
+    #[arg(long)]
+    pub state_dir: Option<String>,
+    #[arg(long, action)]
+    pub persist_metrics: bool,
+    #[arg(long)]
+    pub archive_dir: Option<String>,
+    #[arg(long)]
+    pub capture_dir: Option<String>,
+    // market or mint, repeatable
+    #[arg(long)]
+    pub blacklist: Vec<String>,
+    // market or mint, repeatable
+    #[arg(long)]
+    pub quarantine: Vec<String>,
+    // market or mint, repeatable
+    #[arg(long)]
+    pub priority_market: Vec<String>,
+    #[arg(long)]
+    pub archive_batch: Option<usize>,
+    #[arg(long)]
+    pub archive_flush_ms: Option<u64>,
+    #[arg(long)]
+    pub archive_retention_days: Option<u64>,
+    #[arg(long)]
+    pub state_interval: Option<u64>,
+    #[arg(long, action)]
+    pub repair_from_archive: bool,
+    #[arg(long)]
+    pub ticker_interval: Option<u64>,
+    #[arg(long)]
+    pub summary_interval: Option<u64>,
+    // slot, seq or signature
+    #[arg(long)]
+    pub trade_id: Option<TradeIdScheme>,
+    #[arg(long)]
+    pub sink_retries: Option<u32>,
+    #[arg(long)]
+    pub sink_backoff_ms: Option<u64>,
+    #[arg(long)]
+    pub sink_backoff_max_ms: Option<u64>,
+    #[arg(long)]
+    pub breaker_threshold: Option<u32>,
+    #[arg(long)]
+    pub breaker_open_secs: Option<u64>,
+    #[arg(long, value_enum)]
+    pub source: Option<SourceKind>,
+    #[arg(long)]
+    pub poll_interval_ms: Option<u64>,
+    #[arg(long)]
+    pub helius_url: Option<String>,
+    // grpc endpoint of a jito shredstream proxy, for --source shredstream
+    #[arg(long)]
+    pub shredstream_url: Option<String>,
+    #[arg(long)]
+    pub mock_file: Option<String>,
+    // paces the mock file by block time, 2 twice as fast, 0 as fast as possible
+    #[arg(long)]
+    pub mock_speed: Option<f64>,
+    #[arg(long)]
+    pub market_accounts: Option<String>,
+    #[arg(long)]
+    pub stale_feed_secs: Option<u64>,
+    #[arg(long)]
+    pub prune_idle_days: Option<u64>,
+    // extra grpc metadata, "name: value", repeatable
+    #[arg(long)]
+    pub grpc_header: Vec<String>,
+    #[arg(long, value_enum)]
+    pub grpc_commitment: Option<Commitment>,
+    #[arg(long, value_enum)]
+    pub rpc_commitment: Option<Commitment>,
+    #[arg(long, value_enum)]
+    pub helius_commitment: Option<Commitment>,
+    // "<market>=<commitment>", repeatable, markets without one use the source commitment
+    #[arg(long)]
+    pub market_commitment: Vec<String>,
+    #[arg(long, value_enum)]
+    pub clock_sync: Option<ClockSync>,
+    #[arg(long)]
+    pub ntp_server: Option<String>,
+    #[arg(long)]
+    pub market_info_interval: Option<u64>,
+    #[arg(long)]
+    pub inventory_interval: Option<u64>,
+    #[arg(long)]
+    pub checksum_interval: Option<u64>,
+    #[arg(long)]
+    pub fees_interval: Option<u64>,
+    // "<market>=<name> <HH:MM>-<HH:MM> <timezone> [<days>]", repeatable
+    #[arg(long)]
+    pub session: Vec<String>,
+    #[arg(long)]
+    pub oracle_interval: Option<u64>,
+    #[arg(long)]
+    pub listing_interval: Option<u64>,
+    // base or quote mint, repeatable
+    #[arg(long)]
+    pub listing_mint: Vec<String>,
+    // market name, `*` matches anything, repeatable
+    #[arg(long)]
+    pub listing_name: Vec<String>,
+    #[arg(long)]
+    pub upgrade_check_secs: Option<u64>,
+    #[arg(long, action)]
+    pub bars: bool,
+    #[arg(long, action)]
+    pub pair_feed: bool,
+    #[arg(long, action)]
+    pub zmq_topics: bool,
+    #[arg(long)]
+    pub zmq_binary: Option<String>,
+    // "<quote mint>=<zmq address>", repeatable
+    #[arg(long)]
+    pub quote_feed: Vec<String>,
+    #[arg(long)]
+    pub zmq_filter: Option<String>,
+    #[arg(long)]
+    pub ws_filter: Option<String>,
+    #[arg(long, action)]
+    pub stdout: bool,
+    // topic patterns written to stdout, comma separated
+    #[arg(long)]
+    pub stdout_topics: Option<String>,
+    #[arg(long)]
+    pub stdout_filter: Option<String>,
+    #[arg(long, action)]
+    pub no_zmq: bool,
+    #[arg(long, action)]
+    pub fail_fast: bool,
+    #[arg(long, value_enum)]
+    pub panic_policy: Option<PanicPolicy>,
+    // where crash dumps go, the state dir by default
+    #[arg(long)]
+    pub diagnostics_dir: Option<String>,
+    #[arg(long)]
+    pub lag_slo_ms: Option<u64>,
+    // "<market>=<ms>", repeatable, markets without one use --lag-slo-ms
+    #[arg(long)]
+    pub market_lag_slo: Vec<String>,
+    // lag is sampled this often while it rises or is over the objective
+    #[arg(long)]
+    pub health_min_interval_ms: Option<u64>,
+    // and backs off to this while it is stable
+    #[arg(long)]
+    pub health_max_interval_ms: Option<u64>,
+    // 0-100, markets below breach their lag objective and /readyz fails
+    #[arg(long)]
+    pub health_min_score: Option<u8>,
+    // reference feed to compare against, tcp:// (zmq) or ws://
+    #[arg(long)]
+    pub shadow: Option<String>,
+    #[arg(long)]
+    pub shadow_window_secs: Option<u64>,
+    // comma separated bootstrap brokers of the kafka sink (built with the `kafka` feature)
+    #[arg(long)]
+    pub kafka_brokers: Option<String>,
+    #[arg(long)]
+    pub kafka_topic: Option<String>,
+    // Confluent compatible schema registry, trades are Avro encoded when set
+    #[arg(long)]
+    pub schema_registry: Option<String>,
+    #[arg(long, value_enum)]
+    pub seq_guard: Option<SeqGuard>,
+    #[arg(long)]
+    pub catch_up_signatures: Option<usize>,
+    // comma separated sink names
+    #[arg(long)]
+    pub fast_sinks: Option<String>,
+    // comma separated sink names
+    #[arg(long)]
+    pub encrypted_sinks: Option<String>,
+    // "<sink>=<threads>", repeatable
+    #[arg(long)]
+    pub sink_workers: Vec<String>,
+    // "<group>=<key id>:<base64 key>", repeatable
+    #[arg(long)]
+    pub feed_key: Vec<String>,
+    // "<group>=<market>", repeatable
+    #[arg(long)]
+    pub feed_group_market: Vec<String>,
+    // filter expression, repeatable
+    #[arg(long)]
+    pub alert: Vec<String>,
+    // seconds an alert (rule and market) or lag breach stays quiet after it went out, 0 = off
+    #[arg(long)]
+    pub alert_cooldown_secs: Option<u64>,
+    #[arg(long)]
+    pub control: Option<String>,
+    #[arg(long)]
+    pub replay_buffer: Option<usize>,
+    #[arg(long, action)]
+    pub consumer_heartbeats: bool,
+    #[arg(long)]
+    pub consumer_stale_secs: Option<u64>,
+    // messages per second per websocket connection
+    #[arg(long)]
+    pub ws_client_rate: Option<u32>,
+    #[arg(long)]
+    pub ws_send_timeout_ms: Option<u64>,
+    #[arg(long, value_enum)]
+    pub ws_slow_client: Option<SlowClient>,
+    // archived trades per websocket replay request
+    #[arg(long)]
+    pub ws_replay_limit: Option<usize>,
+    // older trade schema version published next to the current one
+    #[arg(long)]
+    pub legacy_schema_version: Option<u32>,
+    // unix seconds the legacy schema version is published until
+    #[arg(long)]
+    pub legacy_schema_until: Option<u64>,
+    #[arg(long, action)]
+    pub intake_runtime: bool,
+    #[arg(long)]
+    pub intake_core: Option<usize>,
+    #[arg(long)]
+    pub publish_core: Option<usize>,
+    #[arg(long)]
+    pub yield_every: Option<usize>,
+    #[arg(long)]
+    pub owner_lookups: Option<usize>,
+    #[arg(long)]
+    pub owner_wait_ms: Option<u64>,
+    #[arg(long)]
+    pub book_poll_ms: Option<u64>,
+    // seconds between archived owner snapshots, needs --archive-dir
+    #[arg(long)]
+    pub owner_snapshot_interval: Option<u64>,
+    #[arg(long, action)]
+    pub owners_from_archive: bool,
+    // comma separated, applied in this order, an empty list disables enrichment
+    #[arg(long, value_enum, value_delimiter = ',', num_args = 0..)]
+    pub enrichers: Option<Vec<EnricherKind>>,
+    #[arg(long)]
+    pub labels_file: Option<String>,
+    // comma separated paths of annotation plugins
+    #[arg(long)]
+    pub plugins: Option<String>,
+    // "<currency>=market:<pubkey>|oracle:<pubkey>|fixed:<price>", repeatable
+    #[arg(long)]
+    pub quote_conversion: Vec<String>,
+    #[arg(long)]
+    pub enricher_timeout_ms: Option<u64>,
+    // "<enricher>=<ms>", repeatable, enrichers without one use --enricher-timeout-ms
+    #[arg(long)]
+    pub enricher_timeout: Vec<String>,
+    // "<enricher>=<filter>", repeatable, the enricher only runs on the trades which match
+    #[arg(long)]
+    pub enricher_filter: Vec<String>,
+    #[arg(long, value_enum)]
+    pub decimals_check: Option<DecimalsCheck>,
+    #[arg(long)]
+    pub bootstrap_concurrency: Option<usize>,
+    #[arg(long, action)]
+    pub prewarm_owners: bool,
+    // load exactly this file instead of ./.env
+    #[arg(long, conflicts_with = "profile")]
+    pub env_file: Option<String>,
+    // load ./.env.<profile> instead of ./.env
+    #[arg(long)]
+    pub profile: Option<String>,
+    // test the sources, decoding and sinks once, print a JSON report and exit
+    #[arg(long, action)]
+    pub self_test: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Clone, Debug)]
+pub enum Command {
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    // percentiles of the lags of the archived trades per market, needs --archive-dir
+    LatencyReport {
+        // the window ends now
+        #[arg(long, default_value_t = 24)]
+        hours: u64,
+    },
+    // replays a recorded day (--mock-file format) through the configured pipeline and
+    // sinks at each speed and reports the highest sustainable one
+    SimulateLoad {
+        #[arg(long)]
+        file: String,
+        // multiples of the recorded rate, run in ascending order
+        #[arg(long, value_delimiter = ',', default_value = "1,2,5,10,20")]
+        speeds: Vec<f64>,
+        // per speed at most
+        #[arg(long, default_value_t = 60)]
+        seconds: u64,
+    },
+    // projects message rates, bandwidth and RPC calls from the recent activity of the markets
+    Estimate {
+        // the sampled window ends now
+        #[arg(long, default_value_t = 60)]
+        minutes: u64,
+        // per market at most, busy markets are projected from the span these cover
+        #[arg(long, default_value_t = 5000)]
+        transactions: usize,
+        // transactions per market read in full for fills and sizes
+        #[arg(long, default_value_t = 50)]
+        samples: usize,
+    },
+    // prints the trades of a running printer as they are published
+    Tail {
+        // tcp://... (ZMQ) or ws://..., default the ZMQ endpoint of --host/--port
+        #[arg(long)]
+        url: Option<String>,
+        // market names or ids, all when empty
+        #[arg(long, value_delimiter = ',')]
+        markets: Vec<String>,
+        #[arg(long, value_enum, default_value = "pretty")]
+        format: TailFormat,
+        // also print control messages, gaps and reconnects to stderr
+        #[arg(long, action)]
+        control: bool,
+    },
+    // writes the trades of a market over whole UTC days to a signed, checksummed file
+    Export {
+        #[arg(long)]
+        market: String,
+        // first and last day, YYYY-MM-DD
+        #[arg(long)]
+        from: String,
+        #[arg(long)]
+        to: String,
+        // default archive with --archive-dir, backfill otherwise
+        #[arg(long, value_enum)]
+        source: Option<ExportSource>,
+        // Solana keypair file the export is signed with
+        #[arg(long)]
+        keypair: String,
+        // default export-<market>-<from>-<to>.jsonl
+        #[arg(long)]
+        out: Option<String>,
+    },
+    // checks the checksum and signature of an export file
+    VerifyExport {
+        file: String,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum ExportSource {
+    // the trades of --archive-dir as they were published
+    Archive,
+    // the fills read again from the transactions of the market over RPC
+    Backfill,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum TailFormat {
+    // one aligned line per trade, colored by side on a terminal
+    Pretty,
+    // the trade as published
+    Json,
+    Csv,
+}
+
+#[derive(clap::Subcommand, Clone, Copy, Debug)]
+pub enum ConfigCommand {
+    // resolve the markets, test the endpoints and print the effective configuration as JSON
+    Check,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecimalsCheck {
+    // exit at startup when market and mint decimals differ
+    Fail,
+    Warn,
+    Off,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeqGuard {
+    Off,
+    // publish with seqRegressed
+    Flag,
+    // publish only the seq_regression message
+    Drop,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnricherKind {
+    // makerOwner/takerOwner and delegates from the OpenOrders accounts
+    Owners,
+    // priceUsd and notionalUsd
+    Usd,
+    // makerLabel/takerLabel from --labels-file
+    Labels,
+    // oracleSpreadBps against the last oracle price
+    Spread,
+    // annotations and filtering by the libraries of --plugins
+    Plugins,
+    // makerTimeInForce and makerExpiry from the order books
+    Tif,
+}
+
+impl EnricherKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EnricherKind::Owners => "owners",
+            EnricherKind::Usd => "usd",
+            EnricherKind::Labels => "labels",
+            EnricherKind::Spread => "spread",
+            EnricherKind::Plugins => "plugins",
+            EnricherKind::Tif => "tif",
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum PanicPolicy {
+    // write the crash dump, the panicking task or thread ends and the rest keeps running
+    Continue,
+    // write the crash dump and exit with exit::PANIC
+    Exit,
+    // write the crash dump and abort, for a core dump
+    Abort,
 }
 
-#[derive(clap::ValueEnum, Clone, Debug)]
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum SlowClient {
+    // close the connection once it falls behind the broadcast buffer or a send times out
+    Disconnect,
+    // skip the missed messages and keep the connection
+    Skip,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Commitment {
     Processed,
     Confirmed,
     Finalized,
 }
 
+impl Commitment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Commitment::Processed => "processed",
+            Commitment::Confirmed => "confirmed",
+            Commitment::Finalized => "finalized",
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum SourceKind {
+    // yellowstone geyser grpc
+    Geyser,
+    // getBlock polling against a plain rpc node
+    Poll,
+    // helius enhanced websocket, see --helius-url
+    Helius,
+    // replays --mock-file, for tests
+    Mock,
+    // jito shredstream entries simulated over rpc, experimental (`shredstream` feature)
+    Shredstream,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum ClockSync {
+    // sntp query, falls back to rpc
+    Ntp,
+    // block time of the latest confirmed slot, only corrects skews above 2 s
+    Rpc,
+    Off,
+}
+
 pub struct Config {
     pub rpc_url: String,
     pub market_keys: Vec<Pubkey>,
@@ -41,16 +539,221 @@ pub struct Config {
     pub commitment: Commitment,
     pub connect: bool,
     pub x_token: String,
-    pub check: u64,
+    // shell command printing a new x-token, run when geyser rejects the current one
+    pub token_refresh_command: Option<String>,
+    // how long a delivered geyser slot may take to reach the subscribed commitment, 0 disables
+    pub commitment_check_ms: u64,
+    pub embed_lag: bool,
+    // sorted keys and fixed number formatting in every payload, for hashing and signing
+    pub canonical_json: bool,
+    pub admin: Option<String>,
+    // off until enabled with POST /features, see features.json in the state dir
+    pub disable_features: Vec<Feature>,
+    pub profiling: bool,
+    pub ws: Option<String>,
+    pub api_keys: Option<String>,
+    pub queue_size: usize,
+    // pipeline shards, each with its own sources, intake, enrich and encode stage, all
+    // publishing through the same sinks
+    pub shards: usize,
+    // drop repeated FillLogs (same market and seq_num) within one transaction
+    pub fill_dedup: bool,
+    // (signature, log index) pairs remembered to drop transactions delivered twice, 0 off
+    pub signature_dedup: usize,
+    // prefixes of the log lines carrying events, for test validators and simulators
+    pub log_prefixes: Vec<String>,
+    pub state_dir: String,
+    // counters carried over restarts in metrics.json, see counters.rs
+    pub persist_metrics: bool,
+    // partitioned jsonl archive of all trades
+    pub archive_dir: Option<String>,
+    // directory of the file sinks added on the admin port, none can be added without it
+    pub capture_dir: Option<String>,
+    // markets, or markets of these mints, which are never subscribed
+    pub blacklist: Vec<Pubkey>,
+    // markets, or markets of these mints, whose trades only go to the quarantine topic
+    pub quarantine: Vec<Pubkey>,
+    // markets, or markets of these mints, whose fills pass the queued ones of other markets
+    pub priority_markets: Vec<Pubkey>,
+    // archived trades held at most before the complete slots among them are written
+    pub archive_batch: usize,
+    // the newest slot is written after this long without trades
+    pub archive_flush_ms: u64,
+    // days of archived trades, checksums and owner snapshots kept, 0 keeps them forever
+    pub archive_retention_days: u64,
+    // seconds between saves of the volume aggregates and sequences to the state dir
+    pub state_interval: u64,
+    // add archived trades newer than the saved volumes at startup
+    pub repair_from_archive: bool,
+    pub ticker_interval: u64,
+    // seconds between summary log lines, 0 disables them
+    pub summary_interval: u64,
+    pub trade_id: TradeIdScheme,
+    pub sink_retries: u32,
+    // pause before the first retry of a sink, doubled per retry up to sink_backoff_max_ms
+    pub sink_backoff_ms: u64,
+    pub sink_backoff_max_ms: u64,
+    pub breaker_threshold: u32,
+    pub breaker_open_secs: u64,
+    pub source: SourceKind,
+    pub poll_interval_ms: u64,
+    pub helius_url: String,
+    pub shredstream_url: String,
+    // JSON lines of transactions replayed by the mock source
+    pub mock_file: Option<String>,
+    // multiple of the recorded block times the mock file is replayed at, 0 unpaced
+    pub mock_speed: f64,
+    // {"<market>": "<base64 account data>"}, loaded instead of fetching the markets over RPC
+    pub market_accounts: Option<String>,
+    // geyser resubscribes after this long without transactions while blocks keep coming, 0 never does
+    pub stale_feed_secs: u64,
+    // geyser drops markets without transactions for this many days, 0 never does
+    pub prune_idle_days: u64,
+    pub grpc_headers: Vec<(String, String)>,
+    // per endpoint overrides of the positional commitment
+    pub grpc_commitment: Option<Commitment>,
+    pub rpc_commitment: Option<Commitment>,
+    pub helius_commitment: Option<Commitment>,
+    // per market overrides of the source commitment, each commitment gets its own subscription
+    pub market_commitments: Vec<(Pubkey, Commitment)>,
+    pub clock_sync: ClockSync,
+    pub ntp_server: String,
+    pub market_info_interval: u64,
+    // seconds between maker inventory summaries, 0 disables
+    pub inventory_interval: u64,
+    // seconds between checksum manifests of the published trades, 0 disables
+    pub checksum_interval: u64,
+    // seconds between fee revenue summaries, 0 disables
+    pub fees_interval: u64,
+    // trading sessions with open and close events and session OHLCV
+    pub sessions: Vec<Session>,
+    // seconds between oracle price polls, 0 disables
+    pub oracle_interval: u64,
+    // seconds between polls of the program's markets for the listing feed, 0 disables
+    pub listing_interval: u64,
+    // markets of these mints are reported on the listing feed, empty for all
+    pub listing_mints: Vec<Pubkey>,
+    // market name patterns of the listing feed, empty for all
+    pub listing_names: Vec<String>,
+    // seconds between checks of the OpenBook program for upgrades, 0 disables
+    pub upgrade_check_secs: u64,
+    pub bars: bool,
+    // trades of markets with the same mints merged on pairs.<BASE>.<QUOTE>
+    pub pair_feed: bool,
+    pub zmq_topics: bool,
+    // PUB endpoint for fixed layout binary trade frames
+    pub zmq_binary: Option<String>,
+    // extra PUB endpoints with only the markets quoted in the mint
+    pub quote_feeds: Vec<(Pubkey, String)>,
+    pub zmq_filter: Option<TradeFilter>,
+    pub ws_filter: Option<TradeFilter>,
+    // NDJSON on stdout for shell pipelines, logs always go to stderr
+    pub stdout: bool,
+    pub stdout_topics: Vec<String>,
+    pub stdout_filter: Option<TradeFilter>,
+    // skip the ZMQ PUB socket, e.g. with only --stdout
+    pub no_zmq: bool,
+    // exit (see exit.rs) instead of reconnecting sources and pausing failed sinks
+    pub fail_fast: bool,
+    // what a panic does after writing the crash dump (see postmortem.rs)
+    pub panic_policy: PanicPolicy,
+    pub diagnostics_dir: Option<String>,
+    // max lag from block time to publishing per market, 0 for none
+    pub lag_slo_ms: u64,
+    pub market_lag_slos: Vec<(Pubkey, u64)>,
+    // adaptive lag sampling of the health score
+    pub health_min_interval_ms: u64,
+    pub health_max_interval_ms: u64,
+    // lowest health score of a market or the feed which is still healthy
+    pub health_min_score: u8,
+    // reference feed the published trades are compared with
+    pub shadow: Option<String>,
+    // how long a trade waits for its counterpart in the other feed
+    pub shadow_window_secs: u64,
+    // kafka sink, trades only, keyed by market
+    pub kafka_brokers: Option<String>,
+    pub kafka_topic: String,
+    // registry the Avro schema of the kafka trades is registered with
+    pub schema_registry: Option<String>,
+    // what happens to fills whose seq_num goes backwards
+    pub seq_guard: SeqGuard,
+    // newest transactions per market searched for the fills missed while down, 0 disables
+    pub catch_up_signatures: usize,
+    // sinks which get trades before the enrichers ran instead of the enriched ones
+    pub fast_sinks: Vec<String>,
+    // sinks whose messages are sealed for every consumer group of feed_keys
+    pub encrypted_sinks: Vec<String>,
+    // sinks sealed and sent by worker threads of their own, with the number of sealing threads
+    pub sink_workers: Vec<(String, usize)>,
+    // one active key per consumer group
+    pub feed_keys: Vec<FeedKey>,
+    // markets a consumer group is entitled to, groups without any get every market
+    pub feed_group_markets: Vec<(String, Pubkey)>,
+    pub alerts: Vec<Expr>,
+    // repeats of an alert within this many seconds are dropped, also across restarts
+    pub alert_cooldown_secs: u64,
+    // zmq REP endpoint for replay requests
+    pub control: Option<String>,
+    pub replay_buffer: usize,
+    // accept heartbeats of consumers on the websocket and control socket, see /consumers
+    pub consumer_heartbeats: bool,
+    // seconds without heartbeat after which a consumer shows as stale
+    pub consumer_stale_secs: u64,
+    pub ws_client_rate: Option<u32>,
+    pub ws_send_timeout_ms: u64,
+    pub ws_slow_client: SlowClient,
+    // archived trades sent per websocket replay request at most
+    pub ws_replay_limit: usize,
+    // schema migration overlap: the older version and when it stops, None = no overlap
+    pub legacy_schema_version: Option<u32>,
+    pub legacy_schema_until: Option<u64>,
+    // source on its own thread with a current-thread runtime, implied by intake_core
+    pub intake_runtime: bool,
+    pub intake_core: Option<usize>,
+    pub publish_core: Option<usize>,
+    // items the enrich and encode stages handle before yielding to other tasks, 0 never yields
+    pub yield_every: usize,
+    // owner lookups in flight, trades over it are published with unresolved owners
+    pub owner_lookups: usize,
+    // how long a trade waits for the owner lookups of its maker and taker
+    pub owner_wait_ms: u64,
+    // how often the tif enricher reads the bids and asks of the markets
+    pub book_poll_ms: u64,
+    // seconds between snapshots of the owner map to <archive dir>/owners, 0 disables them
+    pub owner_snapshot_interval: u64,
+    // resolve owners against the archived snapshots of each trade's slot first, for replays
+    pub owners_from_archive: bool,
+    pub enrichers: Vec<EnricherKind>,
+    // {"<pubkey>": "<label>"} for the labels enricher
+    pub labels_file: Option<String>,
+    // shared libraries run in this order by the plugins enricher
+    pub plugins: Vec<String>,
+    // USD prices of quote currencies for the usd enricher, currencies without one are
+    // priced from the trades of the watched markets
+    pub quote_conversions: Vec<(String, QuoteConversion)>,
+    // a trade waits at most this long for one enricher, then is published without its fields
+    pub enricher_timeout_ms: u64,
+    pub enricher_timeouts: Vec<(EnricherKind, u64)>,
+    // enrichers limited to some markets or trades, the others run on every trade
+    pub enricher_filters: Vec<(EnricherKind, TradeFilter)>,
+    // market decimals against mint decimals at startup
+    pub decimals_check: DecimalsCheck,
+    // startup requests in flight: account batches of 100, and markets whose owners are pre-warmed
+    pub bootstrap_concurrency: usize,
+    // resolve the owners of every OpenOrders account of the markets before subscribing
+    pub prewarm_owners: bool,
+    pub command: Option<Command>,
+    // run the self-test instead of the printer
+    pub self_test: bool,
+    // the .env file that was loaded, if any
+    pub env_file: Option<String>,
 }
 
 impl Config {
-    pub fn new() -> Self {
-        // Load environment variables from .env files
-        Self::load_env_files();
-        
-        // Start with default values
-        let mut config = Config {
+    // The defaults of every setting, without reading the command line or environment,
+    // e.g. for embedding (see streamer.rs)
+    pub fn defaults() -> Self {
+        Config {
             rpc_url: "https://api.mainnet-beta.solana.com".to_string(),
             market_keys: vec![],
             port: "8585".to_string(),
@@ -59,7 +762,143 @@ impl Config {
             commitment: Commitment::Finalized,
             connect: false,
             x_token: "x-token".to_string(),
-            check: 1000,
+            token_refresh_command: None,
+            commitment_check_ms: 0,
+            embed_lag: false,
+            canonical_json: false,
+            admin: None,
+            disable_features: vec![],
+            profiling: false,
+            ws: None,
+            api_keys: None,
+            queue_size: 10000,
+            shards: 1,
+            fill_dedup: true,
+            signature_dedup: 10000,
+            log_prefixes: vec![PROGRAM_DATA_PREFIX.to_string()],
+            state_dir: "state".to_string(),
+            persist_metrics: false,
+            archive_dir: None,
+            capture_dir: None,
+            blacklist: vec![],
+            quarantine: vec![],
+            priority_markets: vec![],
+            archive_batch: 1000,
+            archive_flush_ms: 1000,
+            archive_retention_days: 0,
+            state_interval: 10,
+            repair_from_archive: false,
+            ticker_interval: 10,
+            summary_interval: 60,
+            trade_id: TradeIdScheme::Slot,
+            sink_retries: 2,
+            sink_backoff_ms: 10,
+            sink_backoff_max_ms: 200,
+            breaker_threshold: 5,
+            breaker_open_secs: 30,
+            source: SourceKind::Geyser,
+            poll_interval_ms: 400,
+            helius_url: "wss://atlas-mainnet.helius-rpc.com/?api-key=".to_string(),
+            shredstream_url: "http://127.0.0.1:9999".to_string(),
+            mock_file: None,
+            mock_speed: 0.0,
+            market_accounts: None,
+            grpc_headers: vec![],
+            stale_feed_secs: 300,
+            prune_idle_days: 0,
+            grpc_commitment: None,
+            rpc_commitment: None,
+            helius_commitment: None,
+            market_commitments: vec![],
+            clock_sync: ClockSync::Ntp,
+            ntp_server: "pool.ntp.org:123".to_string(),
+            market_info_interval: 60,
+            inventory_interval: 0,
+            checksum_interval: 0,
+            fees_interval: 0,
+            sessions: vec![],
+            oracle_interval: 0,
+            listing_interval: 0,
+            listing_mints: vec![],
+            listing_names: vec![],
+            upgrade_check_secs: 60,
+            bars: false,
+            pair_feed: false,
+            zmq_topics: false,
+            zmq_binary: None,
+            quote_feeds: vec![],
+            zmq_filter: None,
+            ws_filter: None,
+            stdout: false,
+            stdout_topics: vec!["trades".to_string()],
+            stdout_filter: None,
+            no_zmq: false,
+            fail_fast: false,
+            panic_policy: PanicPolicy::Continue,
+            diagnostics_dir: None,
+            lag_slo_ms: 0,
+            market_lag_slos: vec![],
+            health_min_interval_ms: 100,
+            health_max_interval_ms: 5000,
+            health_min_score: 50,
+            shadow: None,
+            shadow_window_secs: 30,
+            kafka_brokers: None,
+            kafka_topic: "openbook.trades".to_string(),
+            schema_registry: None,
+            seq_guard: SeqGuard::Flag,
+            catch_up_signatures: 0,
+            fast_sinks: vec![],
+            encrypted_sinks: vec![],
+            sink_workers: vec![],
+            feed_keys: vec![],
+            feed_group_markets: vec![],
+            alerts: vec![],
+            alert_cooldown_secs: 0,
+            control: None,
+            replay_buffer: 10000,
+            consumer_heartbeats: false,
+            consumer_stale_secs: 30,
+            ws_client_rate: None,
+            ws_send_timeout_ms: 5000,
+            ws_slow_client: SlowClient::Disconnect,
+            ws_replay_limit: 100000,
+            legacy_schema_version: None,
+            legacy_schema_until: None,
+            intake_runtime: false,
+            intake_core: None,
+            publish_core: None,
+            yield_every: 0,
+            owner_lookups: 32,
+            owner_wait_ms: 2000,
+            book_poll_ms: 1000,
+            owner_snapshot_interval: 0,
+            owners_from_archive: false,
+            enrichers: vec![EnricherKind::Owners],
+            labels_file: None,
+            plugins: vec![],
+            quote_conversions: vec![],
+            enricher_timeout_ms: 3000,
+            enricher_timeouts: vec![],
+            enricher_filters: vec![],
+            decimals_check: DecimalsCheck::Fail,
+            bootstrap_concurrency: 4,
+            prewarm_owners: false,
+            command: None,
+            self_test: false,
+            env_file: None,
+        }
+    }
+
+    pub fn new() -> Self {
+        // Parse CLI arguments first, they pick the env file
+        let cli = Cli::parse();
+        let env_file = Self::load_env_file(&cli);
+        
+        // Start with default values
+        let mut config = Config {
+            env_file,
+            ..Config::defaults()
         };
         
         // Default market string
@@ -86,82 +925,1324 @@ impl Config {
             config.x_token = x_token;
         }
         
-        if let Ok(env_market) = std::env::var("MARKET") {
-            market_str = env_market;
+        if let Ok(token_refresh_command) = std::env::var("TOKEN_REFRESH_COMMAND") {
+            config.token_refresh_command = Some(token_refresh_command);
         }
         
-        // Parse CLI arguments
-        let cli = Cli::parse();
+        if let Ok(commitment_check_ms) = std::env::var("COMMITMENT_CHECK_MS") {
+            config.commitment_check_ms = commitment_check_ms.parse().unwrap();
+        }
         
-        // Override with CLI arguments if they exist
-        if let Some(rpc_url) = cli.rpc_url {
-            config.rpc_url = rpc_url;
+        if let Ok(admin) = std::env::var("ADMIN_ADDR") {
+            config.admin = Some(admin);
         }
         
-        if let Some(port) = cli.port {
-            config.port = port;
+        if let Ok(disable_features) = std::env::var("DISABLE_FEATURES") {
+            config.disable_features = disable_features
+                .split(',')
+                .filter(|feature| !feature.trim().is_empty())
+                .map(|feature| Feature::from_str(feature.trim(), true).unwrap())
+                .collect();
         }
         
-        if let Some(host) = cli.host {
-            config.host = host;
+        if let Ok(profiling) = std::env::var("PROFILING") {
+            config.profiling = profiling == "true" || profiling == "1";
         }
         
-        if let Some(grpc) = cli.grpc {
-            config.grpc = grpc;
+        if let Ok(ws) = std::env::var("WS_ADDR") {
+            config.ws = Some(ws);
         }
         
-        if let Some(commitment) = cli.commitment {
-            config.commitment = commitment;
+        if let Ok(api_keys) = std::env::var("API_KEYS_FILE") {
+            config.api_keys = Some(api_keys);
         }
         
-        if let Some(x_token) = cli.x_token {
-            config.x_token = x_token;
+        if let Ok(embed_lag) = std::env::var("EMBED_LAG") {
+            config.embed_lag = embed_lag == "true" || embed_lag == "1";
         }
         
-        if let Some(check) = cli.check {
-            config.check = check;
+        if let Ok(canonical_json) = std::env::var("CANONICAL_JSON") {
+            config.canonical_json = canonical_json == "true" || canonical_json == "1";
         }
         
-        config.connect = cli.connect;
+        if let Ok(queue_size) = std::env::var("QUEUE_SIZE") {
+            config.queue_size = queue_size.parse().unwrap();
+        }
         
-        let markets = if !cli.market.is_empty() {
-            cli.market
-        } else {
-            // Parse the market string (from default or environment)
-            market_str
-                .split(',')
-                .map(|s| s.trim().to_string())
-                .filter(|s| !s.is_empty())
-                .collect()
-        };
+        if let Ok(shards) = std::env::var("SHARDS") {
+            config.shards = shards.parse().unwrap();
+        }
         
-        // Convert market strings to Pubkeys
-        config.market_keys = markets
-            .iter()
-            .map(|market_key| Pubkey::from_str(market_key).unwrap())
-            .collect();
+        if let Ok(fill_dedup) = std::env::var("FILL_DEDUP") {
+            config.fill_dedup = fill_dedup == "true" || fill_dedup == "1";
+        }
         
-        config
-    }
-    
-    fn load_env_files() {
-        // Try loading from multiple possible locations
-        let env_paths = vec![
-            ".env",
-            "../.env",
-            "../../.env",
-            // Add more potential paths if needed
-        ];
-
-        for path in env_paths {
-            info!("Trying to load .env from: {}", path);
-            match dotenv::from_path(path) {
-                Ok(_) => {
-                    info!("Successfully loaded .env from: {}", path);
-                    break;
-                },
-                Err(e) => info!("Could not load .env from {}: {}", path, e),
-            }
+        if let Ok(signature_dedup) = std::env::var("SIGNATURE_DEDUP") {
+            config.signature_dedup = signature_dedup.parse().unwrap();
         }
-    }
-} 
\ No newline at end of file
+        
+        // trimmed, the payload is found after the whitespace following a prefix anyway
+        if let Ok(log_prefixes) = std::env::var("LOG_PREFIXES") {
+            config.log_prefixes = parse_topics(&log_prefixes);
+        }
+        
+        if let Ok(state_dir) = std::env::var("STATE_DIR") {
+            config.state_dir = state_dir;
+        }
+        
+        if let Ok(persist_metrics) = std::env::var("PERSIST_METRICS") {
+            config.persist_metrics = persist_metrics == "true" || persist_metrics == "1";
+        }
+        
+        if let Some(archive_dir) = cli.archive_dir {
+            config.archive_dir = Some(archive_dir);
+        }
+        
+        if let Ok(archive_dir) = std::env::var("ARCHIVE_DIR") {
+            config.archive_dir = Some(archive_dir);
+        }
+        
+        if let Ok(capture_dir) = std::env::var("CAPTURE_DIR") {
+            config.capture_dir = Some(capture_dir);
+        }
+        
+        if let Some(capture_dir) = cli.capture_dir {
+            config.capture_dir = Some(capture_dir);
+        }
+        
+        if let Ok(blacklist) = std::env::var("BLACKLIST") {
+            config.blacklist = parse_topics(&blacklist)
+                .iter()
+                .map(|key| Pubkey::from_str(key).unwrap())
+                .collect();
+        }
+        
+        if !cli.blacklist.is_empty() {
+            config.blacklist = cli.blacklist.iter().map(|key| Pubkey::from_str(key).unwrap()).collect();
+        }
+        
+        if let Ok(quarantine) = std::env::var("QUARANTINE") {
+            config.quarantine = parse_topics(&quarantine)
+                .iter()
+                .map(|key| Pubkey::from_str(key).unwrap())
+                .collect();
+        }
+        
+        if !cli.quarantine.is_empty() {
+            config.quarantine = cli.quarantine.iter().map(|key| Pubkey::from_str(key).unwrap()).collect();
+        }
+        
+        if let Ok(priority_markets) = std::env::var("PRIORITY_MARKETS") {
+            config.priority_markets = parse_topics(&priority_markets)
+                .iter()
+                .map(|key| Pubkey::from_str(key).unwrap())
+                .collect();
+        }
+        
+        if !cli.priority_market.is_empty() {
+            config.priority_markets = cli.priority_market.iter().map(|key| Pubkey::from_str(key).unwrap()).collect();
+        }
+        
+        if let Ok(archive_batch) = std::env::var("ARCHIVE_BATCH") {
+            config.archive_batch = archive_batch.parse().unwrap();
+        }
+        
+        if let Some(archive_batch) = cli.archive_batch {
+            config.archive_batch = archive_batch;
+        }
+        
+        if let Ok(archive_flush_ms) = std::env::var("ARCHIVE_FLUSH_MS") {
+            config.archive_flush_ms = archive_flush_ms.parse().unwrap();
+        }
+        
+        if let Some(archive_flush_ms) = cli.archive_flush_ms {
+            config.archive_flush_ms = archive_flush_ms;
+        }
+        
+        if let Ok(archive_retention_days) = std::env::var("ARCHIVE_RETENTION_DAYS") {
+            config.archive_retention_days = archive_retention_days.parse().unwrap();
+        }
+        
+        if let Some(archive_retention_days) = cli.archive_retention_days {
+            config.archive_retention_days = archive_retention_days;
+        }
+        
+        if let Ok(state_interval) = std::env::var("STATE_INTERVAL") {
+            config.state_interval = state_interval.parse().unwrap();
+        }
+        
+        if let Ok(repair_from_archive) = std::env::var("REPAIR_FROM_ARCHIVE") {
+            config.repair_from_archive = repair_from_archive == "true" || repair_from_archive == "1";
+        }
+        
+        if let Ok(ticker_interval) = std::env::var("TICKER_INTERVAL") {
+            config.ticker_interval = ticker_interval.parse().unwrap();
+        }
+        
+        if let Ok(summary_interval) = std::env::var("SUMMARY_INTERVAL") {
+            config.summary_interval = summary_interval.parse().unwrap();
+        }
+        
+        if let Ok(trade_id) = std::env::var("TRADE_ID") {
+            config.trade_id = trade_id.parse().unwrap();
+        }
+        
+        if let Ok(sink_retries) = std::env::var("SINK_RETRIES") {
+            config.sink_retries = sink_retries.parse().unwrap();
+        }
+        
+        if let Ok(sink_backoff_ms) = std::env::var("SINK_BACKOFF_MS") {
+            config.sink_backoff_ms = sink_backoff_ms.parse().unwrap();
+        }
+        
+        if let Ok(sink_backoff_max_ms) = std::env::var("SINK_BACKOFF_MAX_MS") {
+            config.sink_backoff_max_ms = sink_backoff_max_ms.parse().unwrap();
+        }
+        
+        if let Ok(breaker_threshold) = std::env::var("BREAKER_THRESHOLD") {
+            config.breaker_threshold = breaker_threshold.parse().unwrap();
+        }
+        
+        if let Ok(breaker_open_secs) = std::env::var("BREAKER_OPEN_SECS") {
+            config.breaker_open_secs = breaker_open_secs.parse().unwrap();
+        }
+        
+        if let Ok(source) = std::env::var("SOURCE") {
+            config.source = SourceKind::from_str(&source, true).unwrap();
+        }
+        
+        if let Ok(poll_interval_ms) = std::env::var("POLL_INTERVAL_MS") {
+            config.poll_interval_ms = poll_interval_ms.parse().unwrap();
+        }
+        
+        if let Ok(stale_feed_secs) = std::env::var("STALE_FEED_SECS") {
+            config.stale_feed_secs = stale_feed_secs.parse().unwrap();
+        }
+        
+        if let Ok(prune_idle_days) = std::env::var("PRUNE_IDLE_DAYS") {
+            config.prune_idle_days = prune_idle_days.parse().unwrap();
+        }
+        
+        if let Ok(helius_url) = std::env::var("HELIUS_WS_URL") {
+            config.helius_url = helius_url;
+        }
+        
+        if let Ok(shredstream_url) = std::env::var("SHREDSTREAM_URL") {
+            config.shredstream_url = shredstream_url;
+        }
+        
+        if let Ok(mock_file) = std::env::var("MOCK_FILE") {
+            config.mock_file = Some(mock_file);
+        }
+        
+        if let Ok(mock_speed) = std::env::var("MOCK_SPEED") {
+            config.mock_speed = mock_speed.parse().unwrap();
+        }
+        
+        if let Ok(market_accounts) = std::env::var("MARKET_ACCOUNTS") {
+            config.market_accounts = Some(market_accounts);
+        }
+        
+        if let Ok(grpc_headers) = std::env::var("GRPC_HEADERS") {
+            config.grpc_headers = grpc_headers
+                .split(',')
+                .filter(|header| !header.trim().is_empty())
+                .map(parse_header)
+                .collect();
+        }
+        
+        if let Ok(market_commitments) = std::env::var("MARKET_COMMITMENTS") {
+            config.market_commitments = market_commitments
+                .split(',')
+                .filter(|market_commitment| !market_commitment.trim().is_empty())
+                .map(parse_market_commitment)
+                .collect();
+        }
+        
+        if let Ok(grpc_commitment) = std::env::var("GRPC_COMMITMENT") {
+            config.grpc_commitment = Some(Commitment::from_str(&grpc_commitment, true).unwrap());
+        }
+        
+        if let Ok(rpc_commitment) = std::env::var("RPC_COMMITMENT") {
+            config.rpc_commitment = Some(Commitment::from_str(&rpc_commitment, true).unwrap());
+        }
+        
+        if let Ok(helius_commitment) = std::env::var("HELIUS_COMMITMENT") {
+            config.helius_commitment = Some(Commitment::from_str(&helius_commitment, true).unwrap());
+        }
+        
+        if let Ok(clock_sync) = std::env::var("CLOCK_SYNC") {
+            config.clock_sync = ClockSync::from_str(&clock_sync, true).unwrap();
+        }
+        
+        if let Ok(ntp_server) = std::env::var("NTP_SERVER") {
+            config.ntp_server = ntp_server;
+        }
+        
+        if let Ok(market_info_interval) = std::env::var("MARKET_INFO_INTERVAL") {
+            config.market_info_interval = market_info_interval.parse().unwrap();
+        }
+        
+        if let Ok(inventory_interval) = std::env::var("INVENTORY_INTERVAL") {
+            config.inventory_interval = inventory_interval.parse().unwrap();
+        }
+        
+        if let Ok(checksum_interval) = std::env::var("CHECKSUM_INTERVAL") {
+            config.checksum_interval = checksum_interval.parse().unwrap();
+        }
+        
+        if let Ok(fees_interval) = std::env::var("FEES_INTERVAL") {
+            config.fees_interval = fees_interval.parse().unwrap();
+        }
+        
+        if let Ok(sessions) = std::env::var("SESSIONS") {
+            config.sessions = sessions
+                .split(',')
+                .filter(|session| !session.trim().is_empty())
+                .map(parse_session)
+                .collect();
+        }
+        
+        if let Ok(oracle_interval) = std::env::var("ORACLE_INTERVAL") {
+            config.oracle_interval = oracle_interval.parse().unwrap();
+        }
+        
+        if let Ok(listing_interval) = std::env::var("LISTING_INTERVAL") {
+            config.listing_interval = listing_interval.parse().unwrap();
+        }
+        
+        if let Ok(listing_mints) = std::env::var("LISTING_MINTS") {
+            config.listing_mints = parse_topics(&listing_mints)
+                .iter()
+                .map(|key| Pubkey::from_str(key).unwrap())
+                .collect();
+        }
+        
+        if let Ok(listing_names) = std::env::var("LISTING_NAMES") {
+            config.listing_names = parse_topics(&listing_names);
+        }
+        
+        if let Ok(upgrade_check_secs) = std::env::var("UPGRADE_CHECK_SECS") {
+            config.upgrade_check_secs = upgrade_check_secs.parse().unwrap();
+        }
+        
+        if let Ok(bars) = std::env::var("BARS") {
+            config.bars = bars == "true" || bars == "1";
+        }
+        
+        if let Ok(pair_feed) = std::env::var("PAIR_FEED") {
+            config.pair_feed = pair_feed == "true" || pair_feed == "1";
+        }
+        
+        if let Ok(zmq_topics) = std::env::var("ZMQ_TOPICS") {
+            config.zmq_topics = zmq_topics == "true" || zmq_topics == "1";
+        }
+        
+        if let Ok(intake_runtime) = std::env::var("INTAKE_RUNTIME") {
+            config.intake_runtime = intake_runtime == "true" || intake_runtime == "1";
+        }
+        
+        if let Ok(intake_core) = std::env::var("INTAKE_CORE") {
+            config.intake_core = Some(intake_core.parse().unwrap());
+        }
+        
+        if let Ok(publish_core) = std::env::var("PUBLISH_CORE") {
+            config.publish_core = Some(publish_core.parse().unwrap());
+        }
+        
+        if let Ok(yield_every) = std::env::var("YIELD_EVERY") {
+            config.yield_every = yield_every.parse().unwrap();
+        }
+        
+        if let Ok(owner_lookups) = std::env::var("OWNER_LOOKUPS") {
+            config.owner_lookups = owner_lookups.parse().unwrap();
+        }
+        
+        if let Ok(owner_wait_ms) = std::env::var("OWNER_WAIT_MS") {
+            config.owner_wait_ms = owner_wait_ms.parse().unwrap();
+        }
+        
+        if let Ok(book_poll_ms) = std::env::var("BOOK_POLL_MS") {
+            config.book_poll_ms = book_poll_ms.parse().unwrap();
+        }
+        
+        if let Ok(owner_snapshot_interval) = std::env::var("OWNER_SNAPSHOT_INTERVAL") {
+            config.owner_snapshot_interval = owner_snapshot_interval.parse().unwrap();
+        }
+        
+        if let Ok(owners_from_archive) = std::env::var("OWNERS_FROM_ARCHIVE") {
+            config.owners_from_archive = owners_from_archive == "true" || owners_from_archive == "1";
+        }
+        
+        if let Ok(enrichers) = std::env::var("ENRICHERS") {
+            config.enrichers = enrichers
+                .split(',')
+                .filter(|enricher| !enricher.trim().is_empty())
+                .map(|enricher| EnricherKind::from_str(enricher.trim(), true).unwrap())
+                .collect();
+        }
+        
+        if let Ok(labels_file) = std::env::var("LABELS_FILE") {
+            config.labels_file = Some(labels_file);
+        }
+        
+        if let Ok(plugins) = std::env::var("PLUGINS") {
+            config.plugins = parse_topics(&plugins);
+        }
+        
+        if let Ok(quote_conversions) = std::env::var("QUOTE_CONVERSIONS") {
+            config.quote_conversions = quote_conversions
+                .split(',')
+                .filter(|quote_conversion| !quote_conversion.trim().is_empty())
+                .map(parse_quote_conversion)
+                .collect();
+        }
+        
+        if let Ok(enricher_timeout_ms) = std::env::var("ENRICHER_TIMEOUT_MS") {
+            config.enricher_timeout_ms = enricher_timeout_ms.parse().unwrap();
+        }
+        
+        if let Ok(enricher_timeouts) = std::env::var("ENRICHER_TIMEOUTS") {
+            config.enricher_timeouts = enricher_timeouts
+                .split(',')
+                .filter(|enricher_timeout| !enricher_timeout.trim().is_empty())
+                .map(parse_enricher_timeout)
+                .collect();
+        }
+        
+        // one variable per enricher, filters contain the separators of a list
+        for kind in EnricherKind::value_variants() {
+            let name = format!("ENRICHER_FILTER_{}", kind.as_str().to_uppercase());
+            if let Ok(filter) = std::env::var(name) {
+                config.enricher_filters.push((*kind, filter.parse().unwrap()));
+            }
+        }
+        
+        if let Ok(decimals_check) = std::env::var("DECIMALS_CHECK") {
+            config.decimals_check = DecimalsCheck::from_str(&decimals_check, true).unwrap();
+        }
+        
+        if let Ok(bootstrap_concurrency) = std::env::var("BOOTSTRAP_CONCURRENCY") {
+            config.bootstrap_concurrency = bootstrap_concurrency.parse().unwrap();
+        }
+        
+        if let Ok(prewarm_owners) = std::env::var("PREWARM_OWNERS") {
+            config.prewarm_owners = prewarm_owners == "true" || prewarm_owners == "1";
+        }
+        
+        if let Ok(zmq_binary) = std::env::var("ZMQ_BINARY_ADDR") {
+            config.zmq_binary = Some(zmq_binary);
+        }
+        
+        if let Ok(quote_feeds) = std::env::var("QUOTE_FEEDS") {
+            config.quote_feeds = quote_feeds
+                .split(',')
+                .filter(|quote_feed| !quote_feed.trim().is_empty())
+                .map(parse_quote_feed)
+                .collect();
+        }
+        
+        if let Ok(zmq_filter) = std::env::var("ZMQ_FILTER") {
+            config.zmq_filter = Some(zmq_filter.parse().unwrap());
+        }
+        
+        if let Ok(stdout) = std::env::var("STDOUT") {
+            config.stdout = stdout == "true" || stdout == "1";
+        }
+        
+        if let Ok(stdout_topics) = std::env::var("STDOUT_TOPICS") {
+            config.stdout_topics = parse_topics(&stdout_topics);
+        }
+        
+        if let Ok(stdout_filter) = std::env::var("STDOUT_FILTER") {
+            config.stdout_filter = Some(stdout_filter.parse().unwrap());
+        }
+        
+        if let Ok(no_zmq) = std::env::var("NO_ZMQ") {
+            config.no_zmq = no_zmq == "true" || no_zmq == "1";
+        }
+        
+        if let Ok(fail_fast) = std::env::var("FAIL_FAST") {
+            config.fail_fast = fail_fast == "true" || fail_fast == "1";
+        }
+        
+        if let Ok(panic_policy) = std::env::var("PANIC_POLICY") {
+            config.panic_policy = PanicPolicy::from_str(&panic_policy, true).unwrap();
+        }
+        
+        if let Ok(diagnostics_dir) = std::env::var("DIAGNOSTICS_DIR") {
+            config.diagnostics_dir = Some(diagnostics_dir);
+        }
+        
+        if let Ok(lag_slo_ms) = std::env::var("LAG_SLO_MS") {
+            config.lag_slo_ms = lag_slo_ms.parse().unwrap();
+        }
+        
+        if let Ok(market_lag_slos) = std::env::var("MARKET_LAG_SLOS") {
+            config.market_lag_slos = market_lag_slos
+                .split(',')
+                .filter(|market_lag_slo| !market_lag_slo.trim().is_empty())
+                .map(parse_market_lag_slo)
+                .collect();
+        }
+        
+        if let Ok(health_min_interval_ms) = std::env::var("HEALTH_MIN_INTERVAL_MS") {
+            config.health_min_interval_ms = health_min_interval_ms.parse().unwrap();
+        }
+        
+        if let Ok(health_max_interval_ms) = std::env::var("HEALTH_MAX_INTERVAL_MS") {
+            config.health_max_interval_ms = health_max_interval_ms.parse().unwrap();
+        }
+        
+        if let Ok(health_min_score) = std::env::var("HEALTH_MIN_SCORE") {
+            config.health_min_score = health_min_score.parse().unwrap();
+        }
+        
+        if let Ok(shadow) = std::env::var("SHADOW_FEED") {
+            config.shadow = Some(shadow);
+        }
+        
+        if let Ok(shadow_window_secs) = std::env::var("SHADOW_WINDOW_SECS") {
+            config.shadow_window_secs = shadow_window_secs.parse().unwrap();
+        }
+        
+        if let Ok(kafka_brokers) = std::env::var("KAFKA_BROKERS") {
+            config.kafka_brokers = Some(kafka_brokers);
+        }
+        
+        if let Ok(kafka_topic) = std::env::var("KAFKA_TOPIC") {
+            config.kafka_topic = kafka_topic;
+        }
+        
+        if let Ok(schema_registry) = std::env::var("SCHEMA_REGISTRY_URL") {
+            config.schema_registry = Some(schema_registry);
+        }
+        
+        if let Ok(seq_guard) = std::env::var("SEQ_GUARD") {
+            config.seq_guard = SeqGuard::from_str(&seq_guard, true).unwrap();
+        }
+        
+        if let Ok(catch_up_signatures) = std::env::var("CATCH_UP_SIGNATURES") {
+            config.catch_up_signatures = catch_up_signatures.parse().unwrap();
+        }
+        
+        if let Ok(fast_sinks) = std::env::var("FAST_SINKS") {
+            config.fast_sinks = parse_topics(&fast_sinks);
+        }
+        
+        if let Ok(encrypted_sinks) = std::env::var("ENCRYPTED_SINKS") {
+            config.encrypted_sinks = parse_topics(&encrypted_sinks);
+        }
+        
+        if let Ok(sink_workers) = std::env::var("SINK_WORKERS") {
+            config.sink_workers = sink_workers
+                .split(',')
+                .filter(|sink_workers| !sink_workers.trim().is_empty())
+                .map(parse_sink_workers)
+                .collect();
+        }
+        
+        if let Ok(feed_keys) = std::env::var("FEED_KEYS") {
+            config.feed_keys = feed_keys
+                .split(',')
+                .filter(|feed_key| !feed_key.trim().is_empty())
+                .map(parse_feed_key)
+                .collect();
+        }
+        
+        if let Ok(feed_group_markets) = std::env::var("FEED_GROUP_MARKETS") {
+            config.feed_group_markets = feed_group_markets
+                .split(',')
+                .filter(|feed_group_market| !feed_group_market.trim().is_empty())
+                .map(parse_feed_group_market)
+                .collect();
+        }
+        
+        if let Ok(ws_filter) = std::env::var("WS_FILTER") {
+            config.ws_filter = Some(ws_filter.parse().unwrap());
+        }
+        
+        if let Ok(alerts) = std::env::var("ALERTS") {
+            config.alerts = alerts
+                .split(';')
+                .filter(|alert| !alert.trim().is_empty())
+                .map(|alert| alert.parse().unwrap())
+                .collect();
+        }
+        
+        if let Ok(alert_cooldown_secs) = std::env::var("ALERT_COOLDOWN_SECS") {
+            config.alert_cooldown_secs = alert_cooldown_secs.parse().unwrap();
+        }
+        
+        if let Ok(control) = std::env::var("CONTROL_ADDR") {
+            config.control = Some(control);
+        }
+        
+        if let Ok(replay_buffer) = std::env::var("REPLAY_BUFFER") {
+            config.replay_buffer = replay_buffer.parse().unwrap();
+        }
+        
+        if let Ok(consumer_heartbeats) = std::env::var("CONSUMER_HEARTBEATS") {
+            config.consumer_heartbeats = consumer_heartbeats == "true" || consumer_heartbeats == "1";
+        }
+        
+        if let Ok(consumer_stale_secs) = std::env::var("CONSUMER_STALE_SECS") {
+            config.consumer_stale_secs = consumer_stale_secs.parse().unwrap();
+        }
+        
+        if let Ok(ws_client_rate) = std::env::var("WS_CLIENT_RATE") {
+            config.ws_client_rate = Some(ws_client_rate.parse().unwrap());
+        }
+        
+        if let Ok(ws_send_timeout_ms) = std::env::var("WS_SEND_TIMEOUT_MS") {
+            config.ws_send_timeout_ms = ws_send_timeout_ms.parse().unwrap();
+        }
+        
+        if let Ok(ws_replay_limit) = std::env::var("WS_REPLAY_LIMIT") {
+            config.ws_replay_limit = ws_replay_limit.parse().unwrap();
+        }
+        
+        if let Ok(legacy_schema_version) = std::env::var("LEGACY_SCHEMA_VERSION") {
+            config.legacy_schema_version = Some(legacy_schema_version.parse().unwrap());
+        }
+        
+        if let Ok(legacy_schema_until) = std::env::var("LEGACY_SCHEMA_UNTIL") {
+            config.legacy_schema_until = Some(legacy_schema_until.parse().unwrap());
+        }
+        
+        if let Ok(ws_slow_client) = std::env::var("WS_SLOW_CLIENT") {
+            config.ws_slow_client = SlowClient::from_str(&ws_slow_client, true).unwrap();
+        }
+        
+        if let Ok(env_market) = std::env::var("MARKET") {
+            market_str = env_market;
+        }
+        
+        
+        // Override with CLI arguments if they exist
+        if let Some(rpc_url) = cli.rpc_url {
+            config.rpc_url = rpc_url;
+        }
+        
+        if let Some(port) = cli.port {
+            config.port = port;
+        }
+        
+        if let Some(host) = cli.host {
+            config.host = host;
+        }
+        
+        if let Some(grpc) = cli.grpc {
+            config.grpc = grpc;
+        }
+        
+        if let Some(commitment) = cli.commitment {
+            config.commitment = commitment;
+        }
+        
+        if let Some(x_token) = cli.x_token {
+            config.x_token = x_token;
+        }
+        
+        if let Some(token_refresh_command) = cli.token_refresh_command {
+            config.token_refresh_command = Some(token_refresh_command);
+        }
+        
+        if let Some(commitment_check_ms) = cli.commitment_check_ms {
+            config.commitment_check_ms = commitment_check_ms;
+        }
+        
+        if let Some(admin) = cli.admin {
+            config.admin = Some(admin);
+        }
+        
+        if let Some(disable_features) = cli.disable_features {
+            config.disable_features = disable_features;
+        }
+        
+        if let Some(ws) = cli.ws {
+            config.ws = Some(ws);
+        }
+        
+        if let Some(api_keys) = cli.api_keys {
+            config.api_keys = Some(api_keys);
+        }
+        
+        if let Some(queue_size) = cli.queue_size {
+            config.queue_size = queue_size;
+        }
+        
+        if let Some(shards) = cli.shards {
+            config.shards = shards;
+        }
+        
+        if let Some(fill_dedup) = cli.fill_dedup {
+            config.fill_dedup = fill_dedup;
+        }
+        
+        if let Some(signature_dedup) = cli.signature_dedup {
+            config.signature_dedup = signature_dedup;
+        }
+        
+        if !cli.log_prefix.is_empty() {
+            config.log_prefixes = cli.log_prefix;
+        }
+        
+        if let Some(state_dir) = cli.state_dir {
+            config.state_dir = state_dir;
+        }
+        
+        config.persist_metrics = config.persist_metrics || cli.persist_metrics;
+        
+        if let Some(state_interval) = cli.state_interval {
+            config.state_interval = state_interval;
+        }
+        
+        config.repair_from_archive = config.repair_from_archive || cli.repair_from_archive;
+        
+        if let Some(ticker_interval) = cli.ticker_interval {
+            config.ticker_interval = ticker_interval;
+        }
+        
+        if let Some(summary_interval) = cli.summary_interval {
+            config.summary_interval = summary_interval;
+        }
+        
+        if let Some(trade_id) = cli.trade_id {
+            config.trade_id = trade_id;
+        }
+        
+        if let Some(sink_retries) = cli.sink_retries {
+            config.sink_retries = sink_retries;
+        }
+        
+        if let Some(sink_backoff_ms) = cli.sink_backoff_ms {
+            config.sink_backoff_ms = sink_backoff_ms;
+        }
+        
+        if let Some(sink_backoff_max_ms) = cli.sink_backoff_max_ms {
+            config.sink_backoff_max_ms = sink_backoff_max_ms;
+        }
+        
+        if let Some(breaker_threshold) = cli.breaker_threshold {
+            config.breaker_threshold = breaker_threshold;
+        }
+        
+        if let Some(breaker_open_secs) = cli.breaker_open_secs {
+            config.breaker_open_secs = breaker_open_secs;
+        }
+        
+        if let Some(source) = cli.source {
+            config.source = source;
+        }
+        
+        if let Some(poll_interval_ms) = cli.poll_interval_ms {
+            config.poll_interval_ms = poll_interval_ms;
+        }
+        
+        if let Some(stale_feed_secs) = cli.stale_feed_secs {
+            config.stale_feed_secs = stale_feed_secs;
+        }
+        
+        if let Some(prune_idle_days) = cli.prune_idle_days {
+            config.prune_idle_days = prune_idle_days;
+        }
+        
+        if let Some(helius_url) = cli.helius_url {
+            config.helius_url = helius_url;
+        }
+        
+        if let Some(shredstream_url) = cli.shredstream_url {
+            config.shredstream_url = shredstream_url;
+        }
+        
+        if let Some(mock_file) = cli.mock_file {
+            config.mock_file = Some(mock_file);
+        }
+        
+        if let Some(mock_speed) = cli.mock_speed {
+            config.mock_speed = mock_speed;
+        }
+        
+        if let Some(market_accounts) = cli.market_accounts {
+            config.market_accounts = Some(market_accounts);
+        }
+        
+        if !cli.grpc_header.is_empty() {
+            config.grpc_headers = cli.grpc_header.iter().map(|header| parse_header(header)).collect();
+        }
+        
+        if !cli.market_commitment.is_empty() {
+            config.market_commitments = cli
+                .market_commitment
+                .iter()
+                .map(|market_commitment| parse_market_commitment(market_commitment))
+                .collect();
+        }
+        
+        if let Some(grpc_commitment) = cli.grpc_commitment {
+            config.grpc_commitment = Some(grpc_commitment);
+        }
+        
+        if let Some(rpc_commitment) = cli.rpc_commitment {
+            config.rpc_commitment = Some(rpc_commitment);
+        }
+        
+        if let Some(helius_commitment) = cli.helius_commitment {
+            config.helius_commitment = Some(helius_commitment);
+        }
+        
+        if let Some(clock_sync) = cli.clock_sync {
+            config.clock_sync = clock_sync;
+        }
+        
+        if let Some(ntp_server) = cli.ntp_server {
+            config.ntp_server = ntp_server;
+        }
+        
+        if let Some(market_info_interval) = cli.market_info_interval {
+            config.market_info_interval = market_info_interval;
+        }
+        
+        if let Some(inventory_interval) = cli.inventory_interval {
+            config.inventory_interval = inventory_interval;
+        }
+        
+        if let Some(checksum_interval) = cli.checksum_interval {
+            config.checksum_interval = checksum_interval;
+        }
+        
+        if let Some(fees_interval) = cli.fees_interval {
+            config.fees_interval = fees_interval;
+        }
+        
+        if !cli.session.is_empty() {
+            config.sessions = cli.session.iter().map(|session| parse_session(session)).collect();
+        }
+        
+        if let Some(oracle_interval) = cli.oracle_interval {
+            config.oracle_interval = oracle_interval;
+        }
+        
+        if let Some(listing_interval) = cli.listing_interval {
+            config.listing_interval = listing_interval;
+        }
+        
+        if !cli.listing_mint.is_empty() {
+            config.listing_mints = cli.listing_mint.iter().map(|key| Pubkey::from_str(key).unwrap()).collect();
+        }
+        
+        if !cli.listing_name.is_empty() {
+            config.listing_names = cli.listing_name;
+        }
+        
+        if let Some(upgrade_check_secs) = cli.upgrade_check_secs {
+            config.upgrade_check_secs = upgrade_check_secs;
+        }
+        
+        if let Some(zmq_filter) = cli.zmq_filter {
+            config.zmq_filter = Some(zmq_filter.parse().unwrap());
+        }
+        
+        config.stdout = config.stdout || cli.stdout;
+        config.no_zmq = config.no_zmq || cli.no_zmq;
+        config.fail_fast = config.fail_fast || cli.fail_fast;
+        
+        if let Some(panic_policy) = cli.panic_policy {
+            config.panic_policy = panic_policy;
+        }
+        
+        if let Some(diagnostics_dir) = cli.diagnostics_dir {
+            config.diagnostics_dir = Some(diagnostics_dir);
+        }
+        
+        if let Some(lag_slo_ms) = cli.lag_slo_ms {
+            config.lag_slo_ms = lag_slo_ms;
+        }
+        
+        if !cli.market_lag_slo.is_empty() {
+            config.market_lag_slos = cli
+                .market_lag_slo
+                .iter()
+                .map(|market_lag_slo| parse_market_lag_slo(market_lag_slo))
+                .collect();
+        }
+        
+        if let Some(health_min_interval_ms) = cli.health_min_interval_ms {
+            config.health_min_interval_ms = health_min_interval_ms;
+        }
+        
+        if let Some(health_max_interval_ms) = cli.health_max_interval_ms {
+            config.health_max_interval_ms = health_max_interval_ms;
+        }
+        
+        if let Some(health_min_score) = cli.health_min_score {
+            config.health_min_score = health_min_score;
+        }
+        
+        if let Some(shadow) = cli.shadow {
+            config.shadow = Some(shadow);
+        }
+        
+        if let Some(shadow_window_secs) = cli.shadow_window_secs {
+            config.shadow_window_secs = shadow_window_secs;
+        }
+        
+        if let Some(kafka_brokers) = cli.kafka_brokers {
+            config.kafka_brokers = Some(kafka_brokers);
+        }
+        
+        if let Some(kafka_topic) = cli.kafka_topic {
+            config.kafka_topic = kafka_topic;
+        }
+        
+        if let Some(schema_registry) = cli.schema_registry {
+            config.schema_registry = Some(schema_registry);
+        }
+        
+        if let Some(seq_guard) = cli.seq_guard {
+            config.seq_guard = seq_guard;
+        }
+        
+        if let Some(catch_up_signatures) = cli.catch_up_signatures {
+            config.catch_up_signatures = catch_up_signatures;
+        }
+        
+        if let Some(fast_sinks) = cli.fast_sinks {
+            config.fast_sinks = parse_topics(&fast_sinks);
+        }
+        
+        if let Some(encrypted_sinks) = cli.encrypted_sinks {
+            config.encrypted_sinks = parse_topics(&encrypted_sinks);
+        }
+        
+        if !cli.sink_workers.is_empty() {
+            config.sink_workers = cli.sink_workers.iter().map(|sink_workers| parse_sink_workers(sink_workers)).collect();
+        }
+        
+        if !cli.feed_key.is_empty() {
+            config.feed_keys = cli.feed_key.iter().map(|feed_key| parse_feed_key(feed_key)).collect();
+        }
+        
+        if !cli.feed_group_market.is_empty() {
+            config.feed_group_markets = cli
+                .feed_group_market
+                .iter()
+                .map(|feed_group_market| parse_feed_group_market(feed_group_market))
+                .collect();
+        }
+        
+        if let Some(stdout_topics) = cli.stdout_topics {
+            config.stdout_topics = parse_topics(&stdout_topics);
+        }
+        
+        if let Some(stdout_filter) = cli.stdout_filter {
+            config.stdout_filter = Some(stdout_filter.parse().unwrap());
+        }
+        
+        if let Some(ws_filter) = cli.ws_filter {
+            config.ws_filter = Some(ws_filter.parse().unwrap());
+        }
+        
+        if !cli.alert.is_empty() {
+            config.alerts = cli.alert.iter().map(|alert| alert.parse().unwrap()).collect();
+        }
+        
+        if let Some(alert_cooldown_secs) = cli.alert_cooldown_secs {
+            config.alert_cooldown_secs = alert_cooldown_secs;
+        }
+        
+        if let Some(control) = cli.control {
+            config.control = Some(control);
+        }
+        
+        if let Some(replay_buffer) = cli.replay_buffer {
+            config.replay_buffer = replay_buffer;
+        }
+        
+        config.consumer_heartbeats = config.consumer_heartbeats || cli.consumer_heartbeats;
+        
+        if let Some(consumer_stale_secs) = cli.consumer_stale_secs {
+            config.consumer_stale_secs = consumer_stale_secs;
+        }
+        
+        if let Some(ws_client_rate) = cli.ws_client_rate {
+            config.ws_client_rate = Some(ws_client_rate);
+        }
+        
+        if let Some(ws_send_timeout_ms) = cli.ws_send_timeout_ms {
+            config.ws_send_timeout_ms = ws_send_timeout_ms;
+        }
+        
+        if let Some(ws_replay_limit) = cli.ws_replay_limit {
+            config.ws_replay_limit = ws_replay_limit;
+        }
+        
+        if let Some(legacy_schema_version) = cli.legacy_schema_version {
+            config.legacy_schema_version = Some(legacy_schema_version);
+        }
+        
+        if let Some(legacy_schema_until) = cli.legacy_schema_until {
+            config.legacy_schema_until = Some(legacy_schema_until);
+        }
+        
+        if let Some(ws_slow_client) = cli.ws_slow_client {
+            config.ws_slow_client = ws_slow_client;
+        }
+        
+        config.connect = cli.connect;
+        config.command = cli.command;
+        config.self_test = cli.self_test;
+        config.profiling = config.profiling || cli.profiling;
+        config.embed_lag = config.embed_lag || cli.embed_lag;
+        config.canonical_json = config.canonical_json || cli.canonical_json;
+        config.bars = config.bars || cli.bars;
+        config.pair_feed = config.pair_feed || cli.pair_feed;
+        config.zmq_topics = config.zmq_topics || cli.zmq_topics;
+        config.intake_runtime = config.intake_runtime || cli.intake_runtime || config.intake_core.is_some() || cli.intake_core.is_some();
+        
+        if let Some(intake_core) = cli.intake_core {
+            config.intake_core = Some(intake_core);
+        }
+        
+        if let Some(publish_core) = cli.publish_core {
+            config.publish_core = Some(publish_core);
+        }
+        
+        if let Some(yield_every) = cli.yield_every {
+            config.yield_every = yield_every;
+        }
+        
+        if let Some(owner_lookups) = cli.owner_lookups {
+            config.owner_lookups = owner_lookups;
+        }
+        
+        if let Some(owner_wait_ms) = cli.owner_wait_ms {
+            config.owner_wait_ms = owner_wait_ms;
+        }
+        
+        if let Some(book_poll_ms) = cli.book_poll_ms {
+            config.book_poll_ms = book_poll_ms;
+        }
+        
+        if let Some(owner_snapshot_interval) = cli.owner_snapshot_interval {
+            config.owner_snapshot_interval = owner_snapshot_interval;
+        }
+        
+        config.owners_from_archive = config.owners_from_archive || cli.owners_from_archive;
+        
+        if let Some(enrichers) = cli.enrichers {
+            config.enrichers = enrichers;
+        }
+        
+        if let Some(labels_file) = cli.labels_file {
+            config.labels_file = Some(labels_file);
+        }
+        
+        if let Some(plugins) = cli.plugins {
+            config.plugins = parse_topics(&plugins);
+        }
+        
+        if !cli.quote_conversion.is_empty() {
+            config.quote_conversions = cli
+                .quote_conversion
+                .iter()
+                .map(|quote_conversion| parse_quote_conversion(quote_conversion))
+                .collect();
+        }
+        
+        if let Some(enricher_timeout_ms) = cli.enricher_timeout_ms {
+            config.enricher_timeout_ms = enricher_timeout_ms;
+        }
+        
+        if !cli.enricher_timeout.is_empty() {
+            config.enricher_timeouts = cli
+                .enricher_timeout
+                .iter()
+                .map(|enricher_timeout| parse_enricher_timeout(enricher_timeout))
+                .collect();
+        }
+        
+        if !cli.enricher_filter.is_empty() {
+            config.enricher_filters = cli
+                .enricher_filter
+                .iter()
+                .map(|enricher_filter| parse_enricher_filter(enricher_filter))
+                .collect();
+        }
+        
+        if let Some(decimals_check) = cli.decimals_check {
+            config.decimals_check = decimals_check;
+        }
+        
+        if let Some(bootstrap_concurrency) = cli.bootstrap_concurrency {
+            config.bootstrap_concurrency = bootstrap_concurrency;
+        }
+        
+        config.prewarm_owners = config.prewarm_owners || cli.prewarm_owners;
+        
+        if let Some(zmq_binary) = cli.zmq_binary {
+            config.zmq_binary = Some(zmq_binary);
+        }
+        
+        if !cli.quote_feed.is_empty() {
+            config.quote_feeds = cli.quote_feed.iter().map(|quote_feed| parse_quote_feed(quote_feed)).collect();
+        }
+        
+        let markets = if !cli.market.is_empty() {
+            cli.market
+        } else {
+            // Parse the market string (from default or environment)
+            market_str
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+        
+        // Convert market strings to Pubkeys
+        config.market_keys = dedup_markets(
+            markets
+                .iter()
+                .filter(|market_key| !market_key.trim().is_empty())
+                .map(|market_key| Pubkey::from_str(market_key.trim()).unwrap()),
+        );
+        
+        // a later override of the same market wins
+        let mut market_commitments: Vec<(Pubkey, Commitment)> = Vec::new();
+        for (market, commitment) in config.market_commitments.drain(..) {
+            if let Some(index) = market_commitments.iter().position(|(key, _)| *key == market) {
+                warn!("commitment of market {} given twice, using {}", market, commitment.as_str());
+                market_commitments.remove(index);
+            }
+            if !config.market_keys.contains(&market) {
+                warn!("commitment given for market {} which is not subscribed", market);
+            }
+            market_commitments.push((market, commitment));
+        }
+        config.market_commitments = market_commitments;
+        
+        config
+    }
+    
+    // Commitment of the configured source, the positional commitment unless overridden for its endpoint
+    pub fn source_commitment(&self) -> Commitment {
+        let commitment = match self.source {
+            SourceKind::Geyser => self.grpc_commitment,
+            SourceKind::Poll => self.rpc_commitment,
+            SourceKind::Helius => self.helius_commitment,
+            SourceKind::Mock => None,
+            // transactions are simulated before they are executed
+            SourceKind::Shredstream => Some(Commitment::Processed),
+        };
+        commitment.unwrap_or(self.commitment)
+    }
+
+    // The override of the market or the source commitment
+    pub fn market_commitment(&self, market: &Pubkey) -> Commitment {
+        self.market_commitments
+            .iter()
+            .find(|(key, _)| key == market)
+            .map_or(self.source_commitment(), |(_, commitment)| *commitment)
+    }
+
+    // The lag objective of the market in ms (the last override given or --lag-slo-ms), None without one
+    pub fn lag_slo(&self, market: &Pubkey) -> Option<u64> {
+        let ms = self
+            .market_lag_slos
+            .iter()
+            .rev()
+            .find(|(key, _)| key == market)
+            .map_or(self.lag_slo_ms, |(_, ms)| *ms);
+        (ms > 0).then_some(ms)
+    }
+
+    // The override of the enricher (the last one given) or --enricher-timeout-ms
+    pub fn enricher_timeout(&self, enricher: EnricherKind) -> Duration {
+        let ms = self
+            .enricher_timeouts
+            .iter()
+            .rev()
+            .find(|(kind, _)| *kind == enricher)
+            .map_or(self.enricher_timeout_ms, |(_, ms)| *ms);
+        Duration::from_millis(ms)
+    }
+
+    // How long archive files are kept, None keeps them forever
+    pub fn archive_retention(&self) -> Option<Duration> {
+        (self.archive_retention_days > 0).then(|| Duration::from_secs(self.archive_retention_days * 86_400))
+    }
+
+    // The older schema version of a migration, also when its overlap is already over
+    pub fn legacy_schema(&self) -> Option<LegacySchema> {
+        self.legacy_schema_version.map(|version| LegacySchema {
+            version,
+            until: self.legacy_schema_until,
+        })
+    }
+
+    // The filter of the enricher (the last one given), None runs it on every trade
+    pub fn enricher_filter(&self, enricher: EnricherKind) -> Option<TradeFilter> {
+        self.enricher_filters
+            .iter()
+            .rev()
+            .find(|(kind, _)| *kind == enricher)
+            .map(|(_, filter)| filter.clone())
+    }
+
+    // Markets grouped by commitment, one subscription per group
+    pub fn commitment_groups(&self, markets: &[Pubkey]) -> Vec<(Commitment, Vec<Pubkey>)> {
+        let mut groups: Vec<(Commitment, Vec<Pubkey>)> = Vec::new();
+        for market in markets {
+            let commitment = self.market_commitment(market);
+            match groups.iter_mut().find(|(group, _)| *group == commitment) {
+                Some((_, keys)) => keys.push(*market),
+                None => groups.push((commitment, vec![*market])),
+            }
+        }
+        groups
+    }
+    
+    // --env-file (ENV_FILE) or --profile (ENV_PROFILE) must exist, without either
+    // only ./.env is tried. Variables already set in the environment are kept.
+    fn load_env_file(cli: &Cli) -> Option<String> {
+        let explicit = cli
+            .env_file
+            .clone()
+            .or_else(|| cli.profile.as_ref().map(|profile| format!(".env.{}", profile)))
+            .or_else(|| std::env::var("ENV_FILE").ok())
+            .or_else(|| std::env::var("ENV_PROFILE").ok().map(|profile| format!(".env.{}", profile)));
+        match explicit {
+            Some(path) => {
+                if let Err(e) = dotenv::from_path(&path) {
+                    panic!("could not load env file {}: {}", path, e);
+                }
+                info!("Loaded env file {}", path);
+                Some(path)
+            }
+            None => match dotenv::from_path(".env") {
+                Ok(_) => {
+                    info!("Loaded env file .env");
+                    Some(".env".to_string())
+                }
+                Err(e) => {
+                    info!("No .env loaded: {}", e);
+                    None
+                }
+            },
+        }
+    }
+}
+
+// Keeps the first of repeated markets, the same key twice would double its
+// subscription filter and every one of its fills
+fn dedup_markets(markets: impl Iterator<Item = Pubkey>) -> Vec<Pubkey> {
+    let mut unique = Vec::new();
+    let mut duplicates = HashSet::new();
+    for market in markets {
+        if !unique.contains(&market) {
+            unique.push(market);
+        } else if duplicates.insert(market) {
+            warn!("market {} is configured more than once, subscribing once", market);
+        }
+    }
+    unique
+}
+
+// "<market>=<commitment>"
+fn parse_market_commitment(market_commitment: &str) -> (Pubkey, Commitment) {
+    let (market, commitment) = market_commitment
+        .split_once('=')
+        .unwrap_or_else(|| panic!("invalid market commitment {}, expected <market>=<commitment>", market_commitment));
+    (
+        Pubkey::from_str(market.trim()).unwrap(),
+        Commitment::from_str(commitment.trim(), true).unwrap(),
+    )
+}
+
+// "<market>=<name> <HH:MM>-<HH:MM> <timezone> [<days>]"
+fn parse_session(session: &str) -> Session {
+    session
+        .parse()
+        .unwrap_or_else(|err| panic!("invalid session {}: {}", session, err))
+}
+
+// "<quote mint>=<zmq address>"
+fn parse_quote_feed(quote_feed: &str) -> (Pubkey, String) {
+    let (mint, addr) = quote_feed
+        .split_once('=')
+        .unwrap_or_else(|| panic!("invalid quote feed {}, expected <quote mint>=<zmq address>", quote_feed));
+    (Pubkey::from_str(mint.trim()).unwrap(), addr.trim().to_string())
+}
+
+// "<market>=<ms>"
+fn parse_market_lag_slo(market_lag_slo: &str) -> (Pubkey, u64) {
+    let (market, ms) = market_lag_slo
+        .split_once('=')
+        .unwrap_or_else(|| panic!("invalid market lag objective {}, expected <market>=<ms>", market_lag_slo));
+    (Pubkey::from_str(market.trim()).unwrap(), ms.trim().parse().unwrap())
+}
+
+// "<enricher>=<ms>"
+fn parse_enricher_timeout(enricher_timeout: &str) -> (EnricherKind, u64) {
+    let (enricher, ms) = enricher_timeout
+        .split_once('=')
+        .unwrap_or_else(|| panic!("invalid enricher timeout {}, expected <enricher>=<ms>", enricher_timeout));
+    (EnricherKind::from_str(enricher.trim(), true).unwrap(), ms.trim().parse().unwrap())
+}
+
+// "<currency>=<conversion>"
+fn parse_quote_conversion(quote_conversion: &str) -> (String, QuoteConversion) {
+    let (currency, conversion) = quote_conversion
+        .split_once('=')
+        .unwrap_or_else(|| panic!("invalid quote conversion {}, expected <currency>=<conversion>", quote_conversion));
+    let conversion = conversion
+        .parse()
+        .unwrap_or_else(|err| panic!("invalid quote conversion of {}: {}", currency.trim(), err));
+    (currency.trim().to_string(), conversion)
+}
+
+// "<enricher>=<filter>", the filter takes the rest of the value
+fn parse_enricher_filter(enricher_filter: &str) -> (EnricherKind, TradeFilter) {
+    let (enricher, filter) = enricher_filter
+        .split_once('=')
+        .unwrap_or_else(|| panic!("invalid enricher filter {}, expected <enricher>=<filter>", enricher_filter));
+    (EnricherKind::from_str(enricher.trim(), true).unwrap(), filter.trim().parse().unwrap())
+}
+
+// "<sink>=<threads>"
+fn parse_sink_workers(sink_workers: &str) -> (String, usize) {
+    let (sink, threads) = sink_workers
+        .split_once('=')
+        .unwrap_or_else(|| panic!("invalid sink workers {}, expected <sink>=<threads>", sink_workers));
+    (sink.trim().to_string(), threads.trim().parse().unwrap())
+}
+
+// "<group>=<key id>:<base64 key>"
+fn parse_feed_group_market(feed_group_market: &str) -> (String, Pubkey) {
+    let (group, market) = feed_group_market
+        .split_once('=')
+        .unwrap_or_else(|| panic!("invalid feed group market {}, expected <group>=<market>", feed_group_market));
+    (group.trim().to_string(), Pubkey::from_str(market.trim()).unwrap())
+}
+
+fn parse_feed_key(feed_key: &str) -> FeedKey {
+    FeedKey::parse(feed_key).unwrap_or_else(|err| panic!("invalid feed key: {}", err))
+}
+
+pub(crate) fn parse_topics(topics: &str) -> Vec<String> {
+    topics
+        .split(',')
+        .map(|topic| topic.trim().to_string())
+        .filter(|topic| !topic.is_empty())
+        .collect()
+}
+
+// "name: value" or "name=value"
+fn parse_header(header: &str) -> (String, String) {
+    let (name, value) = header
+        .split_once(':')
+        .or_else(|| header.split_once('='))
+        .unwrap_or_else(|| panic!("invalid header {}, expected name: value", header));
+    (name.trim().to_lowercase(), value.trim().to_string())
+}
@@ -0,0 +1,137 @@
+use crate::clock::now_ms;
+use crate::metrics::{metrics, Metrics};
+use openbookv2_decoder::ClientHeartbeat;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+// consumers tracked at most, heartbeats of further names are rejected
+const MAX_CONSUMERS: usize = 1000;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MarketLag {
+    feed_seq: u64,
+    published: u64,
+    lag: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConsumerReport {
+    // "ws" or "control"
+    via: &'static str,
+    last_seen_secs: i64,
+    stale: bool,
+    lag: u64,
+    markets: BTreeMap<String, MarketLag>,
+}
+
+struct Consumer {
+    via: &'static str,
+    last_seen_ms: i64,
+    feed_seqs: BTreeMap<String, u64>,
+}
+
+#[derive(Default)]
+struct Registry {
+    // last published feed_seq per market id
+    published: HashMap<String, u64>,
+    consumers: BTreeMap<String, Consumer>,
+}
+
+// Last feedSeq each consumer echoed per market (--consumer-heartbeats), against the last
+// published one, so "missing trades" can be told apart from a consumer falling behind or
+// gone. Shared by the encode stages, the websocket server, the control socket and /consumers.
+#[derive(Clone)]
+pub struct ConsumerRegistry {
+    registry: Arc<Mutex<Registry>>,
+    // seconds without heartbeat after which a consumer shows as stale
+    stale_after: i64,
+}
+
+impl ConsumerRegistry {
+    pub fn new(stale_after: u64) -> Self {
+        ConsumerRegistry {
+            registry: Arc::new(Mutex::new(Registry::default())),
+            stale_after: stale_after as i64,
+        }
+    }
+
+    pub fn published(&self, market_id: &str, feed_seq: u64) {
+        let mut registry = self.registry.lock().unwrap();
+        match registry.published.get_mut(market_id) {
+            Some(published) => *published = (*published).max(feed_seq),
+            None => {
+                registry.published.insert(market_id.to_string(), feed_seq);
+            }
+        }
+    }
+
+    // Records the heartbeat, returns the consumer's lag over all markets
+    pub fn heartbeat(&self, via: &'static str, heartbeat: ClientHeartbeat) -> Result<u64, String> {
+        if heartbeat.consumer.is_empty() {
+            return Err("heartbeat without consumer".to_string());
+        }
+        let mut registry = self.registry.lock().unwrap();
+        if !registry.consumers.contains_key(&heartbeat.consumer) && registry.consumers.len() >= MAX_CONSUMERS {
+            return Err(format!("more than {} consumers", MAX_CONSUMERS));
+        }
+        let consumer = registry.consumers.entry(heartbeat.consumer.clone()).or_insert_with(|| Consumer {
+            via,
+            last_seen_ms: 0,
+            feed_seqs: BTreeMap::new(),
+        });
+        consumer.via = via;
+        consumer.last_seen_ms = now_ms();
+        consumer.feed_seqs.extend(heartbeat.feed_seqs);
+        let lag = Self::markets(&registry.published, &registry.consumers[&heartbeat.consumer])
+            .values()
+            .map(|market| market.lag)
+            .sum();
+        metrics()
+            .consumer_lag
+            .get_or_create(&Metrics::consumer(&heartbeat.consumer))
+            .set(lag as i64);
+        Ok(lag)
+    }
+
+    fn markets(published: &HashMap<String, u64>, consumer: &Consumer) -> BTreeMap<String, MarketLag> {
+        consumer
+            .feed_seqs
+            .iter()
+            .map(|(market_id, feed_seq)| {
+                let published = published.get(market_id).copied().unwrap_or(0);
+                let lag = MarketLag {
+                    feed_seq: *feed_seq,
+                    published,
+                    lag: published.saturating_sub(*feed_seq),
+                };
+                (market_id.clone(), lag)
+            })
+            .collect()
+    }
+
+    // Every consumer with its lag per market, for GET /consumers
+    pub fn report(&self) -> serde_json::Value {
+        let registry = self.registry.lock().unwrap();
+        let now = now_ms();
+        let consumers: BTreeMap<&String, ConsumerReport> = registry
+            .consumers
+            .iter()
+            .map(|(name, consumer)| {
+                let markets = Self::markets(&registry.published, consumer);
+                let last_seen_secs = (now - consumer.last_seen_ms) / 1000;
+                let report = ConsumerReport {
+                    via: consumer.via,
+                    last_seen_secs,
+                    stale: last_seen_secs >= self.stale_after,
+                    lag: markets.values().map(|market| market.lag).sum(),
+                    markets,
+                };
+                (name, report)
+            })
+            .collect();
+        serde_json::json!({ "consumers": consumers })
+    }
+}
@@ -0,0 +1,116 @@
+use crate::metrics::{metrics, MarketLabels, Metrics};
+use crate::sink::HealthRegistry;
+use crate::state;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::interval;
+
+// The counters of --persist-metrics as of the last save, per market or sink name
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct SavedCounters {
+    #[serde(default)]
+    trades_published: BTreeMap<String, u64>,
+    #[serde(default)]
+    quarantined_trades: BTreeMap<String, u64>,
+    #[serde(default)]
+    seq_regressions: BTreeMap<String, u64>,
+    #[serde(default)]
+    sink_failures: BTreeMap<String, u64>,
+    #[serde(default)]
+    dropped_fills: u64,
+    #[serde(default)]
+    redelivered_fills: u64,
+}
+
+// Carries the counters dashboards and SLA reports take rate() and increase() of over
+// restarts: loaded into the metrics before the first fill and saved to metrics.json in the
+// state dir every interval and once the pipeline drained. A crash loses at most one
+// interval, which shows as a small dip instead of a reset.
+#[derive(Clone)]
+pub struct CounterStore {
+    path: PathBuf,
+    // label values to save, the watched markets and every name restored, so markets
+    // unsubscribed for a while keep their totals
+    markets: BTreeSet<String>,
+    sinks: BTreeSet<String>,
+    health: HealthRegistry,
+}
+
+impl CounterStore {
+    pub fn restore(path: PathBuf, markets: impl IntoIterator<Item = String>, health: HealthRegistry) -> Self {
+        let saved: SavedCounters = state::load(&path);
+        let metrics = metrics();
+        for (market, value) in saved.trades_published.iter() {
+            metrics.trades_published.get_or_create(&Metrics::market(market)).inc_by(*value);
+        }
+        for (market, value) in saved.quarantined_trades.iter() {
+            metrics.quarantined_trades.get_or_create(&Metrics::market(market)).inc_by(*value);
+        }
+        for (market, value) in saved.seq_regressions.iter() {
+            metrics.seq_regressions.get_or_create(&Metrics::market(market)).inc_by(*value);
+        }
+        for (sink, value) in saved.sink_failures.iter() {
+            metrics.sink_failures.get_or_create(&Metrics::sink(sink)).inc_by(*value);
+        }
+        metrics.dropped_fills.inc_by(saved.dropped_fills);
+        metrics.redelivered_fills.inc_by(saved.redelivered_fills);
+        let mut markets: BTreeSet<String> = markets.into_iter().collect();
+        markets.extend(saved.trades_published.into_keys());
+        markets.extend(saved.quarantined_trades.into_keys());
+        markets.extend(saved.seq_regressions.into_keys());
+        CounterStore {
+            path,
+            markets,
+            sinks: saved.sink_failures.into_keys().collect(),
+            health,
+        }
+    }
+
+    pub fn save(&self) {
+        let metrics = metrics();
+        let per_market = |family: &Family<MarketLabels, Counter>| -> BTreeMap<String, u64> {
+            self.markets
+                .iter()
+                .map(|market| (market.clone(), family.get_or_create(&Metrics::market(market)).get()))
+                .filter(|(_, value)| *value > 0)
+                .collect()
+        };
+        let mut sinks = self.sinks.clone();
+        sinks.extend(self.health.lock().unwrap().keys().cloned());
+        let saved = SavedCounters {
+            trades_published: per_market(&metrics.trades_published),
+            quarantined_trades: per_market(&metrics.quarantined_trades),
+            seq_regressions: per_market(&metrics.seq_regressions),
+            sink_failures: sinks
+                .into_iter()
+                .map(|sink| {
+                    let value = metrics.sink_failures.get_or_create(&Metrics::sink(&sink)).get();
+                    (sink, value)
+                })
+                .filter(|(_, value)| *value > 0)
+                .collect(),
+            dropped_fills: metrics.dropped_fills.get(),
+            redelivered_fills: metrics.redelivered_fills.get(),
+        };
+        state::save(&self.path, &saved);
+    }
+
+    // Saves every `every` until the sources stop, the last save follows the drained pipeline
+    pub async fn run(self, every: Duration, mut stopped: watch::Receiver<bool>) {
+        let mut ticker = interval(every);
+        // the first tick is immediate and would save what was just restored
+        ticker.tick().await;
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => self.save(),
+                _ = stopped.changed() => break,
+            }
+        }
+    }
+}
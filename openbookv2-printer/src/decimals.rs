@@ -0,0 +1,41 @@
+use crate::bootstrap;
+use openbookv2_decoder::parse_name;
+use openbookv2_generated::state::Market;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_program::pubkey::Pubkey;
+use std::collections::BTreeMap;
+
+// decimals byte of an spl token (and token-2022) mint: mint authority option (36), supply (8)
+const MINT_DECIMALS_OFFSET: usize = 44;
+
+// Compares the decimals stored in every market, which all price and size conversions
+// use, with the decimals of its mints. Returns one line per mismatch.
+pub async fn verify(
+    client: &RpcClient,
+    markets: &BTreeMap<Pubkey, Market>,
+    concurrency: usize,
+) -> Result<Vec<String>, String> {
+    let mut checks = Vec::new();
+    for (key, market) in markets.iter() {
+        let name = parse_name(&market.name).replace('\0', "");
+        checks.push((*key, name.clone(), "base", market.base_mint, market.base_decimals));
+        checks.push((*key, name, "quote", market.quote_mint, market.quote_decimals));
+    }
+    let mints: Vec<Pubkey> = checks.iter().map(|(_, _, _, mint, _)| *mint).collect();
+    let accounts = bootstrap::fetch_accounts(client, &mints, concurrency, "mints")
+        .await
+        .map_err(|err| err.to_string())?;
+    let mut mismatches = Vec::new();
+    for ((market, name, side, mint, decimals), account) in checks.into_iter().zip(accounts) {
+        let mint_decimals = account.and_then(|account| account.data.get(MINT_DECIMALS_OFFSET).copied());
+        match mint_decimals {
+            Some(mint_decimals) if mint_decimals == decimals => {}
+            Some(mint_decimals) => mismatches.push(format!(
+                "market {} ({}) has {} decimals {} but mint {} has {}",
+                name, market, side, decimals, mint, mint_decimals
+            )),
+            None => mismatches.push(format!("{} mint {} of market {} ({}) not found", side, mint, name, market)),
+        }
+    }
+    Ok(mismatches)
+}
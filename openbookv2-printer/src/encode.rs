@@ -0,0 +1,414 @@
+use crate::alert_state::AlertState;
+use crate::alloc_audit;
+use crate::bars::BarAggregator;
+use crate::checksums::ChecksumTracker;
+use crate::clock::now_ms;
+use crate::consumers::ConsumerRegistry;
+use crate::expr::Expr;
+use crate::features::{Feature, FeatureFlags};
+use crate::fees::FeeTracker;
+use crate::fill_rate::FillRate;
+use crate::inventory::InventoryTracker;
+use crate::legacy_schema::LegacySchema;
+use crate::lifecycle::MarketLifecycle;
+use crate::metrics::{metrics, Metrics};
+use crate::pairs::PairFeed;
+use crate::pipeline::{Enriched, Outgoing, StageReceiver, StageSender, Yielder};
+use crate::replay::ReplayBuffer;
+use crate::sessions::SessionTracker;
+use crate::sink::{
+    bar_topic, market_topic, TOPIC_CHECKSUMS, TOPIC_CONTROL, TOPIC_FEES, TOPIC_PAIRS, TOPIC_QUARANTINE, TOPIC_SESSIONS,
+    TOPIC_TRADES,
+};
+use crate::slo::LagSlo;
+use crate::volume::VolumeTracker;
+use log::{info, warn};
+use openbookv2_decoder::canonical::{canonicalize, to_canonical_string};
+use openbookv2_decoder::{ControlMessage, Trade};
+use serde::Serialize;
+use solana_program::pubkey::Pubkey;
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::interval;
+
+// Keeps the per market aggregates (volumes, bars, inventory, fees, sessions, fill rates, pairs),
+// evaluates alerts and serializes everything for the sinks.
+pub struct EncodeStage {
+    pub market_names: BTreeMap<Pubkey, String>,
+    pub volumes: VolumeTracker,
+    pub volume_path: PathBuf,
+    // seconds between saves of the volumes
+    pub state_interval: u64,
+    pub ticker_interval: u64,
+    pub fill_rate: FillRate,
+    pub bars: Option<BarAggregator>,
+    pub inventory: InventoryTracker,
+    pub inventory_path: PathBuf,
+    pub inventory_interval: u64,
+    pub checksums: ChecksumTracker,
+    pub checksums_path: PathBuf,
+    pub checksum_interval: u64,
+    pub fees: FeeTracker,
+    pub fees_path: PathBuf,
+    pub fees_interval: u64,
+    pub sessions: SessionTracker,
+    pub sessions_path: PathBuf,
+    pub pair_feed: Option<PairFeed>,
+    pub replay_buffer: Option<Arc<Mutex<ReplayBuffer>>>,
+    pub alerts: Vec<Expr>,
+    pub alert_state: AlertState,
+    pub alert_state_path: PathBuf,
+    pub embed_lag: bool,
+    // trades also published in this older schema version during a migration
+    pub legacy_schema: Option<LegacySchema>,
+    // sorted keys and fixed number formatting (--canonical-json)
+    pub canonical: bool,
+    // bars and alerts are skipped while switched off at runtime
+    pub features: FeatureFlags,
+    // last published feed_seq per market for the consumer heartbeats
+    pub consumers: Option<ConsumerRegistry>,
+    // market ids of --quarantine, their trades only go to the quarantine topic
+    pub quarantined: HashSet<String>,
+    // market ids of --priority-market, everything carrying their trades takes the priority lane
+    pub priority: HashSet<String>,
+    pub lag_slo: LagSlo,
+    pub yield_every: usize,
+}
+
+// Every payload of the feed, in canonical form with --canonical-json
+pub fn to_json<T: Serialize>(value: &T, canonical: bool) -> String {
+    if canonical {
+        to_canonical_string(value).unwrap()
+    } else {
+        serde_json::to_string(value).unwrap()
+    }
+}
+
+fn control(message: &ControlMessage, canonical: bool) -> Outgoing {
+    Outgoing {
+        topic: TOPIC_CONTROL.to_string(),
+        payload: to_json(message, canonical),
+        trade: None,
+        fast: false,
+    }
+}
+
+fn checksum(message: &ControlMessage, canonical: bool) -> Outgoing {
+    let market_name = match message {
+        ControlMessage::Checksum { market_name, .. } => market_name.as_str(),
+        _ => "",
+    };
+    Outgoing {
+        topic: market_topic(TOPIC_CHECKSUMS, market_name),
+        payload: to_json(message, canonical),
+        trade: None,
+        fast: false,
+    }
+}
+
+fn fees(message: &ControlMessage, canonical: bool) -> Outgoing {
+    let market_name = match message {
+        ControlMessage::Fees { market_name, .. } => market_name.as_str(),
+        _ => "",
+    };
+    Outgoing {
+        topic: market_topic(TOPIC_FEES, market_name),
+        payload: to_json(message, canonical),
+        trade: None,
+        fast: false,
+    }
+}
+
+fn session(message: &ControlMessage, canonical: bool) -> Outgoing {
+    let market_name = match message {
+        ControlMessage::SessionOpen { market_name, .. } | ControlMessage::SessionClose { market_name, .. } => {
+            market_name.as_str()
+        }
+        _ => "",
+    };
+    Outgoing {
+        topic: market_topic(TOPIC_SESSIONS, market_name),
+        payload: to_json(message, canonical),
+        trade: None,
+        fast: false,
+    }
+}
+
+impl EncodeStage {
+    pub async fn run(mut self, mut input: StageReceiver<Enriched>, output: StageSender<Outgoing>) {
+        let tick_secs = if self.ticker_interval == 0 { 10 } else { self.ticker_interval };
+        let mut ticker = interval(Duration::from_secs(tick_secs));
+        let mut bar_ticker = interval(Duration::from_secs(1));
+        let mut state_ticker = interval(Duration::from_secs(self.state_interval.max(1)));
+        let mut inventory_ticker = interval(Duration::from_secs(self.inventory_interval.max(1)));
+        let mut checksum_ticker = interval(Duration::from_secs(self.checksum_interval.max(1)));
+        let mut fees_ticker = interval(Duration::from_secs(self.fees_interval.max(1)));
+        let mut session_ticker = interval(Duration::from_secs(1));
+        // the first tick is immediate and would publish an empty period
+        inventory_ticker.tick().await;
+        checksum_ticker.tick().await;
+        let mut yielder = Yielder::new(self.yield_every);
+        loop {
+            let outgoing = tokio::select! {
+                enriched = input.recv() => match enriched {
+                    Some(Enriched::Trade { trade, block_time_ms, received_ms }) => {
+                        alloc_audit::measure("encode", || self.trade(trade, block_time_ms, received_ms))
+                    }
+                    Some(Enriched::Control { topic, message }) => {
+                        if let ControlMessage::MarketClosed { market_id, reason, .. } = &message {
+                            if reason != MarketLifecycle::Idle.as_str() {
+                                self.market_names.retain(|market, _| market.to_string() != *market_id);
+                            }
+                        }
+                        vec![Outgoing {
+                            topic,
+                            payload: to_json(&message, self.canonical),
+                            trade: None,
+                            fast: false,
+                        }]
+                    }
+                    None => break,
+                },
+                _ = ticker.tick() => self.ticker(),
+                _ = bar_ticker.tick(), if self.bars.is_some() => {
+                    let bars = self.bars.as_mut().unwrap().flush(now_ms() / 1000);
+                    bars.iter()
+                        .map(|bar| Outgoing {
+                            topic: bar_topic(bar),
+                            payload: to_json(bar, self.canonical),
+                            trade: None,
+                            fast: false,
+                        })
+                        .collect()
+                }
+                _ = state_ticker.tick() => {
+                    self.volumes.save(&self.volume_path);
+                    if self.fees_interval > 0 {
+                        self.fees.save(&self.fees_path);
+                    }
+                    if !self.sessions.is_empty() {
+                        self.sessions.save(&self.sessions_path);
+                    }
+                    Vec::new()
+                }
+                _ = fees_ticker.tick(), if self.fees_interval > 0 => {
+                    let summaries = self.fees.summaries(now_ms() / 1000);
+                    summaries.iter().map(|summary| fees(summary, self.canonical)).collect()
+                }
+                _ = session_ticker.tick(), if !self.sessions.is_empty() => {
+                    let markets = self
+                        .market_names
+                        .iter()
+                        .map(|(market, market_name)| (market.to_string(), market_name.as_str()))
+                        .filter(|(market_id, _)| !self.quarantined.contains(market_id));
+                    let messages = self.sessions.tick(now_ms() / 1000, markets);
+                    messages.iter().map(|message| session(message, self.canonical)).collect()
+                }
+                _ = inventory_ticker.tick(), if self.inventory_interval > 0 => {
+                    let summaries = self.inventory.summaries(now_ms() / 1000);
+                    self.inventory.save(&self.inventory_path);
+                    summaries.iter().map(|summary| control(summary, self.canonical)).collect()
+                }
+                _ = checksum_ticker.tick(), if self.checksum_interval > 0 => {
+                    let manifests = self.checksums.flush();
+                    self.checksums.save(&self.checksums_path);
+                    manifests.iter().map(|manifest| checksum(manifest, self.canonical)).collect()
+                }
+            };
+            for item in outgoing {
+                let priority = item.trade.as_ref().is_some_and(|trade| self.priority.contains(&trade.market_id));
+                if !output.send_to(item, priority).await {
+                    warn!("publish stage is gone, stopping the encode stage");
+                    return;
+                }
+            }
+            yielder.tick().await;
+        }
+        // the trades since the last save
+        self.volumes.save(&self.volume_path);
+        self.inventory.save(&self.inventory_path);
+        if self.fees_interval > 0 {
+            self.fees.save(&self.fees_path);
+        }
+        // open sessions continue after a restart
+        if !self.sessions.is_empty() {
+            self.sessions.save(&self.sessions_path);
+        }
+        // the last period is cut short rather than lost, its chain is saved
+        if self.checksum_interval > 0 {
+            for manifest in self.checksums.flush() {
+                output.send(checksum(&manifest, self.canonical)).await;
+            }
+            self.checksums.save(&self.checksums_path);
+        }
+    }
+
+    fn fire(&mut self, key: &str) -> bool {
+        let fired = self.alert_state.fire(key, now_ms() / 1000, &self.alert_state_path);
+        if !fired {
+            metrics().alerts_suppressed.inc();
+        }
+        fired
+    }
+
+    fn ticker(&mut self) -> Vec<Outgoing> {
+        let mut outgoing = Vec::new();
+        if self.ticker_interval > 0 {
+            let now = now_ms() / 1000;
+            for (market, market_name) in self.market_names.iter() {
+                let market_id = market.to_string();
+                if self.quarantined.contains(&market_id) {
+                    continue;
+                }
+                let snapshot = self.volumes.snapshot(&market_id, now);
+                let message = ControlMessage::Ticker {
+                    market_id,
+                    market_name: market_name.replace('\0', ""),
+                    last_price: snapshot.last_price,
+                    cum_volume_24h: snapshot.volume_24h,
+                    cum_volume_day: snapshot.volume_day,
+                };
+                outgoing.push(control(&message, self.canonical));
+            }
+        }
+        self.fill_rate.refresh(now_ms() / 1000);
+        outgoing
+    }
+
+    fn trade(&mut self, mut trade: Trade, block_time_ms: Option<i64>, received_ms: i64) -> Vec<Outgoing> {
+        // no aggregates, alerts or replay for quarantined markets
+        if self.quarantined.contains(&trade.market_id) {
+            trade.quarantined = true;
+            metrics()
+                .quarantined_trades
+                .get_or_create(&Metrics::market(&trade.market_name))
+                .inc();
+            return vec![Outgoing {
+                topic: market_topic(TOPIC_QUARANTINE, &trade.market_name),
+                payload: to_json(&trade, self.canonical),
+                trade: Some(trade),
+                fast: false,
+            }];
+        }
+        let mut outgoing = Vec::new();
+        self.volumes.record(&trade);
+        self.fill_rate.record(&trade.market_name, now_ms() / 1000);
+        metrics()
+            .trade_notional
+            .get_or_create(&Metrics::market(&trade.market_name))
+            .observe(trade.price_double * trade.quantity_double);
+        let lag_ms = block_time_ms.map(|block_time_ms| now_ms() - block_time_ms);
+        if let Some(lag_ms) = lag_ms {
+            metrics()
+                .publish_latency_seconds
+                .get_or_create(&Metrics::market(&trade.market_name))
+                .observe(lag_ms.max(0) as f64 / 1000.0, Some(Metrics::signature(&trade.signature)));
+        }
+        if self.embed_lag {
+            trade.lag_ms = lag_ms;
+            trade.received_lag_ms = block_time_ms.map(|block_time_ms| received_ms - block_time_ms);
+        }
+        if let Some(slo) = lag_ms.and_then(|lag_ms| self.lag_slo.record(&trade, lag_ms)) {
+            // clearing always goes out, breaches only once per cool-down
+            let breached = matches!(slo, ControlMessage::LagSlo { breached: true, .. });
+            if !breached || self.fire(&format!("lag_slo|{}", trade.market_id)) {
+                outgoing.push(control(&slo, self.canonical));
+            }
+        }
+        if self.inventory_interval > 0 {
+            self.inventory.record(&trade);
+        }
+        if self.fees_interval > 0 {
+            self.fees.record(&trade);
+        }
+        if !self.sessions.is_empty() {
+            let messages = self.sessions.record(&trade);
+            outgoing.extend(messages.iter().map(|message| session(message, self.canonical)));
+        }
+        if let Some(bars) = self.bars.as_mut().filter(|_| self.features.enabled(Feature::Bars)) {
+            if let Some(bar) = bars.record(&trade) {
+                outgoing.push(Outgoing {
+                    topic: bar_topic(&bar),
+                    payload: to_json(&bar, self.canonical),
+                    trade: None,
+                    fast: false,
+                });
+            }
+        }
+        let t = to_json(&trade, self.canonical);
+        info!("{:?}, signature: {}", t, trade.signature);
+        if self.checksum_interval > 0 {
+            self.checksums.record(&trade, &t);
+        }
+        let topic = market_topic(TOPIC_TRADES, &trade.market_name);
+        // the current version first, consumers which get both keep the first of a feed_seq
+        outgoing.push(Outgoing {
+            topic: topic.clone(),
+            payload: t,
+            trade: Some(trade.clone()),
+            fast: false,
+        });
+        if let Some(legacy) = self.legacy_schema {
+            if legacy.active((now_ms() / 1000) as u64) {
+                match legacy.payload(&trade) {
+                    Some(payload) => outgoing.push(Outgoing {
+                        topic: legacy.topic(&topic),
+                        payload: match self.canonical {
+                            true => canonicalize(&payload).unwrap(),
+                            false => payload,
+                        },
+                        trade: Some(trade.clone()),
+                        fast: false,
+                    }),
+                    None => warn!("trade {} has no schema version {} payload", trade.trade_id, legacy.version),
+                }
+            } else {
+                info!("overlap of schema version {} ended, publishing only the current version", legacy.version);
+                self.legacy_schema = None;
+            }
+        }
+        if let Some(pair_feed) = self.pair_feed.as_mut() {
+            if let Some(tagged) = pair_feed.tag(&trade) {
+                outgoing.push(Outgoing {
+                    topic: market_topic(TOPIC_PAIRS, tagged.pair.as_deref().unwrap_or_default()),
+                    payload: to_json(&tagged, self.canonical),
+                    trade: Some(tagged),
+                    fast: false,
+                });
+            }
+        }
+        if let Some(replay_buffer) = self.replay_buffer.as_ref() {
+            replay_buffer.lock().unwrap().push(&trade);
+        }
+        if let Some(consumers) = self.consumers.as_ref() {
+            consumers.published(&trade.market_id, trade.feed_seq);
+        }
+        let alerting = self.features.enabled(Feature::Alerts);
+        let rules: Vec<String> = self
+            .alerts
+            .iter()
+            .filter(|rule| alerting && rule.matches(&trade))
+            .map(|rule| rule.to_string())
+            .collect();
+        for rule in rules {
+            if !self.fire(&format!("{}|{}", rule, trade.market_id)) {
+                continue;
+            }
+            let alert = ControlMessage::Alert {
+                rule,
+                market_id: trade.market_id.clone(),
+                market_name: trade.market_name.clone(),
+                trade_id: trade.trade_id.clone(),
+                signature: trade.signature.clone(),
+                price: trade.price_double,
+                size: trade.quantity_double,
+            };
+            let a = control(&alert, self.canonical);
+            warn!("{}", a.payload);
+            outgoing.push(a);
+        }
+        outgoing
+    }
+}
@@ -0,0 +1,677 @@
+use crate::catch_up::{self, CaughtUp};
+use crate::config::SeqGuard;
+use crate::enricher::{EnricherChain, Fill};
+use crate::encode::to_json;
+use crate::error::{DecodeError, Error};
+use crate::features::{Feature, FeatureFlags};
+use crate::intake::{RecentFills, Update};
+use crate::market_info::MarketInfoTracker;
+use crate::metrics::{metrics, Metrics};
+use crate::oracle::Oracles;
+use crate::pipeline::{Enriched, Outgoing, StageReceiver, StageSender, Yielder};
+use crate::sequences::SequenceGuard;
+use crate::sink::{market_topic, TOPIC_CONTROL, TOPIC_DIAGNOSTICS, TOPIC_PRICES, TOPIC_TRADES};
+use crate::upgrades::UpgradeGuard;
+use crate::utils::get_owner_account_for_ooa;
+use log::{debug, info, warn};
+use openbookv2_decoder::{ControlMessage, LogFormat, MarketParams, Trade, TradeIdScheme};
+use openbookv2_generated::state::Market;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_program::pubkey::Pubkey;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::interval;
+
+// Turns decoded updates into trades and control messages. Owns everything that
+// needs RPC: market parameters, oracle prices and the enrichers (owner resolution).
+pub struct EnrichStage {
+    pub client: Arc<RpcClient>,
+    pub markets: BTreeMap<Pubkey, Market>,
+    pub market_names: BTreeMap<Pubkey, String>,
+    pub market_info: MarketInfoTracker,
+    pub market_info_path: PathBuf,
+    pub market_info_interval: u64,
+    pub oracles: Oracles,
+    pub oracle_interval: u64,
+    // oracle polls are skipped while switched off at runtime
+    pub features: FeatureFlags,
+    pub trade_id: TradeIdScheme,
+    // commitment of the subscription of every market, tagged on its trades
+    pub commitments: BTreeMap<Pubkey, &'static str>,
+    pub yield_every: usize,
+    // --enrichers, applied to every trade
+    pub enrichers: EnricherChain,
+    // last published seq_num per market, checked as --seq-guard says
+    pub sequences: SequenceGuard,
+    pub sequences_path: PathBuf,
+    pub seq_guard: SeqGuard,
+    pub state_interval: u64,
+    // the publish queue, with --fast-sinks trades are teed into it before the enrichers
+    pub fast: Option<StageSender<Outgoing>>,
+    // --quarantine markets, their trades skip the fast path
+    pub quarantined: HashSet<Pubkey>,
+    // fast trades in canonical form like the encode stage's (--canonical-json)
+    pub canonical: bool,
+    // market ids of --priority-market, their trades take the priority lane
+    pub priority: HashSet<String>,
+    // --catch-up-signatures, 0 disables
+    pub catch_up: usize,
+    pub log_format: LogFormat,
+    // markets with an incompatible layout after an OpenBook upgrade, their fills are skipped
+    pub upgrades: UpgradeGuard,
+    // start of the process in unix ms, tagged on every trade as feed_epoch
+    pub feed_epoch: u64,
+    // referrer rebates published recently, every subscription of the shard pushes them
+    pub rebates: RecentFills,
+    // market of the OpenOrders accounts which settled with a referrer
+    pub rebate_markets: HashMap<Pubkey, Pubkey>,
+}
+
+type CatchUpResult = (Pubkey, Result<CaughtUp, String>);
+
+// The market of an update which has to stay in order with the fills of the market
+fn update_market(update: &Update) -> Option<Pubkey> {
+    match update {
+        Update::Fill { fill_log, .. } => Some(fill_log.market),
+        Update::DataLoss { market, .. } | Update::MarketClosed { market, .. } => Some(*market),
+        Update::ReferrerRebate { .. } | Update::CommitmentDowngrade { .. } => None,
+    }
+}
+
+// Moves the feed_seqs of live updates behind the fills caught up for their market
+fn shift(mut update: Update, offsets: &HashMap<Pubkey, i64>) -> Update {
+    match &mut update {
+        Update::Fill { fill_log, feed_seq, .. } => {
+            *feed_seq = feed_seq.saturating_add_signed(offsets.get(&fill_log.market).copied().unwrap_or(0));
+        }
+        Update::DataLoss {
+            market,
+            from_seq,
+            to_seq,
+        } => {
+            let offset = offsets.get(market).copied().unwrap_or(0);
+            *from_seq = from_seq.saturating_add_signed(offset);
+            *to_seq = to_seq.saturating_add_signed(offset);
+        }
+        _ => {}
+    }
+    update
+}
+
+fn feed_seq(update: &Update) -> u64 {
+    match update {
+        Update::Fill { feed_seq, .. } => *feed_seq,
+        _ => 0,
+    }
+}
+
+// Merges the caught up fills of a market (oldest first, without feed_seq) with its live
+// updates held meanwhile. The caught up fills older than the first live fill are numbered
+// from feed_seq 1 on, the newer ones came in live as well. The first live fill continues
+// after them: live fills dropped by the intake queue before it are among the caught up
+// ones, so their data_loss is left out, later ones keep theirs. Returns the updates in
+// publishing order, the number of caught up fills among them and the feed_seq offset of the
+// live updates to come.
+fn merge_catch_up(caught_up: Vec<Update>, live: Vec<Update>) -> (Vec<Update>, usize, i64) {
+    // the lowest seq_num, the sources may deliver fills out of order, and the first feed_seq
+    let first_live = live
+        .iter()
+        .filter(|update| matches!(update, Update::Fill { .. }))
+        .map(|update| (catch_up::seq_num(update), feed_seq(update)))
+        .reduce(|(seq_num, first), (other, _)| (seq_num.min(other), first));
+    let mut merged: Vec<Update> = caught_up
+        .into_iter()
+        .filter(|update| first_live.map_or(true, |(seq_num, _)| catch_up::seq_num(update) < seq_num))
+        .collect();
+    for (index, update) in merged.iter_mut().enumerate() {
+        if let Update::Fill { feed_seq, .. } = update {
+            *feed_seq = index as u64 + 1;
+        }
+    }
+    let caught_up = merged.len();
+    let offset = match first_live {
+        Some((_, feed_seq)) => caught_up as i64 - (feed_seq as i64 - 1),
+        None => caught_up as i64,
+    };
+    let offsets = live.first().and_then(update_market).map(|market| HashMap::from([(market, offset)]));
+    for update in live {
+        if let (Update::DataLoss { to_seq, .. }, Some((_, feed_seq))) = (&update, first_live) {
+            if *to_seq < feed_seq {
+                continue;
+            }
+        }
+        merged.push(match offsets.as_ref() {
+            Some(offsets) => shift(update, offsets),
+            None => update,
+        });
+    }
+    (merged, caught_up, offset)
+}
+
+fn control(message: ControlMessage) -> Enriched {
+    let topic = match &message {
+        ControlMessage::ResolutionFailed { .. }
+        | ControlMessage::CommitmentDowngrade { .. }
+        | ControlMessage::ProgramUpgrade { .. } => TOPIC_DIAGNOSTICS,
+        _ => TOPIC_CONTROL,
+    };
+    Enriched::Control {
+        topic: topic.to_string(),
+        message,
+    }
+}
+
+impl EnrichStage {
+    pub async fn run(mut self, mut updates: StageReceiver<Update>, output: StageSender<Enriched>) {
+        let info_secs = if self.market_info_interval == 0 { 60 } else { self.market_info_interval };
+        let mut info_ticker = interval(Duration::from_secs(info_secs));
+        let mut info_published = false;
+        if self.oracle_interval > 0 && self.oracles.is_empty() {
+            warn!("--oracle-interval is set but neither the markets nor --quote-conversion have an oracle");
+        }
+        let mut oracle_ticker = interval(Duration::from_secs(self.oracle_interval.max(1)));
+        let mut state_ticker = interval(Duration::from_secs(self.state_interval.max(1)));
+        let mut enrichers = std::mem::take(&mut self.enrichers);
+        info!("enrichers: {}", enrichers.names().join(" -> "));
+        let mut yielder = Yielder::new(self.yield_every);
+        // live updates of the markets still catching up, and the feed_seq offset of the
+        // markets which did
+        let (catch_up_sender, mut caught_up) = mpsc::unbounded_channel();
+        let mut held = self.start_catch_up(catch_up_sender);
+        let mut offsets = HashMap::new();
+        let mut upgraded = self.upgrades.upgrades();
+        loop {
+            let enriched = tokio::select! {
+                update = updates.recv() => match update {
+                    Some(update) => match update_market(&update).and_then(|market| held.get_mut(&market)) {
+                        Some(live) => {
+                            live.push(update);
+                            Vec::new()
+                        }
+                        None => self.enrich(shift(update, &offsets), &mut enrichers).await,
+                    },
+                    None => break,
+                },
+                Some((market, result)) = caught_up.recv(), if !held.is_empty() => {
+                    let live = held.remove(&market).unwrap_or_default();
+                    let fills = self.missed_fills(market, result);
+                    let total = fills.len();
+                    let (merged, caught_up, offset) = merge_catch_up(fills, live);
+                    self.caught_up(market, caught_up, total);
+                    offsets.insert(market, offset);
+                    let mut enriched = Vec::new();
+                    for update in merged {
+                        enriched.extend(self.enrich(update, &mut enrichers).await);
+                    }
+                    enriched
+                }
+                finished = enrichers.next(), if !enrichers.idle() => finished.into_iter().map(control).collect(),
+                _ = oracle_ticker.tick(), if self.oracle_interval > 0 && !self.oracles.is_empty() => {
+                    if !self.features.enabled(Feature::Oracles) {
+                        continue;
+                    }
+                    self.oracles
+                        .prices(&self.client, &self.market_names)
+                        .await
+                        .into_iter()
+                        .map(|price| {
+                            let topic = match &price {
+                                ControlMessage::Price { market_name, .. } => market_topic(TOPIC_PRICES, market_name),
+                                ControlMessage::ConversionPrice { currency, .. } => {
+                                    market_topic(TOPIC_PRICES, &format!("{}-USD", currency))
+                                }
+                                _ => TOPIC_PRICES.to_string(),
+                            };
+                            Enriched::Control { topic, message: price }
+                        })
+                        .collect()
+                }
+                _ = state_ticker.tick(), if self.seq_guard != SeqGuard::Off => {
+                    self.sequences.save(&self.sequences_path);
+                    Vec::new()
+                }
+                // parameters and versions of the upgraded program, without waiting for the ticker
+                Ok(()) = upgraded.changed() => self.market_info().await,
+                _ = info_ticker.tick() => {
+                    if self.market_info_interval > 0 || !info_published {
+                        info_published = true;
+                        self.market_info().await
+                    } else {
+                        Vec::new()
+                    }
+                }
+            };
+            for item in enriched {
+                if let Enriched::Control { message, .. } = &item {
+                    enrichers.observe(message);
+                }
+                let priority = matches!(&item, Enriched::Trade { trade, .. } if self.priority.contains(&trade.market_id));
+                if !output.send_to(item, priority).await {
+                    warn!("encode stage is gone, stopping the enrich stage");
+                    break;
+                }
+            }
+            yielder.tick().await;
+        }
+        if self.seq_guard != SeqGuard::Off {
+            self.sequences.save(&self.sequences_path);
+        }
+    }
+
+    // Looks for the fills published neither before the restart nor by the live stream, for
+    // every market with a saved seq_num. Returns the markets whose live updates have to wait.
+    fn start_catch_up(&self, sender: mpsc::UnboundedSender<CatchUpResult>) -> HashMap<Pubkey, Vec<Update>> {
+        let mut held = HashMap::new();
+        if self.catch_up == 0 {
+            return held;
+        }
+        if self.seq_guard == SeqGuard::Off {
+            warn!("--catch-up-signatures has no effect with --seq-guard off, no seq_nums are saved");
+            return held;
+        }
+        for market in self.markets.keys().copied() {
+            let Some(after) = self.sequences.last(&market.to_string()) else {
+                continue;
+            };
+            held.insert(market, Vec::new());
+            let client = self.client.clone();
+            let log_format = self.log_format.clone();
+            let (limit, sender) = (self.catch_up, sender.clone());
+            tokio::spawn(async move {
+                let result = catch_up::missed_fills(&client, market, after, limit, &log_format).await;
+                let _ = sender.send((market, result));
+            });
+        }
+        if !held.is_empty() {
+            info!("catching up {} markets, their live fills wait until it is done", held.len());
+        }
+        held
+    }
+
+    // The caught up fills of the market, oldest first
+    fn missed_fills(&self, market: Pubkey, result: Result<CaughtUp, String>) -> Vec<Update> {
+        let market_name = self.market_names.get(&market).map_or(String::new(), |name| name.replace('\0', ""));
+        let caught_up = match result {
+            Ok(caught_up) => caught_up,
+            Err(err) => {
+                warn!("could not catch up market {}: {}", market_name, err);
+                return Vec::new();
+            }
+        };
+        if !caught_up.complete {
+            warn!(
+                "catching up market {} stopped after {} signatures, older fills are missing",
+                market_name, caught_up.scanned
+            );
+        }
+        caught_up.fills
+    }
+
+    fn caught_up(&self, market: Pubkey, caught_up: usize, total: usize) {
+        let market_name = self.market_names.get(&market).map_or(String::new(), |name| name.replace('\0', ""));
+        info!(
+            "caught up {} fills of market {}, {} more came in live",
+            caught_up,
+            market_name,
+            total - caught_up
+        );
+        metrics()
+            .caught_up_fills
+            .get_or_create(&Metrics::market(&market_name))
+            .inc_by(caught_up as u64);
+    }
+
+    async fn market_info(&mut self) -> Vec<Enriched> {
+        // parameters can change on-chain, re-read them before announcing
+        let keys: Vec<Pubkey> = self.markets.keys().copied().collect();
+        match self.client.get_multiple_accounts(&keys).await {
+            Ok(accounts) => {
+                for (key, account) in keys.iter().zip(accounts) {
+                    let (Some(account), Some(known)) = (account, self.markets.get(key)) else {
+                        continue;
+                    };
+                    if let Some(market) = self.upgrades.check(key, &account.data, known) {
+                        self.markets.insert(*key, market);
+                    }
+                }
+            }
+            Err(err) => warn!("could not refresh market parameters: {}", err),
+        }
+        let mut messages = Vec::new();
+        for (key, market) in self.markets.iter().filter(|(key, _)| !self.upgrades.is_incompatible(key)) {
+            let market_id = key.to_string();
+            self.market_info.update(&market_id, market);
+            let market_name = self.market_names.get(key).map_or("", |name| name.as_str());
+            if let Some(message) = self.market_info.message(&market_id, market_name) {
+                messages.push(control(message));
+            }
+        }
+        self.market_info.save(&self.market_info_path);
+        messages
+    }
+
+    // Whether the settlement was on a market of this shard: the other shards, which see the
+    // transaction for their own markets, skip it. Published when the market cannot be read.
+    async fn rebate_of_shard(&mut self, open_orders_account: &Pubkey) -> bool {
+        let market = match self.rebate_markets.get(open_orders_account) {
+            Some(market) => *market,
+            None => match get_owner_account_for_ooa(&self.client, open_orders_account).await {
+                Ok(owner) => {
+                    self.rebate_markets.insert(*open_orders_account, owner.market);
+                    owner.market
+                }
+                Err(err) => {
+                    warn!("market of referrer rebate of {} unknown: {:?}", open_orders_account, err);
+                    return true;
+                }
+            },
+        };
+        self.markets.contains_key(&market)
+    }
+
+    async fn enrich(&mut self, update: Update, enrichers: &mut EnricherChain) -> Vec<Enriched> {
+        #[cfg(feature = "chaos")]
+        crate::chaos::enrich_delay().await;
+        let (fill_log, tx_hash, position, fill_index, feed_seq, block_time, cost, received_ms, pre_confirmed) =
+            match update {
+                Update::Fill {
+                    fill_log,
+                    signature,
+                    position,
+                    fill_index,
+                    feed_seq,
+                    block_time_ms,
+                    block_time_estimated,
+                    cost,
+                    received_ms,
+                    pre_confirmed,
+                } => {
+                    let block_time = (block_time_ms, block_time_estimated);
+                    (fill_log, signature, position, fill_index, feed_seq, block_time, cost, received_ms, pre_confirmed)
+                }
+                Update::DataLoss {
+                    market,
+                    from_seq,
+                    to_seq,
+                } => {
+                    let message = ControlMessage::DataLoss {
+                        market_id: market.to_string(),
+                        from_seq,
+                        to_seq,
+                    };
+                    warn!("{}", serde_json::to_string(&message).unwrap());
+                    return vec![control(message)];
+                }
+                Update::MarketClosed {
+                    market,
+                    lifecycle,
+                    signature,
+                } => {
+                    // no owner resolution or publishing for this market from now on, an idle
+                    // market stays known for when the source subscribes it again
+                    let market_name = match lifecycle.is_final() {
+                        true => {
+                            self.markets.remove(&market);
+                            self.market_names.remove(&market).unwrap_or_default()
+                        }
+                        false => self.market_names.get(&market).cloned().unwrap_or_default(),
+                    };
+                    let message = ControlMessage::MarketClosed {
+                        market_id: market.to_string(),
+                        market_name: market_name.replace('\0', ""),
+                        reason: lifecycle.as_str().to_string(),
+                        signature,
+                    };
+                    warn!("{}", serde_json::to_string(&message).unwrap());
+                    return vec![control(message)];
+                }
+                Update::ReferrerRebate {
+                    settle_funds_log,
+                    signature,
+                    log_index,
+                } => {
+                    if !self.rebates.insert(&signature, log_index) {
+                        debug!("referrer rebate at log {} of {} was published before", log_index, signature);
+                        return Vec::new();
+                    }
+                    if !self.rebate_of_shard(&settle_funds_log.open_orders_account).await {
+                        return Vec::new();
+                    }
+                    let message = ControlMessage::ReferrerRebate {
+                        open_orders_account: settle_funds_log.open_orders_account.to_string(),
+                        referrer: settle_funds_log.referrer.map(|referrer| referrer.to_string()).unwrap_or_default(),
+                        referrer_rebate: settle_funds_log.referrer_rebate,
+                        signature,
+                    };
+                    return vec![control(message)];
+                }
+                Update::CommitmentDowngrade {
+                    slot,
+                    requested,
+                    observed,
+                } => {
+                    let message = ControlMessage::CommitmentDowngrade {
+                        slot,
+                        requested: requested.to_string(),
+                        observed: observed.to_string(),
+                    };
+                    return vec![control(message)];
+                }
+            };
+        let Some(market) = self.markets.get(&fill_log.market) else {
+            let err = Error::from(DecodeError::UnknownMarket {
+                market: fill_log.market.to_string(),
+                signature: tx_hash,
+            });
+            err.record();
+            warn!("{}", err);
+            return Vec::new();
+        };
+        let market_name: &String = self.market_names.get(&fill_log.market).unwrap();
+        if self.upgrades.is_incompatible(&fill_log.market) {
+            let market_name = market_name.replace('\0', "");
+            metrics().upgrade_skipped_fills.get_or_create(&Metrics::market(&market_name)).inc();
+            return Vec::new();
+        }
+        let mut seq_regressed = None;
+        if self.seq_guard != SeqGuard::Off {
+            if let Err(last_seq_num) = self.sequences.advance(&fill_log.market.to_string(), fill_log.seq_num) {
+                let market_name = market_name.replace('\0', "");
+                metrics().seq_regressions.get_or_create(&Metrics::market(&market_name)).inc();
+                let message = ControlMessage::SeqRegression {
+                    market_id: fill_log.market.to_string(),
+                    market_name,
+                    seq_num: fill_log.seq_num,
+                    last_seq_num,
+                    feed_seq,
+                    signature: tx_hash.clone(),
+                    dropped: self.seq_guard == SeqGuard::Drop,
+                };
+                warn!("{}", serde_json::to_string(&message).unwrap());
+                if self.seq_guard == SeqGuard::Drop {
+                    return vec![control(message)];
+                }
+                seq_regressed = Some(control(message));
+            }
+        }
+        let trade_id = self.trade_id.trade_id(&fill_log, &tx_hash, &position);
+        let mut trade = Trade::new(
+            &fill_log,
+            &MarketParams::from(market),
+            market_name.clone().replace('\0', ""),
+            tx_hash,
+        );
+        trade.feed_seq = feed_seq;
+        trade.feed_epoch = self.feed_epoch;
+        trade.trade_id = trade_id;
+        trade.slot = position.slot;
+        trade.params_version = self.market_info.version(&trade.market_id);
+        (trade.fill_index, trade.fill_count) = fill_index;
+        // negative maker fee rate: fill_log.maker_fee is paid to the maker
+        trade.maker_rebate = market.maker_fee < 0;
+        trade.commitment = self.commitments.get(&fill_log.market).map(|commitment| commitment.to_string());
+        trade.compute_units = cost.compute_units;
+        trade.priority_fee = cost.priority_fee;
+        trade.seq_regressed = seq_regressed.is_some();
+        trade.pre_confirmed = pre_confirmed;
+        let (block_time_ms, block_time_estimated) = block_time;
+        trade.block_time_ms = block_time_ms;
+        trade.block_time_estimated = block_time_estimated;
+        if let Some(fast) = self.fast.as_ref().filter(|_| !self.quarantined.contains(&fill_log.market)) {
+            let outgoing = Outgoing {
+                topic: market_topic(TOPIC_TRADES, &trade.market_name),
+                payload: to_json(&trade, self.canonical),
+                trade: Some(trade.clone()),
+                fast: true,
+            };
+            if !fast.send_to(outgoing, self.priority.contains(&trade.market_id)).await {
+                warn!("publish stage is gone, dropping fast trade");
+            }
+        }
+        let fill = Fill { log: &fill_log };
+        let mut enriched: Vec<Enriched> = enrichers.enrich(&fill, &mut trade).await.into_iter().map(control).collect();
+        enriched.extend(seq_regressed);
+        // filtered out by a plugin, consumers skip its feedSeq on the marker
+        if enrichers.dropped() {
+            enriched.push(control(ControlMessage::TradeFiltered {
+                market_id: trade.market_id,
+                market_name: trade.market_name,
+                feed_seq: trade.feed_seq,
+                trade_id: trade.trade_id,
+            }));
+            return enriched;
+        }
+        enriched.push(Enriched::Trade {
+            trade,
+            block_time_ms,
+            received_ms,
+        });
+        enriched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intake::TxCost;
+    use openbookv2_decoder::{FillLog, FillPosition};
+
+    fn fill(market: Pubkey, seq_num: u64, feed_seq: u64) -> Update {
+        Update::Fill {
+            fill_log: FillLog {
+                market,
+                taker_side: 0,
+                maker_slot: 0,
+                maker_out: false,
+                timestamp: 0,
+                seq_num,
+                maker: Pubkey::default(),
+                maker_client_order_id: 0,
+                maker_fee: 0,
+                maker_timestamp: 0,
+                taker: Pubkey::default(),
+                taker_client_order_id: 0,
+                taker_fee_ceil: 0,
+                price: 1,
+                quantity: 1,
+            },
+            signature: format!("sig{}", seq_num),
+            position: FillPosition::default(),
+            fill_index: (0, 1),
+            feed_seq,
+            block_time_ms: None,
+            block_time_estimated: false,
+            cost: TxCost::default(),
+            received_ms: 0,
+            pre_confirmed: false,
+        }
+    }
+
+    fn loss(market: Pubkey, from_seq: u64, to_seq: u64) -> Update {
+        Update::DataLoss {
+            market,
+            from_seq,
+            to_seq,
+        }
+    }
+
+    // "<seq_num>@<feed_seq>" for fills, "loss <from>..<to>" for data losses
+    fn published(updates: &[Update]) -> Vec<String> {
+        updates
+            .iter()
+            .map(|update| match update {
+                Update::Fill {
+                    fill_log, feed_seq, ..
+                } => format!("{}@{}", fill_log.seq_num, feed_seq),
+                Update::DataLoss { from_seq, to_seq, .. } => format!("loss {}..{}", from_seq, to_seq),
+                _ => "other".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn overlapping_caught_up_fills_are_published_once() {
+        let market = Pubkey::new_unique();
+        let caught_up = (11..=15).map(|seq_num| fill(market, seq_num, 0)).collect();
+        let live = vec![fill(market, 14, 1), fill(market, 15, 2), fill(market, 16, 3)];
+        let (merged, count, offset) = merge_catch_up(caught_up, live);
+        assert_eq!(published(&merged), ["11@1", "12@2", "13@3", "14@4", "15@5", "16@6"]);
+        assert_eq!((count, offset), (3, 3));
+        assert_eq!(published(&[shift(fill(market, 17, 4), &HashMap::from([(market, offset)]))]), ["17@7"]);
+    }
+
+    #[test]
+    fn live_fills_out_of_order_filter_by_the_lowest_seq_num() {
+        let market = Pubkey::new_unique();
+        let caught_up = (11..=14).map(|seq_num| fill(market, seq_num, 0)).collect();
+        let live = vec![fill(market, 14, 1), fill(market, 13, 2)];
+        let (merged, count, _) = merge_catch_up(caught_up, live);
+        assert_eq!(published(&merged), ["11@1", "12@2", "14@3", "13@4"]);
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn live_seq_below_the_first_caught_up_one_skips_the_catch_up() {
+        let market = Pubkey::new_unique();
+        let caught_up = (20..=22).map(|seq_num| fill(market, seq_num, 0)).collect();
+        let live = vec![fill(market, 18, 1), fill(market, 19, 2)];
+        let (merged, count, offset) = merge_catch_up(caught_up, live);
+        assert_eq!(published(&merged), ["18@1", "19@2"]);
+        assert_eq!((count, offset), (0, 0));
+    }
+
+    #[test]
+    fn data_loss_while_held_is_rebased() {
+        let market = Pubkey::new_unique();
+        // live fills 1..=2 (seq_nums 11 and 12) were dropped by the intake queue, 5..=6 as well
+        let caught_up = (11..=12).map(|seq_num| fill(market, seq_num, 0)).collect();
+        let live = vec![
+            loss(market, 1, 2),
+            fill(market, 13, 3),
+            fill(market, 14, 4),
+            loss(market, 5, 6),
+            fill(market, 17, 7),
+        ];
+        let (merged, count, offset) = merge_catch_up(caught_up, live);
+        assert_eq!(published(&merged), ["11@1", "12@2", "13@3", "14@4", "loss 5..6", "17@7"]);
+        assert_eq!((count, offset), (2, 0));
+        // fewer caught up fills than were dropped, e.g. an incomplete catch-up
+        let caught_up = vec![fill(market, 12, 0)];
+        let live = vec![loss(market, 1, 2), fill(market, 13, 3), loss(market, 4, 4), fill(market, 15, 5)];
+        let (merged, _, offset) = merge_catch_up(caught_up, live);
+        assert_eq!(published(&merged), ["12@1", "13@2", "loss 3..3", "15@4"]);
+        assert_eq!(offset, -1);
+    }
+
+    #[test]
+    fn data_loss_without_live_fills_follows_the_caught_up_ones() {
+        let market = Pubkey::new_unique();
+        let caught_up = (11..=12).map(|seq_num| fill(market, seq_num, 0)).collect();
+        let (merged, count, offset) = merge_catch_up(caught_up, vec![loss(market, 1, 3)]);
+        assert_eq!(published(&merged), ["11@1", "12@2", "loss 3..5"]);
+        assert_eq!((count, offset), (2, 2));
+    }
+}
@@ -0,0 +1,32 @@
+use crate::enricher::{Enricher, Fill};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use openbookv2_decoder::{ControlMessage, Trade};
+use std::collections::HashMap;
+
+// Names for known owners (or OpenOrders accounts) from a json file,
+// {"<pubkey>": "market maker a", ...}, run it after owners
+pub struct LabelsEnricher {
+    labels: HashMap<String, String>,
+}
+
+impl LabelsEnricher {
+    pub fn load(path: Option<&str>) -> Self {
+        let path = path.expect("the labels enricher needs --labels-file");
+        let data = std::fs::read(path).unwrap_or_else(|err| panic!("could not read {}: {}", path, err));
+        let labels = serde_json::from_slice(&data).unwrap_or_else(|err| panic!("invalid labels in {}: {}", path, err));
+        LabelsEnricher { labels }
+    }
+}
+
+impl Enricher for LabelsEnricher {
+    fn name(&self) -> &'static str {
+        "labels"
+    }
+
+    fn enrich<'a>(&'a mut self, _fill: &'a Fill<'a>, trade: &'a mut Trade) -> BoxFuture<'a, Vec<ControlMessage>> {
+        trade.maker_label = self.labels.get(&trade.maker_owner).cloned();
+        trade.taker_label = self.labels.get(&trade.taker_owner).cloned();
+        async { Vec::new() }.boxed()
+    }
+}
@@ -0,0 +1,220 @@
+use crate::config::{Config, EnricherKind};
+use crate::error::{EnrichError, Error};
+use crate::features::{Feature, FeatureFlags};
+use crate::filter::TradeFilter;
+use crate::metrics::{metrics, Metrics};
+use crate::owner_snapshots::{OwnerHistory, OwnerSnapshots};
+use crate::utils::OoaOwner;
+use futures::future::{select_all, BoxFuture};
+use futures::FutureExt;
+use labels::LabelsEnricher;
+use log::warn;
+use openbookv2_decoder::{ControlMessage, FillLog, Trade};
+use owners::OwnersEnricher;
+use plugins::PluginsEnricher;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_program::pubkey::Pubkey;
+use openbookv2_generated::state::Market;
+use spread::SpreadEnricher;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tif::TifEnricher;
+use tokio::time::timeout;
+use usd::UsdEnricher;
+
+pub mod labels;
+pub mod owners;
+pub mod plugins;
+pub mod spread;
+pub mod tif;
+pub mod usd;
+
+// The fill a trade was built from, makerOwner/takerOwner of a fresh trade are its
+// OpenOrders accounts
+pub struct Fill<'a> {
+    pub log: &'a FillLog,
+}
+
+// One step of the enrich stage, applied to every trade in the order of --enrichers
+pub trait Enricher: Send {
+    fn name(&self) -> &'static str;
+    // Sets its fields on the trade, the returned messages are published ahead of it
+    fn enrich<'a>(&'a mut self, fill: &'a Fill<'a>, trade: &'a mut Trade) -> BoxFuture<'a, Vec<ControlMessage>>;
+    // Control messages of the enrich stage (oracle prices, market info)
+    fn observe(&mut self, _message: &ControlMessage) {}
+    // The last trade is filtered out and not published, e.g. by a plugin
+    fn dropped(&self) -> bool {
+        false
+    }
+    // Work which finishes after its trade was published, e.g. owner lookups over budget.
+    // next() is only polled while idle() is false.
+    fn idle(&self) -> bool {
+        true
+    }
+    fn next(&mut self) -> BoxFuture<'_, Vec<ControlMessage>> {
+        futures::future::pending().boxed()
+    }
+}
+
+// One enricher of the chain with its timeout and the trades it runs on
+pub struct ChainedEnricher {
+    pub enricher: Box<dyn Enricher>,
+    pub timeout: Duration,
+    // from --enricher-filter, None runs on every trade
+    pub filter: Option<TradeFilter>,
+    // the feature switching it off at runtime
+    pub feature: Option<Feature>,
+}
+
+// Every enricher runs with its own timeout, one that hangs (e.g. on RPC) leaves its
+// fields unset and its name in enrichmentPending instead of holding up the stage
+#[derive(Default)]
+pub struct EnricherChain {
+    enrichers: Vec<ChainedEnricher>,
+    features: Option<FeatureFlags>,
+    // an enricher dropped the last trade, the enrichers after it did not run
+    dropped: bool,
+}
+
+impl EnricherChain {
+    pub fn new(enrichers: Vec<ChainedEnricher>, features: Option<FeatureFlags>) -> Self {
+        EnricherChain {
+            enrichers,
+            features,
+            dropped: false,
+        }
+    }
+
+    pub fn dropped(&self) -> bool {
+        self.dropped
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.enrichers.iter().map(|chained| chained.enricher.name()).collect()
+    }
+
+    pub async fn enrich(&mut self, fill: &Fill<'_>, trade: &mut Trade) -> Vec<ControlMessage> {
+        let mut messages = Vec::new();
+        self.dropped = false;
+        let features = self.features.as_ref();
+        for chained in self.enrichers.iter_mut() {
+            let (enricher, limit) = (&mut chained.enricher, &chained.timeout);
+            let name = enricher.name();
+            // switched off with POST /features, its fields stay unset like without it
+            if let (Some(feature), Some(features)) = (chained.feature, features) {
+                if !features.enabled(feature) {
+                    continue;
+                }
+            }
+            // seen as the earlier enrichers left the trade, e.g. with notionalUsd after usd
+            if chained.filter.as_ref().is_some_and(|filter| !filter.matches(trade)) {
+                metrics().enricher_skipped.get_or_create(&Metrics::enricher(name)).inc();
+                continue;
+            }
+            match timeout(*limit, enricher.enrich(fill, trade)).await {
+                Ok(enriched) => {
+                    messages.extend(enriched);
+                    if enricher.dropped() {
+                        self.dropped = true;
+                        break;
+                    }
+                }
+                Err(_) => {
+                    warn!("{} enricher timed out after {:?} on trade {}", name, limit, trade.trade_id);
+                    metrics().enricher_timeouts.get_or_create(&Metrics::enricher(name)).inc();
+                    Error::Enrich {
+                        name,
+                        error: EnrichError::Timeout(*limit),
+                    }
+                    .record();
+                    trade.enrichment_pending.push(name.to_string());
+                }
+            }
+        }
+        messages
+    }
+
+    pub fn observe(&mut self, message: &ControlMessage) {
+        for chained in self.enrichers.iter_mut() {
+            chained.enricher.observe(message);
+        }
+    }
+
+    pub fn idle(&self) -> bool {
+        self.enrichers.iter().all(|chained| chained.enricher.idle())
+    }
+
+    pub async fn next(&mut self) -> Vec<ControlMessage> {
+        let pending: Vec<_> = self
+            .enrichers
+            .iter_mut()
+            .filter(|chained| !chained.enricher.idle())
+            .map(|chained| chained.enricher.next())
+            .collect();
+        if pending.is_empty() {
+            return Vec::new();
+        }
+        select_all(pending).await.0
+    }
+}
+
+// The chain selected by --enrichers for `markets`, `owners` are the pre-warmed owners of OpenOrders
+// accounts, `shard_dir` is below the archive dir for owner snapshots of one of several shards
+pub fn from_config(
+    config: &Config,
+    client: Arc<RpcClient>,
+    markets: &BTreeMap<Pubkey, Market>,
+    owners: HashMap<Pubkey, OoaOwner>,
+    shard_dir: Option<&str>,
+    features: FeatureFlags,
+) -> EnricherChain {
+    let mut owners = Some(owners);
+    let archive = config.archive_dir.as_deref().map(Path::new);
+    if archive.is_none() && (config.owner_snapshot_interval > 0 || config.owners_from_archive) {
+        warn!("owner snapshots need --archive-dir");
+    }
+    let mut snapshots = archive.filter(|_| config.owner_snapshot_interval > 0).map(|archive| {
+        let dir: PathBuf = match shard_dir {
+            Some(shard) => archive.join("owners").join(shard),
+            None => archive.join("owners"),
+        };
+        OwnerSnapshots::new(dir, Duration::from_secs(config.owner_snapshot_interval), config.archive_retention())
+    });
+    let mut history = archive.filter(|_| config.owners_from_archive).map(OwnerHistory::open);
+    let enrichers = config
+        .enrichers
+        .iter()
+        .map(|kind| {
+            let enricher: Box<dyn Enricher> = match kind {
+                EnricherKind::Owners => Box::new(
+                    OwnersEnricher::new(
+                        client.clone(),
+                        config.owner_lookups,
+                        Duration::from_millis(config.owner_wait_ms),
+                    )
+                    .prewarm(owners.take().unwrap_or_default())
+                    .snapshots(snapshots.take())
+                    .history(history.take()),
+                ),
+                EnricherKind::Usd => Box::new(UsdEnricher::new(&config.quote_conversions)),
+                EnricherKind::Labels => Box::new(LabelsEnricher::load(config.labels_file.as_deref())),
+                EnricherKind::Spread => Box::new(SpreadEnricher::default()),
+                EnricherKind::Plugins => Box::new(PluginsEnricher::load(&config.plugins)),
+                EnricherKind::Tif => Box::new(TifEnricher::new(
+                    client.clone(),
+                    markets.values().flat_map(|market| [market.bids, market.asks]).collect(),
+                    Duration::from_millis(config.book_poll_ms),
+                )),
+            };
+            ChainedEnricher {
+                enricher,
+                timeout: config.enricher_timeout(*kind),
+                filter: config.enricher_filter(*kind),
+                feature: (*kind == EnricherKind::Owners).then_some(Feature::Owners),
+            }
+        })
+        .collect();
+    EnricherChain::new(enrichers, Some(features))
+}
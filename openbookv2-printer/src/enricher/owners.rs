@@ -0,0 +1,383 @@
+use crate::enricher::{Enricher, Fill};
+use crate::error::{EnrichError, Error};
+use crate::metrics::{metrics, Metrics};
+use crate::owner_snapshots::{OwnerHistory, OwnerRecord, OwnerSnapshot, OwnerSnapshots};
+use crate::utils::{get_owner_account_for_ooa, LookupFailure, OoaOwner};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::warn;
+use openbookv2_decoder::{ControlMessage, Trade, TradeCorrection};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_program::pubkey::Pubkey;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::{timeout_at, Instant};
+
+// A trade published with the OpenOrders account as owner, gets a trade_correction
+// control message once the lookup finishes
+struct Waiter {
+    market_id: String,
+    trade_id: String,
+    // maker or taker side of the trade
+    maker: bool,
+}
+
+fn side(maker: bool) -> &'static str {
+    if maker {
+        "maker"
+    } else {
+        "taker"
+    }
+}
+
+// Resolves OpenOrders accounts to their owners with at most `budget` lookups in
+// flight. A trade waits at most `wait` for its lookups, keys over the budget are
+// queued and not waited for, so an OOA storm cannot hold up publishing. An account
+// opened for another market than the fill's is never attributed, see same_market.
+// Accounts which can never be resolved are not looked up again and published once on
+// the diagnostics topic. The map can be archived periodically, and a replay can resolve
+// against the archived snapshots of its trades' time instead of the current accounts.
+pub struct OwnersEnricher {
+    client: Arc<RpcClient>,
+    budget: usize,
+    wait: Duration,
+    cache: HashMap<Pubkey, OoaOwner>,
+    // closed accounts, not OpenOrders accounts and accounts of another market
+    failed: HashMap<Pubkey, LookupFailure>,
+    lookups: JoinSet<(Pubkey, Result<OoaOwner, LookupFailure>)>,
+    in_flight: HashSet<Pubkey>,
+    deferred: VecDeque<Pubkey>,
+    waiting: HashMap<Pubkey, Vec<Waiter>>,
+    // newest trade slot when each cached owner was resolved
+    resolved_at: HashMap<Pubkey, u64>,
+    // slot and block time of the newest trade
+    newest: (u64, u64),
+    snapshots: Option<OwnerSnapshots>,
+    history: Option<OwnerHistory>,
+}
+
+impl OwnersEnricher {
+    pub fn new(client: Arc<RpcClient>, budget: usize, wait: Duration) -> Self {
+        OwnersEnricher {
+            client,
+            budget: budget.max(1),
+            wait,
+            cache: HashMap::new(),
+            failed: HashMap::new(),
+            lookups: JoinSet::new(),
+            in_flight: HashSet::new(),
+            deferred: VecDeque::new(),
+            waiting: HashMap::new(),
+            resolved_at: HashMap::new(),
+            newest: (0, 0),
+            snapshots: None,
+            history: None,
+        }
+    }
+
+    // Owners known before the first trade, see bootstrap::prewarm_owners
+    pub fn prewarm(mut self, owners: HashMap<Pubkey, OoaOwner>) -> Self {
+        self.cache.extend(owners);
+        self
+    }
+
+    // Archives the map every --owner-snapshot-interval
+    pub fn snapshots(mut self, snapshots: Option<OwnerSnapshots>) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+
+    // Owners from the archived snapshots first, see --owners-from-archive
+    pub fn history(mut self, history: Option<OwnerHistory>) -> Self {
+        self.history = history;
+        self
+    }
+
+    fn snapshot(&self) -> OwnerSnapshot {
+        let (slot, time) = self.newest;
+        OwnerSnapshot {
+            slot,
+            time,
+            owners: self
+                .cache
+                .iter()
+                .map(|(key, owner)| {
+                    let record = OwnerRecord {
+                        owner: owner.owner.to_string(),
+                        delegate: owner.delegate.map(|delegate| delegate.to_string()),
+                        market: owner.market.to_string(),
+                        slot: self.resolved_at.get(key).copied().unwrap_or(0),
+                    };
+                    (key.to_string(), record)
+                })
+                .collect(),
+        }
+    }
+
+    fn start(&mut self, key: Pubkey) {
+        if self.cache.contains_key(&key)
+            || self.failed.contains_key(&key)
+            || self.in_flight.contains(&key)
+            || self.deferred.contains(&key)
+        {
+            return;
+        }
+        if self.in_flight.len() >= self.budget {
+            self.deferred.push_back(key);
+            return;
+        }
+        self.in_flight.insert(key);
+        metrics().owner_lookups.set(self.in_flight.len() as i64);
+        let client = self.client.clone();
+        self.lookups
+            .spawn(async move { (key, get_owner_account_for_ooa(&client, &key).await) });
+    }
+
+    // Owners of the keys, None for keys still pending once the wait is over. Other
+    // trades' lookups finishing meanwhile come back as control messages.
+    async fn resolve<const N: usize>(
+        &mut self,
+        keys: [Pubkey; N],
+        slot: u64,
+    ) -> ([Option<Result<OoaOwner, LookupFailure>>; N], Vec<ControlMessage>) {
+        let mut owners = keys.map(|key| self.known(&key, slot));
+        for (owner, key) in owners.iter().zip(keys) {
+            if owner.is_none() {
+                self.start(key);
+            }
+        }
+        let deadline = Instant::now() + self.wait;
+        let mut messages = Vec::new();
+        while owners
+            .iter()
+            .zip(keys.iter())
+            .any(|(owner, key)| owner.is_none() && self.in_flight.contains(key))
+        {
+            let Ok(Some(done)) = timeout_at(deadline, self.lookups.join_next()).await else {
+                break;
+            };
+            let Some((key, owner)) = self.done(done) else {
+                continue;
+            };
+            for (resolved, k) in owners.iter_mut().zip(keys.iter()) {
+                if resolved.is_none() && *k == key {
+                    *resolved = Some(owner.clone());
+                }
+            }
+            messages.extend(self.complete(key, owner));
+        }
+        (owners, messages)
+    }
+
+    fn known(&mut self, key: &Pubkey, slot: u64) -> Option<Result<OoaOwner, LookupFailure>> {
+        if let Some(owner) = self.history.as_mut().and_then(|history| history.get(key, slot)) {
+            return Some(Ok(owner));
+        }
+        if let Some(owner) = self.cache.get(key) {
+            return Some(Ok(*owner));
+        }
+        self.failed.get(key).map(|failure| Err(failure.clone()))
+    }
+
+    // A resolution_failed message the first time an account fails for good
+    fn fail(
+        &mut self,
+        key: Pubkey,
+        failure: LookupFailure,
+        market_id: &str,
+        trade_id: &str,
+        maker: bool,
+    ) -> Option<ControlMessage> {
+        let reason = failure.reason();
+        if self.failed.insert(key, failure).is_some() {
+            return None;
+        }
+        warn!("owner of {} ({} of a {} fill) can not be resolved: {}", key, side(maker), market_id, reason);
+        metrics().owners_failed.get_or_create(&Metrics::reason(reason)).inc();
+        Some(ControlMessage::ResolutionFailed {
+            market_id: market_id.to_string(),
+            account: key.to_string(),
+            side: side(maker).to_string(),
+            reason: reason.to_string(),
+            trade_id: trade_id.to_string(),
+        })
+    }
+
+    fn wait_for(&mut self, key: Pubkey, waiter: Waiter) {
+        self.waiting.entry(key).or_default().push(waiter);
+    }
+
+    fn done(
+        &mut self,
+        done: Result<(Pubkey, Result<OoaOwner, LookupFailure>), tokio::task::JoinError>,
+    ) -> Option<(Pubkey, Result<OoaOwner, LookupFailure>)> {
+        match done {
+            Ok((key, owner)) => {
+                self.in_flight.remove(&key);
+                metrics().owner_lookups.set(self.in_flight.len() as i64);
+                Some((key, owner))
+            }
+            Err(err) => {
+                let err = Error::Enrich {
+                    name: "owners",
+                    error: EnrichError::Lookup(err.to_string()),
+                };
+                err.record();
+                warn!("{}", err);
+                None
+            }
+        }
+    }
+
+    fn complete(&mut self, key: Pubkey, owner: Result<OoaOwner, LookupFailure>) -> Vec<ControlMessage> {
+        let waiters = self.waiting.remove(&key).unwrap_or_default();
+        let mut messages = Vec::new();
+        match owner {
+            Ok(owner) => {
+                self.cache.insert(key, owner);
+                self.resolved_at.insert(key, self.newest.0);
+                for waiter in waiters {
+                    if !same_market(key, &owner, &waiter.market_id) {
+                        messages.extend(self.fail(
+                            key,
+                            LookupFailure::OtherMarket,
+                            &waiter.market_id,
+                            &waiter.trade_id,
+                            waiter.maker,
+                        ));
+                        continue;
+                    }
+                    let mut correction = TradeCorrection {
+                        market_id: waiter.market_id,
+                        trade_id: waiter.trade_id,
+                        ..Default::default()
+                    };
+                    let resolved = Some(owner.owner.to_string());
+                    let delegate = owner.delegate.map(|delegate| delegate.to_string());
+                    if waiter.maker {
+                        (correction.maker_owner, correction.maker_delegate) = (resolved, delegate);
+                    } else {
+                        (correction.taker_owner, correction.taker_delegate) = (resolved, delegate);
+                    }
+                    messages.push(ControlMessage::TradeCorrection(correction));
+                }
+            }
+            Err(failure) if failure.permanent() => {
+                if let Some(waiter) = waiters.first() {
+                    messages.extend(self.fail(key, failure, &waiter.market_id, &waiter.trade_id, waiter.maker));
+                }
+            }
+            Err(failure) if !waiters.is_empty() => {
+                warn!("owner of {} not found ({:?}), {} trades keep it as owner", key, failure, waiters.len());
+            }
+            Err(_) => {}
+        }
+        while self.in_flight.len() < self.budget {
+            let Some(next) = self.deferred.pop_front() else {
+                break;
+            };
+            self.start(next);
+        }
+        messages
+    }
+}
+
+impl Enricher for OwnersEnricher {
+    fn name(&self) -> &'static str {
+        "owners"
+    }
+
+    fn enrich<'a>(&'a mut self, fill: &'a Fill<'a>, trade: &'a mut Trade) -> BoxFuture<'a, Vec<ControlMessage>> {
+        async move {
+            let ooa = [fill.log.maker, fill.log.taker];
+            self.newest = self.newest.max((trade.slot, trade.time_stamp));
+            let ([maker_owner, taker_owner], mut resolved) = self.resolve(ooa, trade.slot).await;
+            let market = fill.log.market.to_string();
+            // a failed lookup keeps the OpenOrders account, as does one still pending or
+            // one of another market
+            for (key, owner, maker) in [(ooa[0], &maker_owner, true), (ooa[1], &taker_owner, false)] {
+                let failure = match owner {
+                    Some(Ok(owner)) if same_market(key, owner, &market) => {
+                        let (resolved, delegate) = (owner.owner.to_string(), owner.delegate.map(|d| d.to_string()));
+                        if maker {
+                            (trade.maker_owner, trade.maker_delegate) = (resolved, delegate);
+                        } else {
+                            (trade.taker_owner, trade.taker_delegate) = (resolved, delegate);
+                        }
+                        continue;
+                    }
+                    Some(Ok(_)) => {
+                        trade.owner_market_mismatch = true;
+                        LookupFailure::OtherMarket
+                    }
+                    Some(Err(failure)) if failure.permanent() => failure.clone(),
+                    _ => continue,
+                };
+                resolved.extend(self.fail(key, failure, &market, &trade.trade_id, maker));
+            }
+            for (key, owner, maker) in [(ooa[0], maker_owner, true), (ooa[1], taker_owner, false)] {
+                if owner.is_none() {
+                    trade.owners_unresolved = true;
+                    self.wait_for(
+                        key,
+                        Waiter {
+                            market_id: trade.market_id.clone(),
+                            trade_id: trade.trade_id.clone(),
+                            maker,
+                        },
+                    );
+                }
+            }
+            if trade.owners_unresolved {
+                metrics().owners_unresolved.inc();
+            }
+            if self.snapshots.as_ref().is_some_and(OwnerSnapshots::due) {
+                let snapshot = self.snapshot();
+                if let Some(snapshots) = self.snapshots.as_mut() {
+                    snapshots.write(snapshot);
+                }
+            }
+            resolved
+        }
+        .boxed()
+    }
+
+    fn idle(&self) -> bool {
+        self.lookups.is_empty()
+    }
+
+    fn next(&mut self) -> BoxFuture<'_, Vec<ControlMessage>> {
+        async move {
+            let Some(done) = self.lookups.join_next().await else {
+                return Vec::new();
+            };
+            match self.done(done) {
+                Some((key, owner)) => self.complete(key, owner),
+                None => Vec::new(),
+            }
+        }
+        .boxed()
+    }
+}
+
+// Whether the OpenOrders account was opened for the fill's market. Anything else is a
+// decoding or attribution bug, recorded as an enrich error instead of corrupting the
+// owner of the trade.
+fn same_market(key: Pubkey, owner: &OoaOwner, market: &str) -> bool {
+    if owner.market.to_string() == market {
+        return true;
+    }
+    let err = Error::Enrich {
+        name: "owners",
+        error: EnrichError::MarketMismatch {
+            account: key,
+            market: market.to_string(),
+            actual: owner.market,
+        },
+    };
+    err.record();
+    warn!("{}", err);
+    false
+}
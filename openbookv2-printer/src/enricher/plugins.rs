@@ -0,0 +1,127 @@
+use crate::enricher::{Enricher, Fill};
+use crate::metrics::{metrics, Metrics};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use libloading::{Library, Symbol};
+use log::{info, warn};
+use openbookv2_decoder::{ControlMessage, Trade};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+// int32_t openbook_plugin_annotate(const uint8_t *trade, size_t len, uint8_t **out, size_t *out_len)
+type AnnotateFn = unsafe extern "C" fn(*const u8, usize, *mut *mut u8, *mut usize) -> i32;
+// void openbook_plugin_free(uint8_t *out, size_t out_len), gets back what annotate put in out
+type FreeFn = unsafe extern "C" fn(*mut u8, usize);
+
+// return codes of openbook_plugin_annotate, anything else is an error
+const KEEP: i32 = 0;
+const DROP: i32 = 1;
+
+struct Plugin {
+    name: String,
+    annotate: AnnotateFn,
+    free: FreeFn,
+    // keeps the functions above loaded
+    _library: Library,
+}
+
+impl Plugin {
+    fn load(path: &str) -> Result<Self, String> {
+        // loading runs the initializers of the library, plugins are trusted like the binary
+        let library = unsafe { Library::new(path) }.map_err(|err| err.to_string())?;
+        let (annotate, free) = unsafe {
+            let annotate: Symbol<AnnotateFn> =
+                library.get(b"openbook_plugin_annotate\0").map_err(|err| err.to_string())?;
+            let free: Symbol<FreeFn> = library.get(b"openbook_plugin_free\0").map_err(|err| err.to_string())?;
+            (*annotate, *free)
+        };
+        let stem = Path::new(path).file_stem().and_then(|stem| stem.to_str()).unwrap_or(path);
+        Ok(Plugin {
+            name: stem.strip_prefix("lib").unwrap_or(stem).to_string(),
+            annotate,
+            free,
+            _library: library,
+        })
+    }
+
+    // The annotations for the trade JSON, None drops the trade
+    fn annotate(&self, trade: &[u8]) -> Result<Option<Map<String, Value>>, String> {
+        let mut out: *mut u8 = std::ptr::null_mut();
+        let mut out_len = 0;
+        let code = unsafe { (self.annotate)(trade.as_ptr(), trade.len(), &mut out, &mut out_len) };
+        let annotations = if out.is_null() {
+            Ok(Map::new())
+        } else {
+            let bytes = unsafe { std::slice::from_raw_parts(out, out_len) };
+            let annotations = match bytes {
+                [] => Ok(Map::new()),
+                bytes => serde_json::from_slice(bytes).map_err(|err| format!("invalid annotations: {}", err)),
+            };
+            unsafe { (self.free)(out, out_len) };
+            annotations
+        };
+        match code {
+            KEEP => annotations.map(Some),
+            DROP => Ok(None),
+            code => Err(format!("returned {}", code)),
+        }
+    }
+}
+
+// Runs the shared libraries of --plugins on every trade, in their order. Each gets the
+// trade as JSON (with the annotations of the plugins before it) and either keeps it,
+// optionally with a JSON object merged into `annotations`, or drops it. Plugins run inline
+// on the enrich stage and can not be interrupted by the enricher timeout.
+pub struct PluginsEnricher {
+    plugins: Vec<Plugin>,
+    dropped: bool,
+}
+
+impl PluginsEnricher {
+    pub fn load(paths: &[String]) -> Self {
+        if paths.is_empty() {
+            panic!("the plugins enricher needs --plugins");
+        }
+        let plugins = paths
+            .iter()
+            .map(|path| {
+                let plugin =
+                    Plugin::load(path).unwrap_or_else(|err| panic!("could not load plugin {}: {}", path, err));
+                info!("loaded plugin {} from {}", plugin.name, path);
+                plugin
+            })
+            .collect();
+        PluginsEnricher { plugins, dropped: false }
+    }
+}
+
+impl Enricher for PluginsEnricher {
+    fn name(&self) -> &'static str {
+        "plugins"
+    }
+
+    fn enrich<'a>(&'a mut self, _fill: &'a Fill<'a>, trade: &'a mut Trade) -> BoxFuture<'a, Vec<ControlMessage>> {
+        self.dropped = false;
+        for plugin in self.plugins.iter() {
+            let json = serde_json::to_vec(&*trade).unwrap();
+            match plugin.annotate(&json) {
+                Ok(Some(annotations)) if annotations.is_empty() => {}
+                Ok(Some(annotations)) => trade.annotations.get_or_insert_with(Default::default).extend(annotations),
+                Ok(None) => {
+                    metrics().plugin_dropped.get_or_create(&Metrics::enricher(&plugin.name)).inc();
+                    self.dropped = true;
+                    break;
+                }
+                Err(err) => {
+                    warn!("plugin {} failed on trade {}: {}", plugin.name, trade.trade_id, err);
+                    metrics().plugin_errors.get_or_create(&Metrics::enricher(&plugin.name)).inc();
+                }
+            }
+        }
+        async { Vec::new() }.boxed()
+    }
+
+    fn dropped(&self) -> bool {
+        self.dropped
+    }
+}
@@ -0,0 +1,32 @@
+use crate::enricher::{Enricher, Fill};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use openbookv2_decoder::{ControlMessage, Trade};
+use std::collections::HashMap;
+
+// Distance of the fill price from the last oracle price of its market in basis points,
+// positive above the oracle. Needs --oracle-interval, markets without an oracle price
+// get no spread.
+#[derive(Default)]
+pub struct SpreadEnricher {
+    oracle_prices: HashMap<String, f64>,
+}
+
+impl Enricher for SpreadEnricher {
+    fn name(&self) -> &'static str {
+        "spread"
+    }
+
+    fn enrich<'a>(&'a mut self, _fill: &'a Fill<'a>, trade: &'a mut Trade) -> BoxFuture<'a, Vec<ControlMessage>> {
+        if let Some(oracle_price) = self.oracle_prices.get(&trade.market_id).filter(|price| **price > 0.0) {
+            trade.oracle_spread_bps = Some((trade.price_double - oracle_price) / oracle_price * 10_000.0);
+        }
+        async { Vec::new() }.boxed()
+    }
+
+    fn observe(&mut self, message: &ControlMessage) {
+        if let ControlMessage::Price { market_id, price, .. } = message {
+            self.oracle_prices.insert(market_id.clone(), *price);
+        }
+    }
+}
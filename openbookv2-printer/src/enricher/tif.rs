@@ -0,0 +1,112 @@
+use crate::enricher::{Enricher, Fill};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::warn;
+use openbookv2_decoder::{ControlMessage, Trade};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_program::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::time::interval;
+
+// BookSide layout: discriminator, roots, reserved roots and reserved bytes, then the
+// OrderTreeNodes header before its 1024 nodes of 88 bytes each
+const NODES_OFFSET: usize = 8 + 2 * 8 + 4 * 8 + 256 + 4 + 4 + 4 + 4 + 512;
+const NODE_SIZE: usize = 88;
+const LEAF_TAG: u8 = 2;
+// orders gone from the book are kept this long, the fill which took them may come later
+const RETAIN: Duration = Duration::from_secs(600);
+// getMultipleAccounts takes at most 100 keys
+const CHUNK: usize = 100;
+
+// A resting order by its OpenOrders account, slot in that account and placement time,
+// all three are in the fill log as maker, maker_slot and maker_timestamp
+type OrderKey = (Pubkey, u8, u64);
+
+#[derive(Clone, Copy)]
+struct RestingOrder {
+    time_in_force: u16,
+    seen: Instant,
+}
+
+type Orders = Arc<Mutex<HashMap<OrderKey, RestingOrder>>>;
+
+// The leaves of a BookSide account
+fn leaves(data: &[u8]) -> impl Iterator<Item = (OrderKey, u16)> + '_ {
+    data.get(NODES_OFFSET..)
+        .unwrap_or_default()
+        .chunks_exact(NODE_SIZE)
+        .filter(|node| node[0] == LEAF_TAG)
+        .map(|node| {
+            let owner_slot = node[1];
+            let time_in_force = u16::from_le_bytes([node[2], node[3]]);
+            let owner = Pubkey::try_from(&node[24..56]).unwrap();
+            let timestamp = u64::from_le_bytes(node[64..72].try_into().unwrap());
+            ((owner, owner_slot, timestamp), time_in_force)
+        })
+}
+
+// Reads the book sides every `every` until the enricher is dropped
+async fn poll(
+    client: Arc<RpcClient>,
+    books: Vec<Pubkey>,
+    every: Duration,
+    orders: Weak<Mutex<HashMap<OrderKey, RestingOrder>>>,
+) {
+    let mut ticker = interval(every);
+    loop {
+        ticker.tick().await;
+        let mut accounts = Vec::with_capacity(books.len());
+        for chunk in books.chunks(CHUNK) {
+            match client.get_multiple_accounts(chunk).await {
+                Ok(chunk) => accounts.extend(chunk.into_iter().flatten()),
+                Err(err) => warn!("could not read the order books: {}", err),
+            }
+        }
+        let Some(orders) = orders.upgrade() else {
+            return;
+        };
+        let now = Instant::now();
+        let mut orders = orders.lock().unwrap();
+        for account in accounts.iter() {
+            for (key, time_in_force) in leaves(&account.data) {
+                orders.insert(key, RestingOrder { time_in_force, seen: now });
+            }
+        }
+        orders.retain(|_, order| now.duration_since(order.seen) < RETAIN);
+    }
+}
+
+// makerTimeInForce and makerExpiry of the maker order. The fill log does not carry them,
+// so the bids and asks of the markets are read every --book-poll-ms and the fill is
+// matched to the order seen resting on them. Orders placed and taken between two reads
+// are not seen and get neither field.
+pub struct TifEnricher {
+    orders: Orders,
+}
+
+impl TifEnricher {
+    // `books` are the bids and asks accounts of the markets
+    pub fn new(client: Arc<RpcClient>, books: Vec<Pubkey>, every: Duration) -> Self {
+        let orders = Orders::default();
+        tokio::spawn(poll(client, books, every, Arc::downgrade(&orders)));
+        TifEnricher { orders }
+    }
+}
+
+impl Enricher for TifEnricher {
+    fn name(&self) -> &'static str {
+        "tif"
+    }
+
+    fn enrich<'a>(&'a mut self, fill: &'a Fill<'a>, trade: &'a mut Trade) -> BoxFuture<'a, Vec<ControlMessage>> {
+        let key = (fill.log.maker, fill.log.maker_slot, fill.log.maker_timestamp);
+        if let Some(order) = self.orders.lock().unwrap().get(&key) {
+            trade.maker_time_in_force = Some(order.time_in_force);
+            trade.maker_expiry =
+                (order.time_in_force > 0).then(|| fill.log.maker_timestamp + order.time_in_force as u64);
+        }
+        async { Vec::new() }.boxed()
+    }
+}
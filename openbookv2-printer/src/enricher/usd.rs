@@ -0,0 +1,196 @@
+use crate::enricher::{Enricher, Fill};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use openbookv2_decoder::{ControlMessage, Trade, TradeCorrection};
+use solana_program::pubkey::Pubkey;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+
+const USD: [&str; 3] = ["USDC", "USDT", "USD"];
+// unpriced trades kept per quote currency for a trade_correction, oldest dropped first
+const UNPRICED_PER_CURRENCY: usize = 1000;
+
+// Where the USD price of a quote currency comes from, "market:<pubkey>",
+// "oracle:<pubkey>" or "fixed:<price>"
+#[derive(Clone, Debug)]
+pub enum QuoteConversion {
+    // the last USD price of the currency traded as base on this watched market
+    Market(Pubkey),
+    // a pyth price account, read every --oracle-interval
+    Oracle(Pubkey),
+    Fixed(f64),
+}
+
+impl QuoteConversion {
+    fn source(&self) -> &'static str {
+        match self {
+            QuoteConversion::Market(_) => "market",
+            QuoteConversion::Oracle(_) => "oracle",
+            QuoteConversion::Fixed(_) => "fixed",
+        }
+    }
+}
+
+impl FromStr for QuoteConversion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s
+            .split_once(':')
+            .ok_or_else(|| format!("invalid quote conversion {}, expected market:, oracle: or fixed:", s))?;
+        let value = value.trim();
+        match kind.trim() {
+            "market" => Pubkey::from_str(value).map(QuoteConversion::Market).map_err(|err| err.to_string()),
+            "oracle" => Pubkey::from_str(value).map(QuoteConversion::Oracle).map_err(|err| err.to_string()),
+            "fixed" => value.parse::<f64>().map(QuoteConversion::Fixed).map_err(|err| err.to_string()),
+            other => Err(format!("unknown quote conversion {}, expected market, oracle or fixed", other)),
+        }
+    }
+}
+
+// A trade published without USD fields because its quote currency had no price yet
+struct Unpriced {
+    market_id: String,
+    trade_id: String,
+    price_double: f64,
+    quantity_double: f64,
+}
+
+// Prices trades in USD: directly for USD quoted markets, otherwise through the USD
+// price of the quote currency. That is the --quote-conversion of the currency if it has
+// one, else the last USD price of the currency seen on another watched market (e.g.
+// SOL-USDC for JUP-SOL). Trades without such a price get no USD fields, a
+// trade_correction follows with them once the quote currency is priced.
+#[derive(Default)]
+pub struct UsdEnricher {
+    conversions: HashMap<String, QuoteConversion>,
+    // last USD price per currency
+    prices: HashMap<String, f64>,
+    // trades waiting for a USD price of their quote currency
+    unpriced: HashMap<String, VecDeque<Unpriced>>,
+    // corrections for trades priced by an oracle update, published by next()
+    ready: Vec<ControlMessage>,
+}
+
+fn currencies(market_name: &str) -> Option<(&str, &str)> {
+    let mut parts = market_name.splitn(2, ['-', '/']);
+    Some((parts.next()?, parts.next()?))
+}
+
+impl UsdEnricher {
+    pub fn new(conversions: &[(String, QuoteConversion)]) -> Self {
+        let mut enricher = UsdEnricher::default();
+        for (currency, conversion) in conversions {
+            if let QuoteConversion::Fixed(price) = conversion {
+                enricher.prices.insert(currency.clone(), *price);
+            }
+            enricher.conversions.insert(currency.clone(), conversion.clone());
+        }
+        enricher
+    }
+
+    fn source(&self, currency: &str) -> &'static str {
+        if USD.contains(&currency) {
+            return "usd";
+        }
+        self.conversions.get(currency).map_or("trades", QuoteConversion::source)
+    }
+
+    // Whether a trade of `market_id` prices its base currency: always without a
+    // conversion, only on the configured market with one
+    fn learns(&self, currency: &str, market_id: &str) -> bool {
+        match self.conversions.get(currency) {
+            None => true,
+            Some(QuoteConversion::Market(market)) => market.to_string() == market_id,
+            Some(_) => false,
+        }
+    }
+
+    // USD fields of the trades which waited for a price of `currency`
+    fn corrections(&mut self, currency: &str, currency_usd: f64) -> Vec<ControlMessage> {
+        let Some(unpriced) = self.unpriced.remove(currency) else {
+            return Vec::new();
+        };
+        let source = self.source(currency);
+        unpriced
+            .into_iter()
+            .map(|trade| {
+                let price_usd = trade.price_double * currency_usd;
+                ControlMessage::TradeCorrection(TradeCorrection {
+                    market_id: trade.market_id,
+                    trade_id: trade.trade_id,
+                    price_usd: Some(price_usd),
+                    notional_usd: Some(price_usd * trade.quantity_double),
+                    quote_usd: Some(currency_usd),
+                    usd_source: Some(source.to_string()),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+}
+
+impl Enricher for UsdEnricher {
+    fn name(&self) -> &'static str {
+        "usd"
+    }
+
+    fn enrich<'a>(&'a mut self, _fill: &'a Fill<'a>, trade: &'a mut Trade) -> BoxFuture<'a, Vec<ControlMessage>> {
+        if let Some((base, quote)) = currencies(&trade.market_name) {
+            let quote_usd = if USD.contains(&quote) {
+                Some(1.0)
+            } else {
+                self.prices.get(quote).copied()
+            };
+            match quote_usd {
+                Some(quote_usd) => {
+                    let price_usd = trade.price_double * quote_usd;
+                    trade.price_usd = Some(price_usd);
+                    trade.notional_usd = Some(price_usd * trade.quantity_double);
+                    trade.quote_usd = Some(quote_usd);
+                    trade.usd_source = Some(self.source(quote).to_string());
+                    if !self.learns(base, &trade.market_id) {
+                        return async { Vec::new() }.boxed();
+                    }
+                    self.prices.insert(base.to_string(), price_usd);
+                    let corrections = self.corrections(base, price_usd);
+                    return async { corrections }.boxed();
+                }
+                None => {
+                    let unpriced = self.unpriced.entry(quote.to_string()).or_default();
+                    if unpriced.len() >= UNPRICED_PER_CURRENCY {
+                        unpriced.pop_front();
+                    }
+                    unpriced.push_back(Unpriced {
+                        market_id: trade.market_id.clone(),
+                        trade_id: trade.trade_id.clone(),
+                        price_double: trade.price_double,
+                        quantity_double: trade.quantity_double,
+                    });
+                }
+            }
+        }
+        async { Vec::new() }.boxed()
+    }
+
+    fn observe(&mut self, message: &ControlMessage) {
+        let ControlMessage::ConversionPrice { currency, price, .. } = message else {
+            return;
+        };
+        if !matches!(self.conversions.get(currency), Some(QuoteConversion::Oracle(_))) {
+            return;
+        }
+        self.prices.insert(currency.clone(), *price);
+        let corrections = self.corrections(currency, *price);
+        self.ready.extend(corrections);
+    }
+
+    fn idle(&self) -> bool {
+        self.ready.is_empty()
+    }
+
+    fn next(&mut self) -> BoxFuture<'_, Vec<ControlMessage>> {
+        let ready = std::mem::take(&mut self.ready);
+        async { ready }.boxed()
+    }
+}
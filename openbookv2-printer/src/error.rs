@@ -0,0 +1,132 @@
+use crate::exit;
+use crate::metrics::{metrics, Metrics};
+use solana_program::pubkey::Pubkey;
+use std::time::Duration;
+use thiserror::Error;
+
+// Failures by the part of the pipeline they happen in, every recorded one is counted in
+// openbook_errors{category,kind}
+#[derive(Debug, Error)]
+pub enum Error {
+    // invalid settings or markets, restarting does not help
+    #[error("{0}")]
+    Config(String),
+    #[error("{name} source: {error}")]
+    Source { name: &'static str, error: SourceError },
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+    #[error("{name} enricher: {error}")]
+    Enrich { name: &'static str, error: EnrichError },
+    #[error("sink {name}: {error}")]
+    Sink { name: String, error: SinkError },
+}
+
+#[derive(Debug, Error)]
+pub enum SourceError {
+    #[error("connect failed: {0}")]
+    Connect(String),
+    #[error("subscribe failed: {0}")]
+    Subscribe(String),
+    #[error("stream failed: {0}")]
+    Stream(String),
+    #[error("stream ended")]
+    Ended,
+    #[error("rpc failed: {0}")]
+    Rpc(String),
+}
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("{signature} has a fill of market {market}, which is not watched")]
+    UnknownMarket { market: String, signature: String },
+}
+
+#[derive(Debug, Error)]
+pub enum EnrichError {
+    #[error("timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("lookup failed: {0}")]
+    Lookup(String),
+    #[error("OpenOrders account {account} of a {market} fill belongs to market {actual}")]
+    MarketMismatch {
+        account: Pubkey,
+        market: String,
+        actual: Pubkey,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum SinkError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Zmq(#[from] zmq::Error),
+    #[error("invalid payload: {0}")]
+    Payload(#[from] serde_json::Error),
+    #[error("could not open {target}: {reason}")]
+    Open { target: String, reason: String },
+    #[error("{0}")]
+    Closed(&'static str),
+    // the kafka producer refused a trade, e.g. its queue is full
+    #[error("kafka: {0}")]
+    Kafka(String),
+    // never comes back, e.g. stdout once its reader exits, the sink is removed
+    #[error("{0}")]
+    Gone(&'static str),
+}
+
+impl Error {
+    pub fn category(&self) -> &'static str {
+        match self {
+            Error::Config(_) => "config",
+            Error::Source { .. } => "source",
+            Error::Decode(_) => "decode",
+            Error::Enrich { .. } => "enrich",
+            Error::Sink { .. } => "sink",
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Config(_) => "invalid",
+            Error::Source { error, .. } => match error {
+                SourceError::Connect(_) => "connect",
+                SourceError::Subscribe(_) => "subscribe",
+                SourceError::Stream(_) => "stream",
+                SourceError::Ended => "ended",
+                SourceError::Rpc(_) => "rpc",
+            },
+            Error::Decode(DecodeError::UnknownMarket { .. }) => "unknown_market",
+            Error::Enrich { error, .. } => match error {
+                EnrichError::Timeout(_) => "timeout",
+                EnrichError::Lookup(_) => "lookup",
+                EnrichError::MarketMismatch { .. } => "market_mismatch",
+            },
+            Error::Sink { error, .. } => match error {
+                SinkError::Io(_) => "io",
+                SinkError::Zmq(_) => "zmq",
+                SinkError::Payload(_) => "payload",
+                SinkError::Open { .. } => "open",
+                SinkError::Closed(_) => "closed",
+                SinkError::Kafka(_) => "kafka",
+                SinkError::Gone(_) => "gone",
+            },
+        }
+    }
+
+    // What the binary exits with when this error stops it, see exit.rs
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => exit::CONFIG,
+            Error::Source { .. } | Error::Decode(_) | Error::Enrich { .. } => exit::SOURCE,
+            Error::Sink { .. } => exit::SINK,
+        }
+    }
+
+    pub fn record(&self) {
+        metrics()
+            .errors
+            .get_or_create(&Metrics::error(self.category(), self.kind()))
+            .inc();
+    }
+}
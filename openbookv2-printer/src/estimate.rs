@@ -0,0 +1,286 @@
+use crate::check;
+use crate::clock::now_ms;
+use crate::config::{Config, EnricherKind, SourceKind};
+use crate::exit;
+use crate::oracle::Oracles;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::stream::{self, StreamExt};
+use log::{info, warn};
+use openbookv2_decoder::{parse_name, LogFormat, MarketParams, Trade};
+use openbookv2_generated::state::Market;
+use serde_json::json;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::{EncodedTransaction, UiTransactionEncoding};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+
+// getTransaction requests in flight
+const PARALLELISM: usize = 8;
+// mainnet averages about 400 ms per slot
+const SLOTS_PER_SECOND: f64 = 2.5;
+// getMultipleAccounts takes at most 100 keys
+const ACCOUNTS_PER_REQUEST: usize = 100;
+const GB: f64 = 1e9;
+
+// Successful transactions of one market in the window, newest first
+struct Activity {
+    // signature and block time
+    transactions: Vec<(String, i64)>,
+    // the window was cut short by --transactions, the rates are over the span read
+    truncated: bool,
+    seconds: f64,
+}
+
+// What the sampled transactions of one market looked like
+#[derive(Default)]
+struct Sample {
+    transactions: u64,
+    // the raw transaction plus its logs, about what geyser sends per transaction
+    bytes: u64,
+    fills: u64,
+    trade_bytes: u64,
+    open_orders: HashSet<Pubkey>,
+}
+
+async fn activity(client: &RpcClient, market: &Pubkey, since: i64, limit: usize) -> Result<Activity, String> {
+    let mut transactions = Vec::new();
+    let mut before = None;
+    let mut truncated = false;
+    'pages: loop {
+        if transactions.len() >= limit {
+            truncated = true;
+            break;
+        }
+        let config = GetConfirmedSignaturesForAddress2Config {
+            before,
+            until: None,
+            limit: Some((limit - transactions.len()).min(1000)),
+            commitment: Some(CommitmentConfig::confirmed()),
+        };
+        let page = client
+            .get_signatures_for_address_with_config(market, config)
+            .await
+            .map_err(|err| err.to_string())?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        before = Some(Signature::from_str(&last.signature).map_err(|err| err.to_string())?);
+        for status in page {
+            let Some(block_time) = status.block_time else {
+                continue;
+            };
+            if block_time < since {
+                break 'pages;
+            }
+            if status.err.is_none() {
+                transactions.push((status.signature, block_time));
+            }
+        }
+    }
+    let seconds = match (truncated, transactions.first(), transactions.last()) {
+        (true, Some((_, newest)), Some((_, oldest))) => (newest - oldest).max(1) as f64,
+        _ => ((now_ms() / 1000) as i64 - since) as f64,
+    };
+    Ok(Activity {
+        transactions,
+        truncated,
+        seconds,
+    })
+}
+
+// Reads up to `samples` of the transactions, spread over the window
+async fn sample(
+    client: &RpcClient,
+    key: &Pubkey,
+    market: &Market,
+    activity: &Activity,
+    samples: usize,
+    log_format: &LogFormat,
+) -> Sample {
+    let transaction_config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(CommitmentConfig::confirmed()),
+        max_supported_transaction_version: Some(0),
+    };
+    let step = (activity.transactions.len() / samples.max(1)).max(1);
+    let signatures: Vec<&String> = activity
+        .transactions
+        .iter()
+        .step_by(step)
+        .take(samples)
+        .map(|(signature, _)| signature)
+        .collect();
+    let params = MarketParams::from(market);
+    let name = parse_name(&market.name).replace('\0', "");
+    let mut sample = Sample::default();
+    let mut transactions = stream::iter(signatures)
+        .map(|signature| async move {
+            let parsed = Signature::from_str(signature).map_err(|err| err.to_string())?;
+            let transaction = client
+                .get_transaction_with_config(&parsed, transaction_config)
+                .await
+                .map_err(|err| format!("getTransaction {}: {}", signature, err))?;
+            Ok::<_, String>((signature, transaction))
+        })
+        .buffer_unordered(PARALLELISM);
+    while let Some(result) = transactions.next().await {
+        let (signature, transaction) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                warn!("{}", err);
+                continue;
+            }
+        };
+        sample.transactions += 1;
+        if let EncodedTransaction::Binary(data, _) = &transaction.transaction.transaction {
+            sample.bytes += STANDARD.decode(data).map_or(0, |data| data.len() as u64);
+        }
+        let Some(OptionSerializer::Some(logs)) = transaction.transaction.meta.map(|meta| meta.log_messages) else {
+            continue;
+        };
+        sample.bytes += logs.iter().map(|log| log.len() as u64).sum::<u64>();
+        for fill_log in logs.iter().filter_map(|log| log_format.decode_fill_log(log)) {
+            if fill_log.market != *key {
+                continue;
+            }
+            sample.fills += 1;
+            sample.open_orders.insert(fill_log.maker);
+            sample.open_orders.insert(fill_log.taker);
+            // without the fields of the enrichers
+            let trade = Trade::new(&fill_log, &params, name.clone(), signature.clone());
+            sample.trade_bytes += serde_json::to_string(&trade).unwrap().len() as u64;
+        }
+    }
+    sample
+}
+
+fn per(total: u64, count: u64) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        total as f64 / count as f64
+    }
+}
+
+// Calls per second of the parts of the configuration which poll the RPC node. Owner
+// lookups are an upper bound, the OpenOrders accounts already resolved are cached.
+fn rpc_rates(
+    config: &Config,
+    markets: &BTreeMap<Pubkey, Market>,
+    open_orders_per_second: f64,
+) -> BTreeMap<&'static str, f64> {
+    let mut rates = BTreeMap::new();
+    if matches!(config.source, SourceKind::Poll) {
+        rates.insert("getSlot", 1000.0 / config.poll_interval_ms.max(1) as f64);
+        rates.insert("getBlock", SLOTS_PER_SECOND);
+    }
+    if config.enrichers.contains(&EnricherKind::Owners) {
+        rates.insert("getAccountInfo (owners)", open_orders_per_second);
+    }
+    let mut multiple_accounts = 0.0;
+    if config.enrichers.contains(&EnricherKind::Tif) {
+        let requests = (markets.len() * 2).div_ceil(ACCOUNTS_PER_REQUEST);
+        multiple_accounts += requests as f64 * 1000.0 / config.book_poll_ms.max(1) as f64;
+    }
+    if config.oracle_interval > 0 {
+        let requests = Oracles::new(markets, &config.quote_conversions).keys().len().div_ceil(ACCOUNTS_PER_REQUEST);
+        multiple_accounts += requests as f64 / config.oracle_interval as f64;
+    }
+    if multiple_accounts > 0.0 {
+        rates.insert("getMultipleAccounts", multiple_accounts);
+    }
+    rates
+}
+
+// `estimate`: reads the transactions of the configured markets over the last `minutes`
+// (at most `limit` per market) and samples `samples` of them per market in full, then
+// projects the message rates and bandwidth of the source and the published feed and the
+// RPC calls of the configuration. For sizing geyser plans and hardware before going live.
+pub async fn run(config: &Config, minutes: u64, limit: usize, samples: usize) -> i32 {
+    let client = RpcClient::new(config.rpc_url.clone());
+    let (checks, markets) = check::markets(&client, config).await;
+    if markets.len() < config.market_keys.len() {
+        let failed: Vec<String> = checks
+            .iter()
+            .filter(|check| check["ok"] == false)
+            .map(|check| format!("{}: {}", check["check"], check["error"]))
+            .collect();
+        exit::fatal(exit::SOURCE, &format!("could not load the markets: {}", failed.join("; ")));
+    }
+    let log_format = LogFormat::new(config.log_prefixes.clone());
+    let since = (now_ms() / 1000) as i64 - (minutes * 60) as i64;
+    let mut report = Vec::new();
+    // signatures of all markets, a transaction touching two of them is one geyser message
+    let mut signatures: HashMap<String, i64> = HashMap::new();
+    let mut transactions_per_second = 0.0;
+    let mut fills_per_second = 0.0;
+    let mut source_bytes_per_second = 0.0;
+    let mut feed_bytes_per_second = 0.0;
+    let mut open_orders_per_second = 0.0;
+    for (key, market) in markets.iter() {
+        let name = parse_name(&market.name).replace('\0', "");
+        info!("reading the last {} minutes of {}", minutes, name);
+        let activity = match activity(&client, key, since, limit).await {
+            Ok(activity) => activity,
+            Err(err) => exit::fatal(exit::SOURCE, &format!("getSignaturesForAddress {}: {}", key, err)),
+        };
+        let sample = sample(&client, key, market, &activity, samples, &log_format).await;
+        let rate = activity.transactions.len() as f64 / activity.seconds;
+        let fills_per_transaction = per(sample.fills, sample.transactions);
+        transactions_per_second += rate;
+        fills_per_second += rate * fills_per_transaction;
+        source_bytes_per_second += rate * per(sample.bytes, sample.transactions);
+        feed_bytes_per_second += rate * fills_per_transaction * per(sample.trade_bytes, sample.fills);
+        open_orders_per_second += rate * per(sample.open_orders.len() as u64, sample.transactions);
+        signatures.extend(activity.transactions.iter().cloned());
+        report.push(json!({
+            "marketId": key.to_string(),
+            "marketName": name,
+            "transactions": activity.transactions.len(),
+            "truncated": activity.truncated,
+            "sampled": sample.transactions,
+            "transactionsPerSecond": rate,
+            "fillsPerTransaction": fills_per_transaction,
+            "fillsPerSecond": rate * fills_per_transaction,
+        }));
+    }
+    // transactions of several markets are counted once per market above
+    let total: usize = report.iter().map(|market| market["transactions"].as_u64().unwrap() as usize).sum();
+    let shared = per(signatures.len() as u64, total as u64);
+    let mut per_minute: HashMap<i64, u64> = HashMap::new();
+    for block_time in signatures.values() {
+        *per_minute.entry(block_time / 60).or_default() += 1;
+    }
+    let peak = per_minute.values().max().copied().unwrap_or(0) as f64 / 60.0;
+    let rpc = rpc_rates(config, &markets, open_orders_per_second);
+    let report = json!({
+        "minutes": minutes,
+        "markets": report,
+        "source": {
+            "transactionsPerSecond": transactions_per_second * shared,
+            "peakTransactionsPerSecond": peak,
+            "bytesPerSecond": source_bytes_per_second * shared,
+            "gbPerDay": source_bytes_per_second * shared * 86400.0 / GB,
+        },
+        "feed": {
+            "tradesPerSecond": fills_per_second,
+            "tradesPerDay": fills_per_second * 86400.0,
+            "bytesPerSecond": feed_bytes_per_second,
+            "gbPerDay": feed_bytes_per_second * 86400.0 / GB,
+        },
+        "rpc": {
+            "callsPerSecond": rpc.values().sum::<f64>(),
+            "callsPerDay": rpc.values().sum::<f64>() * 86400.0,
+            "byMethod": rpc,
+        },
+    });
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    exit::OK
+}
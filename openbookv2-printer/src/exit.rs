@@ -0,0 +1,53 @@
+use crate::error::Error;
+use crate::postmortem;
+use log::error;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Exit codes of the printer, so a supervisor can tell configuration failures (restarting
+// does not help) from failures a restart may cure
+pub const OK: i32 = 0;
+// `config check` found a problem
+pub const CHECK_FAILED: i32 = 1;
+// invalid flags, environment or files, markets which can not be used; clap exits with 2
+// on usage errors as well
+pub const CONFIG: i32 = 2;
+// the source or the RPC node failed, restartable
+pub const SOURCE: i32 = 3;
+// a sink could not be set up or failed for good, restartable
+pub const SINK: i32 = 4;
+// a panic with --panic-policy exit
+pub const PANIC: i32 = 5;
+
+static FAIL_FAST: AtomicBool = AtomicBool::new(false);
+
+pub fn set_fail_fast(fail_fast: bool) {
+    FAIL_FAST.store(fail_fast, Ordering::Relaxed);
+}
+
+pub fn fatal(code: i32, message: &str) -> ! {
+    error!("{}, exiting with {}", message, code);
+    postmortem::dump(message);
+    std::process::exit(code)
+}
+
+// Called where a source reconnects or a sink breaker opens. Normally the printer retries,
+// with --fail-fast it exits with the code of the error and leaves the restart to the
+// supervisor.
+pub fn retry_or_exit(err: &Error) {
+    if FAIL_FAST.load(Ordering::Relaxed) {
+        fatal(err.exit_code(), &format!("{} (--fail-fast)", err));
+    }
+}
+
+// Config::new panics on invalid values, those exit with CONFIG instead of a panic's 101
+pub fn config_errors_exit() -> Box<dyn Fn(&std::panic::PanicHookInfo<'_>) + Send + Sync> {
+    Box::new(|info| {
+        let message = info
+            .payload()
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .or_else(|| info.payload().downcast_ref::<&str>().copied())
+            .unwrap_or("invalid configuration");
+        fatal(CONFIG, &format!("invalid configuration: {}", message))
+    })
+}
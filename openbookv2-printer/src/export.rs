@@ -0,0 +1,346 @@
+use crate::archive;
+use crate::catch_up::TxIndexes;
+use crate::checksums::to_hex;
+use crate::clock::now_ms;
+use crate::config::{Config, ExportSource};
+use crate::exit;
+use anchor_lang::AnchorDeserialize;
+use chrono::{Duration, NaiveDate};
+use futures::stream::{self, StreamExt};
+use log::{info, warn};
+use openbookv2_decoder::{parse_name, FillPosition, LogFormat, MarketParams, Trade};
+use openbookv2_generated::state::Market;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::{read_keypair_file, Signature, Signer};
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::UiTransactionEncoding;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+// version of the file layout below, raised on any change readers have to know about
+const FORMAT_VERSION: u32 = 1;
+// getTransaction requests in flight
+const PARALLELISM: usize = 8;
+
+// First and last line of an export, the trades as published are in between. The trailer
+// holds the SHA-256 of every byte before it and the ed25519 signature of that digest.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", rename_all_fields = "camelCase")]
+enum Record {
+    ExportHeader {
+        format_version: u32,
+        market_id: String,
+        market_name: String,
+        // first and last day, both included
+        from: String,
+        to: String,
+        // the unix seconds the trades are in, end excluded
+        start: u64,
+        end: u64,
+        source: String,
+        created_at: u64,
+        printer_version: String,
+    },
+    ExportTrailer {
+        trades: u64,
+        sha256: String,
+        signer: String,
+        signature: String,
+    },
+}
+
+fn day(date: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .unwrap_or_else(|_| exit::fatal(exit::CONFIG, &format!("{} is not a YYYY-MM-DD date", date)))
+}
+
+fn midnight(date: NaiveDate) -> u64 {
+    date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp() as u64
+}
+
+// A fill is its transaction and log line, every trade id scheme ends in the log index.
+// None for trades archived without a trade id.
+fn fill_key(trade: &Trade) -> Option<(String, String)> {
+    let (_, log_index) = trade.trade_id.rsplit_once(':')?;
+    Some((trade.signature.clone(), log_index.to_string()))
+}
+
+// The archived trades of the market in [start, end) in publishing order, which is their
+// order in the blocks, and the name of the market from them. A fill archived twice, e.g.
+// around a restart, is exported once.
+fn from_archive(dir: &str, market_id: &str, start: u64, end: u64) -> (String, Vec<Trade>) {
+    let mut trades = Vec::new();
+    let mut seen = HashSet::new();
+    archive::replay(Path::new(dir), &[market_id.to_string()], start, |trade| {
+        if trade.market_id != market_id || trade.time_stamp >= end {
+            return;
+        }
+        if fill_key(&trade).map_or(true, |key| seen.insert(key)) {
+            trades.push(trade);
+        }
+    });
+    // a day's file is in order, a slot can only straddle midnight
+    trades.sort_by_key(|trade| trade.slot);
+    let name = trades.first().map_or(String::new(), |trade: &Trade| trade.market_name.clone());
+    (name, trades)
+}
+
+// Signatures of the successful transactions of the market in [start, end), newest first.
+// Pages back from the newest transaction, so old ranges take many pages.
+async fn signatures(client: &RpcClient, market: &Pubkey, start: u64, end: u64) -> Result<Vec<String>, String> {
+    let mut signatures = Vec::new();
+    let mut before = None;
+    'pages: loop {
+        let config = GetConfirmedSignaturesForAddress2Config {
+            before,
+            until: None,
+            limit: Some(1000),
+            commitment: Some(CommitmentConfig::finalized()),
+        };
+        let page = client
+            .get_signatures_for_address_with_config(market, config)
+            .await
+            .map_err(|err| err.to_string())?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        before = Some(Signature::from_str(&last.signature).map_err(|err| err.to_string())?);
+        for status in page {
+            let Some(block_time) = status.block_time else {
+                warn!("transaction {} has no block time, it is not exported", status.signature);
+                continue;
+            };
+            if (block_time as u64) < start {
+                break 'pages;
+            }
+            if (block_time as u64) < end && status.err.is_none() {
+                signatures.push(status.signature);
+            }
+        }
+    }
+    Ok(signatures)
+}
+
+// The fills of the market in [start, end) read again from its transactions, converted with
+// the current parameters of the market and without the fields of the enrichers. Fails on
+// any transaction which can not be read, an export is complete or not written.
+async fn backfill(config: &Config, key: &Pubkey, start: u64, end: u64) -> Result<(String, Vec<Trade>), String> {
+    let client = RpcClient::new(config.rpc_url.clone());
+    let account = client.get_account(key).await.map_err(|err| err.to_string())?;
+    let mut fields = account.data.get(8..).ok_or("too few bytes for a market")?;
+    let market = Market::deserialize(&mut fields).map_err(|err| err.to_string())?;
+    let params = MarketParams::from(&market);
+    let name = parse_name(&market.name).replace('\0', "");
+    let log_format = LogFormat::new(config.log_prefixes.clone());
+    let signatures = signatures(&client, key, start, end).await?;
+    info!("reading {} transactions of {}", signatures.len(), name);
+    let transaction_config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(CommitmentConfig::finalized()),
+        max_supported_transaction_version: Some(0),
+    };
+    let client = &client;
+    let mut transactions = stream::iter(signatures)
+        .map(|signature| async move {
+            let parsed = Signature::from_str(&signature).map_err(|err| err.to_string())?;
+            let transaction = client
+                .get_transaction_with_config(&parsed, transaction_config)
+                .await
+                .map_err(|err| format!("getTransaction {}: {}", signature, err))?;
+            Ok::<_, String>((signature, transaction))
+        })
+        .buffered(PARALLELISM);
+    // with the block index and log index of the fill
+    let mut trades = Vec::new();
+    let mut tx_indexes = TxIndexes::default();
+    while let Some(result) = transactions.next().await {
+        let (signature, transaction) = result?;
+        let Some(OptionSerializer::Some(logs)) = transaction.transaction.meta.map(|meta| meta.log_messages) else {
+            continue;
+        };
+        // numbered like the live feed, over the deduplicated fills of all markets
+        let mut seen = HashSet::new();
+        let fills: Vec<_> = logs
+            .iter()
+            .enumerate()
+            .filter_map(|(log_index, log)| log_format.decode_fill_log(log).map(|fill_log| (log_index, fill_log)))
+            .filter(|(_, fill_log)| seen.insert((fill_log.market, fill_log.seq_num)))
+            .collect();
+        let fill_count = fills.len() as u32;
+        if !fills.iter().any(|(_, fill_log)| fill_log.market == *key) {
+            continue;
+        }
+        let tx_index = tx_indexes
+            .get(client, transaction.slot, &signature, CommitmentConfig::finalized())
+            .await?;
+        for (fill_index, (log_index, fill_log)) in fills.into_iter().enumerate() {
+            if fill_log.market != *key {
+                continue;
+            }
+            let position = FillPosition {
+                slot: transaction.slot,
+                tx_index,
+                log_index: log_index as u32,
+            };
+            let mut trade = Trade::new(&fill_log, &params, name.clone(), signature.clone());
+            trade.trade_id = config.trade_id.trade_id(&fill_log, &signature, &position);
+            trade.slot = transaction.slot;
+            (trade.fill_index, trade.fill_count) = (fill_index as u32, fill_count);
+            trade.maker_rebate = market.maker_fee < 0;
+            trades.push(((position.slot, position.tx_index, position.log_index), trade));
+        }
+    }
+    // the order of the fills in the blocks, oldest first
+    trades.sort_by_key(|(position, _)| *position);
+    Ok((name, trades.into_iter().map(|(_, trade)| trade).collect()))
+}
+
+// `export`: writes the trades of `market` from the first second of `from` to the last of
+// `to` (UTC) to one file, oldest first, and signs it with the keypair. Prints the file, the
+// number of trades and the digest as JSON.
+pub async fn run(
+    config: &Config,
+    market: &str,
+    from: &str,
+    to: &str,
+    source: Option<ExportSource>,
+    keypair: &str,
+    out: Option<&str>,
+) -> i32 {
+    let key = Pubkey::from_str(market)
+        .unwrap_or_else(|_| exit::fatal(exit::CONFIG, &format!("{} is not a market pubkey", market)));
+    let (first, last) = (day(from), day(to));
+    if last < first {
+        exit::fatal(exit::CONFIG, "--to is before --from");
+    }
+    let (start, end) = (midnight(first), midnight(last + Duration::days(1)));
+    let keypair = read_keypair_file(keypair)
+        .unwrap_or_else(|err| exit::fatal(exit::CONFIG, &format!("could not read the keypair {}: {}", keypair, err)));
+    let source = source.unwrap_or(match config.archive_dir {
+        Some(_) => ExportSource::Archive,
+        None => ExportSource::Backfill,
+    });
+    let (market_name, trades) = match source {
+        ExportSource::Archive => {
+            let Some(dir) = config.archive_dir.as_deref() else {
+                exit::fatal(exit::CONFIG, "--source archive needs --archive-dir");
+            };
+            from_archive(dir, market, start, end)
+        }
+        ExportSource::Backfill => match backfill(config, &key, start, end).await {
+            Ok(backfilled) => backfilled,
+            Err(err) => exit::fatal(exit::SOURCE, &format!("could not backfill {}: {}", market, err)),
+        },
+    };
+    let path = out.map_or_else(|| format!("export-{}-{}-{}.jsonl", market, from, to), str::to_string);
+    let header = Record::ExportHeader {
+        format_version: FORMAT_VERSION,
+        market_id: market.to_string(),
+        market_name: market_name.replace('\0', ""),
+        from: first.to_string(),
+        to: last.to_string(),
+        start,
+        end,
+        source: format!("{:?}", source).to_lowercase(),
+        created_at: (now_ms() / 1000) as u64,
+        printer_version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    let file = File::create(&path)
+        .unwrap_or_else(|err| exit::fatal(exit::SINK, &format!("could not create {}: {}", path, err)));
+    let mut writer = BufWriter::new(file);
+    let mut digest = Sha256::new();
+    let lines = std::iter::once(serde_json::to_string(&header).unwrap())
+        .chain(trades.iter().map(|trade| serde_json::to_string(trade).unwrap()));
+    for line in lines {
+        let line = format!("{}\n", line);
+        digest.update(line.as_bytes());
+        if let Err(err) = writer.write_all(line.as_bytes()) {
+            exit::fatal(exit::SINK, &format!("could not write {}: {}", path, err));
+        }
+    }
+    let digest: [u8; 32] = digest.finalize().into();
+    let trailer = Record::ExportTrailer {
+        trades: trades.len() as u64,
+        sha256: to_hex(&digest),
+        signer: keypair.pubkey().to_string(),
+        signature: keypair.sign_message(&digest).to_string(),
+    };
+    let written = writeln!(writer, "{}", serde_json::to_string(&trailer).unwrap()).and_then(|_| writer.flush());
+    if let Err(err) = written {
+        exit::fatal(exit::SINK, &format!("could not write {}: {}", path, err));
+    }
+    println!(
+        "{}",
+        json!({
+            "file": path,
+            "trades": trades.len(),
+            "sha256": to_hex(&digest),
+            "signer": keypair.pubkey().to_string(),
+        })
+    );
+    exit::OK
+}
+
+// Recomputes the digest of everything before the trailer and checks the count and the
+// signature against the signer in the trailer. Which signers to trust is up to the reader.
+fn verify_file(path: &str) -> Result<(String, u64), String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let mut digest = Sha256::new();
+    let mut lines = 0u64;
+    let mut previous: Option<String> = None;
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|err| err.to_string())?;
+        if let Some(previous) = previous.replace(line) {
+            digest.update(format!("{}\n", previous).as_bytes());
+            lines += 1;
+        }
+    }
+    let Some(Ok(Record::ExportTrailer {
+        trades,
+        sha256,
+        signer,
+        signature,
+    })) = previous.map(|line| serde_json::from_str(&line))
+    else {
+        return Err("the last line is not an export_trailer".to_string());
+    };
+    let digest: [u8; 32] = digest.finalize().into();
+    if to_hex(&digest) != sha256 {
+        return Err(format!("sha256 is {}, the trailer says {}", to_hex(&digest), sha256));
+    }
+    // the header is not a trade
+    if lines.saturating_sub(1) != trades {
+        return Err(format!("{} trades, the trailer says {}", lines.saturating_sub(1), trades));
+    }
+    let signer_key = Pubkey::from_str(&signer).map_err(|err| format!("signer {}: {}", signer, err))?;
+    let signature = Signature::from_str(&signature).map_err(|err| format!("signature: {}", err))?;
+    if !signature.verify(signer_key.as_ref(), &digest) {
+        return Err(format!("the signature is not by {}", signer));
+    }
+    Ok((signer, trades))
+}
+
+// `verify-export`: prints the result as JSON, exits with the check failed code when the
+// file was changed or the signature does not match
+pub fn verify(path: &str) -> i32 {
+    match verify_file(path) {
+        Ok((signer, trades)) => {
+            println!("{}", json!({"file": path, "ok": true, "signer": signer, "trades": trades}));
+            exit::OK
+        }
+        Err(err) => {
+            println!("{}", json!({"file": path, "ok": false, "error": err}));
+            exit::CHECK_FAILED
+        }
+    }
+}
@@ -0,0 +1,438 @@
+use openbookv2_decoder::Trade;
+use std::fmt;
+use std::str::FromStr;
+
+// Boolean expressions over trades, e.g.
+// market == 'SOL-USDC' && notional_usd > 10000 && (side == 'buy' || maker == '<pubkey>')
+#[derive(Clone, Debug)]
+pub struct Expr {
+    source: String,
+    root: Node,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Num(f64),
+    Str(String),
+    Bool(bool),
+    // e.g. notional_usd of a market not quoted in USD, compares false with everything
+    Null,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    Market,
+    MarketId,
+    Base,
+    Quote,
+    Side,
+    Price,
+    Size,
+    Notional,
+    NotionalUsd,
+    Maker,
+    Taker,
+    Slot,
+    FeedSeq,
+}
+
+const FIELDS: &str = "market, market_id, base, quote, side, price, size, notional, notional_usd, maker, taker, slot, feed_seq";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Literal(Value),
+    Field(Field),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Compare(Box<Node>, CmpOp, Box<Node>),
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        Some(match name {
+            "market" => Field::Market,
+            "market_id" => Field::MarketId,
+            "base" => Field::Base,
+            "quote" => Field::Quote,
+            "side" => Field::Side,
+            "price" => Field::Price,
+            "size" => Field::Size,
+            "notional" => Field::Notional,
+            "notional_usd" => Field::NotionalUsd,
+            "maker" => Field::Maker,
+            "taker" => Field::Taker,
+            "slot" => Field::Slot,
+            "feed_seq" => Field::FeedSeq,
+            _ => return None,
+        })
+    }
+
+    fn value(&self, trade: &Trade) -> Value {
+        match self {
+            Field::Market => Value::Str(trade.market_name.clone()),
+            Field::MarketId => Value::Str(trade.market_id.clone()),
+            // the currencies of the market name, e.g. SOL and USDC of SOL-USDC
+            Field::Base => Value::Str(market_currencies(trade).0.to_string()),
+            Field::Quote => Value::Str(market_currencies(trade).1.to_string()),
+            Field::Side => Value::Str(if trade.taker_side == 0 { "buy" } else { "sell" }.to_string()),
+            Field::Price => Value::Num(trade.price_double),
+            Field::Size => Value::Num(trade.quantity_double),
+            Field::Notional => Value::Num(trade.price_double * trade.quantity_double),
+            Field::NotionalUsd => {
+                // set by the usd enricher, also for markets quoted in other currencies
+                if let Some(notional_usd) = trade.notional_usd {
+                    return Value::Num(notional_usd);
+                }
+                if matches!(market_currencies(trade).1, "USDC" | "USDT" | "USD") {
+                    Value::Num(trade.price_double * trade.quantity_double)
+                } else {
+                    Value::Null
+                }
+            }
+            Field::Maker => Value::Str(trade.maker_owner.clone()),
+            Field::Taker => Value::Str(trade.taker_owner.clone()),
+            Field::Slot => Value::Num(trade.slot as f64),
+            Field::FeedSeq => Value::Num(trade.feed_seq as f64),
+        }
+    }
+}
+
+// Base and quote currency from the market name, e.g. SOL-USDC or SOL/USDC, the quote is
+// empty for names without a separator
+fn market_currencies(trade: &Trade) -> (&str, &str) {
+    let name = trade.market_name.trim_end_matches('\0');
+    name.rsplit_once(['-', '/']).unwrap_or((name, ""))
+}
+
+impl Node {
+    fn eval(&self, trade: &Trade) -> Value {
+        match self {
+            Node::Literal(value) => value.clone(),
+            Node::Field(field) => field.value(trade),
+            Node::Not(node) => Value::Bool(!node.eval(trade).truthy()),
+            Node::And(left, right) => Value::Bool(left.eval(trade).truthy() && right.eval(trade).truthy()),
+            Node::Or(left, right) => Value::Bool(left.eval(trade).truthy() || right.eval(trade).truthy()),
+            Node::Compare(left, op, right) => Value::Bool(compare(&left.eval(trade), *op, &right.eval(trade))),
+        }
+    }
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        matches!(self, Value::Bool(true))
+    }
+}
+
+fn compare(left: &Value, op: CmpOp, right: &Value) -> bool {
+    let ordering = match (left, right) {
+        (Value::Num(a), Value::Num(b)) => a.partial_cmp(b),
+        (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+    let Some(ordering) = ordering else {
+        return false;
+    };
+    match op {
+        CmpOp::Eq => ordering.is_eq(),
+        CmpOp::Ne => ordering.is_ne(),
+        CmpOp::Gt => ordering.is_gt(),
+        CmpOp::Ge => ordering.is_ge(),
+        CmpOp::Lt => ordering.is_lt(),
+        CmpOp::Le => ordering.is_le(),
+    }
+}
+
+impl Expr {
+    pub fn matches(&self, trade: &Trade) -> bool {
+        self.root.eval(trade).truthy()
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Op(CmpOp),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            ' ' | '\t' | '\n' => i += 1,
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            '&' if next == Some('&') => {
+                tokens.push(Token::And);
+                i += 2;
+            }
+            '|' if next == Some('|') => {
+                tokens.push(Token::Or);
+                i += 2;
+            }
+            '=' if next == Some('=') => {
+                tokens.push(Token::Op(CmpOp::Eq));
+                i += 2;
+            }
+            '!' if next == Some('=') => {
+                tokens.push(Token::Op(CmpOp::Ne));
+                i += 2;
+            }
+            '!' => {
+                tokens.push(Token::Not);
+                i += 1;
+            }
+            '>' | '<' => {
+                let or_equal = next == Some('=');
+                tokens.push(Token::Op(match (c, or_equal) {
+                    ('>', true) => CmpOp::Ge,
+                    ('>', false) => CmpOp::Gt,
+                    ('<', true) => CmpOp::Le,
+                    _ => CmpOp::Lt,
+                }));
+                i += if or_equal { 2 } else { 1 };
+            }
+            '\'' | '"' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&ch| ch == c)
+                    .ok_or_else(|| format!("unterminated string at {}", i))?;
+                tokens.push(Token::Str(chars[i + 1..i + 1 + end].iter().collect()));
+                i += end + 2;
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '.' => {
+                let len = chars[i..]
+                    .iter()
+                    .skip(1)
+                    .take_while(|ch| ch.is_ascii_digit() || **ch == '.' || **ch == '_')
+                    .count()
+                    + 1;
+                let text: String = chars[i..i + len].iter().filter(|ch| **ch != '_').collect();
+                let number = text.parse().map_err(|_| format!("invalid number {}", text))?;
+                tokens.push(Token::Num(number));
+                i += len;
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|ch| ch.is_ascii_alphanumeric() || **ch == '_')
+                    .count();
+                tokens.push(Token::Ident(chars[i..i + len].iter().collect()));
+                i += len;
+            }
+            c => return Err(format!("unexpected character {} at {}", c, i)),
+        }
+    }
+    Ok(tokens)
+}
+
+// or := and ('||' and)*, and := unary ('&&' unary)*, unary := '!' unary | cmp,
+// cmp := primary (op primary)?, primary := '(' or ')' | literal | field
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut node = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut node = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            node = Node::And(Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Node::Not(Box::new(self.unary()?)));
+        }
+        self.cmp()
+    }
+
+    fn cmp(&mut self) -> Result<Node, String> {
+        let left = self.primary()?;
+        if let Some(Token::Op(op)) = self.peek().cloned() {
+            self.pos += 1;
+            let right = self.primary()?;
+            return Ok(Node::Compare(Box::new(left), op, Box::new(right)));
+        }
+        Ok(left)
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Open) => {
+                let node = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(node),
+                    _ => Err("missing )".to_string()),
+                }
+            }
+            Some(Token::Str(s)) => Ok(Node::Literal(Value::Str(s))),
+            Some(Token::Num(n)) => Ok(Node::Literal(Value::Num(n))),
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Ok(Node::Literal(Value::Bool(true))),
+                "false" => Ok(Node::Literal(Value::Bool(false))),
+                _ => Field::parse(&name)
+                    .map(Node::Field)
+                    .ok_or_else(|| format!("unknown field {}, expected one of {}", name, FIELDS)),
+            },
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let root = parser.or()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(format!("unexpected {:?}", parser.tokens[parser.pos]));
+        }
+        Ok(Expr {
+            source: s.to_string(),
+            root,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn trade(market_name: &str, taker_side: u8, price: f64, size: f64) -> Trade {
+        serde_json::from_value(json!({
+            "timeStamp": 1_700_000_000,
+            "makerOwner": "maker",
+            "takerOwner": "taker",
+            "priceDouble": price,
+            "quantityDouble": size,
+            "marketId": "market",
+            "takerSide": taker_side,
+            "marketName": market_name,
+            "signature": "signature",
+            "slot": 42,
+            "feedSeq": 7,
+        }))
+        .unwrap()
+    }
+
+    fn matches(expr: &str, trade: &Trade) -> bool {
+        expr.parse::<Expr>().unwrap().matches(trade)
+    }
+
+    #[test]
+    fn compares_fields_with_literals() {
+        let trade = trade("SOL-USDC\0\0\0\0\0\0\0\0", 0, 150.0, 2.0);
+        assert!(matches("market == 'SOL-USDC\0\0\0\0\0\0\0\0'", &trade));
+        assert!(matches("base == 'SOL' && quote == \"USDC\"", &trade));
+        assert!(matches("side == 'buy' && side != 'sell'", &trade));
+        assert!(matches("price > 149.5 && price >= 150 && price < 150.5 && price <= 150", &trade));
+        assert!(matches("notional == 300 && notional_usd == 300", &trade));
+        assert!(matches("slot == 42 && feed_seq == 7 && maker == 'maker' && taker == 'taker'", &trade));
+        assert!(matches("size > 1_000 || size == 2", &trade));
+        assert!(!matches("price > 150", &trade));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let trade = trade("SOL/USDC", 1, 10.0, 1.0);
+        assert!(matches("side == 'buy' && price > 100 || quote == 'USDC'", &trade));
+        assert!(!matches("side == 'buy' && (price > 100 || quote == 'USDC')", &trade));
+        assert!(matches("!(side == 'buy') && !false", &trade));
+    }
+
+    #[test]
+    fn notional_usd_is_null_for_other_quotes() {
+        let mut trade = trade("mSOL-SOL", 0, 1.1, 10.0);
+        assert!(!matches("notional_usd > 0", &trade));
+        assert!(!matches("notional_usd <= 0", &trade));
+        trade.notional_usd = Some(1650.0);
+        assert!(matches("notional_usd == 1650", &trade));
+    }
+
+    #[test]
+    fn mismatched_types_compare_false() {
+        let trade = trade("SOL-USDC", 0, 150.0, 2.0);
+        assert!(!matches("price == '150'", &trade));
+        assert!(!matches("market != 1", &trade));
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        let err = |expr: &str| expr.parse::<Expr>().unwrap_err();
+        assert!(err("volume > 1").starts_with("unknown field volume"));
+        assert_eq!(err("market == 'SOL"), "unterminated string at 10");
+        assert_eq!(err("(price > 1"), "missing )");
+        assert_eq!(err("price > 1 price"), "unexpected Ident(\"price\")");
+        assert_eq!(err("price > "), "unexpected end of expression");
+        assert_eq!(err("price # 1"), "unexpected character # at 6");
+    }
+
+    #[test]
+    fn displays_its_source() {
+        assert_eq!("price > 1".parse::<Expr>().unwrap().to_string(), "price > 1");
+    }
+}
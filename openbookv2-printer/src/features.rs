@@ -0,0 +1,97 @@
+use crate::metrics::{metrics, Metrics};
+use crate::state;
+use log::info;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Subsystems operators can switch off at runtime to shed load (POST /features on the
+// admin server), checked per trade or tick by the stages
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    // 1 second bars (--bars)
+    Bars,
+    // the owners enricher, its RPC lookups
+    Owners,
+    // alert rules (--alert)
+    Alerts,
+    // oracle polls (--oracle-interval)
+    Oracles,
+}
+
+const FEATURES: [Feature; 4] = [Feature::Bars, Feature::Owners, Feature::Alerts, Feature::Oracles];
+
+impl Feature {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Feature::Bars => "bars",
+            Feature::Owners => "owners",
+            Feature::Alerts => "alerts",
+            Feature::Oracles => "oracles",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Feature> {
+        FEATURES.into_iter().find(|feature| feature.as_str() == name)
+    }
+}
+
+// Whether each feature is on, shared by all shards. Every change is saved, the saved
+// state wins over --disable-features at the next start.
+#[derive(Clone)]
+pub struct FeatureFlags {
+    enabled: Arc<[AtomicBool; 4]>,
+    path: Arc<Mutex<PathBuf>>,
+}
+
+impl FeatureFlags {
+    pub fn load(path: &Path, disabled: &[Feature]) -> Self {
+        let saved: BTreeMap<String, bool> = state::load(path);
+        let enabled = FEATURES.map(|feature| {
+            let enabled = saved.get(feature.as_str()).copied().unwrap_or(!disabled.contains(&feature));
+            if !enabled {
+                info!("feature {} is disabled", feature.as_str());
+            }
+            metrics().feature_enabled.get_or_create(&Metrics::feature(feature.as_str())).set(enabled as i64);
+            AtomicBool::new(enabled)
+        });
+        FeatureFlags {
+            enabled: Arc::new(enabled),
+            path: Arc::new(Mutex::new(path.to_path_buf())),
+        }
+    }
+
+    pub fn enabled(&self, feature: Feature) -> bool {
+        self.enabled[feature as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, bool> {
+        FEATURES
+            .into_iter()
+            .map(|feature| (feature.as_str().to_string(), self.enabled(feature)))
+            .collect()
+    }
+
+    // Applies "<feature>" = "true"/"false" pairs, all or nothing
+    pub fn update<'a>(&self, changes: impl Iterator<Item = (&'a String, &'a String)>) -> Result<(), String> {
+        let mut parsed = Vec::new();
+        for (name, value) in changes {
+            let feature = Feature::parse(name).ok_or_else(|| format!("unknown feature {}", name))?;
+            let enabled = value
+                .parse::<bool>()
+                .map_err(|_| format!("{} must be true or false, not {}", name, value))?;
+            parsed.push((feature, enabled));
+        }
+        // one writer at a time, so the saved file matches the flags
+        let path = self.path.lock().unwrap();
+        for (feature, enabled) in parsed {
+            if self.enabled[feature as usize].swap(enabled, Ordering::Relaxed) != enabled {
+                info!("feature {} {} at runtime", feature.as_str(), if enabled { "enabled" } else { "disabled" });
+            }
+            metrics().feature_enabled.get_or_create(&Metrics::feature(feature.as_str())).set(enabled as i64);
+        }
+        state::save(&path, &self.snapshot());
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305};
+use crate::auth::GLOBAL_TYPES;
+use openbookv2_decoder::ControlMessage;
+use serde::Deserialize;
+use solana_program::pubkey::Pubkey;
+use std::fmt;
+
+// The active key of a consumer group, "<group>=<key id>:<base64 32 byte key>"
+#[derive(Clone)]
+pub struct FeedKey {
+    pub group: String,
+    // in every envelope, so consumers know which key to use while a rotation rolls out
+    pub key_id: String,
+    key: Key,
+}
+
+impl FeedKey {
+    pub fn parse(feed_key: &str) -> Result<FeedKey, String> {
+        let (group, key) = feed_key
+            .split_once('=')
+            .ok_or_else(|| "expected <group>=<key id>:<base64 key>".to_string())?;
+        let (key_id, key) = key
+            .split_once(':')
+            .ok_or_else(|| "expected <group>=<key id>:<base64 key>".to_string())?;
+        let key = STANDARD.decode(key.trim()).map_err(|err| err.to_string())?;
+        if key.len() != 32 {
+            return Err(format!("key of group {} has {} bytes, expected 32", group.trim(), key.len()));
+        }
+        Ok(FeedKey {
+            group: group.trim().to_string(),
+            key_id: key_id.trim().to_string(),
+            key: *Key::from_slice(&key),
+        })
+    }
+}
+
+// keeps the key itself out of logs and `config check`
+impl fmt::Debug for FeedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.group, self.key_id)
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct Routing {
+    #[serde(rename = "marketId")]
+    market_id: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+}
+
+#[derive(Clone)]
+struct Group {
+    key: FeedKey,
+    cipher: XChaCha20Poly1305,
+    // market ids of --feed-group-market, empty for all markets
+    markets: Vec<String>,
+}
+
+impl Group {
+    // like a market restricted api key: messages of other markets and messages without a
+    // market which are not global are not sealed for the group
+    fn entitled(&self, routing: &Routing) -> bool {
+        if self.markets.is_empty() {
+            return true;
+        }
+        match routing.market_id.as_deref() {
+            Some(market_id) => self.markets.iter().any(|market| market == market_id),
+            None => routing.kind.as_deref().is_some_and(|kind| GLOBAL_TYPES.contains(&kind)),
+        }
+    }
+}
+
+// Seals every payload of the encrypted sinks once per consumer group entitled to it. The
+// envelope names no market, so a shared broker does not learn which market traded.
+#[derive(Clone)]
+pub struct FeedCipher {
+    groups: Vec<Group>,
+}
+
+impl FeedCipher {
+    pub fn new(keys: &[FeedKey], group_markets: &[(String, Pubkey)]) -> Self {
+        let groups = keys
+            .iter()
+            .map(|key| Group {
+                key: key.clone(),
+                cipher: XChaCha20Poly1305::new(&key.key),
+                markets: group_markets
+                    .iter()
+                    .filter(|(group, _)| *group == key.group)
+                    .map(|(_, market)| market.to_string())
+                    .collect(),
+            })
+            .collect();
+        FeedCipher { groups }
+    }
+
+    // One `encrypted` control message per entitled group, with a random nonce each
+    pub fn seal(&self, payload: &str) -> Vec<String> {
+        let routing = serde_json::from_str::<Routing>(payload).unwrap_or_default();
+        self.groups
+            .iter()
+            .filter(|group| group.entitled(&routing))
+            .map(|Group { key, cipher, .. }| {
+                let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+                let aad = format!("{}:{}", key.group, key.key_id);
+                let data = cipher
+                    .encrypt(
+                        &nonce,
+                        Payload {
+                            msg: payload.as_bytes(),
+                            aad: aad.as_bytes(),
+                        },
+                    )
+                    .expect("payload too large to encrypt");
+                let message = ControlMessage::Encrypted {
+                    group: key.group.clone(),
+                    key_id: key.key_id.clone(),
+                    nonce: STANDARD.encode(nonce),
+                    data: STANDARD.encode(data),
+                };
+                serde_json::to_string(&message).unwrap()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chacha20poly1305::XNonce;
+    use serde_json::json;
+
+    fn feed_key(group: &str) -> FeedKey {
+        FeedKey::parse(&format!("{}=1:{}", group, STANDARD.encode([7u8; 32]))).unwrap()
+    }
+
+    fn groups(sealed: &[String]) -> Vec<String> {
+        sealed
+            .iter()
+            .map(|envelope| match serde_json::from_str(envelope).unwrap() {
+                ControlMessage::Encrypted { group, .. } => group,
+                other => panic!("not an envelope: {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn feed_keys_need_32_bytes() {
+        assert!(FeedKey::parse("desk=1:AAAA").is_err());
+        assert!(FeedKey::parse("desk").is_err());
+        assert_eq!(format!("{:?}", feed_key("desk")), "desk=1");
+    }
+
+    #[test]
+    fn envelopes_open_with_the_group_key_and_name_no_market() {
+        let market = Pubkey::new_unique();
+        let cipher = FeedCipher::new(&[feed_key("desk")], &[]);
+        let payload = json!({"marketId": market.to_string(), "feedSeq": 1}).to_string();
+        let sealed = cipher.seal(&payload);
+        assert_eq!(sealed.len(), 1);
+        assert!(!sealed[0].contains(&market.to_string()));
+        let ControlMessage::Encrypted { group, key_id, nonce, data } = serde_json::from_str(&sealed[0]).unwrap() else {
+            panic!("not an envelope");
+        };
+        let nonce = STANDARD.decode(nonce).unwrap();
+        let opened = XChaCha20Poly1305::new(&feed_key("desk").key)
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &STANDARD.decode(data).unwrap(),
+                    aad: format!("{}:{}", group, key_id).as_bytes(),
+                },
+            )
+            .unwrap();
+        assert_eq!(opened, payload.as_bytes());
+    }
+
+    #[test]
+    fn groups_only_get_their_markets_and_global_messages() {
+        let (market_a, market_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let cipher = FeedCipher::new(&[feed_key("desk-a"), feed_key("all")], &[("desk-a".to_string(), market_a)]);
+        let of = |market: Pubkey| json!({"marketId": market.to_string()}).to_string();
+        assert_eq!(groups(&cipher.seal(&of(market_a))), ["desk-a", "all"]);
+        assert_eq!(groups(&cipher.seal(&of(market_b))), ["all"]);
+        let rebate = json!({"type": "referrer_rebate", "referrer": "referrer"}).to_string();
+        assert_eq!(groups(&cipher.seal(&rebate)), ["all"]);
+        let upgrade = json!({"type": "program_upgrade", "slot": 1}).to_string();
+        assert_eq!(groups(&cipher.seal(&upgrade)), ["desk-a", "all"]);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::state;
+use openbookv2_decoder::feed::FeeTotals;
+use openbookv2_decoder::{ControlMessage, Trade};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+
+const MINUTES_1H: i64 = 60;
+const MINUTES_24H: i64 = 1440;
+
+#[derive(Serialize, Deserialize, Default)]
+struct MarketFees {
+    market_name: String,
+    // per minute totals for the rolling 24h window, oldest first
+    minutes: VecDeque<(i64, FeeTotals)>,
+}
+
+// Fee revenue per market in quote UI units over the last hour and day, persisted so a
+// restart does not empty the windows
+#[derive(Serialize, Deserialize, Default)]
+pub struct FeeTracker {
+    markets: BTreeMap<String, MarketFees>,
+}
+
+impl FeeTracker {
+    pub fn load(path: &Path) -> Self {
+        state::load(path)
+    }
+
+    pub fn save(&self, path: &Path) {
+        state::save(path, self)
+    }
+
+    pub fn record(&mut self, trade: &Trade) {
+        let market = self.markets.entry(trade.market_id.clone()).or_default();
+        market.market_name = trade.market_name.clone();
+        // maker_fee is paid to the maker instead with maker_rebate
+        let (maker_fees, maker_rebates) = match trade.maker_rebate {
+            true => (0.0, trade.maker_fee),
+            false => (trade.maker_fee, 0.0),
+        };
+        let fees = FeeTotals {
+            taker_fees: trade.taker_fee,
+            maker_fees,
+            maker_rebates,
+            net: trade.taker_fee + maker_fees - maker_rebates,
+            fills: 1,
+        };
+        let minute = trade.time_stamp as i64 / 60;
+        match market.minutes.back_mut() {
+            Some((last, bucket)) if *last >= minute => bucket.add(&fees),
+            _ => market.minutes.push_back((minute, fees)),
+        }
+    }
+
+    // One message per market with fills in the last 24h, windows ending now
+    pub fn summaries(&mut self, now: i64) -> Vec<ControlMessage> {
+        let minute = now / 60;
+        let mut messages = Vec::new();
+        for (market_id, market) in self.markets.iter_mut() {
+            while market.minutes.front().is_some_and(|(start, _)| *start <= minute - MINUTES_24H) {
+                market.minutes.pop_front();
+            }
+            if market.minutes.is_empty() {
+                continue;
+            }
+            let (mut last_1h, mut last_24h) = (FeeTotals::default(), FeeTotals::default());
+            for (start, fees) in market.minutes.iter() {
+                last_24h.add(fees);
+                if *start > minute - MINUTES_1H {
+                    last_1h.add(fees);
+                }
+            }
+            messages.push(ControlMessage::Fees {
+                market_id: market_id.clone(),
+                market_name: market.market_name.clone(),
+                last_1h,
+                last_24h,
+            });
+        }
+        self.markets.retain(|_, market| !market.minutes.is_empty());
+        messages
+    }
+}
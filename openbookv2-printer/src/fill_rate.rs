@@ -0,0 +1,53 @@
+use crate::metrics::{metrics, Metrics};
+use std::collections::{BTreeMap, VecDeque};
+
+const WINDOW_SECS: i64 = 60;
+
+// Fills of the last minute per market in 1 second buckets, exported as
+// openbook_market_fills_per_minute
+#[derive(Default)]
+pub struct FillRate {
+    markets: BTreeMap<String, VecDeque<(i64, u64)>>,
+}
+
+impl FillRate {
+    // markets start at 0 so dead subscriptions show up before their first fill
+    pub fn new<'a>(market_names: impl Iterator<Item = &'a String>) -> Self {
+        let mut fill_rate = FillRate::default();
+        for market_name in market_names {
+            fill_rate.markets.insert(market_name.clone(), VecDeque::new());
+            metrics()
+                .market_fills_per_minute
+                .get_or_create(&Metrics::market(market_name))
+                .set(0);
+        }
+        fill_rate
+    }
+
+    pub fn record(&mut self, market_name: &str, now: i64) {
+        let buckets = self.markets.entry(market_name.to_string()).or_default();
+        match buckets.back_mut() {
+            Some((second, count)) if *second == now => *count += 1,
+            _ => buckets.push_back((now, 1)),
+        }
+        Self::update(market_name, buckets, now);
+    }
+
+    // called periodically so markets without fills decay to 0
+    pub fn refresh(&mut self, now: i64) {
+        for (market_name, buckets) in self.markets.iter_mut() {
+            Self::update(market_name, buckets, now);
+        }
+    }
+
+    fn update(market_name: &str, buckets: &mut VecDeque<(i64, u64)>, now: i64) {
+        while buckets.front().is_some_and(|(second, _)| *second <= now - WINDOW_SECS) {
+            buckets.pop_front();
+        }
+        let fills: u64 = buckets.iter().map(|(_, count)| count).sum();
+        metrics()
+            .market_fills_per_minute
+            .get_or_create(&Metrics::market(market_name))
+            .set(fills as i64);
+    }
+}
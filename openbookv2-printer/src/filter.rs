@@ -0,0 +1,170 @@
+use crate::expr::Expr;
+use openbookv2_decoder::Trade;
+use std::str::FromStr;
+
+// Which trades a sink receives, either an expression (see expr.rs) or a rule list
+#[derive(Clone, Debug)]
+pub enum TradeFilter {
+    Expr(Expr),
+    Rules(Rules),
+}
+
+impl TradeFilter {
+    pub fn matches(&self, trade: &Trade) -> bool {
+        match self {
+            TradeFilter::Expr(expr) => expr.matches(trade),
+            TradeFilter::Rules(rules) => rules.matches(trade),
+        }
+    }
+}
+
+impl FromStr for TradeFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<Expr>() {
+            Ok(expr) => Ok(TradeFilter::Expr(expr)),
+            Err(expr_err) => s.parse::<Rules>().map(TradeFilter::Rules).map_err(|rules_err| {
+                format!("invalid filter {}: as expression: {}, as rules: {}", s, expr_err, rules_err)
+            }),
+        }
+    }
+}
+
+// e.g. "market=SOL-USDC,JUP-USDC;min_size=100;side=buy;owner=<pubkey>". All given
+// criteria must match, lists match any of their values.
+#[derive(Clone, Debug, Default)]
+pub struct Rules {
+    // market names or ids
+    markets: Vec<String>,
+    // base quantity in UI units
+    min_size: Option<f64>,
+    // taker side, 0 bid (buy) and 1 ask (sell)
+    side: Option<u8>,
+    // maker or taker owner
+    owners: Vec<String>,
+}
+
+impl Rules {
+    pub fn matches(&self, trade: &Trade) -> bool {
+        if !self.markets.is_empty()
+            && !self
+                .markets
+                .iter()
+                .any(|market| *market == trade.market_id || *market == trade.market_name)
+        {
+            return false;
+        }
+        if self.min_size.is_some_and(|min_size| trade.quantity_double < min_size) {
+            return false;
+        }
+        if self.side.is_some_and(|side| trade.taker_side != side) {
+            return false;
+        }
+        if !self.owners.is_empty()
+            && !self
+                .owners
+                .iter()
+                .any(|owner| *owner == trade.maker_owner || *owner == trade.taker_owner)
+        {
+            return false;
+        }
+        true
+    }
+}
+
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+impl FromStr for Rules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = Rules::default();
+        for rule in s.split(';').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let (key, value) = rule
+                .split_once('=')
+                .ok_or_else(|| format!("invalid filter rule {}, expected key=value", rule))?;
+            let value = value.trim();
+            match key.trim() {
+                "market" => filter.markets = list(value),
+                "min_size" => {
+                    filter.min_size = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid min_size {}", value))?,
+                    )
+                }
+                "side" => {
+                    filter.side = Some(match value {
+                        "buy" | "bid" => 0,
+                        "sell" | "ask" => 1,
+                        _ => return Err(format!("invalid side {}, expected buy or sell", value)),
+                    })
+                }
+                "owner" => filter.owners = list(value),
+                key => return Err(format!("unknown filter key {}, expected market, min_size, side or owner", key)),
+            }
+        }
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn trade(market_name: &str, taker_side: u8, size: f64, maker_owner: &str) -> Trade {
+        serde_json::from_value(json!({
+            "timeStamp": 1_700_000_000,
+            "makerOwner": maker_owner,
+            "takerOwner": "taker",
+            "priceDouble": 150.0,
+            "quantityDouble": size,
+            "marketId": "market",
+            "takerSide": taker_side,
+            "marketName": market_name,
+            "signature": "signature",
+        }))
+        .unwrap()
+    }
+
+    fn matches(filter: &str, trade: &Trade) -> bool {
+        filter.parse::<TradeFilter>().unwrap().matches(trade)
+    }
+
+    #[test]
+    fn rules_must_all_match() {
+        let trade = trade("SOL-USDC", 0, 100.0, "maker");
+        assert!(matches("market=JUP-USDC, SOL-USDC;min_size=100;side=buy;owner=maker", &trade));
+        assert!(matches("market=market", &trade));
+        assert!(matches("owner=someone,taker", &trade));
+        assert!(matches("side=bid;", &trade));
+        assert!(!matches("market=JUP-USDC", &trade));
+        assert!(!matches("min_size=100.5", &trade));
+        assert!(!matches("side=sell", &trade));
+        assert!(!matches("market=SOL-USDC;owner=someone", &trade));
+    }
+
+    #[test]
+    fn an_expression_is_tried_before_rules() {
+        assert!(matches!("size >= 100".parse(), Ok(TradeFilter::Expr(_))));
+        assert!(matches!("min_size=100".parse(), Ok(TradeFilter::Rules(_))));
+        let trade = trade("SOL-USDC", 1, 100.0, "maker");
+        assert!(matches("side == 'sell' && size >= 100", &trade));
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        for filter in ["markets", "min_size=lots", "side=up", "price=1"] {
+            let error = filter.parse::<TradeFilter>().unwrap_err();
+            assert!(error.starts_with(&format!("invalid filter {}:", filter)), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::state;
+use log::info;
+use solana_program::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+// Last fill per market, persisted so idle periods count across restarts. Markets idle for
+// `days` are reported once by `idle` and forgotten, the source drops them from its
+// subscription (--prune-idle-days) until `restore`.
+pub struct IdleMarkets {
+    last_active: BTreeMap<String, i64>,
+    path: PathBuf,
+    days: u64,
+}
+
+impl IdleMarkets {
+    // Markets without a recorded fill count as active now
+    pub fn load(path: PathBuf, markets: &[Pubkey], days: u64, now_secs: i64) -> Self {
+        let mut saved: BTreeMap<String, i64> = state::load(&path);
+        let last_active = markets
+            .iter()
+            .map(|market| {
+                let market = market.to_string();
+                let last = saved.remove(&market).unwrap_or(now_secs);
+                (market, last)
+            })
+            .collect();
+        IdleMarkets { last_active, path, days }
+    }
+
+    pub fn touch(&mut self, market: &str, now_secs: i64) {
+        if let Some(last) = self.last_active.get_mut(market) {
+            *last = now_secs;
+        }
+    }
+
+    // A pruned market which is used again, idle from now on
+    pub fn restore(&mut self, market: &str, now_secs: i64) {
+        self.last_active.insert(market.to_string(), now_secs);
+    }
+
+    pub fn remove(&mut self, market: &str) {
+        self.last_active.remove(market);
+    }
+
+    // Markets without a fill for `days`, with their last one
+    pub fn idle(&mut self, now_secs: i64) -> Vec<(Pubkey, i64)> {
+        let limit = self.days as i64 * DAY_SECS;
+        let idle: Vec<(String, i64)> = self
+            .last_active
+            .iter()
+            .filter(|(_, last)| now_secs - **last >= limit)
+            .map(|(market, last)| (market.clone(), *last))
+            .collect();
+        for (market, last) in idle.iter() {
+            info!("market {} idle since {}, pruning it", market, last);
+            self.last_active.remove(market);
+        }
+        idle.into_iter()
+            .filter_map(|(market, last)| market.parse().ok().map(|market| (market, last)))
+            .collect()
+    }
+
+    pub fn save(&self) {
+        state::save(&self.path, &self.last_active)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn markets(count: u8) -> Vec<Pubkey> {
+        (1..=count).map(|byte| Pubkey::new_from_array([byte; 32])).collect()
+    }
+
+    #[test]
+    fn markets_without_fills_for_days_are_pruned_once() {
+        let markets = markets(2);
+        let mut idle = IdleMarkets::load(PathBuf::from("/nonexistent/activity.json"), &markets, 2, NOW);
+        idle.touch(&markets[0].to_string(), NOW + DAY_SECS);
+        assert!(idle.idle(NOW + 2 * DAY_SECS - 1).is_empty());
+        assert_eq!(idle.idle(NOW + 2 * DAY_SECS), vec![(markets[1], NOW)]);
+        assert!(idle.idle(NOW + 2 * DAY_SECS).is_empty());
+        assert_eq!(idle.idle(NOW + 3 * DAY_SECS), vec![(markets[0], NOW + DAY_SECS)]);
+    }
+
+    #[test]
+    fn only_restored_markets_are_tracked_again() {
+        let markets = markets(1);
+        let market = markets[0].to_string();
+        let mut idle = IdleMarkets::load(PathBuf::from("/nonexistent/activity.json"), &markets, 1, NOW);
+        assert_eq!(idle.idle(NOW + DAY_SECS).len(), 1);
+        // fills of a pruned market do not bring it back by themselves
+        idle.touch(&market, NOW + DAY_SECS);
+        assert!(idle.idle(NOW + 3 * DAY_SECS).is_empty());
+        idle.restore(&market, NOW + 3 * DAY_SECS);
+        assert!(idle.idle(NOW + 4 * DAY_SECS - 1).is_empty());
+        assert_eq!(idle.idle(NOW + 4 * DAY_SECS), vec![(markets[0], NOW + 3 * DAY_SECS)]);
+    }
+}
@@ -0,0 +1,373 @@
+use crate::block_times;
+use crate::clock::now_ms;
+use crate::lifecycle::MarketLifecycle;
+use crate::metrics::metrics;
+use crate::pipeline::{stage_channel, StageReceiver, StageSender};
+use log::{debug, error, warn};
+use openbookv2_decoder::{FillLog, FillPosition, LogFormat, SettleFundsLog};
+use solana_program::pubkey::Pubkey;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use tokio::sync::mpsc::error::TrySendError;
+
+// Lamports of the base fee per signature, the rest of the fee is priority fee
+const LAMPORTS_PER_SIGNATURE: u64 = 5000;
+
+// Execution cost of the transaction of a fill, None where the source does not report it
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TxCost {
+    pub compute_units: Option<u64>,
+    // lamports paid above the base fee, for the whole transaction
+    pub priority_fee: Option<u64>,
+}
+
+impl TxCost {
+    pub fn new(compute_units: Option<u64>, fee: Option<u64>, signatures: Option<usize>) -> Self {
+        let priority_fee = fee
+            .zip(signatures)
+            .map(|(fee, signatures)| fee.saturating_sub(LAMPORTS_PER_SIGNATURE * signatures as u64));
+        TxCost {
+            compute_units,
+            priority_fee,
+        }
+    }
+}
+
+pub enum Update {
+    Fill {
+        fill_log: FillLog,
+        signature: String,
+        position: FillPosition,
+        // (index, count) among the fills of the transaction
+        fill_index: (u32, u32),
+        feed_seq: u64,
+        block_time_ms: Option<i64>,
+        // the source had no block time, block_time_ms is estimated from the slot
+        block_time_estimated: bool,
+        cost: TxCost,
+        // when the fill entered the intake queue
+        received_ms: i64,
+        // from a transaction which was not executed yet, see source::shredstream
+        pre_confirmed: bool,
+    },
+    DataLoss {
+        market: Pubkey,
+        from_seq: u64,
+        to_seq: u64,
+    },
+    MarketClosed {
+        market: Pubkey,
+        lifecycle: MarketLifecycle,
+        signature: String,
+    },
+    ReferrerRebate {
+        settle_funds_log: SettleFundsLog,
+        signature: String,
+        log_index: u32,
+    },
+    CommitmentDowngrade {
+        slot: u64,
+        requested: &'static str,
+        observed: &'static str,
+    },
+}
+
+// Bounded handoff between the source and the enrich stage. The stream must
+// never block on the stages behind it, so when the queue is full fills are dropped and
+// the dropped feed_seq range is reported in order once there is room again.
+pub struct IntakeQueue {
+    sender: StageSender<Update>,
+    feed_seqs: HashMap<Pubkey, u64>,
+    lost: BTreeMap<Pubkey, (u64, u64)>,
+    // updates which must not be dropped, delivered ahead of the next fills of their market
+    // in its lane, so a full lane only holds back the markets of that lane
+    pending: BTreeMap<Pubkey, VecDeque<Update>>,
+    // the same for updates of no market, in the normal lane without holding back fills
+    pending_global: VecDeque<Update>,
+    dedup_fills: bool,
+    recent: RecentFills,
+    log_format: LogFormat,
+    // --priority-market markets, their updates take the priority lane
+    priority: Arc<HashSet<Pubkey>>,
+    // the markets of the source feeding the queue, see split
+    markets: HashSet<Pubkey>,
+    // the source pushes fills of simulated transactions
+    pre_confirmed: bool,
+}
+
+// The last `capacity` (signature, log_index) pairs pushed. Geyser can deliver a
+// transaction twice at processed commitment and after reconnects, which would publish
+// its fills again with new feed_seqs.
+pub(crate) struct RecentFills {
+    capacity: usize,
+    seen: HashSet<(String, u32)>,
+    order: VecDeque<(String, u32)>,
+}
+
+impl RecentFills {
+    pub(crate) fn new(capacity: usize) -> Self {
+        RecentFills {
+            capacity,
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    // false when the pair is in the window already
+    pub(crate) fn insert(&mut self, signature: &str, log_index: u32) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        let key = (signature.to_string(), log_index);
+        if !self.seen.insert(key.clone()) {
+            return false;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+pub fn intake_queue(
+    size: usize,
+    dedup_fills: bool,
+    signature_dedup: usize,
+    log_format: LogFormat,
+    priority: HashSet<Pubkey>,
+) -> (IntakeQueue, StageReceiver<Update>) {
+    let (sender, receiver) = stage_channel("enrich", size);
+    let queue = IntakeQueue {
+        sender,
+        feed_seqs: HashMap::new(),
+        lost: BTreeMap::new(),
+        pending: BTreeMap::new(),
+        pending_global: VecDeque::new(),
+        dedup_fills,
+        recent: RecentFills::new(signature_dedup),
+        log_format,
+        priority: Arc::new(priority),
+        markets: HashSet::new(),
+        pre_confirmed: false,
+    };
+    (queue, receiver)
+}
+
+impl IntakeQueue {
+    // Another queue into the same channel for a parallel source subscribed to `markets`.
+    // feed_seqs are counted per queue, so the sources must not share markets, and only
+    // fills of `markets` are pushed to it.
+    pub fn split(&self, markets: &[Pubkey]) -> IntakeQueue {
+        IntakeQueue {
+            sender: self.sender.clone(),
+            feed_seqs: HashMap::new(),
+            lost: BTreeMap::new(),
+            pending: BTreeMap::new(),
+            pending_global: VecDeque::new(),
+            dedup_fills: self.dedup_fills,
+            recent: RecentFills::new(self.recent.capacity),
+            log_format: self.log_format.clone(),
+            priority: self.priority.clone(),
+            markets: markets.iter().copied().collect(),
+            pre_confirmed: self.pre_confirmed,
+        }
+    }
+
+    // Fills pushed from now on are flagged preConfirmed
+    pub fn mark_pre_confirmed(&mut self) {
+        self.pre_confirmed = true;
+    }
+
+    // false for the markets of other subscriptions, a transaction touching several markets
+    // reaches every subscription of one of them
+    pub fn watches(&self, market: &Pubkey) -> bool {
+        self.markets.contains(market)
+    }
+
+    // --log-prefix, how the sources find events in the log lines
+    pub fn log_format(&self) -> &LogFormat {
+        &self.log_format
+    }
+
+    pub fn dedup_fills(&self) -> bool {
+        self.dedup_fills
+    }
+
+    // false when the fill at log_index of the transaction was pushed recently
+    pub fn first_delivery(&mut self, signature: &str, log_index: u32) -> bool {
+        self.recent.insert(signature, log_index)
+    }
+
+    pub fn push(
+        &mut self,
+        fill_log: FillLog,
+        signature: String,
+        position: FillPosition,
+        fill_index: (u32, u32),
+        block_time_ms: Option<i64>,
+        cost: TxCost,
+    ) {
+        let market = fill_log.market;
+        let feed_seq = self.feed_seqs.entry(market).or_insert(0);
+        *feed_seq += 1;
+        let feed_seq = *feed_seq;
+
+        self.flush_pending();
+        if self.lost.contains_key(&market) || self.pending.contains_key(&market) {
+            self.record_loss(market, feed_seq);
+            return;
+        }
+        // without a block time from the source the slot clock estimates one, before it saw a
+        // block the on-chain clock of the fill is the best there is
+        let block_time_estimated = block_time_ms.is_none();
+        let block_time_ms = block_time_ms
+            .or_else(|| block_times::fitted_ms(position.slot))
+            .unwrap_or(fill_log.timestamp as i64 * 1000);
+        if block_time_estimated {
+            metrics().estimated_block_times.inc();
+        }
+        let update = Update::Fill {
+            fill_log,
+            signature,
+            position,
+            fill_index,
+            feed_seq,
+            block_time_ms: Some(block_time_ms),
+            block_time_estimated,
+            cost,
+            received_ms: now_ms(),
+            pre_confirmed: self.pre_confirmed,
+        };
+        match self.sender.try_send_to(update, self.priority.contains(&market)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => self.record_loss(market, feed_seq),
+            Err(TrySendError::Closed(_)) => error!("enrich stage is gone, dropping fill"),
+        }
+    }
+
+    pub fn push_market_closed(&mut self, market: Pubkey, lifecycle: MarketLifecycle, signature: String) {
+        self.pending.entry(market).or_default().push_back(Update::MarketClosed {
+            market,
+            lifecycle,
+            signature,
+        });
+        self.flush_pending();
+    }
+
+    // No feed_seq, dropped with a warning when the queue is full. The enrich stage publishes
+    // it only for its markets, once for all subscriptions.
+    pub fn push_referrer_rebate(&mut self, settle_funds_log: SettleFundsLog, signature: String, log_index: u32) {
+        if !self.recent.insert(&signature, log_index) {
+            debug!("referrer rebate at log {} of {} was delivered before", log_index, signature);
+            return;
+        }
+        let update = Update::ReferrerRebate {
+            settle_funds_log,
+            signature,
+            log_index,
+        };
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(update) {
+            warn!("publish queue full, dropping referrer rebate");
+        }
+    }
+
+    // Not dropped, the warning a settlement-grade consumer must see
+    pub fn push_commitment_downgrade(&mut self, slot: u64, requested: &'static str, observed: &'static str) {
+        self.pending_global.push_back(Update::CommitmentDowngrade {
+            slot,
+            requested,
+            observed,
+        });
+        self.flush_pending();
+    }
+
+    // Sends the pending loss and lifecycle notifications, per market the loss first and then
+    // the others in order, in the lane of the market. What a full lane does not take stays
+    // pending, the markets of the other lane go on.
+    fn flush_pending(&mut self) {
+        let mut full = [false; 2];
+        let markets: BTreeSet<Pubkey> = self.lost.keys().chain(self.pending.keys()).copied().collect();
+        for market in markets {
+            let priority = self.priority.contains(&market);
+            if full[priority as usize] {
+                continue;
+            }
+            if let Some(&(from_seq, to_seq)) = self.lost.get(&market) {
+                let update = Update::DataLoss {
+                    market,
+                    from_seq,
+                    to_seq,
+                };
+                if self.sender.try_send_to(update, priority).is_err() {
+                    full[priority as usize] = true;
+                    continue;
+                }
+                self.lost.remove(&market);
+            }
+            let Some(updates) = self.pending.get_mut(&market) else {
+                continue;
+            };
+            while let Some(update) = updates.pop_front() {
+                if let Err(err) = self.sender.try_send_to(update, priority) {
+                    if let TrySendError::Full(update) = err {
+                        updates.push_front(update);
+                    }
+                    full[priority as usize] = true;
+                    break;
+                }
+            }
+            if updates.is_empty() {
+                self.pending.remove(&market);
+            }
+        }
+        while !full[0] {
+            let Some(update) = self.pending_global.pop_front() else {
+                break;
+            };
+            if let Err(err) = self.sender.try_send(update) {
+                if let TrySendError::Full(update) = err {
+                    self.pending_global.push_front(update);
+                }
+                full[0] = true;
+            }
+        }
+    }
+
+    fn record_loss(&mut self, market: Pubkey, feed_seq: u64) {
+        metrics().dropped_fills.inc();
+        match self.lost.get_mut(&market) {
+            Some(range) => range.1 = feed_seq,
+            None => {
+                warn!("publish queue full, dropping fills for market {}", market);
+                self.lost.insert(market, (feed_seq, feed_seq));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_fills_remember_a_window_of_deliveries() {
+        let mut recent = RecentFills::new(2);
+        assert!(recent.insert("a", 0));
+        assert!(recent.insert("a", 1));
+        assert!(!recent.insert("a", 0));
+        // the third delivery pushes the oldest out of the window
+        assert!(recent.insert("b", 0));
+        assert!(recent.insert("a", 0));
+        assert!(!recent.insert("b", 0));
+    }
+
+    #[test]
+    fn a_window_of_zero_remembers_nothing() {
+        let mut recent = RecentFills::new(0);
+        assert!(recent.insert("a", 0));
+        assert!(recent.insert("a", 0));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::state;
+use openbookv2_decoder::feed::MakerInventory;
+use openbookv2_decoder::{ControlMessage, Trade};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Default)]
+struct MakerState {
+    // net base bought by the maker since tracking started
+    position: f64,
+    #[serde(skip)]
+    base_delta: f64,
+    #[serde(skip)]
+    quote_delta: f64,
+    #[serde(skip)]
+    fills: u32,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct MarketInventory {
+    market_name: String,
+    makers: BTreeMap<String, MakerState>,
+}
+
+// Net base inventory change of every maker owner per market, the positions are
+// persisted, the deltas cover the time since the last summary
+#[derive(Serialize, Deserialize, Default)]
+pub struct InventoryTracker {
+    markets: BTreeMap<String, MarketInventory>,
+    #[serde(skip)]
+    since: i64,
+}
+
+impl InventoryTracker {
+    pub fn load(path: &Path, now: i64) -> Self {
+        let mut tracker: InventoryTracker = state::load(path);
+        tracker.since = now;
+        tracker
+    }
+
+    pub fn save(&self, path: &Path) {
+        state::save(path, self)
+    }
+
+    pub fn record(&mut self, trade: &Trade) {
+        let market = self.markets.entry(trade.market_id.clone()).or_default();
+        market.market_name = trade.market_name.clone();
+        let maker = market.makers.entry(trade.maker_owner.clone()).or_default();
+        // taker_side 0 is a taker buy, the maker sold
+        let base = if trade.taker_side == 0 {
+            -trade.quantity_double
+        } else {
+            trade.quantity_double
+        };
+        maker.position += base;
+        maker.base_delta += base;
+        maker.quote_delta -= base * trade.price_double;
+        maker.fills += 1;
+    }
+
+    // One message per market with fills since the last call, only makers which filled
+    pub fn summaries(&mut self, now: i64) -> Vec<ControlMessage> {
+        let start = std::mem::replace(&mut self.since, now);
+        let mut messages = Vec::new();
+        for (market_id, market) in self.markets.iter_mut() {
+            let makers: Vec<MakerInventory> = market
+                .makers
+                .iter_mut()
+                .filter(|(_, maker)| maker.fills > 0)
+                .map(|(owner, maker)| {
+                    let inventory = MakerInventory {
+                        owner: owner.clone(),
+                        base_delta: maker.base_delta,
+                        quote_delta: maker.quote_delta,
+                        fills: maker.fills,
+                        position: maker.position,
+                    };
+                    maker.base_delta = 0.0;
+                    maker.quote_delta = 0.0;
+                    maker.fills = 0;
+                    inventory
+                })
+                .collect();
+            if !makers.is_empty() {
+                messages.push(ControlMessage::Inventory {
+                    market_id: market_id.clone(),
+                    market_name: market.market_name.clone(),
+                    start,
+                    end: now,
+                    makers,
+                });
+            }
+        }
+        messages
+    }
+}
@@ -0,0 +1,209 @@
+use crate::error::SinkError;
+use crate::sink::{topic_matches, Sink, TOPIC_TRADES};
+use apache_avro::types::Value as AvroValue;
+use apache_avro::Schema;
+use log::{info, warn};
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{BaseRecord, DeliveryResult, Producer, ProducerContext, ThreadedProducer};
+use rdkafka::ClientContext;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+// the schema of the JSON feed, kept next to the decoder which owns Trade
+pub const TRADE_SCHEMA: &str = include_str!("../../openbookv2-decoder/schemas/trade.avsc");
+const REGISTRY_CONTENT_TYPE: &str = "application/vnd.schemaregistry.v1+json";
+// new versions of the schema must be able to read the trades of the old one
+const COMPATIBILITY: &str = "BACKWARD";
+// how long a dropped sink waits for the queued trades to be delivered
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Reports the trades the brokers did not take, sending only queues them
+struct DeliveryLog;
+
+impl ClientContext for DeliveryLog {}
+
+impl ProducerContext for DeliveryLog {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+        if let Err((err, _)) = result {
+            warn!("kafka did not take a trade: {}", err);
+        }
+    }
+}
+
+// Avro in the Confluent wire format: magic byte 0, the schema id (big endian) and the datum
+struct AvroEncoding {
+    schema: Schema,
+    id: u32,
+}
+
+impl AvroEncoding {
+    fn frame(&self, trade: Value) -> Result<Vec<u8>, SinkError> {
+        // fields missing from the trade take the defaults of the schema, unknown ones are dropped
+        let datum = AvroValue::from(trade)
+            .resolve(&self.schema)
+            .and_then(|value| apache_avro::to_avro_datum(&self.schema, value))
+            .map_err(|err| SinkError::Kafka(format!("trade does not match trade.avsc: {}", err)))?;
+        let mut payload = Vec::with_capacity(datum.len() + 5);
+        payload.push(0);
+        payload.extend_from_slice(&self.id.to_be_bytes());
+        payload.extend_from_slice(&datum);
+        Ok(payload)
+    }
+}
+
+// Publishes the trades to one kafka topic keyed by market, so the trades of a market stay in
+// order within their partition. Avro encoded when a schema registry is configured, the JSON
+// of the feed otherwise. Other topics are not published.
+pub struct KafkaSink {
+    producer: ThreadedProducer<DeliveryLog>,
+    topic: String,
+    avro: Option<AvroEncoding>,
+}
+
+impl KafkaSink {
+    pub fn new(brokers: &str, topic: String, registry: Option<&str>) -> Result<Self, SinkError> {
+        let avro = match registry {
+            Some(url) => {
+                let schema = Schema::parse_str(TRADE_SCHEMA).map_err(|err| SinkError::Open {
+                    target: "trade.avsc".to_string(),
+                    reason: err.to_string(),
+                })?;
+                let id = register(url, &format!("{}-value", topic)).map_err(|reason| SinkError::Open {
+                    target: url.to_string(),
+                    reason,
+                })?;
+                info!("trade schema registered as id {} for {}-value", id, topic);
+                Some(AvroEncoding { schema, id })
+            }
+            None => None,
+        };
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .create_with_context(DeliveryLog)
+            .map_err(|err| SinkError::Open {
+                target: brokers.to_string(),
+                reason: err.to_string(),
+            })?;
+        Ok(KafkaSink { producer, topic, avro })
+    }
+
+    fn encode(&self, trade: Value) -> Result<Vec<u8>, SinkError> {
+        match self.avro.as_ref() {
+            Some(avro) => avro.frame(trade),
+            None => Ok(serde_json::to_vec(&trade)?),
+        }
+    }
+}
+
+impl Sink for KafkaSink {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    fn send(&mut self, topic: &str, payload: &str) -> Result<(), SinkError> {
+        if !topic_matches(TOPIC_TRADES, topic) {
+            return Ok(());
+        }
+        let trade: Value = serde_json::from_str(payload)?;
+        let key = trade["marketId"].as_str().unwrap_or_default().to_string();
+        let payload = self.encode(trade)?;
+        self.producer
+            .send(BaseRecord::to(&self.topic).key(&key).payload(&payload))
+            .map_err(|(err, _)| SinkError::Kafka(err.to_string()))
+    }
+}
+
+impl Drop for KafkaSink {
+    fn drop(&mut self) {
+        if let Err(err) = self.producer.flush(FLUSH_TIMEOUT) {
+            warn!("kafka trades still queued at shutdown: {}", err);
+        }
+    }
+}
+
+// Pins the compatibility of the subject, checks the schema against its latest version and
+// registers it, the id of the (new or existing) version is returned. A schema the registry
+// rejects as incompatible is an error, the printer does not start with it.
+fn register(registry: &str, subject: &str) -> Result<u32, String> {
+    let registry = registry.trim_end_matches('/');
+    let schema = json!({ "schema": TRADE_SCHEMA });
+    ureq::put(&format!("{}/config/{}", registry, subject))
+        .set("Content-Type", REGISTRY_CONTENT_TYPE)
+        .send_json(json!({ "compatibility": COMPATIBILITY }))
+        .map_err(|err| format!("setting the compatibility of {} failed: {}", subject, err))?;
+    let check = ureq::post(&format!("{}/compatibility/subjects/{}/versions/latest", registry, subject))
+        .set("Content-Type", REGISTRY_CONTENT_TYPE)
+        .send_json(schema.clone());
+    match check {
+        Ok(response) => {
+            let body: Value = response.into_json().map_err(|err| err.to_string())?;
+            if body["is_compatible"] != json!(true) {
+                return Err(format!(
+                    "trade.avsc is not {} compatible with the latest version of {}",
+                    COMPATIBILITY, subject
+                ));
+            }
+        }
+        // first version of the subject, nothing to be compatible with
+        Err(ureq::Error::Status(404, _)) => {}
+        Err(err) => return Err(format!("compatibility check of {} failed: {}", subject, err)),
+    }
+    let response = ureq::post(&format!("{}/subjects/{}/versions", registry, subject))
+        .set("Content-Type", REGISTRY_CONTENT_TYPE)
+        .send_json(schema)
+        .map_err(|err| format!("registering trade.avsc as {} failed: {}", subject, err))?;
+    let body: Value = response.into_json().map_err(|err| err.to_string())?;
+    body["id"]
+        .as_u64()
+        .and_then(|id| u32::try_from(id).ok())
+        .ok_or_else(|| format!("registry answered without a schema id: {}", body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoding() -> AvroEncoding {
+        AvroEncoding {
+            schema: Schema::parse_str(TRADE_SCHEMA).unwrap(),
+            id: 7,
+        }
+    }
+
+    #[test]
+    fn frames_trades_in_the_confluent_wire_format() {
+        let encoding = encoding();
+        let trade = json!({
+            "timeStamp": 1_700_000_000,
+            "makerOwner": "maker",
+            "takerOwner": "taker",
+            "priceDouble": 101.5,
+            "quantityDouble": 2.0,
+            "marketId": "market",
+            "takerSide": 1,
+            "marketName": "SOL-USDC",
+            "signature": "sig",
+            "tradeId": "id",
+            "pair": "pair",
+            // not part of the schema
+            "unknown": true,
+        });
+        let payload = encoding.frame(trade).unwrap();
+        assert_eq!(payload[..5], [0, 0, 0, 0, 7]);
+        let decoded = apache_avro::from_avro_datum(&encoding.schema, &mut &payload[5..], None).unwrap();
+        let AvroValue::Record(fields) = decoded else { panic!("not a record: {:?}", decoded) };
+        let field = |name: &str| fields.iter().find(|(field, _)| field == name).unwrap().1.clone();
+        assert_eq!(field("marketId"), AvroValue::String("market".to_string()));
+        assert_eq!(field("takerSide"), AvroValue::Int(1));
+        assert_eq!(field("feedSeq"), AvroValue::Long(0));
+        assert_eq!(field("pair"), AvroValue::Union(1, Box::new(AvroValue::String("pair".to_string()))));
+        assert_eq!(field("makerDelegate"), AvroValue::Union(0, Box::new(AvroValue::Null)));
+    }
+
+    #[test]
+    fn rejects_trades_without_the_required_fields() {
+        assert!(matches!(encoding().frame(json!({ "marketId": "market" })), Err(SinkError::Kafka(_))));
+    }
+}
@@ -0,0 +1,77 @@
+use crate::archive;
+use crate::clock::now_ms;
+use crate::config::Config;
+use crate::exit;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Default)]
+struct MarketLatency {
+    trades: u64,
+    // receive and publish time minus block time of the trades archived with --embed-lag
+    received: Vec<i64>,
+    published: Vec<i64>,
+}
+
+// `latency-report`: percentiles of the archived trades of the last `hours` per market, for
+// SLA reports to consumers. Only trades published with --embed-lag carry the lags.
+pub fn run(config: &Config, hours: u64) -> i32 {
+    let Some(dir) = config.archive_dir.as_deref() else {
+        exit::fatal(exit::CONFIG, "latency-report needs --archive-dir");
+    };
+    let until = (now_ms() / 1000) as u64;
+    let since = until.saturating_sub(hours * 3600);
+    let watched: Vec<String> = config.market_keys.iter().map(|market| market.to_string()).collect();
+    let mut markets: BTreeMap<String, MarketLatency> = BTreeMap::new();
+    archive::replay(Path::new(dir), &watched, since, |trade| {
+        if !watched.is_empty() && !watched.contains(&trade.market_id) {
+            return;
+        }
+        let market = markets.entry(trade.market_name.replace('\0', "")).or_default();
+        market.trades += 1;
+        if let Some(received_lag_ms) = trade.received_lag_ms {
+            market.received.push(received_lag_ms);
+        }
+        if let Some(lag_ms) = trade.lag_ms {
+            market.published.push(lag_ms);
+        }
+    });
+    let report: BTreeMap<String, Value> = markets
+        .into_iter()
+        .map(|(name, mut market)| {
+            let report = json!({
+                "trades": market.trades,
+                "received": percentiles(&mut market.received),
+                "published": percentiles(&mut market.published),
+            });
+            (name, report)
+        })
+        .collect();
+    println!(
+        "{}",
+        json!({
+            "since": since,
+            "until": until,
+            "markets": report,
+        })
+    );
+    0
+}
+
+// Lags in ms by nearest rank, null without samples
+fn percentiles(lags: &mut [i64]) -> Value {
+    if lags.is_empty() {
+        return Value::Null;
+    }
+    lags.sort_unstable();
+    let rank = |p: f64| lags[((p * lags.len() as f64).ceil() as usize).clamp(1, lags.len()) - 1];
+    json!({
+        "count": lags.len(),
+        "p50": rank(0.5),
+        "p90": rank(0.9),
+        "p99": rank(0.99),
+        "p999": rank(0.999),
+        "max": lags[lags.len() - 1],
+    })
+}
@@ -0,0 +1,30 @@
+use openbookv2_decoder::schema::trade_payload;
+use openbookv2_decoder::Trade;
+
+// An older trade schema version published next to the current one until `until` (unix
+// seconds, None = until the flag is removed), so consumers migrate on their own time.
+// Its trades go to the same topics below "v<version>.", e.g. v1.trades.SOL.USDC.
+#[derive(Clone, Copy, Debug)]
+pub struct LegacySchema {
+    pub version: u32,
+    pub until: Option<u64>,
+}
+
+impl LegacySchema {
+    pub fn active(&self, now: u64) -> bool {
+        !self.until.is_some_and(|until| now >= until)
+    }
+
+    pub fn topic(&self, topic: &str) -> String {
+        format!("v{}.{}", self.version, topic)
+    }
+
+    // The topic below the version prefix for messages of this version
+    pub fn strip<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        topic.strip_prefix(&format!("v{}.", self.version))
+    }
+
+    pub fn payload(&self, trade: &Trade) -> Option<String> {
+        trade_payload(trade, self.version)
+    }
+}
@@ -0,0 +1,80 @@
+// The printer as a library: the binary in main.rs reads the configuration from the
+// command line, other services embed the same pipeline with streamer::TradesStreamer.
+
+// the effective configuration of `config check` is one json! object
+#![recursion_limit = "256"]
+
+pub mod constants;
+mod admin;
+mod affinity;
+mod alert_state;
+pub mod alloc_audit;
+mod archive;
+mod auth;
+mod bars;
+mod block_times;
+mod bootstrap;
+pub mod bus;
+mod catch_up;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod check;
+mod checksums;
+mod clock;
+pub mod config;
+mod consumers;
+mod counters;
+mod decimals;
+mod encode;
+mod enrich;
+mod enricher;
+pub mod error;
+pub mod estimate;
+pub mod exit;
+pub mod export;
+mod expr;
+mod features;
+mod fees;
+pub mod feed_crypto;
+mod filter;
+mod fill_rate;
+mod idle;
+mod intake;
+mod inventory;
+#[cfg(feature = "kafka")]
+mod kafka;
+pub mod legacy_schema;
+pub mod latency_report;
+mod lifecycle;
+mod listings;
+mod market_info;
+mod pairs;
+mod metrics;
+mod oracle;
+mod owner_snapshots;
+pub mod pacing;
+mod pipeline;
+pub mod postmortem;
+#[cfg(feature = "profiling")]
+mod profiling;
+mod replay;
+pub mod self_test;
+mod shadow;
+mod sequences;
+mod sessions;
+pub mod simulate_load;
+pub mod sink;
+mod sink_pool;
+mod slo;
+mod source;
+mod state;
+pub mod streamer;
+mod summary;
+mod upgrades;
+pub mod tail;
+mod utils;
+mod volume;
+mod ws;
+
+pub use bus::{Bus, BusMessage};
+pub use streamer::{StreamerBuilder, StreamerHandle, StreamerStats, TradesStreamer};
@@ -0,0 +1,96 @@
+use solana_program::hash::hash;
+use solana_program::pubkey::Pubkey;
+use yellowstone_grpc_proto::prelude::SubscribeUpdateTransactionInfo;
+
+#[derive(Clone, Copy, Debug)]
+pub enum MarketLifecycle {
+    Closed,
+    Expired,
+    // no transactions for --prune-idle-days, dropped by the printer
+    Idle,
+}
+
+impl MarketLifecycle {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MarketLifecycle::Closed => "closed",
+            MarketLifecycle::Expired => "expired",
+            MarketLifecycle::Idle => "idle",
+        }
+    }
+
+    // Closed and expired markets can not trade anymore, an idle one can start again
+    pub fn is_final(&self) -> bool {
+        !matches!(self, MarketLifecycle::Idle)
+    }
+}
+
+fn sighash(name: &str) -> [u8; 8] {
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash(format!("global:{}", name).as_bytes()).to_bytes()[..8]);
+    discriminator
+}
+
+// Accounts the transaction references, directly or through address lookup tables
+pub fn referenced_accounts(tx: &SubscribeUpdateTransactionInfo) -> Vec<Pubkey> {
+    let keys = tx
+        .transaction
+        .iter()
+        .filter_map(|transaction| transaction.message.as_ref())
+        .flat_map(|message| message.account_keys.iter());
+    let loaded = tx
+        .meta
+        .iter()
+        .flat_map(|meta| meta.loaded_writable_addresses.iter().chain(meta.loaded_readonly_addresses.iter()));
+    keys.chain(loaded)
+        .filter_map(|key| Pubkey::try_from(key.as_slice()).ok())
+        .collect()
+}
+
+// Finds top level OpenBook instructions which end trading on a market:
+// close_market, and set_market_expired / prune_orders for expired markets
+pub fn market_lifecycle_events(
+    tx: &SubscribeUpdateTransactionInfo,
+    program_id: &Pubkey,
+) -> Vec<(Pubkey, MarketLifecycle)> {
+    let mut events = vec![];
+    let Some(message) = tx.transaction.as_ref().and_then(|t| t.message.as_ref()) else {
+        return events;
+    };
+    let mut keys: Vec<&Vec<u8>> = message.account_keys.iter().collect();
+    if let Some(meta) = tx.meta.as_ref() {
+        keys.extend(meta.loaded_writable_addresses.iter());
+        keys.extend(meta.loaded_readonly_addresses.iter());
+    }
+    let key_at = |index: usize| -> Option<Pubkey> {
+        keys.get(index)
+            .and_then(|key| Pubkey::try_from(key.as_slice()).ok())
+    };
+
+    let close_market = sighash("close_market");
+    let set_market_expired = sighash("set_market_expired");
+    let prune_orders = sighash("prune_orders");
+    for ix in message.instructions.iter() {
+        if key_at(ix.program_id_index as usize).as_ref() != Some(program_id) || ix.data.len() < 8 {
+            continue;
+        }
+        // position of the market in the instruction accounts, see idl.json
+        let (lifecycle, market_index) = if ix.data[..8] == close_market {
+            (MarketLifecycle::Closed, 1)
+        } else if ix.data[..8] == set_market_expired {
+            (MarketLifecycle::Expired, 1)
+        } else if ix.data[..8] == prune_orders {
+            (MarketLifecycle::Expired, 2)
+        } else {
+            continue;
+        };
+        if let Some(market) = ix
+            .accounts
+            .get(market_index)
+            .and_then(|&index| key_at(index as usize))
+        {
+            events.push((market, lifecycle));
+        }
+    }
+    events
+}
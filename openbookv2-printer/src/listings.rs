@@ -0,0 +1,240 @@
+use crate::clock::now_ms;
+use crate::encode::to_json;
+use crate::pipeline::{Outgoing, StageSender};
+use crate::sink::{market_topic, TOPIC_LISTINGS};
+use crate::state;
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use log::{info, warn};
+use openbookv2_decoder::{parse_name, ControlMessage, MarketParams};
+use openbookv2_generated::state::Market;
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_program::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::interval;
+
+// fees are stored in 10^-6
+const FEE_PER_BPS: f64 = 100.0;
+
+// Which markets of the program the listing feed reports, empty lists match every market
+#[derive(Clone, Debug, Default)]
+pub struct ListingFilter {
+    // base or quote mint
+    pub mints: Vec<Pubkey>,
+    // market names, `*` matches any characters, e.g. "*-USDC"
+    pub names: Vec<String>,
+}
+
+impl ListingFilter {
+    fn matches(&self, market: &Market, name: &str) -> bool {
+        (self.mints.is_empty() || self.mints.contains(&market.base_mint) || self.mints.contains(&market.quote_mint))
+            && (self.names.is_empty() || self.names.iter().any(|pattern| glob(pattern, name)))
+    }
+}
+
+fn glob(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        if i == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.is_empty()
+}
+
+#[derive(Serialize, Deserialize)]
+struct Listing {
+    name: String,
+    base_mint: String,
+    quote_mint: String,
+    params: MarketParams,
+    maker_fee: i64,
+    taker_fee: i64,
+    time_expiry: i64,
+    expired: bool,
+}
+
+impl Listing {
+    fn new(market: &Market, name: String) -> Self {
+        Listing {
+            name,
+            base_mint: market.base_mint.to_string(),
+            quote_mint: market.quote_mint.to_string(),
+            params: MarketParams::from(market),
+            maker_fee: market.maker_fee,
+            taker_fee: market.taker_fee,
+            time_expiry: market.time_expiry,
+            expired: false,
+        }
+    }
+
+    fn message(&self, event: &str, market_id: &str, changed: Vec<String>) -> ControlMessage {
+        ControlMessage::MarketListing {
+            event: event.to_string(),
+            market_id: market_id.to_string(),
+            market_name: self.name.clone(),
+            base_mint: self.base_mint.clone(),
+            quote_mint: self.quote_mint.clone(),
+            params: self.params,
+            maker_fee_bps: self.maker_fee as f64 / FEE_PER_BPS,
+            taker_fee_bps: self.taker_fee as f64 / FEE_PER_BPS,
+            time_expiry: self.time_expiry,
+            changed,
+        }
+    }
+
+    // Names of the message fields which differ
+    fn changes(&self, newer: &Listing) -> Vec<String> {
+        let (Ok(old), Ok(new)) = (
+            serde_json::to_value(self.message("", "", vec![])),
+            serde_json::to_value(newer.message("", "", vec![])),
+        ) else {
+            return Vec::new();
+        };
+        let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+            return Vec::new();
+        };
+        new.iter()
+            .filter(|(key, value)| old.get(*key) != Some(value))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+}
+
+// The markets of the program matching the filter as of the last poll, kept in
+// listings.json in the state dir so a restart only reports what changed meanwhile
+#[derive(Serialize, Deserialize, Default)]
+struct Listings {
+    markets: BTreeMap<String, Listing>,
+    // false until the first poll, which only records the existing markets
+    #[serde(default)]
+    initialized: bool,
+}
+
+impl Listings {
+    fn update(&mut self, current: BTreeMap<String, Listing>, now: i64) -> Vec<ControlMessage> {
+        let mut messages = Vec::new();
+        let report = self.initialized;
+        for (market_id, listing) in self.markets.iter() {
+            if !current.contains_key(market_id) && report {
+                messages.push(listing.message("closed", market_id, vec![]));
+            }
+        }
+        self.markets.retain(|market_id, _| current.contains_key(market_id));
+        for (market_id, mut listing) in current {
+            match self.markets.get(&market_id) {
+                None if report => messages.push(listing.message("created", &market_id, vec![])),
+                None => {}
+                Some(known) => {
+                    listing.expired = known.expired;
+                    let changed = known.changes(&listing);
+                    if !changed.is_empty() {
+                        messages.push(listing.message("params_changed", &market_id, changed));
+                    }
+                }
+            }
+            if !listing.expired && listing.time_expiry != 0 && listing.time_expiry <= now {
+                listing.expired = true;
+                if report {
+                    messages.push(listing.message("expired", &market_id, vec![]));
+                }
+            }
+            self.markets.insert(market_id, listing);
+        }
+        if !self.initialized {
+            info!("{} listed markets match the listing filters", self.markets.len());
+            self.initialized = true;
+        }
+        messages
+    }
+}
+
+async fn program_markets(client: &RpcClient, filter: &ListingFilter) -> Result<BTreeMap<String, Listing>, String> {
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+            0,
+            Market::discriminator().to_vec(),
+        ))]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let accounts = client
+        .get_program_accounts_with_config(&openbookv2_generated::id(), config)
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(accounts
+        .into_iter()
+        .filter_map(|(key, account)| {
+            let market = Market::deserialize(&mut account.data.get(8..)?).ok()?;
+            let name = parse_name(&market.name).replace('\0', "");
+            filter
+                .matches(&market, &name)
+                .then(|| (key.to_string(), Listing::new(&market, name)))
+        })
+        .collect())
+}
+
+// Polls the market accounts of the program every `every` and publishes the differences on
+// listings.<BASE>.<QUOTE>. The printer only subscribes to the transactions of its own
+// markets, so creations elsewhere are only seen in the accounts. Ends with the sources.
+pub async fn run(
+    client: Arc<RpcClient>,
+    filter: ListingFilter,
+    every: Duration,
+    path: PathBuf,
+    output: StageSender<Outgoing>,
+    mut stopped: watch::Receiver<bool>,
+    canonical: bool,
+) {
+    let mut listings: Listings = state::load(&path);
+    let mut ticker = interval(every);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = stopped.changed() => break,
+        }
+        let current = match program_markets(&client, &filter).await {
+            Ok(current) => current,
+            Err(err) => {
+                warn!("could not list the markets of the program: {}", err);
+                continue;
+            }
+        };
+        let messages = listings.update(current, now_ms() / 1000);
+        state::save(&path, &listings);
+        for message in messages {
+            let ControlMessage::MarketListing { event, market_name, .. } = &message else {
+                continue;
+            };
+            info!("market {} {}", market_name, event);
+            let outgoing = Outgoing {
+                topic: market_topic(TOPIC_LISTINGS, market_name),
+                payload: to_json(&message, canonical),
+                trade: None,
+                fast: false,
+            };
+            if !output.send(outgoing).await {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use crate::state;
+use log::info;
+use openbookv2_decoder::{ControlMessage, MarketParams};
+use openbookv2_generated::state::Market;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// fees are stored in 10^-6
+const FEE_PER_BPS: f64 = 100.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+struct MarketSnapshot {
+    params: MarketParams,
+    maker_fee: i64,
+    taker_fee: i64,
+    time_expiry: i64,
+}
+
+impl From<&Market> for MarketSnapshot {
+    fn from(market: &Market) -> Self {
+        MarketSnapshot {
+            params: MarketParams::from(market),
+            maker_fee: market.maker_fee,
+            taker_fee: market.taker_fee,
+            time_expiry: market.time_expiry,
+        }
+    }
+}
+
+// Versioned market parameters, persisted so versions keep increasing across restarts
+#[derive(Serialize, Deserialize, Default)]
+pub struct MarketInfoTracker {
+    markets: BTreeMap<String, (u32, MarketSnapshot)>,
+}
+
+impl MarketInfoTracker {
+    pub fn load(path: &Path) -> Self {
+        state::load(path)
+    }
+
+    pub fn save(&self, path: &Path) {
+        state::save(path, self)
+    }
+
+    // Returns the version of the market's current parameters
+    pub fn update(&mut self, market_id: &str, market: &Market) -> u32 {
+        let snapshot = MarketSnapshot::from(market);
+        match self.markets.get_mut(market_id) {
+            Some((version, known)) => {
+                if *known != snapshot {
+                    *version += 1;
+                    *known = snapshot;
+                    info!("parameters of market {} changed, now version {}", market_id, version);
+                }
+                *version
+            }
+            None => {
+                self.markets.insert(market_id.to_string(), (1, snapshot));
+                1
+            }
+        }
+    }
+
+    pub fn version(&self, market_id: &str) -> u32 {
+        self.markets.get(market_id).map_or(0, |(version, _)| *version)
+    }
+
+    pub fn message(&self, market_id: &str, market_name: &str) -> Option<ControlMessage> {
+        let (version, snapshot) = self.markets.get(market_id)?;
+        Some(ControlMessage::MarketInfo {
+            market_id: market_id.to_string(),
+            market_name: market_name.replace('\0', ""),
+            version: *version,
+            params: snapshot.params,
+            maker_fee_bps: snapshot.maker_fee as f64 / FEE_PER_BPS,
+            taker_fee_bps: snapshot.taker_fee as f64 / FEE_PER_BPS,
+            time_expiry: snapshot.time_expiry,
+        })
+    }
+}
@@ -0,0 +1,590 @@
+use prometheus_client::encoding::text::encode;
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::exemplar::HistogramWithExemplars;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use prometheus_client::registry::Registry;
+use std::sync::atomic::AtomicU64;
+use std::sync::OnceLock;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct MarketLabels {
+    pub market: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct SinkLabels {
+    pub sink: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct StageLabels {
+    pub stage: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct EnricherLabels {
+    pub enricher: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ErrorLabels {
+    pub category: String,
+    pub kind: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct FeatureLabels {
+    pub feature: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ConsumerLabels {
+    pub consumer: String,
+}
+
+// exemplar of a latency observation, the transaction to look up for a spike
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct SignatureLabels {
+    pub signature: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ReasonLabels {
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ShadowLabels {
+    // price, size, side, missing_local or missing_reference
+    pub kind: String,
+}
+
+type HistogramFamily = Family<MarketLabels, Histogram, fn() -> Histogram>;
+type StageHistogramFamily = Family<StageLabels, Histogram, fn() -> Histogram>;
+type LatencyHistogramFamily =
+    Family<MarketLabels, HistogramWithExemplars<SignatureLabels>, fn() -> HistogramWithExemplars<SignatureLabels>>;
+
+// quote notional of a fill, 10 to 10M in decades
+fn notional_histogram() -> Histogram {
+    Histogram::new(exponential_buckets(10.0, 10.0, 7))
+}
+
+// block time to publish of a fill, 50 ms to 25.6 s in powers of two
+fn latency_histogram() -> HistogramWithExemplars<SignatureLabels> {
+    HistogramWithExemplars::new(exponential_buckets(0.05, 2.0, 10))
+}
+
+// allocations of one transaction or trade, 1 to 4096 in powers of two
+fn allocations_histogram() -> Histogram {
+    Histogram::new(exponential_buckets(1.0, 2.0, 13))
+}
+
+pub struct Metrics {
+    registry: Registry,
+    // wall clock minus block time of the last transaction received from geyser
+    pub source_lag_seconds: Gauge<f64, AtomicU64>,
+    pub market_source_lag_seconds: Family<MarketLabels, Gauge<f64, AtomicU64>>,
+    // true time minus local time, applied to all lag values
+    pub clock_offset_seconds: Gauge<f64, AtomicU64>,
+    pub sink_failures: Family<SinkLabels, Counter>,
+    // 1 while the circuit breaker of the sink is open
+    pub sink_open: Family<SinkLabels, Gauge>,
+    pub ws_clients: Gauge,
+    // messages not sent because of the per connection or per api key rate limit
+    pub ws_rate_limited: Counter,
+    pub ws_slow_disconnects: Counter,
+    pub trade_notional: HistogramFamily,
+    pub market_fills_per_minute: Family<MarketLabels, Gauge>,
+    // items waiting in front of a pipeline stage
+    pub stage_queue: Family<StageLabels, Gauge>,
+    pub stage_processed: Family<StageLabels, Counter>,
+    pub owner_lookups: Gauge,
+    // trades published with an OpenOrders account in place of its owner
+    pub owners_unresolved: Counter,
+    // subscriptions torn down because no transactions arrived while slots advanced
+    pub source_resubscribes: Counter,
+    pub source_reconnects: Counter,
+    // totals of the last summary period, see summary.rs
+    pub summary_fills: Gauge,
+    pub summary_lag_avg_seconds: Gauge<f64, AtomicU64>,
+    pub summary_lag_max_seconds: Gauge<f64, AtomicU64>,
+    pub enricher_timeouts: Family<EnricherLabels, Counter>,
+    // 1 while the market breaches its lag objective
+    pub lag_slo_breached: Family<MarketLabels, Gauge>,
+    // trades found in the reference feed as well, see shadow.rs
+    pub shadow_matched: Counter,
+    pub shadow_mismatches: Family<ShadowLabels, Counter>,
+    // allocations per processed item, only observed with the alloc-audit feature
+    pub allocations: StageHistogramFamily,
+    // fills not above the last published seq_num of their market (--seq-guard)
+    pub seq_regressions: Family<MarketLabels, Counter>,
+    // fills of transactions delivered twice by the source (--signature-dedup)
+    pub redelivered_fills: Counter,
+    // trades the archive already holds, e.g. of a replay or backfill
+    pub archive_duplicates: Counter,
+    // failures by error.rs category and kind
+    pub errors: Family<ErrorLabels, Counter>,
+    pub quarantined_trades: Family<MarketLabels, Counter>,
+    pub health_score: Family<MarketLabels, Gauge>,
+    pub owners_failed: Family<ReasonLabels, Counter>,
+    pub dropped_fills: Counter,
+    pub enricher_skipped: Family<EnricherLabels, Counter>,
+    pub publish_latency_seconds: LatencyHistogramFamily,
+    pub alerts_suppressed: Counter,
+    pub feature_enabled: Family<FeatureLabels, Gauge>,
+    pub sink_digests: Family<SinkLabels, Counter>,
+    pub consumer_lag: Family<ConsumerLabels, Gauge>,
+    // labelled with the plugin name
+    pub plugin_dropped: Family<EnricherLabels, Counter>,
+    pub plugin_errors: Family<EnricherLabels, Counter>,
+    pub stage_priority: Family<StageLabels, Counter>,
+    pub token_refreshes: Counter,
+    // non quarantined trades of the enriched path
+    pub trades_published: Family<MarketLabels, Counter>,
+    pub caught_up_fills: Family<MarketLabels, Counter>,
+    pub estimated_block_times: Counter,
+    pub commitment_downgrades: Counter,
+    pub program_upgrades: Counter,
+    pub incompatible_markets: Gauge,
+    pub upgrade_skipped_fills: Family<MarketLabels, Counter>,
+    pub sink_queue: Family<SinkLabels, Gauge>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let mut registry = Registry::default();
+        let source_lag_seconds = Gauge::<f64, AtomicU64>::default();
+        registry.register(
+            "openbook_source_lag_seconds",
+            "Lag of the last received transaction behind its block time",
+            source_lag_seconds.clone(),
+        );
+        let market_source_lag_seconds = Family::<MarketLabels, Gauge<f64, AtomicU64>>::default();
+        registry.register(
+            "openbook_market_source_lag_seconds",
+            "Lag of the last received transaction behind its block time per market",
+            market_source_lag_seconds.clone(),
+        );
+        let clock_offset_seconds = Gauge::<f64, AtomicU64>::default();
+        registry.register(
+            "openbook_clock_offset_seconds",
+            "Offset of the local clock found at startup",
+            clock_offset_seconds.clone(),
+        );
+        let sink_failures = Family::<SinkLabels, Counter>::default();
+        registry.register(
+            "openbook_sink_failures",
+            "Messages which could not be delivered to a sink after retries",
+            sink_failures.clone(),
+        );
+        let sink_open = Family::<SinkLabels, Gauge>::default();
+        registry.register(
+            "openbook_sink_open",
+            "Whether the circuit breaker of a sink is open",
+            sink_open.clone(),
+        );
+        let ws_clients = Gauge::default();
+        registry.register(
+            "openbook_ws_clients",
+            "Connected websocket clients",
+            ws_clients.clone(),
+        );
+        let ws_rate_limited = Counter::default();
+        registry.register(
+            "openbook_ws_rate_limited",
+            "Messages dropped for websocket clients over their rate limit",
+            ws_rate_limited.clone(),
+        );
+        let ws_slow_disconnects = Counter::default();
+        registry.register(
+            "openbook_ws_slow_disconnects",
+            "Websocket clients disconnected for falling behind",
+            ws_slow_disconnects.clone(),
+        );
+        let trade_notional = HistogramFamily::new_with_constructor(notional_histogram);
+        registry.register(
+            "openbook_trade_notional",
+            "Quote notional of fills per market",
+            trade_notional.clone(),
+        );
+        let market_fills_per_minute = Family::<MarketLabels, Gauge>::default();
+        registry.register(
+            "openbook_market_fills_per_minute",
+            "Fills of the last 60 seconds per market",
+            market_fills_per_minute.clone(),
+        );
+        let stage_queue = Family::<StageLabels, Gauge>::default();
+        registry.register(
+            "openbook_stage_queue",
+            "Items queued in front of a pipeline stage",
+            stage_queue.clone(),
+        );
+        let stage_processed = Family::<StageLabels, Counter>::default();
+        registry.register(
+            "openbook_stage_processed",
+            "Items taken from its queue by a pipeline stage",
+            stage_processed.clone(),
+        );
+        let owner_lookups = Gauge::default();
+        registry.register(
+            "openbook_owner_lookups",
+            "Owner lookups in flight",
+            owner_lookups.clone(),
+        );
+        let owners_unresolved = Counter::default();
+        registry.register(
+            "openbook_owners_unresolved",
+            "Trades published before the owner lookup of the maker or taker finished",
+            owners_unresolved.clone(),
+        );
+        let source_resubscribes = Counter::default();
+        registry.register(
+            "openbook_source_resubscribes",
+            "Geyser subscriptions rebuilt after a stale feed",
+            source_resubscribes.clone(),
+        );
+        let source_reconnects = Counter::default();
+        registry.register(
+            "openbook_source_reconnects",
+            "Reconnects of the source",
+            source_reconnects.clone(),
+        );
+        let summary_fills = Gauge::default();
+        registry.register(
+            "openbook_summary_fills",
+            "Fills received in the last summary period",
+            summary_fills.clone(),
+        );
+        let summary_lag_avg_seconds = Gauge::<f64, AtomicU64>::default();
+        registry.register(
+            "openbook_summary_lag_avg_seconds",
+            "Average lag behind block time of the fills of the last summary period",
+            summary_lag_avg_seconds.clone(),
+        );
+        let summary_lag_max_seconds = Gauge::<f64, AtomicU64>::default();
+        registry.register(
+            "openbook_summary_lag_max_seconds",
+            "Maximum lag behind block time of the fills of the last summary period",
+            summary_lag_max_seconds.clone(),
+        );
+        let enricher_timeouts = Family::<EnricherLabels, Counter>::default();
+        registry.register(
+            "openbook_enricher_timeouts",
+            "Trades published without the fields of an enricher which timed out",
+            enricher_timeouts.clone(),
+        );
+        let lag_slo_breached = Family::<MarketLabels, Gauge>::default();
+        registry.register(
+            "openbook_lag_slo_breached",
+            "Whether the market is over its lag objective (--lag-slo-ms)",
+            lag_slo_breached.clone(),
+        );
+        let shadow_matched = Counter::default();
+        registry.register(
+            "openbook_shadow_matched",
+            "Trades found in both the own and the reference feed (--shadow)",
+            shadow_matched.clone(),
+        );
+        let shadow_mismatches = Family::<ShadowLabels, Counter>::default();
+        registry.register(
+            "openbook_shadow_mismatches",
+            "Differences between the own and the reference feed by kind",
+            shadow_mismatches.clone(),
+        );
+        let allocations = StageHistogramFamily::new_with_constructor(allocations_histogram);
+        if cfg!(feature = "alloc-audit") {
+            registry.register(
+                "openbook_allocations",
+                "Allocations per transaction (source) and per published trade (encode, publish)",
+                allocations.clone(),
+            );
+        }
+        let seq_regressions = Family::<MarketLabels, Counter>::default();
+        registry.register(
+            "openbook_seq_regressions",
+            "Fills whose seq_num was not above the last published one of their market",
+            seq_regressions.clone(),
+        );
+        let redelivered_fills = Counter::default();
+        registry.register(
+            "openbook_redelivered_fills",
+            "Fills dropped because their transaction was delivered before",
+            redelivered_fills.clone(),
+        );
+        let archive_duplicates = Counter::default();
+        registry.register(
+            "openbook_archive_duplicates",
+            "Trades not archived because their tradeId is in their partition already",
+            archive_duplicates.clone(),
+        );
+        let errors = Family::<ErrorLabels, Counter>::default();
+        registry.register(
+            "openbook_errors",
+            "Failures by category (config, source, decode, enrich, sink) and kind",
+            errors.clone(),
+        );
+        let quarantined_trades = Family::<MarketLabels, Counter>::default();
+        registry.register(
+            "openbook_quarantined_trades",
+            "Trades of quarantined markets, published on the quarantine topic",
+            quarantined_trades.clone(),
+        );
+        let health_score = Family::<MarketLabels, Gauge>::default();
+        registry.register(
+            "openbook_health_score",
+            "Health score of the market from its smoothed lag, 0-100 (--health-min-score)",
+            health_score.clone(),
+        );
+        let owners_failed = Family::<ReasonLabels, Counter>::default();
+        registry.register(
+            "openbook_owners_failed",
+            "OpenOrders accounts whose owner can never be resolved, by reason",
+            owners_failed.clone(),
+        );
+        let dropped_fills = Counter::default();
+        registry.register(
+            "openbook_dropped_fills",
+            "Fills dropped because the intake queue was full",
+            dropped_fills.clone(),
+        );
+        let enricher_skipped = Family::<EnricherLabels, Counter>::default();
+        registry.register(
+            "openbook_enricher_skipped",
+            "Trades an enricher did not run on because of its --enricher-filter",
+            enricher_skipped.clone(),
+        );
+        let publish_latency_seconds = LatencyHistogramFamily::new_with_constructor(latency_histogram);
+        registry.register(
+            "openbook_publish_latency_seconds",
+            "Block time to publish of fills per market, with the signature of the last fill of a bucket as exemplar",
+            publish_latency_seconds.clone(),
+        );
+        let alerts_suppressed = Counter::default();
+        registry.register(
+            "openbook_alerts_suppressed",
+            "Alerts and lag breaches not published because they fired within --alert-cooldown-secs",
+            alerts_suppressed.clone(),
+        );
+        let feature_enabled = Family::<FeatureLabels, Gauge>::default();
+        registry.register(
+            "openbook_feature_enabled",
+            "Whether the feature is enabled, toggled with POST /features on the admin server",
+            feature_enabled.clone(),
+        );
+        let sink_digests = Family::<SinkLabels, Counter>::default();
+        registry.register(
+            "openbook_sink_digests",
+            "Bursts a paced sink delivered as one digest",
+            sink_digests.clone(),
+        );
+        let consumer_lag = Family::<ConsumerLabels, Gauge>::default();
+        registry.register(
+            "openbook_consumer_lag",
+            "Trades published but not yet processed by a consumer at its last heartbeat, over all markets",
+            consumer_lag.clone(),
+        );
+        let plugin_dropped = Family::<EnricherLabels, Counter>::default();
+        registry.register(
+            "openbook_plugin_dropped",
+            "Trades an annotation plugin filtered out",
+            plugin_dropped.clone(),
+        );
+        let plugin_errors = Family::<EnricherLabels, Counter>::default();
+        registry.register(
+            "openbook_plugin_errors",
+            "Trades published without the annotations of a plugin which failed",
+            plugin_errors.clone(),
+        );
+        let stage_priority = Family::<StageLabels, Counter>::default();
+        registry.register(
+            "openbook_stage_priority",
+            "Items a stage took from its priority lane (--priority-market)",
+            stage_priority.clone(),
+        );
+        let token_refreshes = Counter::default();
+        registry.register(
+            "openbook_token_refreshes",
+            "Geyser x-tokens replaced by --token-refresh-command after an authentication failure",
+            token_refreshes.clone(),
+        );
+        let trades_published = Family::<MarketLabels, Counter>::default();
+        registry.register(
+            "openbook_trades_published",
+            "Trades delivered to the sinks per market, carried over restarts with --persist-metrics",
+            trades_published.clone(),
+        );
+        let caught_up_fills = Family::<MarketLabels, Counter>::default();
+        registry.register(
+            "openbook_caught_up_fills",
+            "Fills missed while the printer was down and published by --catch-up-signatures",
+            caught_up_fills.clone(),
+        );
+        let estimated_block_times = Counter::default();
+        registry.register(
+            "openbook_estimated_block_times",
+            "Fills published with an estimated block time because their source had none",
+            estimated_block_times.clone(),
+        );
+        let commitment_downgrades = Counter::default();
+        registry.register(
+            "openbook_commitment_downgrades",
+            "Slots delivered below the commitment of their subscription (--commitment-check-ms)",
+            commitment_downgrades.clone(),
+        );
+        let program_upgrades = Counter::default();
+        registry.register(
+            "openbook_program_upgrades",
+            "Deployments of the OpenBook program seen by --upgrade-check-secs",
+            program_upgrades.clone(),
+        );
+        let incompatible_markets = Gauge::default();
+        registry.register(
+            "openbook_incompatible_markets",
+            "Markets whose account layout changed with a program upgrade, their fills are skipped",
+            incompatible_markets.clone(),
+        );
+        let upgrade_skipped_fills = Family::<MarketLabels, Counter>::default();
+        registry.register(
+            "openbook_upgrade_skipped_fills",
+            "Fills not decoded because their market has an incompatible layout after a program upgrade",
+            upgrade_skipped_fills.clone(),
+        );
+        let sink_queue = Family::<SinkLabels, Gauge>::default();
+        registry.register(
+            "openbook_sink_queue",
+            "Messages waiting for the workers of a --sink-workers sink",
+            sink_queue.clone(),
+        );
+        Metrics {
+            registry,
+            source_lag_seconds,
+            market_source_lag_seconds,
+            clock_offset_seconds,
+            sink_failures,
+            sink_open,
+            ws_clients,
+            ws_rate_limited,
+            ws_slow_disconnects,
+            trade_notional,
+            market_fills_per_minute,
+            stage_queue,
+            stage_processed,
+            owner_lookups,
+            owners_unresolved,
+            source_resubscribes,
+            source_reconnects,
+            summary_fills,
+            summary_lag_avg_seconds,
+            summary_lag_max_seconds,
+            enricher_timeouts,
+            lag_slo_breached,
+            shadow_matched,
+            shadow_mismatches,
+            allocations,
+            seq_regressions,
+            redelivered_fills,
+            archive_duplicates,
+            errors,
+            quarantined_trades,
+            health_score,
+            owners_failed,
+            dropped_fills,
+            enricher_skipped,
+            publish_latency_seconds,
+            alerts_suppressed,
+            feature_enabled,
+            sink_digests,
+            consumer_lag,
+            plugin_dropped,
+            plugin_errors,
+            stage_priority,
+            token_refreshes,
+            trades_published,
+            caught_up_fills,
+            estimated_block_times,
+            commitment_downgrades,
+            program_upgrades,
+            incompatible_markets,
+            upgrade_skipped_fills,
+            sink_queue,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut buf = String::new();
+        if let Err(err) = encode(&mut buf, &self.registry) {
+            log::error!("encoding metrics failed: {}", err);
+        }
+        buf
+    }
+
+    pub fn feature(feature: &str) -> FeatureLabels {
+        FeatureLabels {
+            feature: feature.to_string(),
+        }
+    }
+
+    pub fn consumer(consumer: &str) -> ConsumerLabels {
+        ConsumerLabels {
+            consumer: consumer.to_string(),
+        }
+    }
+
+    pub fn signature(signature: &str) -> SignatureLabels {
+        SignatureLabels {
+            signature: signature.to_string(),
+        }
+    }
+
+    pub fn market(market: &str) -> MarketLabels {
+        MarketLabels {
+            market: market.to_string(),
+        }
+    }
+
+    pub fn sink(sink: &str) -> SinkLabels {
+        SinkLabels {
+            sink: sink.to_string(),
+        }
+    }
+
+    pub fn stage(stage: &str) -> StageLabels {
+        StageLabels {
+            stage: stage.to_string(),
+        }
+    }
+
+    pub fn enricher(enricher: &str) -> EnricherLabels {
+        EnricherLabels {
+            enricher: enricher.to_string(),
+        }
+    }
+
+    pub fn reason(reason: &str) -> ReasonLabels {
+        ReasonLabels {
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn shadow(kind: &str) -> ShadowLabels {
+        ShadowLabels {
+            kind: kind.to_string(),
+        }
+    }
+
+    pub fn error(category: &str, kind: &str) -> ErrorLabels {
+        ErrorLabels {
+            category: category.to_string(),
+            kind: kind.to_string(),
+        }
+    }
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
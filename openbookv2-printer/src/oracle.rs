@@ -0,0 +1,173 @@
+use crate::enricher::usd::QuoteConversion;
+use anchor_lang::solana_program::hash::hash;
+use log::warn;
+use openbookv2_decoder::feed::OraclePrice;
+use openbookv2_decoder::ControlMessage;
+use openbookv2_generated::state::Market;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_program::pubkey::Pubkey;
+use std::collections::{BTreeMap, HashMap};
+
+// legacy pyth v2 price account
+const PYTH_MAGIC: u32 = 0xa1b2c3d4;
+const PYTH_PRICE_ACCOUNT: u32 = 3;
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+fn i64_at(data: &[u8], offset: usize) -> Option<i64> {
+    Some(i64::from_le_bytes(data.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+fn scaled(value: f64, exponent: i32) -> f64 {
+    value * 10_f64.powi(exponent)
+}
+
+fn decode_pyth_legacy(account: &Pubkey, data: &[u8]) -> Option<OraclePrice> {
+    if u32_at(data, 0)? != PYTH_MAGIC || u32_at(data, 8)? != PYTH_PRICE_ACCOUNT {
+        return None;
+    }
+    let exponent = u32_at(data, 20)? as i32;
+    Some(OraclePrice {
+        account: account.to_string(),
+        price: scaled(i64_at(data, 208)? as f64, exponent),
+        confidence: scaled(u64_at(data, 216)? as f64, exponent),
+        slot: Some(u64_at(data, 232)?),
+        publish_time: Some(i64_at(data, 96)?),
+    })
+}
+
+// pyth pull oracle PriceUpdateV2 (anchor account)
+fn decode_pyth_pull(account: &Pubkey, data: &[u8]) -> Option<OraclePrice> {
+    if data.get(..8)? != &hash(b"account:PriceUpdateV2").to_bytes()[..8] {
+        return None;
+    }
+    // write authority, then the verification level enum: Partial { num_signatures } or Full
+    let message = match data.get(40)? {
+        0 => 42,
+        _ => 41,
+    };
+    let price = i64_at(data, message + 32)?;
+    let confidence = u64_at(data, message + 40)?;
+    let exponent = u32_at(data, message + 48)? as i32;
+    Some(OraclePrice {
+        account: account.to_string(),
+        price: scaled(price as f64, exponent),
+        confidence: scaled(confidence as f64, exponent),
+        slot: u64_at(data, message + 84),
+        publish_time: Some(i64_at(data, message + 52)?),
+    })
+}
+
+fn decode(account: &Pubkey, data: &[u8]) -> Option<OraclePrice> {
+    decode_pyth_legacy(account, data).or_else(|| decode_pyth_pull(account, data))
+}
+
+fn oracle_key(key: Pubkey) -> Option<Pubkey> {
+    (key != Pubkey::default()).then_some(key)
+}
+
+// The oracles configured on the watched markets, oracle_a prices the base and the
+// optional oracle_b the quote, like openbook itself, and the oracles of the quote
+// currencies with an oracle --quote-conversion
+pub struct Oracles {
+    markets: BTreeMap<Pubkey, (Pubkey, Option<Pubkey>)>,
+    conversions: Vec<(String, Pubkey)>,
+}
+
+impl Oracles {
+    pub fn new(markets: &BTreeMap<Pubkey, Market>, conversions: &[(String, QuoteConversion)]) -> Self {
+        let markets = markets
+            .iter()
+            .filter_map(|(key, market)| {
+                let oracle_a = oracle_key(market.oracle_a.key)?;
+                Some((*key, (oracle_a, oracle_key(market.oracle_b.key))))
+            })
+            .collect();
+        let conversions = conversions
+            .iter()
+            .filter_map(|(currency, conversion)| match conversion {
+                QuoteConversion::Oracle(oracle) => Some((currency.clone(), *oracle)),
+                _ => None,
+            })
+            .collect();
+        Oracles { markets, conversions }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.markets.is_empty() && self.conversions.is_empty()
+    }
+
+    // The oracle accounts read every --oracle-interval
+    pub fn keys(&self) -> Vec<Pubkey> {
+        let mut keys: Vec<Pubkey> = self
+            .markets
+            .values()
+            .flat_map(|(oracle_a, oracle_b)| std::iter::once(*oracle_a).chain(*oracle_b))
+            .chain(self.conversions.iter().map(|(_, oracle)| *oracle))
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    // Supports pyth (legacy price accounts and pull PriceUpdateV2), other oracles are skipped
+    pub async fn prices(&self, client: &RpcClient, market_names: &BTreeMap<Pubkey, String>) -> Vec<ControlMessage> {
+        let keys = self.keys();
+        let mut prices = HashMap::new();
+        for chunk in keys.chunks(100) {
+            match client.get_multiple_accounts(chunk).await {
+                Ok(accounts) => {
+                    for (key, account) in chunk.iter().zip(accounts) {
+                        match account.as_ref().and_then(|account| decode(key, &account.data)) {
+                            Some(price) => {
+                                prices.insert(*key, price);
+                            }
+                            None => warn!("oracle {} is not a supported oracle account", key),
+                        }
+                    }
+                }
+                Err(err) => warn!("fetching oracles failed: {}", err),
+            }
+        }
+        let mut messages = Vec::new();
+        for (market, (oracle_a, oracle_b)) in self.markets.iter() {
+            let Some(price_a) = prices.get(oracle_a) else {
+                continue;
+            };
+            let price_b = match oracle_b {
+                Some(oracle_b) => match prices.get(oracle_b) {
+                    Some(price_b) => Some(price_b.clone()),
+                    None => continue,
+                },
+                None => None,
+            };
+            let price = match price_b.as_ref() {
+                Some(price_b) => price_a.price / price_b.price,
+                None => price_a.price,
+            };
+            messages.push(ControlMessage::Price {
+                market_id: market.to_string(),
+                market_name: market_names.get(market).map_or(String::new(), |name| name.replace('\0', "")),
+                price,
+                oracle_a: price_a.clone(),
+                oracle_b: price_b,
+            });
+        }
+        for (currency, oracle) in self.conversions.iter() {
+            if let Some(price) = prices.get(oracle) {
+                messages.push(ControlMessage::ConversionPrice {
+                    currency: currency.clone(),
+                    price: price.price,
+                    oracle: price.clone(),
+                });
+            }
+        }
+        messages
+    }
+}
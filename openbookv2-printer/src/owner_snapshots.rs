@@ -0,0 +1,174 @@
+use crate::state;
+use crate::utils::OoaOwner;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use solana_program::pubkey::Pubkey;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OwnerRecord {
+    pub owner: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delegate: Option<String>,
+    pub market: String,
+    // newest trade slot when the owner was resolved, 0 for owners pre-warmed at startup
+    pub slot: u64,
+}
+
+// The OpenOrders account → owner map of a shard as of its newest trade
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct OwnerSnapshot {
+    pub slot: u64,
+    pub time: u64,
+    pub owners: BTreeMap<String, OwnerRecord>,
+}
+
+// Writes the owners enricher's map every --owner-snapshot-interval to
+// <archive dir>/owners/slot=<slot>.json (below shard-<i>-of-<n>/ with several shards), so
+// backfills attribute old fills to the owners and delegates of their time. Snapshots written
+// longer than the archive retention ago are deleted, except the newest one.
+pub struct OwnerSnapshots {
+    dir: PathBuf,
+    interval: Duration,
+    retention: Option<Duration>,
+    last: Instant,
+}
+
+impl OwnerSnapshots {
+    pub fn new(dir: PathBuf, interval: Duration, retention: Option<Duration>) -> Self {
+        OwnerSnapshots {
+            dir,
+            interval,
+            retention,
+            last: Instant::now(),
+        }
+    }
+
+    pub fn due(&self) -> bool {
+        self.last.elapsed() >= self.interval
+    }
+
+    // Saved off the enrich task, a snapshot of a busy program has many accounts
+    pub fn write(&mut self, snapshot: OwnerSnapshot) {
+        self.last = Instant::now();
+        let dir = self.dir.clone();
+        let retention = self.retention;
+        tokio::task::spawn_blocking(move || {
+            if let Err(err) = std::fs::create_dir_all(&dir) {
+                warn!("could not create {}: {}", dir.display(), err);
+                return;
+            }
+            state::save_plain(&dir.join(format!("slot={}.json", snapshot.slot)), &snapshot);
+            if let Some(retention) = retention {
+                prune(&dir, retention);
+            }
+        });
+    }
+}
+
+// Archived snapshots read back for a replay (--owners-from-archive): the owners of a trade
+// are those of the newest snapshot at or before its slot, or of the oldest one for trades
+// before the first snapshot. Snapshots of all shards are merged.
+pub struct OwnerHistory {
+    // snapshot files per shard directory, by slot
+    files: Vec<BTreeMap<u64, PathBuf>>,
+    // snapshot slot per shard directory of `owners`
+    loaded: Vec<Option<u64>>,
+    owners: HashMap<Pubkey, OoaOwner>,
+}
+
+impl OwnerHistory {
+    pub fn open(archive_dir: &Path) -> Self {
+        let root = archive_dir.join("owners");
+        let mut dirs = vec![root.clone()];
+        if let Ok(entries) = std::fs::read_dir(&root) {
+            dirs.extend(entries.flatten().map(|entry| entry.path()).filter(|path| path.is_dir()));
+        }
+        let files: Vec<BTreeMap<u64, PathBuf>> = dirs.iter().map(|dir| snapshot_files(dir)).collect();
+        info!(
+            "{} archived owner snapshots in {}",
+            files.iter().map(BTreeMap::len).sum::<usize>(),
+            root.display()
+        );
+        OwnerHistory {
+            loaded: vec![None; files.len()],
+            files,
+            owners: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &Pubkey, slot: u64) -> Option<OoaOwner> {
+        self.load(slot);
+        self.owners.get(key).copied()
+    }
+
+    // Swaps in the snapshots for `slot` when a replay moves past the next one
+    fn load(&mut self, slot: u64) {
+        let wanted: Vec<Option<u64>> = self
+            .files
+            .iter()
+            .map(|files| {
+                files
+                    .range(..=slot)
+                    .next_back()
+                    .or_else(|| files.iter().next())
+                    .map(|(slot, _)| *slot)
+            })
+            .collect();
+        if wanted == self.loaded {
+            return;
+        }
+        self.owners.clear();
+        for (files, snapshot_slot) in self.files.iter().zip(wanted.iter()) {
+            let Some(path) = snapshot_slot.and_then(|snapshot_slot| files.get(&snapshot_slot)) else {
+                continue;
+            };
+            let snapshot: OwnerSnapshot = state::load_plain(path);
+            self.owners.extend(snapshot.owners.iter().filter_map(|(key, record)| {
+                Some((
+                    Pubkey::from_str(key).ok()?,
+                    OoaOwner {
+                        owner: Pubkey::from_str(&record.owner).ok()?,
+                        delegate: record.delegate.as_deref().and_then(|delegate| Pubkey::from_str(delegate).ok()),
+                        market: Pubkey::from_str(&record.market).ok()?,
+                    },
+                ))
+            }));
+        }
+        self.loaded = wanted;
+    }
+}
+
+fn prune(dir: &Path, retention: Duration) {
+    let mut files = snapshot_files(dir);
+    files.pop_last();
+    for path in files.into_values() {
+        let expired = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age > retention));
+        if expired {
+            if let Err(err) = std::fs::remove_file(&path) {
+                warn!("could not delete {}: {}", path.display(), err);
+            }
+        }
+    }
+}
+
+fn snapshot_files(dir: &Path) -> BTreeMap<u64, PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return BTreeMap::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let slot = name.strip_prefix("slot=")?.strip_suffix(".json")?.parse().ok()?;
+            Some((slot, entry.path()))
+        })
+        .collect()
+}
@@ -0,0 +1,322 @@
+use crate::error::{Error, SinkError};
+use crate::metrics::{metrics, Metrics};
+use crate::sink::{topic_matches, Sink, TOPIC_TRADES};
+use log::warn;
+use openbookv2_decoder::Trade;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// Topic of the digests a paced sink delivers instead of a burst
+pub const TOPIC_DIGEST: &str = "digest";
+
+#[derive(Clone, Debug)]
+pub struct PacingPolicy {
+    // topic patterns the sink gets (see sink::topic_matches), everything else is dropped
+    pub topics: Vec<String>,
+    // at most one delivery per interval, the messages of a burst wait for the next one
+    pub min_interval: Duration,
+    // payloads kept in one digest, the rest only counts in its summary
+    pub max_payloads: usize,
+}
+
+impl Default for PacingPolicy {
+    fn default() -> Self {
+        PacingPolicy {
+            topics: vec![TOPIC_TRADES.to_string()],
+            min_interval: Duration::from_secs(2),
+            max_payloads: 20,
+        }
+    }
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct MarketDigest {
+    trades: u64,
+    quantity: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notional_usd: Option<f64>,
+    low: f64,
+    high: f64,
+    first_price: f64,
+    last_price: f64,
+}
+
+// A burst as one message, e.g. the hundreds of fills of a large taker sweep
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Digest {
+    r#type: &'static str,
+    messages: u64,
+    // messages counted in the summary but not in payloads (max_payloads)
+    omitted: u64,
+    markets: BTreeMap<String, MarketDigest>,
+    payloads: Vec<Value>,
+}
+
+impl Digest {
+    fn add(&mut self, topic: &str, payload: &str, max_payloads: usize) {
+        self.messages += 1;
+        if topic_matches(TOPIC_TRADES, topic) {
+            if let Ok(trade) = serde_json::from_str::<Trade>(payload) {
+                let market = self.markets.entry(trade.market_name.clone()).or_insert_with(|| MarketDigest {
+                    low: trade.price_double,
+                    high: trade.price_double,
+                    first_price: trade.price_double,
+                    ..Default::default()
+                });
+                market.trades += 1;
+                market.quantity += trade.quantity_double;
+                if let Some(notional) = trade.notional_usd {
+                    *market.notional_usd.get_or_insert(0.0) += notional;
+                }
+                market.low = market.low.min(trade.price_double);
+                market.high = market.high.max(trade.price_double);
+                market.last_price = trade.price_double;
+            }
+        }
+        if self.payloads.len() < max_payloads {
+            self.payloads
+                .push(serde_json::from_str(payload).unwrap_or_else(|_| Value::String(payload.to_string())));
+        } else {
+            self.omitted += 1;
+        }
+    }
+}
+
+// Wraps a low throughput sink (webhooks, chat bots) which must not get a call per fill.
+// The first message after a quiet interval goes out right away, later ones wait for the
+// interval to end and go out as they are when alone, else as one digest on TOPIC_DIGEST:
+// {"type":"digest","messages":..,"omitted":..,"markets":{"SOL-USDC":{"trades":..,..}},"payloads":[..]}.
+// The inner sink runs on its own thread, so a slow call never holds up publishing, and
+// its failures are logged and counted instead of opening the breaker.
+pub struct PacedSink {
+    name: String,
+    topics: Vec<String>,
+    queue: Option<Sender<(String, String)>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl PacedSink {
+    pub fn new(mut inner: impl Sink + 'static, policy: PacingPolicy) -> Self {
+        let name = inner.name().to_string();
+        let (queue, messages) = channel::<(String, String)>();
+        let worker_name = name.clone();
+        let topics = policy.topics.clone();
+        let worker = std::thread::spawn(move || {
+            let mut deliver = |topic: &str, payload: &str| {
+                if let Err(error) = inner.send(topic, payload) {
+                    let err = Error::Sink {
+                        name: worker_name.clone(),
+                        error,
+                    };
+                    err.record();
+                    metrics().sink_failures.get_or_create(&Metrics::sink(&worker_name)).inc();
+                    warn!("{}", err);
+                }
+            };
+            let mut last: Option<Instant> = None;
+            let mut burst: Vec<(String, String)> = Vec::new();
+            loop {
+                let wait = match last {
+                    Some(last) if !burst.is_empty() => policy.min_interval.saturating_sub(last.elapsed()),
+                    _ => Duration::MAX,
+                };
+                let closed = match messages.recv_timeout(wait) {
+                    Ok(message) => {
+                        burst.push(message);
+                        false
+                    }
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Err(RecvTimeoutError::Disconnected) => true,
+                };
+                if !closed && last.is_some_and(|last| last.elapsed() < policy.min_interval) {
+                    continue;
+                }
+                match burst.len() {
+                    0 => {}
+                    1 => {
+                        let (topic, payload) = burst.pop().unwrap();
+                        deliver(&topic, &payload);
+                        last = Some(Instant::now());
+                    }
+                    _ => {
+                        let mut digest = Digest {
+                            r#type: "digest",
+                            ..Default::default()
+                        };
+                        for (topic, payload) in burst.drain(..) {
+                            digest.add(&topic, &payload, policy.max_payloads);
+                        }
+                        metrics().sink_digests.get_or_create(&Metrics::sink(&worker_name)).inc();
+                        deliver(TOPIC_DIGEST, &serde_json::to_string(&digest).unwrap());
+                        last = Some(Instant::now());
+                    }
+                }
+                if closed {
+                    break;
+                }
+            }
+        });
+        PacedSink {
+            name,
+            topics,
+            queue: Some(queue),
+            worker: Some(worker),
+        }
+    }
+}
+
+impl Sink for PacedSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, topic: &str, payload: &str) -> Result<(), SinkError> {
+        if !self.topics.iter().any(|pattern| topic_matches(pattern, topic)) {
+            return Ok(());
+        }
+        self.queue
+            .as_ref()
+            .and_then(|queue| queue.send((topic.to_string(), payload.to_string())).ok())
+            .ok_or(SinkError::Closed("the paced sink stopped"))
+    }
+}
+
+// The last burst is delivered before the sink goes away
+impl Drop for PacedSink {
+    fn drop(&mut self) {
+        self.queue.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    type Sent = Arc<Mutex<Vec<(String, String)>>>;
+
+    struct RecordingSink(Sent);
+
+    impl Sink for RecordingSink {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        fn send(&mut self, topic: &str, payload: &str) -> Result<(), SinkError> {
+            self.0.lock().unwrap().push((topic.to_string(), payload.to_string()));
+            Ok(())
+        }
+    }
+
+    fn trade(market_name: &str, price: f64, quantity: f64) -> String {
+        json!({
+            "timeStamp": 1_700_000_000,
+            "makerOwner": "maker",
+            "takerOwner": "taker",
+            "priceDouble": price,
+            "quantityDouble": quantity,
+            "marketId": "market",
+            "takerSide": 0,
+            "marketName": market_name,
+            "signature": "signature",
+            "notionalUsd": price * quantity,
+        })
+        .to_string()
+    }
+
+    // Sends the messages through a sink pacing them for a minute, dropping it delivers the last burst
+    fn paced(policy: PacingPolicy, messages: &[(&str, String)]) -> Vec<(String, Value)> {
+        let sent = Sent::default();
+        let mut sink = PacedSink::new(
+            RecordingSink(sent.clone()),
+            PacingPolicy {
+                min_interval: Duration::from_secs(60),
+                ..policy
+            },
+        );
+        for (topic, payload) in messages {
+            sink.send(topic, payload).unwrap();
+        }
+        drop(sink);
+        let sent = sent.lock().unwrap();
+        sent.iter()
+            .map(|(topic, payload)| (topic.clone(), serde_json::from_str(payload).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn the_first_message_goes_out_alone_and_a_burst_as_a_digest() {
+        let sent = paced(
+            PacingPolicy::default(),
+            &[
+                ("trades.SOL.USDC", trade("SOL-USDC", 100.0, 1.0)),
+                ("trades.SOL.USDC", trade("SOL-USDC", 101.0, 2.0)),
+                ("trades.SOL.USDC", trade("SOL-USDC", 99.0, 1.0)),
+                ("trades.JUP.USDC", trade("JUP-USDC", 1.0, 10.0)),
+                ("control", json!({"type": "data_loss"}).to_string()),
+            ],
+        );
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].0, "trades.SOL.USDC");
+        assert_eq!(sent[0].1["priceDouble"], 100.0);
+        let (topic, digest) = &sent[1];
+        assert_eq!(topic, TOPIC_DIGEST);
+        assert_eq!(digest["type"], "digest");
+        assert_eq!((digest["messages"].as_u64(), digest["omitted"].as_u64()), (Some(3), Some(0)));
+        assert_eq!(
+            digest["markets"]["SOL-USDC"],
+            json!({
+                "trades": 2,
+                "quantity": 3.0,
+                "notionalUsd": 301.0,
+                "low": 99.0,
+                "high": 101.0,
+                "firstPrice": 101.0,
+                "lastPrice": 99.0,
+            })
+        );
+        assert_eq!(digest["markets"]["JUP-USDC"]["trades"], 1);
+        assert_eq!(digest["payloads"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn a_digest_keeps_at_most_max_payloads() {
+        let policy = PacingPolicy {
+            topics: vec!["*".to_string()],
+            max_payloads: 2,
+            ..Default::default()
+        };
+        let mut messages = vec![("control", json!({"type": "data_loss"}).to_string())];
+        messages.extend((0..4).map(|_| ("control", json!({"type": "data_loss"}).to_string())));
+        messages.push(("control", "not json".to_string()));
+        let sent = paced(policy, &messages);
+        let digest = &sent[1].1;
+        assert_eq!((digest["messages"].as_u64(), digest["omitted"].as_u64()), (Some(5), Some(3)));
+        assert_eq!(digest["payloads"].as_array().unwrap().len(), 2);
+        assert!(digest["markets"].as_object().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_single_waiting_message_goes_out_as_it_is() {
+        let sent = paced(
+            PacingPolicy::default(),
+            &[
+                ("trades.SOL.USDC", trade("SOL-USDC", 100.0, 1.0)),
+                ("trades.SOL.USDC", trade("SOL-USDC", 101.0, 1.0)),
+            ],
+        );
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1].0, "trades.SOL.USDC");
+        assert_eq!(sent[1].1["priceDouble"], 101.0);
+    }
+}
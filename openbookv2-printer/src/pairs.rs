@@ -0,0 +1,42 @@
+use openbookv2_decoder::Trade;
+use openbookv2_generated::state::Market;
+use solana_program::pubkey::Pubkey;
+use std::collections::{BTreeMap, HashMap};
+
+// Merged feed of all watched markets with the same base and quote mint. The pair is
+// named after the first of its markets, trades keep their marketId and get pairSeq,
+// contiguous over the whole pair.
+pub struct PairFeed {
+    pairs: HashMap<String, String>,
+    seqs: HashMap<String, u64>,
+}
+
+impl PairFeed {
+    pub fn new(markets: &BTreeMap<Pubkey, Market>, market_names: &BTreeMap<Pubkey, String>) -> Self {
+        let mut names: BTreeMap<(Pubkey, Pubkey), String> = BTreeMap::new();
+        let mut pairs = HashMap::new();
+        for (key, market) in markets {
+            let market_name = market_names.get(key).map_or(String::new(), |name| name.replace('\0', ""));
+            let pair = names
+                .entry((market.base_mint, market.quote_mint))
+                .or_insert(market_name)
+                .clone();
+            pairs.insert(key.to_string(), pair);
+        }
+        PairFeed {
+            pairs,
+            seqs: HashMap::new(),
+        }
+    }
+
+    // the trade as published on the pair feed
+    pub fn tag(&mut self, trade: &Trade) -> Option<Trade> {
+        let pair = self.pairs.get(&trade.market_id)?;
+        let seq = self.seqs.entry(pair.clone()).or_insert(0);
+        *seq += 1;
+        let mut tagged = trade.clone();
+        tagged.pair = Some(pair.clone());
+        tagged.pair_seq = Some(*seq);
+        Some(tagged)
+    }
+}
@@ -0,0 +1,220 @@
+use crate::alloc_audit;
+use crate::bus::{Bus, BusMessage};
+use crate::metrics::{metrics, Metrics};
+use crate::postmortem;
+use crate::sink::{topic_matches, Sinks, TOPIC_TRADES};
+use log::{info, warn};
+use openbookv2_decoder::{ControlMessage, Trade, TradeFrame};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+// The printer runs as a chain of stages connected by bounded queues:
+//
+//   source (intake + decode) -> enrich -> encode -> publish
+//                                  `--(fast trades, --fast-sinks)--^
+//
+// Every queue is named after the stage reading from it, its depth is the
+// openbook_stage_queue gauge and the items taken by the stage the
+// openbook_stage_processed counter. Only the intake queue drops (see intake.rs),
+// the queues after it block and so slow down the stage in front.
+//
+// Every queue has a second lane for the fills, trades and payloads of --priority-market
+// markets, a stage takes from it first. During a burst they pass the queued items of the
+// other markets, each market stays in order as all its items use the same lane.
+
+// Output of the enrich stage. Trades are the common case, boxing them would cost an
+// allocation per fill.
+#[allow(clippy::large_enum_variant)]
+pub enum Enriched {
+    Trade {
+        trade: Trade,
+        block_time_ms: Option<i64>,
+        received_ms: i64,
+    },
+    Control { topic: String, message: ControlMessage },
+}
+
+// Output of the encode stage, trade is kept for sink filters and binary frames
+pub struct Outgoing {
+    pub topic: String,
+    pub payload: String,
+    pub trade: Option<Trade>,
+    // a trade teed off by the enrich stage before the enrichers, only for --fast-sinks
+    pub fast: bool,
+}
+
+pub struct StageSender<T> {
+    stage: &'static str,
+    sender: mpsc::Sender<T>,
+    priority: mpsc::Sender<T>,
+}
+
+impl<T> Clone for StageSender<T> {
+    fn clone(&self) -> Self {
+        StageSender {
+            stage: self.stage,
+            sender: self.sender.clone(),
+            priority: self.priority.clone(),
+        }
+    }
+}
+
+pub struct StageReceiver<T> {
+    stage: &'static str,
+    receiver: mpsc::Receiver<T>,
+    priority: mpsc::Receiver<T>,
+}
+
+// Both lanes hold `size` items
+pub fn stage_channel<T>(stage: &'static str, size: usize) -> (StageSender<T>, StageReceiver<T>) {
+    let (sender, receiver) = mpsc::channel(size.max(1));
+    let (priority_sender, priority_receiver) = mpsc::channel(size.max(1));
+    (
+        StageSender {
+            stage,
+            sender,
+            priority: priority_sender,
+        },
+        StageReceiver {
+            stage,
+            receiver,
+            priority: priority_receiver,
+        },
+    )
+}
+
+impl<T> StageSender<T> {
+    // false once the stage is gone
+    pub async fn send(&self, item: T) -> bool {
+        self.send_to(item, false).await
+    }
+
+    // Like send, into the priority lane when `priority` is set
+    pub async fn send_to(&self, item: T, priority: bool) -> bool {
+        let queued = metrics().stage_queue.get_or_create(&Metrics::stage(self.stage)).clone();
+        queued.inc();
+        let lane = if priority { &self.priority } else { &self.sender };
+        if lane.send(item).await.is_err() {
+            queued.dec();
+            return false;
+        }
+        true
+    }
+
+    pub fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        self.try_send_to(item, false)
+    }
+
+    pub fn try_send_to(&self, item: T, priority: bool) -> Result<(), TrySendError<T>> {
+        let queued = metrics().stage_queue.get_or_create(&Metrics::stage(self.stage)).clone();
+        queued.inc();
+        let lane = if priority { &self.priority } else { &self.sender };
+        lane.try_send(item).inspect_err(|_| {
+            queued.dec();
+        })
+    }
+}
+
+impl<T> StageReceiver<T> {
+    // The priority lane first. Both lanes close together, so None means both are done.
+    pub async fn recv(&mut self) -> Option<T> {
+        let (item, priority) = tokio::select! {
+            biased;
+            Some(item) = self.priority.recv() => (Some(item), true),
+            item = self.receiver.recv() => (item, false),
+        };
+        self.taken(item.is_some(), priority);
+        item
+    }
+
+    // for stages running on their own thread
+    pub fn blocking_recv(&mut self) -> Option<T> {
+        futures::executor::block_on(self.recv())
+    }
+
+    fn taken(&self, some: bool, priority: bool) {
+        if some {
+            let labels = Metrics::stage(self.stage);
+            metrics().stage_queue.get_or_create(&labels).dec();
+            metrics().stage_processed.get_or_create(&labels).inc();
+            if priority {
+                metrics().stage_priority.get_or_create(&labels).inc();
+            }
+        }
+    }
+}
+
+// Gives other tasks of the runtime a turn every `every` items, so a burst in one
+// stage does not hold up the websocket server or the admin port
+pub struct Yielder {
+    every: usize,
+    count: usize,
+}
+
+impl Yielder {
+    pub fn new(every: usize) -> Self {
+        Yielder { every, count: 0 }
+    }
+
+    pub async fn tick(&mut self) {
+        if self.every == 0 {
+            return;
+        }
+        self.count += 1;
+        if self.count >= self.every {
+            self.count = 0;
+            tokio::task::yield_now().await;
+        }
+    }
+}
+
+// Hands the message to the in-process consumers, the fast path is not on the bus
+fn to_bus(bus: Option<&Bus>, outgoing: Outgoing) {
+    let Some(bus) = bus.filter(|_| !outgoing.fast) else {
+        return;
+    };
+    let trade_topic = topic_matches(TOPIC_TRADES, &outgoing.topic);
+    let message = BusMessage {
+        topic: outgoing.topic,
+        payload: outgoing.payload,
+        trade: outgoing.trade,
+    };
+    bus.publish(message, trade_topic);
+}
+
+// Last stage, on its own thread because sinks send synchronously. The binary
+// socket gets trades without retries or filters, the bus everything after the sinks.
+pub fn publish(
+    mut sinks: Sinks,
+    binary_socket: Option<zmq::Socket>,
+    bus: Option<Bus>,
+    mut input: StageReceiver<Outgoing>,
+) {
+    while let Some(outgoing) = input.blocking_recv() {
+        let Some(trade) = outgoing.trade.as_ref() else {
+            sinks.publish(&outgoing.topic, &outgoing.payload);
+            to_bus(bus.as_ref(), outgoing);
+            continue;
+        };
+        alloc_audit::measure("publish", || {
+            sinks.publish_trade(&outgoing.topic, trade, &outgoing.payload, outgoing.fast);
+            if outgoing.fast || !topic_matches(TOPIC_TRADES, &outgoing.topic) {
+                return;
+            }
+            metrics().trades_published.get_or_create(&Metrics::market(&trade.market_name)).inc();
+            postmortem::record_trade(&outgoing.payload);
+            if let Some(socket) = binary_socket.as_ref() {
+                match TradeFrame::from_trade(trade) {
+                    Some(frame) => {
+                        if let Err(err) = socket.send(&frame.encode()[..], zmq::DONTWAIT) {
+                            warn!("binary frame not sent: {}", err);
+                        }
+                    }
+                    None => warn!("trade {} has no binary frame", trade.trade_id),
+                }
+            }
+        });
+        to_bus(bus.as_ref(), outgoing);
+    }
+    info!("publish stage stopped");
+}
@@ -0,0 +1,114 @@
+use crate::clock::now_ms;
+use crate::config::PanicPolicy;
+use crate::exit;
+use crate::metrics::{metrics, Metrics};
+use crate::sink::HealthRegistry;
+use log::error;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+// published trades kept for the dump
+const LAST_TRADES: usize = 50;
+
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+static SINKS: OnceLock<HealthRegistry> = OnceLock::new();
+static LAST_SLOT: AtomicU64 = AtomicU64::new(0);
+static TRADES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+// Called by the publish stage for every trade published, cheap enough for the hot path
+pub fn record_trade(payload: &str) {
+    let mut trades = TRADES.lock().unwrap();
+    if trades.len() == LAST_TRADES {
+        trades.pop_front();
+    }
+    trades.push_back(payload.to_string());
+}
+
+pub fn record_slot(slot: u64) {
+    LAST_SLOT.fetch_max(slot, Ordering::Relaxed);
+}
+
+pub fn watch_sinks(health: HealthRegistry) {
+    let _ = SINKS.set(health);
+}
+
+// Writes crash-<unix ms>.json with what the pipeline looked like: stage queues, the last
+// slot seen, sink health, every metric (caches, dropped and regressed
+// fills) and the last trades published. Nothing happens before `install`. Runs inside
+// the panic hook, so locks which the panicking thread may hold are only tried.
+pub fn dump(reason: &str) -> Option<PathBuf> {
+    let dir = DIR.get()?;
+    let stages: serde_json::Map<String, serde_json::Value> = ["enrich", "encode", "publish"]
+        .into_iter()
+        .map(|stage| {
+            let labels = Metrics::stage(stage);
+            let stats = json!({
+                "queued": metrics().stage_queue.get_or_create(&labels).get(),
+                "processed": metrics().stage_processed.get_or_create(&labels).get(),
+            });
+            (stage.to_string(), stats)
+        })
+        .collect();
+    let sinks = SINKS.get().and_then(|health| health.try_lock().ok().map(|health| json!(*health)));
+    let trades: Option<Vec<serde_json::Value>> = TRADES.try_lock().ok().map(|trades| {
+        trades
+            .iter()
+            .map(|payload| serde_json::from_str(payload).unwrap_or_else(|_| json!(payload)))
+            .collect()
+    });
+    let report = json!({
+        "reason": reason,
+        "timeMs": now_ms(),
+        "version": env!("CARGO_PKG_VERSION"),
+        "lastSlot": LAST_SLOT.load(Ordering::Relaxed),
+        "stages": stages,
+        "sinks": sinks,
+        "lastTrades": trades,
+        "metrics": metrics().encode(),
+    });
+    let path = dir.join(format!("crash-{}.json", now_ms()));
+    let result = std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::write(&path, serde_json::to_vec_pretty(&report).unwrap()));
+    match result {
+        Ok(()) => {
+            error!("diagnostics written to {}", path.display());
+            Some(path)
+        }
+        Err(err) => {
+            error!("could not write diagnostics to {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+// Replaces the panic hook: every panic, on any thread or task, writes a dump to `dir` and
+// then follows the policy. The default hook still prints the message and backtrace.
+pub fn install(policy: PanicPolicy, dir: PathBuf) {
+    let _ = DIR.set(dir);
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let message = info
+            .payload()
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .or_else(|| info.payload().downcast_ref::<&str>().copied())
+            .unwrap_or("panic");
+        let location = info.location().map(|location| location.to_string()).unwrap_or_default();
+        let reason = format!("panicked at {}: {}", location, message);
+        match policy {
+            PanicPolicy::Continue => {
+                dump(&reason);
+            }
+            // fatal writes the dump
+            PanicPolicy::Exit => exit::fatal(exit::PANIC, &reason),
+            PanicPolicy::Abort => {
+                dump(&reason);
+                std::process::abort()
+            }
+        }
+    }));
+}
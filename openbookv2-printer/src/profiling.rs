@@ -0,0 +1,55 @@
+use crate::admin::Response;
+use pprof::protos::Message;
+use std::time::Duration;
+use tikv_jemalloc_ctl::{epoch, stats};
+use tokio::task::spawn_blocking;
+
+const MAX_PROFILE_SECONDS: u64 = 300;
+
+// Samples the whole process for `seconds` and returns either a pprof protobuf
+// (readable with `go tool pprof`) or a flamegraph svg
+pub async fn cpu_profile(seconds: u64, flamegraph: bool) -> Response {
+    let seconds = seconds.clamp(1, MAX_PROFILE_SECONDS);
+    let result = spawn_blocking(move || -> Result<Vec<u8>, String> {
+        let guard = pprof::ProfilerGuardBuilder::default()
+            .frequency(99)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()
+            .map_err(|err| err.to_string())?;
+        std::thread::sleep(Duration::from_secs(seconds));
+        let report = guard.report().build().map_err(|err| err.to_string())?;
+        let mut body = Vec::new();
+        if flamegraph {
+            report
+                .flamegraph(&mut body)
+                .map_err(|err| err.to_string())?;
+        } else {
+            let profile = report.pprof().map_err(|err| err.to_string())?;
+            profile.encode(&mut body).map_err(|err| err.to_string())?;
+        }
+        Ok(body)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(body)) if flamegraph => Response::new(200, "image/svg+xml", body),
+        Ok(Ok(body)) => Response::new(200, "application/octet-stream", body),
+        Ok(Err(err)) => Response::text(500, format!("profiling failed: {}\n", err)),
+        Err(err) => Response::text(500, format!("profiling task failed: {}\n", err)),
+    }
+}
+
+pub fn heap_stats() -> Response {
+    if let Err(err) = epoch::advance() {
+        return Response::text(500, format!("jemalloc epoch failed: {}\n", err));
+    }
+    let body = serde_json::json!({
+        "allocated": stats::allocated::read().unwrap_or_default(),
+        "active": stats::active::read().unwrap_or_default(),
+        "metadata": stats::metadata::read().unwrap_or_default(),
+        "resident": stats::resident::read().unwrap_or_default(),
+        "mapped": stats::mapped::read().unwrap_or_default(),
+        "retained": stats::retained::read().unwrap_or_default(),
+    });
+    Response::json(body.to_string())
+}
@@ -0,0 +1,196 @@
+use crate::consumers::ConsumerRegistry;
+use log::{error, info, warn};
+use openbookv2_decoder::{ClientHeartbeat, ReplayResponse, Trade};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+// Last published trades per market by feed_seq, for retransmission to consumers which missed them
+pub struct ReplayBuffer {
+    capacity: usize,
+    markets: HashMap<String, VecDeque<Trade>>,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize) -> Self {
+        ReplayBuffer {
+            capacity,
+            markets: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, trade: &Trade) {
+        let trades = self.markets.entry(trade.market_id.clone()).or_default();
+        trades.push_back(trade.clone());
+        while trades.len() > self.capacity {
+            trades.pop_front();
+        }
+    }
+
+    pub fn replay(&self, market_id: &str, from_seq: u64, to_seq: u64) -> ReplayResponse {
+        let mut trades: Vec<Trade> = vec![];
+        if let Some(buffered) = self.markets.get(market_id) {
+            trades.extend(
+                buffered
+                    .iter()
+                    .filter(|trade| trade.feed_seq >= from_seq && trade.feed_seq <= to_seq)
+                    .cloned(),
+            );
+        }
+        // dropped by the publisher (data_loss) or already evicted from the buffer
+        let mut missing = vec![];
+        let mut expected = from_seq;
+        for trade in trades.iter() {
+            if trade.feed_seq > expected {
+                missing.push((expected, trade.feed_seq - 1));
+            }
+            expected = trade.feed_seq + 1;
+        }
+        if expected <= to_seq {
+            missing.push((expected, to_seq));
+        }
+        ReplayResponse {
+            market_id: market_id.to_string(),
+            from_seq,
+            to_seq,
+            trades,
+            missing,
+        }
+    }
+}
+
+fn handle_request(request: &str, buffer: &Mutex<ReplayBuffer>, consumers: Option<&ConsumerRegistry>) -> String {
+    let parts: Vec<&str> = request.split_whitespace().collect();
+    let reply = match parts.as_slice() {
+        ["heartbeat", consumer, markets @ ..] => {
+            let Some(consumers) = consumers else {
+                return serde_json::json!({ "error": "consumer heartbeats are off (--consumer-heartbeats)" }).to_string();
+            };
+            let feed_seqs: Option<BTreeMap<String, u64>> = markets
+                .iter()
+                .map(|market| {
+                    let (market_id, feed_seq) = market.split_once('=')?;
+                    Some((market_id.to_string(), feed_seq.parse().ok()?))
+                })
+                .collect();
+            match feed_seqs {
+                Some(feed_seqs) => {
+                    let heartbeat = ClientHeartbeat {
+                        consumer: consumer.to_string(),
+                        feed_seqs,
+                    };
+                    match consumers.heartbeat("control", heartbeat) {
+                        Ok(lag) => return serde_json::json!({ "lag": lag }).to_string(),
+                        Err(message) => return serde_json::json!({ "error": message }).to_string(),
+                    }
+                }
+                None => "invalid heartbeat, expected: heartbeat <consumer> <market_id>=<feed_seq> ...",
+            }
+        }
+        ["replay", market_id, from_seq, to_seq] => match (from_seq.parse(), to_seq.parse()) {
+            (Ok(from_seq), Ok(to_seq)) if from_seq <= to_seq => {
+                let response = buffer.lock().unwrap().replay(market_id, from_seq, to_seq);
+                return serde_json::to_string(&response).unwrap();
+            }
+            _ => "invalid sequence range",
+        },
+        _ => "unknown command, expected: replay <market_id> <seq_from> <seq_to> or heartbeat <consumer> <market_id>=<feed_seq> ...",
+    };
+    serde_json::json!({ "error": reply }).to_string()
+}
+
+// REP socket answering control requests, one request at a time on its own thread
+pub fn serve(ctx: &zmq::Context, addr: String, buffer: Arc<Mutex<ReplayBuffer>>, consumers: Option<ConsumerRegistry>) {
+    let socket = match ctx.socket(zmq::REP).and_then(|socket| socket.bind(&addr).map(|_| socket)) {
+        Ok(socket) => socket,
+        Err(err) => {
+            error!("control socket failed to bind {}: {}", addr, err);
+            return;
+        }
+    };
+    info!("control socket listening on {}", addr);
+    std::thread::spawn(move || loop {
+        let request = match socket.recv_string(0) {
+            Ok(Ok(request)) => request,
+            Ok(Err(_)) => String::new(),
+            Err(err) => {
+                warn!("control socket receive failed: {}", err);
+                continue;
+            }
+        };
+        let reply = handle_request(&request, &buffer, consumers.as_ref());
+        if let Err(err) = socket.send(reply.as_str(), 0) {
+            warn!("control socket reply failed: {}", err);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn trade(market_id: &str, feed_seq: u64) -> Trade {
+        serde_json::from_value(json!({
+            "timeStamp": 1_700_000_000,
+            "makerOwner": "maker",
+            "takerOwner": "taker",
+            "priceDouble": 150.0,
+            "quantityDouble": 1.0,
+            "marketId": market_id,
+            "takerSide": 0,
+            "marketName": "SOL-USDC",
+            "signature": "signature",
+            "feedSeq": feed_seq,
+        }))
+        .unwrap()
+    }
+
+    fn feed_seqs(response: &ReplayResponse) -> Vec<u64> {
+        response.trades.iter().map(|trade| trade.feed_seq).collect()
+    }
+
+    #[test]
+    fn replays_a_range_and_reports_its_holes() {
+        let mut buffer = ReplayBuffer::new(10);
+        for feed_seq in [1, 2, 4, 5, 8] {
+            buffer.push(&trade("a", feed_seq));
+        }
+        buffer.push(&trade("b", 3));
+        let response = buffer.replay("a", 2, 9);
+        assert_eq!(feed_seqs(&response), vec![2, 4, 5, 8]);
+        assert_eq!(response.missing, vec![(3, 3), (6, 7), (9, 9)]);
+        let response = buffer.replay("a", 4, 5);
+        assert_eq!(feed_seqs(&response), vec![4, 5]);
+        assert!(response.missing.is_empty());
+        let response = buffer.replay("c", 1, 2);
+        assert!(response.trades.is_empty());
+        assert_eq!(response.missing, vec![(1, 2)]);
+    }
+
+    #[test]
+    fn evicts_the_oldest_trades_per_market() {
+        let mut buffer = ReplayBuffer::new(2);
+        for feed_seq in 1..=3 {
+            buffer.push(&trade("a", feed_seq));
+        }
+        buffer.push(&trade("b", 1));
+        let response = buffer.replay("a", 1, 3);
+        assert_eq!(feed_seqs(&response), vec![2, 3]);
+        assert_eq!(response.missing, vec![(1, 1)]);
+        assert_eq!(feed_seqs(&buffer.replay("b", 1, 1)), vec![1]);
+    }
+
+    #[test]
+    fn answers_requests_with_json() {
+        let buffer = Mutex::new(ReplayBuffer::new(10));
+        buffer.lock().unwrap().push(&trade("a", 1));
+        let reply = |request: &str| -> Value { serde_json::from_str(&handle_request(request, &buffer, None)).unwrap() };
+        let response = reply("replay a 1 2");
+        assert_eq!(response["trades"].as_array().unwrap().len(), 1);
+        assert_eq!(response["missing"], json!([[2, 2]]));
+        assert_eq!(reply("replay a 2 1")["error"], "invalid sequence range");
+        assert_eq!(reply("replay a one 2")["error"], "invalid sequence range");
+        assert!(reply("heartbeat me a=1")["error"].as_str().unwrap().contains("--consumer-heartbeats"));
+        assert!(reply("")["error"].as_str().unwrap().starts_with("unknown command"));
+    }
+}
@@ -0,0 +1,242 @@
+use crate::check::{markets, outcome};
+use crate::clock::now_ms;
+use crate::config::Config;
+use crate::sink::{HealthRegistry, Sink, SinkPolicy, Sinks, WsSink, ZmqSink, TOPIC_DIAGNOSTICS};
+use crate::ws::{self, WsMessage, WsPolicy};
+use crate::{exit, source};
+use futures::StreamExt;
+use openbookv2_decoder::{parse_name, LogFormat, MarketParams, Trade};
+use openbookv2_generated::state::Market;
+use serde_json::{json, Value};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::UiTransactionEncoding;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+
+// per subscription and sink round trip
+const TIMEOUT: Duration = Duration::from_secs(10);
+// recent transactions of a market searched for a fill
+const SIGNATURES: usize = 20;
+
+// The message sent through every sink, on the diagnostics topic
+fn probe() -> String {
+    json!({"type": "self_test", "nonce": now_ms()}).to_string()
+}
+
+// The newest fill of the market in its last SIGNATURES transactions, decoded like the
+// sources do. A market without one passes, it may just be quiet.
+async fn recent_fill(client: &RpcClient, config: &Config, key: &Pubkey, market: &Market) -> Result<String, String> {
+    let signatures = client
+        .get_signatures_for_address_with_config(
+            key,
+            GetConfirmedSignaturesForAddress2Config {
+                limit: Some(SIGNATURES),
+                commitment: Some(CommitmentConfig::confirmed()),
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|err| err.to_string())?;
+    let transaction_config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(CommitmentConfig::confirmed()),
+        max_supported_transaction_version: Some(0),
+    };
+    let format = LogFormat::new(config.log_prefixes.clone());
+    for status in signatures.iter().filter(|status| status.err.is_none()) {
+        let signature = Signature::from_str(&status.signature).map_err(|err| err.to_string())?;
+        let transaction = client
+            .get_transaction_with_config(&signature, transaction_config)
+            .await
+            .map_err(|err| err.to_string())?;
+        let Some(OptionSerializer::Some(logs)) = transaction.transaction.meta.map(|meta| meta.log_messages) else {
+            continue;
+        };
+        let Some(fill) = logs
+            .iter()
+            .filter_map(|log| format.decode_fill_log(log))
+            .find(|fill| fill.market == *key)
+        else {
+            continue;
+        };
+        let name = parse_name(&market.name).replace('\0', "");
+        let trade = Trade::new(&fill, &MarketParams::from(market), name, status.signature.clone());
+        if trade.price_double <= 0.0 || trade.quantity_double <= 0.0 {
+            return Err(format!(
+                "fill in {} decodes to price {} and quantity {}",
+                status.signature, trade.price_double, trade.quantity_double
+            ));
+        }
+        return Ok(format!(
+            "{} at {} in {}",
+            trade.quantity_double, trade.price_double, status.signature
+        ));
+    }
+    Ok(format!("no fill in the last {} transactions", signatures.len()))
+}
+
+fn sinks(sink: Box<dyn Sink>) -> Sinks {
+    let policy = SinkPolicy {
+        retries: 0,
+        backoff: Duration::ZERO,
+        backoff_max: Duration::ZERO,
+        failure_threshold: u32::MAX,
+        open_for: Duration::ZERO,
+    };
+    let mut sinks = Sinks::new(policy, HealthRegistry::default());
+    sinks.add(sink, None);
+    sinks
+}
+
+// Publishes through a ZmqSink on the configured endpoint until a subscriber of it gets the
+// probe, ZMQ drops what is sent before the subscription is through
+fn zmq_round_trip(zero_url: String, connect: bool, topics: bool) -> Result<String, String> {
+    let ctx = zmq::Context::new();
+    let publisher = ctx.socket(zmq::PUB).map_err(|err| err.to_string())?;
+    if connect {
+        // the subscribers are behind the remote endpoint
+        publisher.connect(&zero_url).map_err(|err| format!("{}: {}", zero_url, err))?;
+        return Ok(format!("connected to {}, no round trip with --connect", zero_url));
+    }
+    publisher.bind(&zero_url).map_err(|err| format!("{}: {}", zero_url, err))?;
+    let local_url = zero_url.replace("0.0.0.0", "127.0.0.1");
+    let subscriber = ctx.socket(zmq::SUB).map_err(|err| err.to_string())?;
+    subscriber.connect(&local_url).map_err(|err| format!("{}: {}", local_url, err))?;
+    let prefix = if topics { TOPIC_DIAGNOSTICS } else { "" };
+    subscriber.set_subscribe(prefix.as_bytes()).map_err(|err| err.to_string())?;
+    subscriber.set_rcvtimeo(100).map_err(|err| err.to_string())?;
+    let mut sinks = sinks(Box::new(ZmqSink::new(publisher, topics)));
+    let started = Instant::now();
+    let payload = probe();
+    while started.elapsed() < TIMEOUT {
+        sinks.publish(TOPIC_DIAGNOSTICS, &payload);
+        if let Ok(parts) = subscriber.recv_multipart(0) {
+            if parts.last().is_some_and(|part| part == payload.as_bytes()) {
+                return Ok(format!("{} in {} ms", zero_url, started.elapsed().as_millis()));
+            }
+        }
+    }
+    Err(format!("nothing received on {} within {:?}", local_url, TIMEOUT))
+}
+
+async fn ws_probe(url: &str, feed: broadcast::Sender<Arc<WsMessage>>) -> Result<String, String> {
+    let started = Instant::now();
+    let (mut client, _) = tokio_tungstenite::connect_async(url).await.map_err(|err| err.to_string())?;
+    // the server hello, the feed is subscribed right after it
+    client.next().await.ok_or("closed before the hello")?.map_err(|err| err.to_string())?;
+    let mut sinks = sinks(Box::new(WsSink::new(feed)));
+    let payload = probe();
+    loop {
+        sinks.publish(TOPIC_DIAGNOSTICS, &payload);
+        match timeout(Duration::from_millis(100), client.next()).await {
+            Ok(Some(Ok(Message::Text(text)))) if text.contains(&payload) => {
+                let _ = client.close(None).await;
+                return Ok(format!("{} in {} ms", url, started.elapsed().as_millis()));
+            }
+            Ok(Some(Ok(_))) | Err(_) => {}
+            Ok(Some(Err(err))) => return Err(err.to_string()),
+            Ok(None) => return Err("closed by the server".to_string()),
+        }
+    }
+}
+
+// Runs the websocket server on the configured address and publishes through a WsSink until a
+// client of it gets the probe. Api keys are not checked.
+async fn ws_round_trip(config: &Config, addr: &str) -> Result<String, String> {
+    let listener = tokio::net::TcpListener::bind(addr).await.map_err(|err| format!("{}: {}", addr, err))?;
+    let (feed, _) = broadcast::channel(16);
+    let policy = WsPolicy {
+        client_rate: None,
+        send_timeout: Duration::from_millis(config.ws_send_timeout_ms),
+        slow_client: config.ws_slow_client,
+        replay_limit: 0,
+        legacy_schema: None,
+    };
+    let server = tokio::spawn(ws::serve(listener, feed.clone(), None, None, None, policy));
+    let url = format!("ws://{}", addr.replace("0.0.0.0", "127.0.0.1"));
+    let result = timeout(TIMEOUT, ws_probe(&url, feed))
+        .await
+        .unwrap_or_else(|_| Err(format!("nothing received on {} within {:?}", url, TIMEOUT)));
+    server.abort();
+    result
+}
+
+// The archive sink only appends, so a file is written and read back instead of a probe
+fn archive_round_trip(dir: &str) -> Result<String, String> {
+    std::fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir, err))?;
+    let path = std::path::Path::new(dir).join(".self-test");
+    let payload = probe();
+    std::fs::write(&path, &payload).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let read = std::fs::read_to_string(&path).map_err(|err| format!("{}: {}", path.display(), err));
+    let _ = std::fs::remove_file(&path);
+    match read? == payload {
+        true => Ok(format!("{} writable", dir)),
+        false => Err(format!("{} read back differently", path.display())),
+    }
+}
+
+// `--self-test`: subscribes every source once, decodes a recent fill of every market and
+// sends a probe through every sink, then prints {"checks":[...],"ok":...,"elapsedMs":...}
+// and returns the exit code, for deployment gates. Binds the configured ports, so it fails
+// while a printer on them is running.
+pub async fn run(config: &Config) -> i32 {
+    let started = Instant::now();
+    let client = RpcClient::new(config.rpc_url.clone());
+    let mut checks: Vec<Value> = vec![outcome(
+        "rpc",
+        client
+            .get_version()
+            .await
+            .map(|version| format!("solana-core {}", version.solana_core))
+            .map_err(|err| err.to_string()),
+    )];
+    let (market_checks, markets) = markets(&client, config).await;
+    checks.extend(market_checks);
+    for (commitment, keys) in config.commitment_groups(&config.market_keys) {
+        let source = source::from_config(config, &keys, commitment);
+        let result = timeout(TIMEOUT, source.subscribe())
+            .await
+            .unwrap_or_else(|_| Err(format!("no update within {:?}", TIMEOUT)));
+        checks.push(outcome(&format!("subscribe {} {}", source.name(), commitment.as_str()), result));
+    }
+    for (key, market) in markets.iter() {
+        let name = parse_name(&market.name).replace('\0', "");
+        checks.push(outcome(&format!("fill {}", name), recent_fill(&client, config, key, market).await));
+    }
+    if !config.no_zmq {
+        let zero_url = format!("tcp://{}:{}", config.host, config.port);
+        let (connect, topics) = (config.connect, config.zmq_topics);
+        let result = tokio::task::spawn_blocking(move || zmq_round_trip(zero_url, connect, topics))
+            .await
+            .unwrap_or_else(|err| Err(err.to_string()));
+        checks.push(outcome("sink zmq", result));
+    }
+    if let Some(addr) = config.ws.as_deref() {
+        checks.push(outcome("sink ws", ws_round_trip(config, addr).await));
+    }
+    if let Some(dir) = config.archive_dir.as_deref() {
+        checks.push(outcome("sink archive", archive_round_trip(dir)));
+    }
+    let ok = checks.iter().all(|check| check["ok"] == true);
+    let report = json!({
+        "checks": checks,
+        "ok": ok,
+        "elapsedMs": started.elapsed().as_millis() as u64,
+    });
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    if ok {
+        exit::OK
+    } else {
+        exit::CHECK_FAILED
+    }
+}
@@ -0,0 +1,41 @@
+use crate::state;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// Last published on-chain seq_num per market, persisted so fills replayed after a
+// restart or a failover (older than what consumers already have) are noticed
+#[derive(Serialize, Deserialize, Default)]
+pub struct SequenceGuard {
+    markets: BTreeMap<String, u64>,
+}
+
+impl SequenceGuard {
+    pub fn load(path: &Path) -> Self {
+        state::load(path)
+    }
+
+    pub fn save(&self, path: &Path) {
+        state::save(path, self)
+    }
+
+    pub fn last(&self, market_id: &str) -> Option<u64> {
+        self.markets.get(market_id).copied()
+    }
+
+    // Ok when seq_num is above the last one of the market and records it, otherwise
+    // the last one
+    pub fn advance(&mut self, market_id: &str, seq_num: u64) -> Result<(), u64> {
+        match self.markets.get_mut(market_id) {
+            Some(last) if seq_num <= *last => Err(*last),
+            Some(last) => {
+                *last = seq_num;
+                Ok(())
+            }
+            None => {
+                self.markets.insert(market_id.to_string(), seq_num);
+                Ok(())
+            }
+        }
+    }
+}
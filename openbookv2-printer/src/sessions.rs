@@ -0,0 +1,445 @@
+use crate::state;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use openbookv2_decoder::feed::Volume;
+use openbookv2_decoder::{ControlMessage, Trade};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+const MINUTES_PER_DAY: u32 = 1440;
+// a session closes this long after its end, for trades of its last seconds arriving late
+const GRACE_SECS: i64 = 2;
+
+// A trading session of --session, e.g. "SOL-USDC=us 09:30-16:00 America/New_York mon-fri"
+#[derive(Clone, Debug)]
+pub struct Session {
+    // market id, market name or * for every market
+    pub market: String,
+    pub name: String,
+    // minutes after local midnight, an end at or before the start is on the next day
+    pub start: u32,
+    pub end: u32,
+    pub timezone: Tz,
+    // weekdays the session opens on, every day when empty
+    pub days: Vec<Weekday>,
+}
+
+fn parse_time(time: &str) -> Result<u32, String> {
+    let (hours, minutes) = time.split_once(':').ok_or(format!("{} is not HH:MM", time))?;
+    let hours: u32 = hours.parse().map_err(|_| format!("{} is not HH:MM", time))?;
+    let minutes: u32 = minutes.parse().map_err(|_| format!("{} is not HH:MM", time))?;
+    match hours * 60 + minutes {
+        total if minutes < 60 && total <= MINUTES_PER_DAY => Ok(total),
+        _ => Err(format!("{} is not a time of day", time)),
+    }
+}
+
+// "mon-fri", "sat" or "sat+sun"
+fn parse_days(days: &str) -> Result<Vec<Weekday>, String> {
+    let day = |day: &str| Weekday::from_str(day.trim()).map_err(|_| format!("{} is not a weekday", day));
+    if let Some((first, last)) = days.split_once('-') {
+        let (first, last) = (day(first)?, day(last)?);
+        let mut range = vec![first];
+        while *range.last().unwrap() != last {
+            range.push(range.last().unwrap().succ());
+        }
+        return Ok(range);
+    }
+    days.split('+').map(day).collect()
+}
+
+impl FromStr for Session {
+    type Err = String;
+
+    // "<market>=<name> <HH:MM>-<HH:MM> <timezone> [<days>]"
+    fn from_str(session: &str) -> Result<Self, Self::Err> {
+        let (market, rest) = session.split_once('=').ok_or("expected <market>=<name> <HH:MM>-<HH:MM> <timezone>")?;
+        let parts: Vec<&str> = rest.split_whitespace().collect();
+        let (name, hours, timezone, days) = match parts[..] {
+            [name, hours, timezone] => (name, hours, timezone, None),
+            [name, hours, timezone, days] => (name, hours, timezone, Some(days)),
+            _ => return Err("expected <market>=<name> <HH:MM>-<HH:MM> <timezone> [<days>]".to_string()),
+        };
+        let (start, end) = hours.split_once('-').ok_or(format!("{} is not <HH:MM>-<HH:MM>", hours))?;
+        Ok(Session {
+            market: market.trim().to_string(),
+            name: name.to_string(),
+            start: parse_time(start)? % MINUTES_PER_DAY,
+            end: parse_time(end)?,
+            timezone: timezone.parse().map_err(|_| format!("unknown timezone {}", timezone))?,
+            days: days.map(parse_days).transpose()?.unwrap_or_default(),
+        })
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = |minutes: u32| format!("{:02}:{:02}", minutes / 60, minutes % 60);
+        write!(f, "{}={} {}-{} {}", self.market, self.name, time(self.start), time(self.end), self.timezone)?;
+        if !self.days.is_empty() {
+            let days: Vec<String> = self.days.iter().map(|day| day.to_string().to_lowercase()).collect();
+            write!(f, " {}", days.join("+"))?;
+        }
+        Ok(())
+    }
+}
+
+impl Session {
+    pub fn applies_to(&self, market_id: &str, market_name: &str) -> bool {
+        self.market == "*" || self.market == market_id || self.market == market_name
+    }
+
+    // Unix second of a local time, the first one after a gap of a DST change
+    fn instant(&self, local: NaiveDateTime) -> i64 {
+        (0..4)
+            .find_map(|step| {
+                let local = local + Duration::minutes(30 * step);
+                self.timezone.from_local_datetime(&local).earliest()
+            })
+            .map(|time| time.timestamp())
+            .unwrap_or_else(|| Utc.from_utc_datetime(&local).timestamp())
+    }
+
+    // Start and end of the session opening on `date`, None on a day it does not open
+    fn window(&self, date: NaiveDate) -> Option<(i64, i64)> {
+        if !self.days.is_empty() && !self.days.contains(&date.weekday()) {
+            return None;
+        }
+        let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+        let end = match self.end <= self.start {
+            true => self.end + MINUTES_PER_DAY,
+            false => self.end,
+        };
+        Some((
+            self.instant(midnight + Duration::minutes(self.start as i64)),
+            self.instant(midnight + Duration::minutes(end as i64)),
+        ))
+    }
+
+    // The session containing unix second `time`
+    pub fn containing(&self, time: i64) -> Option<(i64, i64)> {
+        let date = self.timezone.timestamp_opt(time, 0).single()?.date_naive();
+        // a session crossing midnight opened the day before
+        [date.pred_opt()?, date]
+            .into_iter()
+            .filter_map(|date| self.window(date))
+            .find(|(start, end)| *start <= time && time < *end)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct SessionStats {
+    market_id: String,
+    market_name: String,
+    session: String,
+    timezone: String,
+    start: i64,
+    end: i64,
+    open: Option<f64>,
+    high: Option<f64>,
+    low: Option<f64>,
+    close: Option<f64>,
+    volume: Volume,
+    trades: u32,
+}
+
+impl SessionStats {
+    fn add(&mut self, trade: &Trade) {
+        let price = trade.price_double;
+        self.open.get_or_insert(price);
+        self.high = Some(self.high.map_or(price, |high| high.max(price)));
+        self.low = Some(self.low.map_or(price, |low| low.min(price)));
+        self.close = Some(price);
+        self.volume.add(&Volume {
+            base: trade.quantity_double,
+            quote: trade.quantity_double * price,
+        });
+        self.trades += 1;
+    }
+
+    fn opened(&self) -> ControlMessage {
+        ControlMessage::SessionOpen {
+            market_id: self.market_id.clone(),
+            market_name: self.market_name.clone(),
+            session: self.session.clone(),
+            timezone: self.timezone.clone(),
+            start: self.start,
+            end: self.end,
+        }
+    }
+
+    fn closed(self) -> ControlMessage {
+        let vwap = (self.volume.base > 0.0).then(|| self.volume.quote / self.volume.base);
+        ControlMessage::SessionClose {
+            market_id: self.market_id,
+            market_name: self.market_name,
+            session: self.session,
+            timezone: self.timezone,
+            start: self.start,
+            end: self.end,
+            open: self.open,
+            high: self.high,
+            low: self.low,
+            close: self.close,
+            vwap,
+            volume: self.volume,
+            trades: self.trades,
+        }
+    }
+}
+
+// OHLCV of the open sessions of --session per market by on-chain fill time. Sessions
+// open and close by the clock, with or without trades, and are persisted so a restart
+// within a session keeps its statistics.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SessionTracker {
+    #[serde(skip)]
+    sessions: Vec<Session>,
+    // by market id and session name
+    open: BTreeMap<String, SessionStats>,
+}
+
+impl SessionTracker {
+    pub fn load(path: &Path, sessions: Vec<Session>) -> Self {
+        let mut tracker: SessionTracker = state::load(path);
+        tracker.sessions = sessions;
+        // sessions no longer configured are dropped
+        let names: Vec<String> = tracker.sessions.iter().map(|session| session.name.clone()).collect();
+        tracker.open.retain(|_, stats| names.contains(&stats.session));
+        tracker
+    }
+
+    pub fn save(&self, path: &Path) {
+        state::save(path, self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    // The stats of the session at `time`, closing the previous one of the market and
+    // opening this one first where needed
+    fn enter(
+        &mut self,
+        index: usize,
+        market_id: &str,
+        market_name: &str,
+        time: i64,
+        messages: &mut Vec<ControlMessage>,
+    ) -> Option<&mut SessionStats> {
+        let session = &self.sessions[index];
+        let (start, end) = session.containing(time)?;
+        let key = format!("{}|{}", market_id, session.name);
+        match self.open.get(&key) {
+            // a late trade of a session already replaced
+            Some(stats) if stats.start > start => return None,
+            Some(stats) if stats.start == start => {}
+            _ => {
+                let stats = SessionStats {
+                    market_id: market_id.to_string(),
+                    market_name: market_name.to_string(),
+                    session: session.name.clone(),
+                    timezone: session.timezone.to_string(),
+                    start,
+                    end,
+                    open: None,
+                    high: None,
+                    low: None,
+                    close: None,
+                    volume: Volume::default(),
+                    trades: 0,
+                };
+                messages.push(stats.opened());
+                if let Some(previous) = self.open.insert(key.clone(), stats) {
+                    messages.insert(messages.len() - 1, previous.closed());
+                }
+            }
+        }
+        self.open.get_mut(&key)
+    }
+
+    // Adds the trade to the sessions of its market, returns the session messages it caused
+    pub fn record(&mut self, trade: &Trade) -> Vec<ControlMessage> {
+        let mut messages = Vec::new();
+        let time = trade.time_stamp as i64;
+        for index in 0..self.sessions.len() {
+            if !self.sessions[index].applies_to(&trade.market_id, &trade.market_name) {
+                continue;
+            }
+            if let Some(stats) = self.enter(index, &trade.market_id, &trade.market_name, time, &mut messages) {
+                stats.add(trade);
+            }
+        }
+        messages
+    }
+
+    // Closes the sessions which ended and opens the ones starting, `markets` are the
+    // market ids and names
+    pub fn tick<'a>(&mut self, now: i64, markets: impl Iterator<Item = (String, &'a str)>) -> Vec<ControlMessage> {
+        let mut messages = Vec::new();
+        let ended: Vec<String> = self
+            .open
+            .iter()
+            .filter(|(_, stats)| stats.end + GRACE_SECS <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in ended {
+            messages.push(self.open.remove(&key).unwrap().closed());
+        }
+        for (market_id, market_name) in markets {
+            let market_name = market_name.replace('\0', "");
+            for index in 0..self.sessions.len() {
+                if self.sessions[index].applies_to(&market_id, &market_name) {
+                    self.enter(index, &market_id, &market_name, now, &mut messages);
+                }
+            }
+        }
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // 2023-11-14 00:00:00 UTC, a Tuesday
+    const TUESDAY: i64 = 1_699_920_000;
+    const HOUR: i64 = 3600;
+
+    fn trade(time_stamp: i64, price: f64, quantity: f64) -> Trade {
+        serde_json::from_value(json!({
+            "timeStamp": time_stamp,
+            "makerOwner": "maker",
+            "takerOwner": "taker",
+            "priceDouble": price,
+            "quantityDouble": quantity,
+            "marketId": "a",
+            "takerSide": 0,
+            "marketName": "SOL-USDC",
+            "signature": "signature",
+        }))
+        .unwrap()
+    }
+
+    fn tracker(session: &str) -> SessionTracker {
+        SessionTracker {
+            sessions: vec![session.parse().unwrap()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_and_prints_sessions() {
+        let session: Session = "SOL-USDC=us 09:30-16:00 America/New_York mon-fri".parse().unwrap();
+        assert_eq!((session.start, session.end), (570, 960));
+        assert_eq!(session.days.len(), 5);
+        assert_eq!(session.to_string(), "SOL-USDC=us 09:30-16:00 America/New_York mon+tue+wed+thu+fri");
+        let session: Session = "*=weekend 00:00-24:00 UTC sat+sun".parse().unwrap();
+        assert_eq!((session.start, session.end), (0, 1440));
+        assert_eq!(session.days, vec![Weekday::Sat, Weekday::Sun]);
+        assert!(session.applies_to("a", "SOL-USDC"));
+
+        assert!("us 09:30-16:00 UTC".parse::<Session>().is_err());
+        assert!("a=us 09:60-16:00 UTC".parse::<Session>().is_err());
+        assert!("a=us 09:30-24:01 UTC".parse::<Session>().is_err());
+        assert!("a=us 09:30-16:00 Mars/Olympus".parse::<Session>().is_err());
+        assert!("a=us 09:30-16:00 UTC someday".parse::<Session>().is_err());
+    }
+
+    #[test]
+    fn finds_the_session_of_a_time() {
+        let session: Session = "a=us 09:30-16:00 America/New_York mon-fri".parse().unwrap();
+        // 09:30 and 16:00 EST are 14:30 and 21:00 UTC
+        let window = Some((TUESDAY + 14 * HOUR + 1800, TUESDAY + 21 * HOUR));
+        assert_eq!(session.containing(TUESDAY + 15 * HOUR), window);
+        assert_eq!(session.containing(TUESDAY + 14 * HOUR), None);
+        assert_eq!(session.containing(TUESDAY + 21 * HOUR), None);
+        // not on the Saturday
+        assert_eq!(session.containing(TUESDAY + 4 * 24 * HOUR + 15 * HOUR), None);
+    }
+
+    #[test]
+    fn a_session_crossing_midnight_opened_the_day_before() {
+        let session: Session = "a=asia 22:00-02:00 UTC".parse().unwrap();
+        let window = Some((TUESDAY + 22 * HOUR, TUESDAY + 26 * HOUR));
+        assert_eq!(session.containing(TUESDAY + 25 * HOUR), window);
+        assert_eq!(session.containing(TUESDAY + 23 * HOUR), window);
+        assert_eq!(session.containing(TUESDAY + 27 * HOUR), None);
+    }
+
+    #[test]
+    fn a_start_in_a_dst_gap_moves_to_the_first_time_after_it() {
+        // 02:30 does not exist in New York on 2024-03-10, the session opens at 03:00 EDT
+        let session: Session = "a=dst 02:30-03:30 America/New_York".parse().unwrap();
+        let midnight = 1_710_028_800;
+        let window = Some((midnight + 7 * HOUR, midnight + 7 * HOUR + 1800));
+        assert_eq!(session.containing(midnight + 7 * HOUR + 60), window);
+    }
+
+    #[test]
+    fn records_the_ohlcv_of_a_session() {
+        let mut sessions = tracker("a=us 14:00-15:00 UTC");
+        let opened = sessions.record(&trade(TUESDAY + 14 * HOUR, 2.0, 1.0));
+        assert!(matches!(opened[..], [ControlMessage::SessionOpen { start, .. }] if start == TUESDAY + 14 * HOUR));
+        assert!(sessions.record(&trade(TUESDAY + 14 * HOUR + 60, 4.0, 1.0)).is_empty());
+        assert!(sessions.record(&trade(TUESDAY + 14 * HOUR + 120, 1.0, 2.0)).is_empty());
+        // a trade outside the session is not recorded
+        assert!(sessions.record(&trade(TUESDAY + 16 * HOUR, 100.0, 1.0)).is_empty());
+
+        // the grace period is waited for before closing
+        let markets = || std::iter::empty::<(String, &str)>();
+        assert!(sessions.tick(TUESDAY + 15 * HOUR, markets()).is_empty());
+        let closed = sessions.tick(TUESDAY + 15 * HOUR + GRACE_SECS, markets());
+        match &closed[..] {
+            [ControlMessage::SessionClose {
+                open,
+                high,
+                low,
+                close,
+                vwap,
+                volume,
+                trades,
+                ..
+            }] => {
+                assert_eq!((*open, *high, *low, *close), (Some(2.0), Some(4.0), Some(1.0), Some(1.0)));
+                assert_eq!(*vwap, Some(2.0));
+                assert_eq!((volume.base, volume.quote), (4.0, 8.0));
+                assert_eq!(*trades, 3);
+            }
+            messages => panic!("expected a session close, got {:?}", messages),
+        }
+    }
+
+    #[test]
+    fn the_clock_opens_and_closes_sessions_without_trades() {
+        let mut sessions = tracker("*=us 14:00-15:00 UTC");
+        let markets = || std::iter::once(("a".to_string(), "SOL-USDC\0\0"));
+        assert!(sessions.tick(TUESDAY + 13 * HOUR, markets()).is_empty());
+        let opened = sessions.tick(TUESDAY + 14 * HOUR, markets());
+        assert!(matches!(&opened[..], [ControlMessage::SessionOpen { market_name, .. }] if market_name == "SOL-USDC"));
+        assert!(sessions.tick(TUESDAY + 14 * HOUR + 60, markets()).is_empty());
+        let closed = sessions.tick(TUESDAY + 15 * HOUR + GRACE_SECS, markets());
+        assert!(matches!(
+            closed[..],
+            [ControlMessage::SessionClose { open: None, vwap: None, trades: 0, .. }]
+        ));
+    }
+
+    #[test]
+    fn the_next_session_closes_the_previous_one_and_ignores_its_late_trades() {
+        let mut sessions = tracker("a=hourly 00:00-24:00 UTC");
+        sessions.record(&trade(TUESDAY + HOUR, 1.0, 1.0));
+        let messages = sessions.record(&trade(TUESDAY + 24 * HOUR + HOUR, 2.0, 1.0));
+        assert!(matches!(
+            messages[..],
+            [ControlMessage::SessionClose { trades: 1, .. }, ControlMessage::SessionOpen { .. }]
+        ));
+        assert!(sessions.record(&trade(TUESDAY + 2 * HOUR, 3.0, 1.0)).is_empty());
+        let open = sessions.open.values().next().unwrap();
+        assert_eq!((open.trades, open.close), (1, Some(2.0)));
+    }
+}
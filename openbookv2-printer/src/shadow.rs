@@ -0,0 +1,194 @@
+use crate::error::SinkError;
+use crate::metrics::{metrics, Metrics};
+use crate::sink::{topic_matches, Sink, TOPIC_TRADES};
+use log::{info, warn};
+use openbookv2_decoder::Trade;
+use openbookv2_trades_client::{ClientEvent, TradesClient};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::interval;
+
+// relative difference of prices and sizes which still counts as equal
+const TOLERANCE: f64 = 1e-9;
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+// Hands the published trades to the comparison, registered like any other sink
+pub struct ShadowSink {
+    trades: UnboundedSender<Trade>,
+}
+
+impl Sink for ShadowSink {
+    fn name(&self) -> &str {
+        "shadow"
+    }
+
+    fn send(&mut self, topic: &str, payload: &str) -> Result<(), SinkError> {
+        if !topic_matches(TOPIC_TRADES, topic) {
+            return Ok(());
+        }
+        let trade: Trade = serde_json::from_str(payload)?;
+        self.trades
+            .send(trade)
+            .map_err(|_| SinkError::Closed("the shadow comparison stopped"))
+    }
+}
+
+// (market, signature, n-th fill of the market in the transaction), feeds number their
+// trades differently but both deliver the fills of a transaction in log order
+type Key = (String, String, u32);
+
+#[derive(Default)]
+struct Side {
+    pending: HashMap<Key, (Trade, Instant)>,
+    // fills seen per (market, signature), for the n of the key
+    occurrences: HashMap<(String, String), (u32, Instant)>,
+}
+
+impl Side {
+    fn key(&mut self, trade: &Trade) -> Key {
+        let (count, _) = self
+            .occurrences
+            .entry((trade.market_id.clone(), trade.signature.clone()))
+            .or_insert((0, Instant::now()));
+        *count += 1;
+        (trade.market_id.clone(), trade.signature.clone(), *count - 1)
+    }
+
+    // Entries older than the window, their trade never showed up on the other side
+    fn expire(&mut self, window: Duration) -> Vec<Trade> {
+        self.occurrences.retain(|_, (_, seen)| seen.elapsed() < window * 2);
+        let expired: Vec<Key> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, seen))| seen.elapsed() >= window)
+            .map(|(key, _)| key.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|key| self.pending.remove(&key).map(|(trade, _)| trade))
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    matched: u64,
+    mismatched: u64,
+    missing_local: u64,
+    missing_reference: u64,
+}
+
+fn differs(a: f64, b: f64) -> bool {
+    (a - b).abs() > TOLERANCE * a.abs().max(b.abs())
+}
+
+fn mismatch(counts: &mut Counts, kind: &str, local: &Trade, reference: &Trade, detail: String) {
+    counts.mismatched += 1;
+    metrics().shadow_mismatches.get_or_create(&Metrics::shadow(kind)).inc();
+    warn!(
+        "shadow {} mismatch on {} {} ({} vs reference {}): {}",
+        kind, local.market_name, local.signature, local.trade_id, reference.trade_id, detail
+    );
+}
+
+fn compare(counts: &mut Counts, local: &Trade, reference: &Trade) {
+    metrics().shadow_matched.inc();
+    counts.matched += 1;
+    if differs(local.price_double, reference.price_double) {
+        let detail = format!("{} vs {}", local.price_double, reference.price_double);
+        mismatch(counts, "price", local, reference, detail);
+    }
+    if differs(local.quantity_double, reference.quantity_double) {
+        let detail = format!("{} vs {}", local.quantity_double, reference.quantity_double);
+        mismatch(counts, "size", local, reference, detail);
+    }
+    if local.taker_side != reference.taker_side {
+        let detail = format!("{} vs {}", local.taker_side, reference.taker_side);
+        mismatch(counts, "side", local, reference, detail);
+    }
+}
+
+fn missing(counts: &mut Counts, kind: &str, trade: &Trade) {
+    if kind == "missing_local" {
+        counts.missing_local += 1;
+    } else {
+        counts.missing_reference += 1;
+    }
+    metrics().shadow_mismatches.get_or_create(&Metrics::shadow(kind)).inc();
+    warn!("shadow {}: {} {} {}", kind, trade.market_name, trade.signature, trade.trade_id);
+}
+
+// The sink for the published trades and the receiver for `run`
+pub fn channel() -> (ShadowSink, UnboundedReceiver<Trade>) {
+    let (trades, receiver) = unbounded_channel();
+    (ShadowSink { trades }, receiver)
+}
+
+// Compares the published trades with a reference feed (--shadow, another printer or a
+// vendor speaking the same JSON over ZMQ or WebSocket). Trades are matched by market,
+// signature and position in the transaction; differing price, size or side and trades
+// missing on either side after `window` are logged and counted, totals every minute.
+pub async fn run(url: String, markets: HashSet<String>, window: Duration, mut local: UnboundedReceiver<Trade>) {
+    let client = if url.starts_with("ws://") || url.starts_with("wss://") {
+        TradesClient::websocket(url.clone())
+    } else {
+        TradesClient::zmq(url.clone())
+    };
+    let mut reference = client.spawn();
+    info!("comparing the published trades with {}", url);
+    let mut own = Side::default();
+    let mut other = Side::default();
+    let mut counts = Counts::default();
+    let mut expiry = interval(Duration::from_secs(1));
+    let mut summary = interval(SUMMARY_INTERVAL);
+    summary.tick().await;
+    loop {
+        tokio::select! {
+            trade = local.recv() => {
+                let Some(trade) = trade else { break };
+                let key = own.key(&trade);
+                match other.pending.remove(&key) {
+                    Some((reference, _)) => compare(&mut counts, &trade, &reference),
+                    None => {
+                        own.pending.insert(key, (trade, Instant::now()));
+                    }
+                }
+            }
+            event = reference.recv() => match event {
+                Some(ClientEvent::Trade(trade)) if markets.contains(&trade.market_id) => {
+                    let key = other.key(&trade);
+                    match own.pending.remove(&key) {
+                        Some((local, _)) => compare(&mut counts, &local, &trade),
+                        None => {
+                            other.pending.insert(key, (*trade, Instant::now()));
+                        }
+                    }
+                }
+                Some(ClientEvent::Disconnected(reason)) => warn!("shadow reference disconnected: {}", reason),
+                Some(_) => {}
+                None => break,
+            },
+            _ = expiry.tick() => {
+                for trade in own.expire(window) {
+                    missing(&mut counts, "missing_reference", &trade);
+                }
+                for trade in other.expire(window) {
+                    missing(&mut counts, "missing_local", &trade);
+                }
+            }
+            _ = summary.tick() => {
+                let period = std::mem::take(&mut counts);
+                info!(
+                    "shadow last {} s: {} matched, {} mismatches, {} missing locally, {} missing in the reference",
+                    SUMMARY_INTERVAL.as_secs(),
+                    period.matched,
+                    period.mismatched,
+                    period.missing_local,
+                    period.missing_reference
+                );
+            }
+        }
+    }
+    warn!("shadow comparison stopped");
+}
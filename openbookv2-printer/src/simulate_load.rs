@@ -0,0 +1,123 @@
+use crate::config::{Config, SourceKind};
+use crate::exit;
+use crate::metrics::metrics;
+use crate::streamer::{self, StreamerHandle};
+use log::info;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// a run is sustainable while no fill is dropped and every queue stays below this share
+const MAX_QUEUE_SHARE: f64 = 0.5;
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Sample {
+    enrich: u64,
+    published: u64,
+    dropped: u64,
+}
+
+fn sample(handle: &StreamerHandle) -> Sample {
+    let stats = handle.stats();
+    Sample {
+        enrich: stats.stages["enrich"].processed,
+        published: stats.stages["publish"].processed,
+        dropped: metrics().dropped_fills.get(),
+    }
+}
+
+// `simulate-load`: replays `file` with the mock source through the configured pipeline
+// and sinks once per speed, at most `seconds` each, and prints per speed the offered and
+// published rates, dropped fills and the fullest queue. Runs stop at the first speed
+// which is not sustainable, its fullest queue names the bottleneck stage. State and
+// archive go to a temporary directory, the configured ones are not touched.
+pub async fn run(file: &str, speeds: &[f64], seconds: u64) -> i32 {
+    let mut speeds = speeds.to_vec();
+    speeds.sort_by(f64::total_cmp);
+    let mut runs = Vec::new();
+    let mut sustainable: Option<(f64, f64)> = None;
+    let mut bottleneck: Option<&'static str> = None;
+    for speed in speeds {
+        let dir = std::env::temp_dir().join(format!("openbookv2-simulate-{}-{}", std::process::id(), speed));
+        let mut config = Config::new();
+        config.source = SourceKind::Mock;
+        config.mock_file = Some(file.to_string());
+        config.mock_speed = speed;
+        config.state_dir = dir.join("state").display().to_string();
+        if config.archive_dir.is_some() {
+            config.archive_dir = Some(dir.join("archive").display().to_string());
+        }
+        let queue_size = config.queue_size.max(1);
+        info!("simulating {} at {}x for at most {} s", file, speed, seconds);
+        let handle = match streamer::start(config, Vec::new(), None).await {
+            Ok(handle) => handle,
+            Err(err) => exit::fatal(err.exit_code(), &err.to_string()),
+        };
+        let (report, stalled) = measure(&handle, speed, seconds, queue_size).await;
+        handle.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+        let ok = report["sustainable"] == true;
+        if ok {
+            sustainable = Some((speed, report["publishedPerSecond"].as_f64().unwrap_or(0.0)));
+        }
+        runs.push(report);
+        if !ok {
+            bottleneck = Some(stalled);
+            break;
+        }
+    }
+    println!(
+        "{}",
+        json!({
+            "runs": runs,
+            "maxSustainableSpeed": sustainable.map(|(speed, _)| speed),
+            "maxSustainablePerSecond": sustainable.map(|(_, rate)| rate),
+            "bottleneck": bottleneck,
+        })
+    );
+    0
+}
+
+// The report of one run and the stage with the fullest queue
+async fn measure(handle: &StreamerHandle, speed: f64, seconds: u64, queue_size: usize) -> (Value, &'static str) {
+    let start = sample(handle);
+    let started = Instant::now();
+    let mut max_queued: BTreeMap<&'static str, i64> = BTreeMap::new();
+    let mut last = start.enrich;
+    let mut idle = 0;
+    while started.elapsed() < Duration::from_secs(seconds) {
+        tokio::time::sleep(SAMPLE_INTERVAL).await;
+        let stats = handle.stats();
+        for (stage, stage_stats) in stats.stages.iter() {
+            let max = max_queued.entry(stage).or_insert(0);
+            *max = (*max).max(stage_stats.queued);
+        }
+        // the file is replayed and every queue drained
+        let enrich = stats.stages["enrich"].processed;
+        let drained = stats.stages.values().all(|stage| stage.queued == 0);
+        idle = if enrich == last && drained { idle + 1 } else { 0 };
+        last = enrich;
+        if idle >= 2 && enrich > start.enrich {
+            break;
+        }
+    }
+    let end = sample(handle);
+    let elapsed = started.elapsed().as_secs_f64();
+    let dropped = end.dropped - start.dropped;
+    let (fullest, queued) = max_queued
+        .iter()
+        .max_by_key(|(_, queued)| **queued)
+        .map(|(stage, queued)| (*stage, *queued))
+        .unwrap_or(("enrich", 0));
+    let sustainable = dropped == 0 && (queued as f64) < queue_size as f64 * MAX_QUEUE_SHARE;
+    let report = json!({
+        "speed": speed,
+        "seconds": elapsed,
+        "offeredPerSecond": (end.enrich - start.enrich + dropped) as f64 / elapsed,
+        "publishedPerSecond": (end.published - start.published) as f64 / elapsed,
+        "dropped": dropped,
+        "maxQueued": max_queued,
+        "sustainable": sustainable,
+    });
+    (report, fullest)
+}
@@ -0,0 +1,715 @@
+use crate::config::parse_topics;
+use crate::error::{Error, SinkError};
+use crate::exit;
+use crate::feed_crypto::FeedCipher;
+use crate::filter::TradeFilter;
+use crate::metrics::{metrics, Metrics};
+use crate::sink_pool::PooledSink;
+use crate::ws::WsMessage;
+use log::{info, warn};
+use openbookv2_decoder::{ControlMessage, Trade};
+use serde::Serialize;
+use solana_program::pubkey::Pubkey;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{ErrorKind, LineWriter, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+pub const TOPIC_TRADES: &str = "trades";
+pub const TOPIC_CONTROL: &str = "control";
+pub const TOPIC_BARS: &str = "bars.1s";
+pub const TOPIC_PAIRS: &str = "pairs";
+pub const TOPIC_PRICES: &str = "prices";
+// trades of quarantined markets (--quarantine), kept apart from the normal feed
+pub const TOPIC_QUARANTINE: &str = "quarantine";
+// problems of the feed itself, e.g. owners which can not be resolved
+pub const TOPIC_DIAGNOSTICS: &str = "diagnostics";
+// checksum manifests of the trades per market and period (--checksum-interval)
+pub const TOPIC_CHECKSUMS: &str = "checksums";
+// fee revenue per market over rolling windows (--fees-interval)
+pub const TOPIC_FEES: &str = "fees";
+// open and close of the trading sessions per market (--session)
+pub const TOPIC_SESSIONS: &str = "sessions";
+// created, closed and changed markets of the listing filters (--listing-interval)
+pub const TOPIC_LISTINGS: &str = "listings";
+
+// Trades and bars are published below their root per market, e.g. trades.SOL.USDC for
+// SOL-USDC, so ZMQ prefixes like "trades.SOL." select a base currency. Names which are
+// not BASE-QUOTE become a single segment.
+pub fn market_topic(root: &str, market_name: &str) -> String {
+    let segment = |part: &str| part.trim().replace(['.', ' ', '\0'], "_");
+    match market_name.split_once(['-', '/']) {
+        Some((base, quote)) => format!("{}.{}.{}", root, segment(base), segment(quote)),
+        None => format!("{}.{}", root, segment(market_name)),
+    }
+}
+
+pub fn bar_topic(bar: &ControlMessage) -> String {
+    match bar {
+        ControlMessage::Bar { market_name, .. } => market_topic(TOPIC_BARS, market_name),
+        _ => TOPIC_BARS.to_string(),
+    }
+}
+
+// Patterns match by segment, `*` matches any one segment and a pattern matches every
+// topic below it: "trades" matches all trades, "trades.*.USDC" all USDC quoted markets.
+pub fn topic_matches(pattern: &str, topic: &str) -> bool {
+    let mut topic = topic.split('.');
+    pattern
+        .split('.')
+        .all(|expected| topic.next().is_some_and(|segment| expected == "*" || expected == segment))
+}
+
+// A destination for published payloads. Errors are handled by Sinks, a sink only reports them.
+pub trait Sink: Send {
+    fn name(&self) -> &str;
+    fn send(&mut self, topic: &str, payload: &str) -> Result<(), SinkError>;
+}
+
+pub struct ZmqSink {
+    socket: zmq::Socket,
+    // send [topic, payload] multipart messages so subscribers can filter by topic
+    topics: bool,
+}
+
+impl ZmqSink {
+    pub fn new(socket: zmq::Socket, topics: bool) -> Self {
+        ZmqSink { socket, topics }
+    }
+}
+
+impl Sink for ZmqSink {
+    fn name(&self) -> &str {
+        "zmq"
+    }
+
+    fn send(&mut self, topic: &str, payload: &str) -> Result<(), SinkError> {
+        if self.topics {
+            self.socket.send_multipart([topic.as_bytes(), payload.as_bytes()], 0)?;
+        } else {
+            self.socket.send(payload, 0)?;
+        }
+        Ok(())
+    }
+}
+
+// A ZMQ endpoint of its own for the markets of one quote currency (--quote-feed), e.g. the
+// USDC quoted markets on one port and the SOL quoted ones on another. Only takes the topics
+// of these markets, per market topics end in .<BASE>.<QUOTE> (see market_topic).
+pub struct QuoteFeedSink {
+    name: String,
+    zmq: ZmqSink,
+    // ".<BASE>.<QUOTE>" of the markets
+    suffixes: Vec<String>,
+}
+
+impl QuoteFeedSink {
+    pub fn new(quote_mint: &Pubkey, socket: zmq::Socket, topics: bool, market_names: &[String]) -> Self {
+        QuoteFeedSink {
+            name: format!("quote.{}", quote_mint),
+            zmq: ZmqSink::new(socket, topics),
+            suffixes: market_names.iter().map(|name| market_topic("", name)).collect(),
+        }
+    }
+}
+
+impl Sink for QuoteFeedSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, topic: &str, payload: &str) -> Result<(), SinkError> {
+        if !self.suffixes.iter().any(|suffix| topic.ends_with(suffix.as_str())) {
+            return Ok(());
+        }
+        self.zmq.send(topic, payload)
+    }
+}
+
+pub struct WsSink {
+    feed: broadcast::Sender<Arc<WsMessage>>,
+}
+
+impl WsSink {
+    pub fn new(feed: broadcast::Sender<Arc<WsMessage>>) -> Self {
+        WsSink { feed }
+    }
+}
+
+impl Sink for WsSink {
+    fn name(&self) -> &str {
+        "ws"
+    }
+
+    fn send(&mut self, topic: &str, payload: &str) -> Result<(), SinkError> {
+        // no connected clients is not an error
+        let _ = self.feed.send(Arc::new(WsMessage::new(topic, payload)));
+        Ok(())
+    }
+}
+
+// One payload per line on stdout for `openbookv2-printer --stdout | jq ...`, only
+// the topics matching one of the patterns. A closed pipe (the reader exited) stops
+// the printer like any other command in a pipeline.
+pub struct StdoutSink {
+    topics: Vec<String>,
+}
+
+impl StdoutSink {
+    pub fn new(topics: Vec<String>) -> Self {
+        StdoutSink { topics }
+    }
+}
+
+impl Sink for StdoutSink {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+
+    fn send(&mut self, topic: &str, payload: &str) -> Result<(), SinkError> {
+        if !self.topics.iter().any(|pattern| topic_matches(pattern, topic)) {
+            return Ok(());
+        }
+        // stdout is line buffered, every trade reaches the reader right away
+        match writeln!(std::io::stdout().lock(), "{}", payload) {
+            Ok(()) => Ok(()),
+            // the reader is gone, the sink is removed while the printer keeps running
+            Err(err) if err.kind() == ErrorKind::BrokenPipe => Err(SinkError::Gone("stdout was closed")),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+// One payload per line appended to a file, only the topics matching one of the patterns.
+// Meant for captures added at runtime with POST /sinks.
+pub struct FileSink {
+    name: String,
+    file: LineWriter<File>,
+    topics: Vec<String>,
+}
+
+impl FileSink {
+    pub fn new(name: String, path: &str, topics: Vec<String>) -> Result<Self, SinkError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| SinkError::Open {
+                target: path.to_string(),
+                reason: err.to_string(),
+            })?;
+        Ok(FileSink {
+            name,
+            file: LineWriter::new(file),
+            topics,
+        })
+    }
+}
+
+impl Sink for FileSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, topic: &str, payload: &str) -> Result<(), SinkError> {
+        if !self.topics.iter().any(|pattern| topic_matches(pattern, topic)) {
+            return Ok(());
+        }
+        writeln!(self.file, "{}", payload)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkState {
+    Healthy,
+    // recent messages failed, still below the breaker threshold
+    Degraded,
+    // breaker is open, messages for this sink are dropped until it closes again
+    Open,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SinkHealth {
+    pub state: SinkState,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
+
+// Shared with the admin server for /readyz
+pub type HealthRegistry = Arc<Mutex<BTreeMap<String, SinkHealth>>>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SinkPolicy {
+    // extra attempts per message before it counts as failed
+    pub retries: u32,
+    // pause before the first retry, doubled for every further one up to backoff_max
+    pub backoff: Duration,
+    pub backoff_max: Duration,
+    // consecutive failed messages which open the breaker
+    pub failure_threshold: u32,
+    pub open_for: Duration,
+}
+
+struct GuardedSink {
+    sink: Box<dyn Sink>,
+    // only applies to trades, other messages go to every sink
+    filter: Option<TradeFilter>,
+    // takes the trades of the fast path instead of the enriched ones (--fast-sinks)
+    fast: bool,
+    // gets every message sealed once per consumer group (--encrypted-sinks)
+    encrypted: bool,
+    // sealed and sent by worker threads (--sink-workers, or one for retries), which retry
+    // themselves
+    pooled: bool,
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+pub struct Sinks {
+    sinks: Vec<GuardedSink>,
+    policy: SinkPolicy,
+    health: HealthRegistry,
+    cipher: Option<FeedCipher>,
+    // sinks added and removed at runtime, see SinkControl
+    commands: Option<Receiver<SinkCommand>>,
+    // queue of the sink workers, set once the startup sinks are pooled
+    queue: Option<usize>,
+}
+
+enum SinkCommand {
+    Add(Box<dyn Sink>, Option<TradeFilter>),
+    Remove(String),
+}
+
+// Adds and removes sinks while the printer runs (POST and DELETE /sinks). The publish stage
+// applies the commands before its next message, the sinks given at startup can not be removed.
+#[derive(Clone)]
+pub struct SinkControl {
+    commands: Sender<SinkCommand>,
+    health: HealthRegistry,
+    // names of the sinks added at runtime
+    runtime: Arc<Mutex<BTreeSet<String>>>,
+    // --capture-dir, the only place sinks added at runtime write to
+    capture_dir: Option<PathBuf>,
+}
+
+impl SinkControl {
+    // Opens the sink described by the query, e.g.
+    // name=capture&path=sol.jsonl&topics=trades.SOL.*,control&filter=min_size=100
+    pub fn open(&self, query: &HashMap<String, String>) -> Result<String, String> {
+        let name = query.get("name").filter(|name| !name.is_empty()).ok_or("name is missing")?;
+        let path = query.get("path").filter(|path| !path.is_empty()).ok_or("path is missing")?;
+        let capture_dir = self.capture_dir.as_ref().ok_or("adding sinks needs --capture-dir")?;
+        let path = capture_path(capture_dir, path)?;
+        let path = path.to_string_lossy();
+        let topics = match query.get("topics") {
+            Some(topics) => parse_topics(topics),
+            None => vec![TOPIC_TRADES.to_string()],
+        };
+        let filter = query.get("filter").map(|filter| TradeFilter::from_str(filter)).transpose()?;
+        let mut runtime = self.runtime.lock().unwrap();
+        if runtime.contains(name) || self.health.lock().unwrap().contains_key(name) {
+            return Err(format!("a sink named {} is already added", name));
+        }
+        let sink = FileSink::new(name.clone(), &path, topics).map_err(|err| err.to_string())?;
+        self.commands
+            .send(SinkCommand::Add(Box::new(sink), filter))
+            .map_err(|_| "the publish stage stopped".to_string())?;
+        runtime.insert(name.clone());
+        info!("adding sink {} writing to {}", name, path);
+        Ok(name.clone())
+    }
+
+    pub fn remove(&self, name: &str) -> Result<(), String> {
+        let mut runtime = self.runtime.lock().unwrap();
+        if !runtime.contains(name) {
+            return Err(format!("no sink named {} was added at runtime", name));
+        }
+        self.commands
+            .send(SinkCommand::Remove(name.to_string()))
+            .map_err(|_| "the publish stage stopped".to_string())?;
+        runtime.remove(name);
+        info!("removing sink {}", name);
+        Ok(())
+    }
+
+    pub fn runtime(&self) -> Vec<String> {
+        self.runtime.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+// The file of a sink added at runtime: relative to the capture dir and inside it, so the
+// admin port can not write anywhere else
+fn capture_path(capture_dir: &Path, path: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(path);
+    if !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(format!("path {} must be relative to --capture-dir, without ..", path));
+    }
+    Ok(capture_dir.join(relative))
+}
+
+impl Sinks {
+    pub fn new(policy: SinkPolicy, health: HealthRegistry) -> Self {
+        Sinks {
+            sinks: vec![],
+            policy,
+            health,
+            cipher: None,
+            commands: None,
+            queue: None,
+        }
+    }
+
+    // Handle for adding and removing sinks once the publish stage owns them
+    pub fn control(&mut self, capture_dir: Option<PathBuf>) -> SinkControl {
+        let (commands, receiver) = channel();
+        self.commands = Some(receiver);
+        SinkControl {
+            commands,
+            health: self.health.clone(),
+            runtime: Arc::default(),
+            capture_dir,
+        }
+    }
+
+    fn apply_commands(&mut self) {
+        let Some(commands) = self.commands.as_ref() else {
+            return;
+        };
+        let commands: Vec<SinkCommand> = commands.try_iter().collect();
+        for command in commands {
+            match command {
+                SinkCommand::Add(sink, filter) => self.add(sink, filter),
+                SinkCommand::Remove(name) => self.remove(&name),
+            }
+        }
+    }
+
+    fn remove(&mut self, name: &str) {
+        // dropping the sink flushes and closes it
+        self.sinks.retain(|guarded| guarded.sink.name() != name);
+        self.health.lock().unwrap().remove(name);
+        metrics().sink_open.get_or_create(&Metrics::sink(name)).set(0);
+    }
+
+    pub fn add(&mut self, sink: Box<dyn Sink>, filter: Option<TradeFilter>) {
+        self.health.lock().unwrap().insert(
+            sink.name().to_string(),
+            SinkHealth {
+                state: SinkState::Healthy,
+                consecutive_failures: 0,
+                last_error: None,
+            },
+        );
+        let mut guarded = GuardedSink {
+            sink,
+            filter,
+            fast: false,
+            encrypted: false,
+            pooled: false,
+            failures: 0,
+            open_until: None,
+        };
+        // added at runtime, retries run on a thread of the sink like for the startup sinks
+        if let Some(queue) = self.queue.filter(|_| self.policy.retries > 0) {
+            guarded.sink = Box::new(PooledSink::spawn(guarded.sink, 1, queue, None, self.policy));
+            guarded.pooled = true;
+        }
+        self.sinks.push(guarded);
+    }
+
+    // Moves the named sinks to the fast path, returns the names which are not added
+    pub fn set_fast(&mut self, names: &[String]) -> Vec<String> {
+        let mut unknown = Vec::new();
+        for name in names {
+            match self.sinks.iter_mut().find(|guarded| guarded.sink.name() == name) {
+                Some(guarded) => guarded.fast = true,
+                None => unknown.push(name.clone()),
+            }
+        }
+        unknown
+    }
+
+    // Encrypts everything the named sinks get with `cipher`, returns the names which are not added
+    pub fn set_encrypted(&mut self, names: &[String], cipher: FeedCipher) -> Vec<String> {
+        let mut unknown = Vec::new();
+        for name in names {
+            match self.sinks.iter_mut().find(|guarded| guarded.sink.name() == name) {
+                Some(guarded) => guarded.encrypted = true,
+                None => unknown.push(name.clone()),
+            }
+        }
+        self.cipher = Some(cipher);
+        unknown
+    }
+
+    // Moves the named sinks to worker threads of their own (see PooledSink), returns the
+    // names which are not added. Called after set_encrypted, the workers seal for them. With
+    // retries every other sink gets one worker as well, so no retry pauses the publish thread
+    // and with it the other sinks.
+    pub fn set_workers(&mut self, workers: &[(String, usize)], queue: usize) -> Vec<String> {
+        let unknown = workers
+            .iter()
+            .map(|(name, _)| name)
+            .filter(|name| !self.sinks.iter().any(|guarded| guarded.sink.name() == name.as_str()))
+            .cloned()
+            .collect();
+        for mut guarded in std::mem::take(&mut self.sinks) {
+            let name = guarded.sink.name().to_string();
+            let threads = match workers.iter().find(|(pooled, _)| *pooled == name) {
+                Some((_, threads)) => Some(*threads),
+                None => (self.policy.retries > 0).then_some(1),
+            };
+            if let Some(threads) = threads {
+                let cipher = self.cipher.clone().filter(|_| guarded.encrypted);
+                guarded.sink = Box::new(PooledSink::spawn(guarded.sink, threads, queue, cipher, self.policy));
+                guarded.encrypted = false;
+                guarded.pooled = true;
+            }
+            self.sinks.push(guarded);
+        }
+        self.queue = Some(queue);
+        unknown
+    }
+
+    // Delivers the payload to every sink whose breaker is not open
+    pub fn publish(&mut self, topic: &str, payload: &str) {
+        self.deliver(topic, payload, None)
+    }
+
+    // Like publish, but skips sinks whose filter rejects the trade and the sinks of the
+    // other path
+    pub fn publish_trade(&mut self, topic: &str, trade: &Trade, payload: &str, fast: bool) {
+        self.deliver(topic, payload, Some((trade, fast)))
+    }
+
+    fn deliver(&mut self, topic: &str, payload: &str, trade: Option<(&Trade, bool)>) {
+        self.apply_commands();
+        let now = Instant::now();
+        // sealed on the first encrypted sink, the others get the same envelopes
+        let mut sealed: Option<Vec<String>> = None;
+        let mut gone = Vec::new();
+        for guarded in self.sinks.iter_mut() {
+            if let Some((trade, fast)) = trade {
+                if guarded.fast != fast {
+                    continue;
+                }
+                if guarded.filter.as_ref().is_some_and(|filter| !filter.matches(trade)) {
+                    continue;
+                }
+            }
+            if guarded.open_until.is_some_and(|until| now < until) {
+                continue;
+            }
+            let policy = if guarded.pooled {
+                SinkPolicy {
+                    retries: 0,
+                    ..self.policy
+                }
+            } else {
+                self.policy
+            };
+            let result = match self.cipher.as_ref().filter(|_| guarded.encrypted) {
+                Some(cipher) => sealed
+                    .get_or_insert_with(|| cipher.seal(payload))
+                    .iter()
+                    .try_for_each(|envelope| send(guarded.sink.as_mut(), topic, envelope, &policy)),
+                None => send(guarded.sink.as_mut(), topic, payload, &policy),
+            };
+            let name = guarded.sink.name().to_string();
+            let mut health = self.health.lock().unwrap();
+            let Some(health) = health.get_mut(&name) else {
+                continue;
+            };
+            match result {
+                Ok(()) => {
+                    if guarded.failures > 0 {
+                        info!("sink {} recovered after {} failed messages", name, guarded.failures);
+                    }
+                    guarded.failures = 0;
+                    guarded.open_until = None;
+                    health.state = SinkState::Healthy;
+                    health.consecutive_failures = 0;
+                    metrics().sink_open.get_or_create(&Metrics::sink(&name)).set(0);
+                }
+                Err(SinkError::Gone(reason)) => {
+                    warn!("sink {} is gone for good ({}), removing it", name, reason);
+                    gone.push(name);
+                }
+                Err(err) => {
+                    let message = err.to_string();
+                    let err = Error::Sink {
+                        name: name.clone(),
+                        error: err,
+                    };
+                    err.record();
+                    guarded.failures += 1;
+                    metrics().sink_failures.get_or_create(&Metrics::sink(&name)).inc();
+                    health.consecutive_failures = guarded.failures;
+                    if guarded.failures >= self.policy.failure_threshold {
+                        // stays open for open_for, then the next message probes the sink again
+                        if health.state != SinkState::Open {
+                            warn!(
+                                "sink {} failed {} times, pausing it for {:?}: {}",
+                                name, guarded.failures, self.policy.open_for, message
+                            );
+                            exit::retry_or_exit(&err);
+                        }
+                        guarded.open_until = Some(now + self.policy.open_for);
+                        health.state = SinkState::Open;
+                        metrics().sink_open.get_or_create(&Metrics::sink(&name)).set(1);
+                    } else {
+                        warn!("sink {} failed: {}", name, message);
+                        health.state = SinkState::Degraded;
+                    }
+                    health.last_error = Some(message);
+                }
+            }
+        }
+        for name in gone {
+            self.remove(&name);
+        }
+    }
+}
+
+// Sends with up to `retries` extra attempts, pausing before each one
+pub(crate) fn send(sink: &mut dyn Sink, topic: &str, payload: &str, policy: &SinkPolicy) -> Result<(), SinkError> {
+    let mut result = attempt(sink, topic, payload);
+    for retry in 0..policy.retries {
+        // a sink that is gone does not come back
+        if matches!(result, Ok(()) | Err(SinkError::Gone(_))) {
+            break;
+        }
+        std::thread::sleep(backoff(policy, retry));
+        result = attempt(sink, topic, payload);
+    }
+    result
+}
+
+// The pause before retry `retry` (0 for the first): the backoff doubled per retry and capped,
+// half of it random so sinks failing together, e.g. on one network outage, do not retry in
+// lockstep
+fn backoff(policy: &SinkPolicy, retry: u32) -> Duration {
+    let doubled = policy.backoff.saturating_mul(1 << retry.min(16)).min(policy.backoff_max);
+    // a fresh RandomState is seeded differently every time
+    let random = RandomState::new().build_hasher().finish();
+    doubled / 2 + doubled.mul_f64((random >> 11) as f64 / (1u64 << 53) as f64 / 2.0)
+}
+
+fn attempt(sink: &mut dyn Sink, topic: &str, payload: &str) -> Result<(), SinkError> {
+    #[cfg(feature = "chaos")]
+    if let Some(err) = crate::chaos::sink_failure(sink.name()) {
+        return Err(err);
+    }
+    sink.send(topic, payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topic_patterns_match_by_segment() {
+        assert!(topic_matches("trades", "trades"));
+        assert!(topic_matches("trades", "trades.SOL.USDC"));
+        assert!(topic_matches("trades.*.USDC", "trades.SOL.USDC"));
+        assert!(topic_matches("trades.SOL", "trades.SOL.USDC"));
+        assert!(topic_matches("*", "control"));
+        assert!(!topic_matches("trades.*.USDC", "trades.SOL.USDT"));
+        assert!(!topic_matches("trades.SOL.USDC", "trades.SOL"));
+        assert!(!topic_matches("trades", "trades_legacy"));
+        assert!(!topic_matches("trade", "trades.SOL.USDC"));
+        assert!(!topic_matches("control", "trades"));
+    }
+
+    #[test]
+    fn capture_paths_stay_in_the_capture_dir() {
+        let dir = Path::new("/var/capture");
+        assert_eq!(capture_path(dir, "sol.jsonl").unwrap(), dir.join("sol.jsonl"));
+        assert_eq!(capture_path(dir, "2026/sol.jsonl").unwrap(), dir.join("2026/sol.jsonl"));
+        assert!(capture_path(dir, "/etc/passwd").is_err());
+        assert!(capture_path(dir, "../state/volumes.json").is_err());
+        assert!(capture_path(dir, "a/../../b").is_err());
+        assert!(capture_path(dir, "./sol.jsonl").is_err());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_max_with_jitter() {
+        let policy = SinkPolicy {
+            retries: 10,
+            backoff: Duration::from_millis(10),
+            backoff_max: Duration::from_millis(50),
+            failure_threshold: 5,
+            open_for: Duration::from_secs(30),
+        };
+        for (retry, full) in [(0, 10), (1, 20), (2, 40), (3, 50), (40, 50)] {
+            let delay = backoff(&policy, retry);
+            let full = Duration::from_millis(full);
+            assert!(delay >= full / 2 && delay <= full, "retry {}: {:?} not in {:?}", retry, delay, full);
+        }
+        let no_backoff = SinkPolicy {
+            backoff: Duration::ZERO,
+            ..policy
+        };
+        assert_eq!(backoff(&no_backoff, 3), Duration::ZERO);
+    }
+
+    struct TestSink {
+        name: &'static str,
+        gone: bool,
+        sent: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Sink for TestSink {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn send(&mut self, _topic: &str, payload: &str) -> Result<(), SinkError> {
+            self.sent.lock().unwrap().push(payload.to_string());
+            match self.gone {
+                true => Err(SinkError::Gone("reader exited")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn gone_sinks_are_removed_without_retries() {
+        let policy = SinkPolicy {
+            retries: 3,
+            backoff: Duration::ZERO,
+            backoff_max: Duration::ZERO,
+            failure_threshold: 5,
+            open_for: Duration::from_secs(30),
+        };
+        let health = HealthRegistry::default();
+        let mut sinks = Sinks::new(policy, health.clone());
+        let gone = Arc::new(Mutex::new(vec![]));
+        let kept = Arc::new(Mutex::new(vec![]));
+        let sink = |name, gone, sent: &Arc<Mutex<Vec<String>>>| {
+            Box::new(TestSink {
+                name,
+                gone,
+                sent: sent.clone(),
+            })
+        };
+        sinks.add(sink("stdout", true, &gone), None);
+        sinks.add(sink("zmq", false, &kept), None);
+        sinks.publish("trades", "1");
+        sinks.publish("trades", "2");
+        assert_eq!(*gone.lock().unwrap(), ["1"]);
+        assert_eq!(*kept.lock().unwrap(), ["1", "2"]);
+        assert_eq!(health.lock().unwrap().keys().collect::<Vec<_>>(), ["zmq"]);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::error::SinkError;
+use crate::feed_crypto::FeedCipher;
+use crate::metrics::{metrics, Metrics};
+use crate::sink::{self, Sink, SinkPolicy};
+use log::info;
+use prometheus_client::metrics::gauge::Gauge;
+use std::collections::BTreeMap;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+// One queued message. Its strings keep their capacity and go back to the publish thread
+// once sent, so a pooled sink allocates only while its traffic grows.
+#[derive(Default)]
+struct Buffer {
+    seq: u64,
+    topic: String,
+    payload: String,
+    // the envelopes of an encrypted sink
+    sealed: Vec<String>,
+}
+
+// A sink of --sink-workers. The publish thread only copies each message, already encoded
+// by the encode stage, into a recycled buffer and queues it; `workers` threads seal the
+// queued messages when the sink is in --encrypted-sinks (and pass them on otherwise) and
+// one thread sends them to the sink in publishing order. A slow or encrypted sink then
+// costs the publish thread, and with it the ZMQ path, a copy per message instead of its
+// sealing and I/O. A full queue fails the message, a failed send fails the next message,
+// so the breaker of the sink still opens.
+pub struct PooledSink {
+    name: String,
+    jobs: Option<SyncSender<Buffer>>,
+    // buffers the sender thread is done with
+    free: Receiver<Buffer>,
+    // last failed send, reported with the next message
+    failed: Arc<Mutex<Option<SinkError>>>,
+    queued: Gauge,
+    seq: u64,
+    sender: Option<JoinHandle<()>>,
+}
+
+impl PooledSink {
+    pub fn spawn(
+        sink: Box<dyn Sink>,
+        workers: usize,
+        queue: usize,
+        cipher: Option<FeedCipher>,
+        policy: SinkPolicy,
+    ) -> Self {
+        let name = sink.name().to_string();
+        let (jobs, job_receiver) = sync_channel(queue.max(1));
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let (sealed, sealed_receiver) = channel();
+        let (free_sender, free) = channel();
+        let failed = Arc::new(Mutex::new(None));
+        let queued = metrics().sink_queue.get_or_create(&Metrics::sink(&name)).clone();
+        let cipher = cipher.map(Arc::new);
+        for index in 0..workers.max(1) {
+            let (jobs, sealed, cipher) = (job_receiver.clone(), sealed.clone(), cipher.clone());
+            std::thread::Builder::new()
+                .name(format!("{}-seal-{}", name, index))
+                .spawn(move || seal(jobs, sealed, cipher))
+                .unwrap();
+        }
+        let sending = Sending {
+            sink,
+            policy,
+            encrypted: cipher.is_some(),
+            failed: failed.clone(),
+            queued: queued.clone(),
+        };
+        let sender = std::thread::Builder::new()
+            .name(format!("{}-send", name))
+            .spawn(move || sending.run(sealed_receiver, free_sender))
+            .unwrap();
+        info!("sink {} is sealed by {} workers", name, workers.max(1));
+        PooledSink {
+            name,
+            jobs: Some(jobs),
+            free,
+            failed,
+            queued,
+            seq: 0,
+            sender: Some(sender),
+        }
+    }
+}
+
+impl Sink for PooledSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, topic: &str, payload: &str) -> Result<(), SinkError> {
+        let Some(jobs) = self.jobs.as_ref() else {
+            return Err(SinkError::Closed("sink workers stopped"));
+        };
+        let mut buffer = self.free.try_recv().unwrap_or_default();
+        buffer.seq = self.seq;
+        buffer.topic.push_str(topic);
+        buffer.payload.push_str(payload);
+        // the sequence only advances for queued messages, the sender thread waits for each
+        match jobs.try_send(buffer) {
+            Ok(()) => {
+                self.seq += 1;
+                self.queued.inc();
+            }
+            Err(TrySendError::Full(_)) => return Err(SinkError::Closed("sink worker queue is full")),
+            Err(TrySendError::Disconnected(_)) => return Err(SinkError::Closed("sink workers stopped")),
+        }
+        match self.failed.lock().unwrap().take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for PooledSink {
+    // Sends what is queued before the sink is closed, like dropping any other sink
+    fn drop(&mut self) {
+        self.jobs.take();
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.join();
+        }
+    }
+}
+
+// Takes the next message from the queue shared by the workers, the lock is only held
+// while waiting so the sealing runs in parallel
+fn seal(jobs: Arc<Mutex<Receiver<Buffer>>>, sealed: Sender<Buffer>, cipher: Option<Arc<FeedCipher>>) {
+    loop {
+        let job = jobs.lock().unwrap().recv();
+        let Ok(mut buffer) = job else {
+            return;
+        };
+        if let Some(cipher) = cipher.as_ref() {
+            buffer.sealed = cipher.seal(&buffer.payload);
+        }
+        if sealed.send(buffer).is_err() {
+            return;
+        }
+    }
+}
+
+struct Sending {
+    sink: Box<dyn Sink>,
+    // retries and backoff, the breaker stays with the publish thread
+    policy: SinkPolicy,
+    encrypted: bool,
+    failed: Arc<Mutex<Option<SinkError>>>,
+    queued: Gauge,
+}
+
+impl Sending {
+    // The workers finish in any order, messages are sent by sequence
+    fn run(mut self, sealed: Receiver<Buffer>, free: Sender<Buffer>) {
+        let mut waiting = BTreeMap::new();
+        let mut next = 0;
+        while let Ok(buffer) = sealed.recv() {
+            waiting.insert(buffer.seq, buffer);
+            while let Some(mut buffer) = waiting.remove(&next) {
+                next += 1;
+                let result = if self.encrypted {
+                    buffer
+                        .sealed
+                        .iter()
+                        .try_for_each(|envelope| sink::send(self.sink.as_mut(), &buffer.topic, envelope, &self.policy))
+                } else {
+                    sink::send(self.sink.as_mut(), &buffer.topic, &buffer.payload, &self.policy)
+                };
+                if let Err(err) = result {
+                    *self.failed.lock().unwrap() = Some(err);
+                }
+                self.queued.dec();
+                buffer.topic.clear();
+                buffer.payload.clear();
+                buffer.sealed.clear();
+                // the publish thread is gone at shutdown
+                let _ = free.send(buffer);
+            }
+        }
+        info!("workers of sink {} stopped", self.sink.name());
+    }
+}
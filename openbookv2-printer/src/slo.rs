@@ -0,0 +1,188 @@
+use crate::clock::now_ms;
+use crate::metrics::{metrics, Metrics};
+use crate::sink::{HealthRegistry, SinkState};
+use log::{info, warn};
+use openbookv2_decoder::{ControlMessage, Trade};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SloHealth {
+    pub breached: bool,
+    pub limit_ms: u64,
+    pub last_lag_ms: Option<i64>,
+    // 0-100, 50 when the smoothed lag is at the limit
+    pub score: u8,
+    // until the next lag sample
+    pub interval_ms: u64,
+    // consecutive samples over the limit so far
+    pub over: u32,
+}
+
+// Shared with the admin server for /readyz, by market name
+pub type SloRegistry = Arc<Mutex<BTreeMap<String, SloHealth>>>;
+
+// The health of the whole feed, the lowest score of any market and sink: an open breaker
+// scores 0, a degraded sink 50
+pub fn health_score(sinks: &HealthRegistry, slos: &SloRegistry) -> u8 {
+    let sinks = sinks
+        .lock()
+        .unwrap()
+        .values()
+        .map(|sink| match sink.state {
+            SinkState::Healthy => 100,
+            SinkState::Degraded => 50,
+            SinkState::Open => 0,
+        })
+        .min();
+    let markets = slos.lock().unwrap().values().map(|slo| slo.score).min();
+    sinks.into_iter().chain(markets).min().unwrap_or(100)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HealthPolicy {
+    // lag is sampled this often while it rises
+    pub min_interval_ms: u64,
+    // and backs off up to this while it is stable
+    pub max_interval_ms: u64,
+    // markets below breach their objective, /readyz fails below it
+    pub min_score: u8,
+}
+
+// weight of a new sample in the smoothed lag
+const SMOOTHING: f64 = 0.3;
+// a sample this much above the previous one counts as rising
+const RISING: f64 = 1.1;
+
+struct MarketSlo {
+    limit_ms: u64,
+    // smoothed lag of the samples
+    lag_ms: Option<f64>,
+    last_sample_ms: i64,
+    last_lag_ms: i64,
+    interval_ms: u64,
+    over: u32,
+    breached: bool,
+}
+
+// Lag objectives per market (--lag-slo-ms), scored by an adaptive sampler: lag is sampled
+// every --health-min-interval-ms while it rises or is over the limit and backs off to
+// --health-max-interval-ms while it is stable. The smoothed lag gives a score of 100 at
+// no lag, 50 at the limit and 0 at twice the limit, a market below --health-min-score
+// breaches its objective. The lowest score of all markets and sinks decides /readyz, so a
+// supervisor can fail over to a standby feed.
+pub struct LagSlo {
+    markets: BTreeMap<String, MarketSlo>,
+    policy: HealthPolicy,
+    registry: SloRegistry,
+}
+
+impl LagSlo {
+    // limits by market id and name
+    pub fn new(limits: Vec<(String, String, u64)>, policy: HealthPolicy, registry: SloRegistry) -> Self {
+        let mut markets = BTreeMap::new();
+        for (market_id, market_name, limit_ms) in limits {
+            registry.lock().unwrap().insert(
+                market_name.clone(),
+                SloHealth {
+                    breached: false,
+                    limit_ms,
+                    last_lag_ms: None,
+                    score: 100,
+                    interval_ms: policy.min_interval_ms,
+                    over: 0,
+                },
+            );
+            metrics().lag_slo_breached.get_or_create(&Metrics::market(&market_name)).set(0);
+            metrics().health_score.get_or_create(&Metrics::market(&market_name)).set(100);
+            markets.insert(
+                market_id,
+                MarketSlo {
+                    limit_ms,
+                    lag_ms: None,
+                    last_sample_ms: 0,
+                    last_lag_ms: 0,
+                    interval_ms: policy.min_interval_ms,
+                    over: 0,
+                    breached: false,
+                },
+            );
+        }
+        LagSlo {
+            markets,
+            policy,
+            registry,
+        }
+    }
+
+    // A lag_slo message when the market breached or cleared its objective with this trade.
+    // Trades before the market's next sample are not looked at.
+    pub fn record(&mut self, trade: &Trade, lag_ms: i64) -> Option<ControlMessage> {
+        let market = self.markets.get_mut(&trade.market_id)?;
+        let now = now_ms();
+        if now - market.last_sample_ms < market.interval_ms as i64 {
+            return None;
+        }
+        let rising = market.lag_ms.is_some() && lag_ms as f64 > market.last_lag_ms as f64 * RISING;
+        let over = lag_ms > market.limit_ms as i64;
+        market.interval_ms = if rising || over {
+            self.policy.min_interval_ms
+        } else {
+            (market.interval_ms * 2).min(self.policy.max_interval_ms)
+        };
+        market.last_sample_ms = now;
+        market.last_lag_ms = lag_ms;
+        market.over = if over { market.over + 1 } else { 0 };
+        let smoothed = match market.lag_ms {
+            Some(smoothed) => smoothed + SMOOTHING * (lag_ms as f64 - smoothed),
+            None => lag_ms as f64,
+        };
+        market.lag_ms = Some(smoothed);
+        let score = score(smoothed, market.limit_ms);
+        let breached = score < self.policy.min_score;
+        let changed = breached != market.breached;
+        market.breached = breached;
+        if let Some(health) = self.registry.lock().unwrap().get_mut(&trade.market_name) {
+            health.breached = breached;
+            health.last_lag_ms = Some(lag_ms);
+            health.score = score;
+            health.interval_ms = market.interval_ms;
+            health.over = market.over;
+        }
+        metrics()
+            .health_score
+            .get_or_create(&Metrics::market(&trade.market_name))
+            .set(score as i64);
+        if !changed {
+            return None;
+        }
+        if breached {
+            warn!(
+                "{} scores {} with a smoothed lag of {:.0} ms over {} ms, last {} ms",
+                trade.market_name, score, smoothed, market.limit_ms, lag_ms
+            );
+        } else {
+            info!("{} is within its lag objective again, score {}, {} ms", trade.market_name, score, lag_ms);
+        }
+        metrics()
+            .lag_slo_breached
+            .get_or_create(&Metrics::market(&trade.market_name))
+            .set(breached as i64);
+        Some(ControlMessage::LagSlo {
+            market_id: trade.market_id.clone(),
+            market_name: trade.market_name.clone(),
+            breached,
+            lag_ms,
+            limit_ms: market.limit_ms,
+            trades: market.over,
+            score,
+        })
+    }
+}
+
+fn score(lag_ms: f64, limit_ms: u64) -> u8 {
+    let limit_ms = limit_ms.max(1) as f64;
+    (100.0 * (1.0 - lag_ms / (2.0 * limit_ms))).clamp(0.0, 100.0).round() as u8
+}
@@ -0,0 +1,615 @@
+use crate::block_times::BlockTimes;
+use crate::clock::now_ms;
+use crate::error::SourceError;
+use crate::idle::IdleMarkets;
+use crate::intake::{IntakeQueue, TxCost};
+use crate::lifecycle::{market_lifecycle_events, referenced_accounts, MarketLifecycle};
+use crate::metrics::metrics;
+use crate::source::{failed, push_fills, record_lag, Source, SourceTransaction};
+use crate::summary;
+use futures::future::BoxFuture;
+use futures::{FutureExt, SinkExt, StreamExt};
+use log::{debug, error, info, warn};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use tonic::metadata::{AsciiMetadataKey, AsciiMetadataValue};
+use tonic::service::Interceptor;
+use tonic::{Code, Request, Status};
+use tonic_health::pb::health_client::HealthClient;
+use yellowstone_grpc_client::{GeyserGrpcBuilderError, GeyserGrpcClient, GeyserGrpcClientError};
+use yellowstone_grpc_proto::geyser::subscribe_update::UpdateOneof;
+use yellowstone_grpc_proto::geyser::CommitmentLevel;
+use yellowstone_grpc_proto::prelude::geyser_client::GeyserClient;
+use yellowstone_grpc_proto::prelude::{
+    SubscribeRequest, SubscribeRequestFilterBlocksMeta, SubscribeRequestFilterSlots, SubscribeRequestFilterTransactions,
+};
+
+// Metadata sent with every request, providers differ in the auth headers they expect
+#[derive(Clone)]
+struct HeaderInterceptor {
+    headers: Vec<(AsciiMetadataKey, AsciiMetadataValue)>,
+}
+
+impl HeaderInterceptor {
+    fn set_token(&mut self, token: AsciiMetadataValue) {
+        let key = AsciiMetadataKey::from_static("x-token");
+        match self.headers.iter_mut().find(|(name, _)| *name == key) {
+            Some((_, value)) => *value = token,
+            None => self.headers.push((key, token)),
+        }
+    }
+}
+
+impl Interceptor for HeaderInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        for (name, value) in self.headers.iter() {
+            request.metadata_mut().insert(name.clone(), value.clone());
+        }
+        Ok(request)
+    }
+}
+
+// how often idle markets are looked for with --prune-idle-days
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+// the one transaction filter of all pruned markets
+const IDLE_FILTER: &str = "idle";
+// --token-refresh-command runs at most this often, a provider rejecting fresh tokens as
+// well is retried like any other failure
+const TOKEN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+const TOKEN_REFRESH_TIMEOUT: Duration = Duration::from_secs(30);
+// slot statuses kept for --commitment-check-ms, far more than a finalization takes
+const SLOT_STATUSES: u64 = 1000;
+
+// Slot status values, the same numbers for CommitmentLevel and for SlotStatus of newer
+// providers
+fn status_name(status: Option<i32>) -> &'static str {
+    match status {
+        Some(0) => "processed",
+        Some(1) => "confirmed",
+        Some(2) => "finalized",
+        Some(_) => "unknown",
+        None => "none",
+    }
+}
+
+// Detects a provider delivering transactions below the commitment of the subscription:
+// the slot of every delivered transaction has to reach the requested status (per the
+// slot updates of the same subscription) within `grace`, otherwise the delivery was
+// downgraded, e.g. confirmed data on a finalized subscription
+struct CommitmentWatch {
+    requested: i32,
+    grace: Duration,
+    // highest status seen of the recent slots
+    statuses: BTreeMap<u64, i32>,
+    // slots delivered before reaching the requested status, with their first delivery
+    pending: BTreeMap<u64, Instant>,
+}
+
+impl CommitmentWatch {
+    fn new(requested: CommitmentLevel, grace: Duration) -> Self {
+        CommitmentWatch {
+            requested: i32::from(requested),
+            grace,
+            statuses: BTreeMap::new(),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    fn slot_status(&mut self, slot: u64, status: i32) {
+        let highest = self.statuses.entry(slot).or_insert(status);
+        *highest = (*highest).max(status);
+        if *highest >= self.requested {
+            self.pending.remove(&slot);
+        }
+        self.statuses = self.statuses.split_off(&slot.saturating_sub(SLOT_STATUSES));
+    }
+
+    // Slot updates missed while reconnecting would report their slots as downgraded
+    fn reconnected(&mut self) {
+        self.pending.clear();
+    }
+
+    fn delivered(&mut self, slot: u64) {
+        if self.statuses.get(&slot).is_some_and(|status| *status >= self.requested) {
+            return;
+        }
+        self.pending.entry(slot).or_insert_with(Instant::now);
+    }
+
+    // Slots past the grace below the requested status, with the status they had
+    fn downgraded(&mut self) -> Vec<(u64, Option<i32>)> {
+        let expired: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, delivered)| delivered.elapsed() >= self.grace)
+            .map(|(slot, _)| *slot)
+            .collect();
+        expired
+            .into_iter()
+            .map(|slot| {
+                self.pending.remove(&slot);
+                (slot, self.statuses.get(&slot).copied())
+            })
+            .collect()
+    }
+}
+
+// Successful transactions of the order book referencing the market. The market alone also
+// matches transactions of other programs which only reference it, e.g. aggregators reading
+// its state.
+fn market_filter(market: &str) -> SubscribeRequestFilterTransactions {
+    SubscribeRequestFilterTransactions {
+        vote: None,
+        failed: Some(false),
+        signature: None,
+        account_include: vec![],
+        account_exclude: vec![],
+        account_required: vec![market.to_string(), openbookv2_generated::id().to_string()],
+    }
+}
+
+// Moves the market to the filter of the pruned markets, which only has to catch the first
+// transaction using one of them again
+fn prune(request: &mut SubscribeRequest, market: &str) {
+    request.transactions.remove(market);
+    request
+        .transactions
+        .entry(IDLE_FILTER.to_string())
+        .or_insert_with(|| SubscribeRequestFilterTransactions {
+            vote: None,
+            failed: Some(false),
+            signature: None,
+            account_include: vec![],
+            account_exclude: vec![],
+            account_required: vec![openbookv2_generated::id().to_string()],
+        })
+        .account_include
+        .push(market.to_string());
+}
+
+fn is_pruned(request: &SubscribeRequest, market: &str) -> bool {
+    request
+        .transactions
+        .get(IDLE_FILTER)
+        .is_some_and(|idle| idle.account_include.iter().any(|pruned| pruned == market))
+}
+
+// Drops the market from the subscription, pruned or not. False when it was not subscribed.
+fn unsubscribe(request: &mut SubscribeRequest, market: &str) -> bool {
+    if request.transactions.remove(market).is_some() {
+        return true;
+    }
+    let Some(idle) = request.transactions.get_mut(IDLE_FILTER) else {
+        return false;
+    };
+    let pruned = idle.account_include.len();
+    idle.account_include.retain(|account| account != market);
+    let removed = idle.account_include.len() < pruned;
+    if idle.account_include.is_empty() {
+        request.transactions.remove(IDLE_FILTER);
+    }
+    removed
+}
+
+// Yellowstone geyser gRPC, one transaction filter per market
+pub struct GeyserSource {
+    endpoint: String,
+    interceptor: HeaderInterceptor,
+    request: SubscribeRequest,
+    stale_after: Option<Duration>,
+    idle: Option<IdleMarkets>,
+    token_refresh: Option<String>,
+    commitment_check: Option<Duration>,
+}
+
+impl GeyserSource {
+    pub fn new(
+        endpoint: String,
+        headers: Vec<(String, String)>,
+        markets: &[Pubkey],
+        commitment: CommitmentLevel,
+        stale_after: Option<Duration>,
+        idle: Option<IdleMarkets>,
+    ) -> Self {
+        let headers = headers
+            .into_iter()
+            .map(|(name, value)| {
+                let key = AsciiMetadataKey::from_str(&name)
+                    .unwrap_or_else(|_| panic!("invalid grpc header name {}", name));
+                let value = AsciiMetadataValue::from_str(&value)
+                    .unwrap_or_else(|_| panic!("invalid value for grpc header {}", name));
+                (key, value)
+            })
+            .collect();
+        let mut transactions = HashMap::new();
+        for key in markets {
+            transactions.insert(key.to_string(), market_filter(&key.to_string()));
+        }
+        let request = SubscribeRequest {
+            accounts: Default::default(),
+            slots: Default::default(),
+            transactions,
+            blocks: Default::default(),
+            blocks_meta: HashMap::from([(
+                "blocks_meta".to_string(),
+                SubscribeRequestFilterBlocksMeta::default(),
+            )]),
+            entry: Default::default(),
+            commitment: Some(i32::from(commitment)),
+            accounts_data_slice: vec![],
+            ping: None,
+            transactions_status: Default::default(),
+        };
+        GeyserSource {
+            endpoint,
+            interceptor: HeaderInterceptor { headers },
+            request,
+            stale_after,
+            idle,
+            token_refresh: None,
+            commitment_check: None,
+        }
+    }
+
+    // Checks the delivered slots against slot updates of all statuses, see CommitmentWatch
+    pub fn commitment_check(mut self, grace: Option<Duration>) -> Self {
+        if grace.is_some() {
+            self.request.slots = HashMap::from([(
+                "slots".to_string(),
+                SubscribeRequestFilterSlots {
+                    filter_by_commitment: Some(false),
+                    ..Default::default()
+                },
+            )]);
+        }
+        self.commitment_check = grace;
+        self
+    }
+
+    // Shell command whose output replaces the x-token when the provider rejects it
+    pub fn token_refresh(mut self, command: Option<String>) -> Self {
+        self.token_refresh = command;
+        self
+    }
+}
+
+// The provider rejected the token, e.g. because it expired
+fn is_auth_failure(status: &Status) -> bool {
+    matches!(status.code(), Code::Unauthenticated | Code::PermissionDenied)
+}
+
+// Runs the refresh command, its trimmed stdout is the new token
+async fn fetch_token(command: &str) -> Result<AsciiMetadataValue, String> {
+    let output = tokio::time::timeout(
+        TOKEN_REFRESH_TIMEOUT,
+        tokio::process::Command::new("sh").arg("-c").arg(command).kill_on_drop(true).output(),
+    )
+    .await
+    .map_err(|_| format!("no token within {:?}", TOKEN_REFRESH_TIMEOUT))?
+    .map_err(|err| err.to_string())?;
+    if !output.status.success() {
+        return Err(format!(
+            "exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if token.is_empty() {
+        return Err("printed no token".to_string());
+    }
+    AsciiMetadataValue::from_str(&token).map_err(|_| "printed a token which is not a header value".to_string())
+}
+
+// After an authentication failure: swaps in a fresh token for the next connection, true
+// when it did so and the subscription can be retried right away
+async fn refresh_token(
+    command: Option<&str>,
+    status: &Status,
+    interceptor: &mut HeaderInterceptor,
+    last_refresh: &mut Option<Instant>,
+) -> bool {
+    let Some(command) = command.filter(|_| is_auth_failure(status)) else {
+        return false;
+    };
+    if last_refresh.is_some_and(|last| last.elapsed() < TOKEN_REFRESH_INTERVAL) {
+        return false;
+    }
+    *last_refresh = Some(Instant::now());
+    match fetch_token(command).await {
+        Ok(token) => {
+            info!("geyser rejected the x-token ({}), resubscribing with a refreshed one", status.code());
+            interceptor.set_token(token);
+            metrics().token_refreshes.inc();
+            true
+        }
+        Err(err) => {
+            warn!("--token-refresh-command failed: {}", err);
+            false
+        }
+    }
+}
+
+async fn connect(
+    endpoint: &str,
+    interceptor: &HeaderInterceptor,
+) -> Result<GeyserGrpcClient<HeaderInterceptor>, GeyserGrpcBuilderError> {
+    let channel = GeyserGrpcClient::build_from_shared(endpoint.to_string())?
+        .endpoint
+        .connect()
+        .await?;
+    Ok(GeyserGrpcClient::new(
+        HealthClient::with_interceptor(channel.clone(), interceptor.clone()),
+        GeyserClient::with_interceptor(channel, interceptor.clone()),
+    ))
+}
+
+impl Source for GeyserSource {
+    fn name(&self) -> &'static str {
+        "geyser"
+    }
+
+    fn run(self: Box<Self>, intake: IntakeQueue) -> BoxFuture<'static, ()> {
+        run(*self, intake).boxed()
+    }
+
+    fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+        async move {
+            let mut client = connect(&self.endpoint, &self.interceptor)
+                .await
+                .map_err(|err| err.to_string())?;
+            client.ping(0).await.map_err(|err| err.to_string())?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn subscribe(&self) -> BoxFuture<'_, Result<String, String>> {
+        async move {
+            let started = Instant::now();
+            let mut client = connect(&self.endpoint, &self.interceptor)
+                .await
+                .map_err(|err| err.to_string())?;
+            let (_subscribe_tx, mut stream) = client
+                .subscribe_with_request(Some(self.request.clone()))
+                .await
+                .map_err(|err| err.to_string())?;
+            match stream.next().await {
+                Some(Ok(_)) => Ok(format!("first update after {} ms", started.elapsed().as_millis())),
+                Some(Err(status)) => Err(status.to_string()),
+                None => Err("subscription closed without updates".to_string()),
+            }
+        }
+        .boxed()
+    }
+}
+
+async fn run(source: GeyserSource, mut intake: IntakeQueue) {
+    let GeyserSource {
+        endpoint,
+        mut interceptor,
+        mut request,
+        stale_after,
+        mut idle,
+        token_refresh,
+        commitment_check,
+    } = source;
+    let requested = request.commitment.and_then(|commitment| CommitmentLevel::try_from(commitment).ok());
+    let mut commitment_watch = match (commitment_check, requested) {
+        // processed is the lowest, nothing to downgrade to
+        (Some(grace), Some(requested)) if requested != CommitmentLevel::Processed => {
+            Some(CommitmentWatch::new(requested, grace))
+        }
+        _ => None,
+    };
+    let mut last_refresh = None;
+    let mut block_times = BlockTimes::default();
+    let mut idle_checked = Instant::now();
+    let mut connected_before = false;
+    'outer: loop {
+        if connected_before {
+            summary::record_reconnect();
+        }
+        connected_before = true;
+        if let Some(watch) = commitment_watch.as_mut() {
+            watch.reconnected();
+        }
+        let mut grpc_client = match connect(&endpoint, &interceptor).await {
+            Ok(client) => client,
+            Err(err) => {
+                error!("Failed to connect to GRPC: {:?}. Retrying in 5 seconds...", err);
+                failed("geyser", SourceError::Connect(err.to_string()));
+                sleep(Duration::from_secs(5)).await;
+                continue 'outer;
+            }
+        };
+        if let Ok(pong) = grpc_client.ping(0).await {
+            info!("{:?}", pong);
+        }
+
+        // Add error handling for the GRPC client connection
+        let subscribe_result = grpc_client
+            .subscribe_with_request(Some(request.clone()))
+            .await;
+            
+        let (mut subscribe_tx, mut stream) = match subscribe_result {
+            Ok(result) => result,
+            Err(err) => {
+                error!("Failed to subscribe to GRPC: {:?}. Retrying in 5 seconds...", err);
+                failed("geyser", SourceError::Subscribe(err.to_string()));
+                if let GeyserGrpcClientError::TonicStatus(status) = &err {
+                    if refresh_token(token_refresh.as_deref(), status, &mut interceptor, &mut last_refresh).await {
+                        continue 'outer;
+                    }
+                }
+                sleep(Duration::from_secs(5)).await;
+                continue 'outer; // Retry the outer loop
+            }
+        };
+        
+        // blocks_meta keeps arriving on a subscription whose transaction filters stopped
+        // matching, which otherwise only a restart fixes
+        let mut last_tx = Instant::now();
+        let mut last_tx_slot = 0;
+        loop {
+            let message = stream.next().await;
+            match message {
+                Some(Ok(msg)) => {
+                    debug!("new message: {msg:?}");
+                    match msg.update_oneof {
+                        Some(UpdateOneof::Transaction(txn)) => {
+                            last_tx = Instant::now();
+                            last_tx_slot = last_tx_slot.max(txn.slot);
+                            if let Some(watch) = commitment_watch.as_mut() {
+                                watch.delivered(txn.slot);
+                            }
+                            let block_time_ms = block_times.estimate_ms(txn.slot);
+                            record_lag(block_time_ms, msg.filters.iter().filter(|filter| *filter != IDLE_FILTER));
+                            let tx = txn.transaction.unwrap();
+                            let signature = Signature::try_from(tx.signature.as_slice()).unwrap().to_string();
+                            for (market, lifecycle) in
+                                market_lifecycle_events(&tx, &openbookv2_generated::id())
+                            {
+                                if !unsubscribe(&mut request, &market.to_string()) {
+                                    continue;
+                                }
+                                if let Some(idle) = idle.as_mut() {
+                                    idle.remove(&market.to_string());
+                                }
+                                warn!(
+                                    "market {} {}, dropping it from the subscription",
+                                    market,
+                                    lifecycle.as_str()
+                                );
+                                if let Err(err) = subscribe_tx.send(request.clone()).await {
+                                    warn!("updating the subscription failed: {:?}", err);
+                                }
+                                intake.push_market_closed(market, lifecycle, signature.clone());
+                            }
+                            let pruned_used = msg.filters.iter().any(|filter| filter == IDLE_FILTER);
+                            if let (Some(idle), true) = (idle.as_mut(), pruned_used) {
+                                // pruned markets used again get their own filter back, the fills of
+                                // this transaction are pushed like those of any watched market
+                                let restored: Vec<String> = referenced_accounts(&tx)
+                                    .iter()
+                                    .map(|account| account.to_string())
+                                    .filter(|account| is_pruned(&request, account))
+                                    .collect();
+                                for market in restored.iter() {
+                                    info!("pruned market {} is used again, subscribing it", market);
+                                    unsubscribe(&mut request, market);
+                                    request.transactions.insert(market.clone(), market_filter(market));
+                                    idle.restore(market, now_ms() / 1000);
+                                }
+                                if !restored.is_empty() {
+                                    if let Err(err) = subscribe_tx.send(request.clone()).await {
+                                        warn!("updating the subscription failed: {:?}", err);
+                                    }
+                                }
+                            }
+                            let signatures = tx.transaction.as_ref().map(|transaction| transaction.signatures.len());
+                            let meta = tx.meta.unwrap();
+                            let cost = TxCost::new(meta.compute_units_consumed, Some(meta.fee), signatures);
+                            let logs = meta.log_messages;
+                            if let Some(idle) = idle.as_mut() {
+                                // only fills keep a market active, cancels and cranks do not
+                                for log in logs.iter() {
+                                    if let Some(fill_log) = intake.log_format().decode_fill_log(log) {
+                                        idle.touch(&fill_log.market.to_string(), now_ms() / 1000);
+                                    }
+                                }
+                            }
+                            push_fills(
+                                &mut intake,
+                                SourceTransaction {
+                                    signature,
+                                    slot: txn.slot,
+                                    tx_index: tx.index,
+                                    logs: &logs,
+                                    block_time_ms,
+                                    cost,
+                                },
+                            );
+                        }
+                        Some(UpdateOneof::Slot(slot)) => {
+                            if let Some(watch) = commitment_watch.as_mut() {
+                                watch.slot_status(slot.slot, slot.status);
+                            }
+                        }
+                        Some(UpdateOneof::BlockMeta(meta)) => {
+                            if let Some(block_time) = meta.block_time {
+                                block_times.insert(meta.slot, block_time.timestamp);
+                            }
+                            if last_tx_slot == 0 {
+                                last_tx_slot = meta.slot;
+                            }
+                            if let (Some(idle), true) = (idle.as_mut(), idle_checked.elapsed() >= IDLE_CHECK_INTERVAL) {
+                                idle_checked = Instant::now();
+                                let pruned = idle.idle(now_ms() / 1000);
+                                idle.save();
+                                for (market, _) in pruned.iter() {
+                                    prune(&mut request, &market.to_string());
+                                }
+                                if !pruned.is_empty() {
+                                    if let Err(err) = subscribe_tx.send(request.clone()).await {
+                                        warn!("updating the subscription failed: {:?}", err);
+                                    }
+                                }
+                                // no signature, nothing on-chain ended the market
+                                for (market, _) in pruned {
+                                    intake.push_market_closed(market, MarketLifecycle::Idle, String::new());
+                                }
+                            }
+                            if let Some(stale_after) = stale_after {
+                                if meta.slot > last_tx_slot && last_tx.elapsed() >= stale_after {
+                                    warn!(
+                                        "no transactions for {:?} while slots advanced from {} to {}, resubscribing",
+                                        last_tx.elapsed(),
+                                        last_tx_slot,
+                                        meta.slot
+                                    );
+                                    metrics().source_resubscribes.inc();
+                                    break;
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                    if let Some(watch) = commitment_watch.as_mut() {
+                        for (slot, status) in watch.downgraded() {
+                            let requested = status_name(Some(watch.requested));
+                            warn!(
+                                "slot {} was delivered on a {} subscription but is {} after {:?}",
+                                slot,
+                                requested,
+                                status_name(status),
+                                watch.grace
+                            );
+                            metrics().commitment_downgrades.inc();
+                            intake.push_commitment_downgrade(slot, requested, status_name(status));
+                        }
+                    }
+                }
+                Some(Err(e)) => {
+                    error!("Stream error: {:?}. Reconnecting...", e);
+                    failed("geyser", SourceError::Stream(e.to_string()));
+                    if refresh_token(token_refresh.as_deref(), &e, &mut interceptor, &mut last_refresh).await {
+                        break;
+                    }
+                    sleep(Duration::from_secs(1)).await;
+                    break; // Exit inner loop to reconnect
+                }
+                None => {
+                    warn!("Stream returned None. Restarting connection...");
+                    failed("geyser", SourceError::Ended);
+                    sleep(Duration::from_secs(1)).await;
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::error::SourceError;
+use crate::intake::{IntakeQueue, TxCost};
+use crate::source::{failed, push_fills, Source, SourceTransaction};
+use crate::summary;
+use futures::future::BoxFuture;
+use futures::{FutureExt, SinkExt, StreamExt};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::{debug, error, info, warn};
+use serde_json::{json, Value};
+use solana_program::pubkey::Pubkey;
+use std::time::Duration;
+use tokio::time::{interval, sleep};
+use tokio_tungstenite::tungstenite::Message;
+
+// Helius enhanced websocket (transactionSubscribe), e.g.
+// wss://atlas-mainnet.helius-rpc.com/?api-key=<key>. Notifications carry neither the
+// position of the transaction in its block nor the block time.
+pub struct HeliusSource {
+    url: String,
+    markets: Vec<String>,
+    commitment: &'static str,
+}
+
+impl HeliusSource {
+    pub fn new(url: String, markets: &[Pubkey], commitment: &'static str) -> Self {
+        HeliusSource {
+            url,
+            markets: markets.iter().map(|market| market.to_string()).collect(),
+            commitment,
+        }
+    }
+
+    fn subscribe_request(&self) -> String {
+        json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "transactionSubscribe",
+            "params": [
+                {
+                    "accountInclude": self.markets,
+                    "accountRequired": [openbookv2_generated::id().to_string()],
+                    "failed": false,
+                    "vote": false,
+                },
+                {
+                    "commitment": self.commitment,
+                    "encoding": "base64",
+                    "transactionDetails": "full",
+                    "showRewards": false,
+                    "maxSupportedTransactionVersion": 0,
+                }
+            ]
+        })
+        .to_string()
+    }
+}
+
+impl Source for HeliusSource {
+    fn name(&self) -> &'static str {
+        "helius"
+    }
+
+    fn run(self: Box<Self>, intake: IntakeQueue) -> BoxFuture<'static, ()> {
+        run(*self, intake).boxed()
+    }
+
+    fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+        async move {
+            let (mut ws, _) = tokio_tungstenite::connect_async(self.url.as_str())
+                .await
+                .map_err(|err| err.to_string())?;
+            let _ = ws.close(None).await;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+async fn run(source: HeliusSource, mut intake: IntakeQueue) {
+    let mut connected_before = false;
+    loop {
+        if connected_before {
+            summary::record_reconnect();
+        }
+        connected_before = true;
+        match tokio_tungstenite::connect_async(source.url.as_str()).await {
+            Ok((mut ws, _)) => {
+                if let Err(err) = ws.send(Message::Text(source.subscribe_request())).await {
+                    error!("transactionSubscribe failed: {}. Reconnecting...", err);
+                    failed("helius", SourceError::Subscribe(err.to_string()));
+                    sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+                // idle connections are closed by the provider
+                let mut ping = interval(Duration::from_secs(30));
+                loop {
+                    tokio::select! {
+                        _ = ping.tick() => {
+                            if ws.send(Message::Ping(vec![])).await.is_err() {
+                                break;
+                            }
+                        }
+                        message = ws.next() => match message {
+                            Some(Ok(Message::Text(text))) => handle_message(&text, &mut intake),
+                            Some(Ok(Message::Close(_))) | None => {
+                                warn!("websocket source closed. Reconnecting...");
+                                failed("helius", SourceError::Ended);
+                                break;
+                            }
+                            Some(Ok(_)) => {}
+                            Some(Err(err)) => {
+                                error!("websocket source error: {}. Reconnecting...", err);
+                                failed("helius", SourceError::Stream(err.to_string()));
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            Err(err) => {
+                error!("Failed to connect to {}: {}. Retrying in 5 seconds...", source.url, err);
+                failed("helius", SourceError::Connect(err.to_string()));
+            }
+        }
+        sleep(Duration::from_secs(5)).await;
+    }
+}
+
+fn handle_message(text: &str, intake: &mut IntakeQueue) {
+    let message: Value = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(err) => {
+            warn!("websocket source sent invalid json: {}", err);
+            return;
+        }
+    };
+    if let Some(err) = message.get("error") {
+        error!("transactionSubscribe error: {}", err);
+        return;
+    }
+    if message["method"] != "transactionNotification" {
+        if let Some(subscription) = message.get("result") {
+            info!("transactionSubscribe subscription {}", subscription);
+        }
+        return;
+    }
+    debug!("new message: {}", text);
+    let result = &message["params"]["result"];
+    let meta = &result["transaction"]["meta"];
+    if !meta["err"].is_null() {
+        return;
+    }
+    let (Some(slot), Some(signature), Some(logs)) = (
+        result["slot"].as_u64(),
+        result["signature"].as_str(),
+        meta["logMessages"].as_array(),
+    ) else {
+        return;
+    };
+    let logs: Vec<String> = logs
+        .iter()
+        .filter_map(|log| log.as_str().map(str::to_string))
+        .collect();
+    // the base64 transaction starts with its number of signatures (compact-u16, one
+    // byte below 128)
+    let signatures = result["transaction"]["transaction"][0]
+        .as_str()
+        .and_then(|encoded| STANDARD.decode(encoded).ok())
+        .and_then(|bytes| bytes.first().copied())
+        .filter(|count| *count < 0x80)
+        .map(usize::from);
+    let cost = TxCost::new(meta["computeUnitsConsumed"].as_u64(), meta["fee"].as_u64(), signatures);
+    push_fills(
+        intake,
+        SourceTransaction {
+            signature: signature.to_string(),
+            slot,
+            tx_index: 0,
+            logs: &logs,
+            block_time_ms: None,
+            cost,
+        },
+    );
+}
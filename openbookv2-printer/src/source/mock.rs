@@ -0,0 +1,131 @@
+use crate::intake::{IntakeQueue, TxCost};
+use crate::source::{push_fills, Source, SourceTransaction};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::{info, warn};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+// how often the end of the file is checked for appended lines
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(100);
+
+// One line of the --mock-file
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MockTransaction {
+    signature: String,
+    slot: u64,
+    #[serde(default)]
+    tx_index: u64,
+    #[serde(default)]
+    block_time_ms: Option<i64>,
+    logs: Vec<String>,
+    #[serde(default)]
+    compute_units: Option<u64>,
+    #[serde(default)]
+    priority_fee: Option<u64>,
+}
+
+// Replays recorded transactions from a JSON lines file and follows the lines appended
+// later, like tail -f. For contract tests and local runs without a node.
+pub struct MockSource {
+    path: String,
+    // multiple of the recorded block times, 0 replays as fast as the intake takes them
+    speed: f64,
+}
+
+impl MockSource {
+    pub fn new(path: String, speed: f64) -> Self {
+        MockSource { path, speed }
+    }
+
+    fn open(&self) -> Result<BufReader<File>, String> {
+        File::open(&self.path)
+            .map(BufReader::new)
+            .map_err(|err| format!("could not open {}: {}", self.path, err))
+    }
+}
+
+impl Source for MockSource {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn run(self: Box<Self>, intake: IntakeQueue) -> BoxFuture<'static, ()> {
+        run(*self, intake).boxed()
+    }
+
+    fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+        async move { self.open().map(|_| ()) }.boxed()
+    }
+}
+
+async fn run(source: MockSource, mut intake: IntakeQueue) {
+    let mut file = match source.open() {
+        Ok(file) => file,
+        Err(err) => {
+            warn!("mock source: {}", err);
+            return futures::future::pending().await;
+        }
+    };
+    let mut replayed = 0;
+    let mut number = 0;
+    // wall clock and block time of the first paced transaction
+    let mut start: Option<(Instant, i64)> = None;
+    let mut line = String::new();
+    loop {
+        // a line without its newline yet is completed by the next read
+        match file.read_line(&mut line) {
+            Ok(_) if line.ends_with('\n') => {}
+            Ok(_) => {
+                if replayed > 0 && line.is_empty() {
+                    info!("mock source replayed {} transactions from {}", replayed, source.path);
+                    replayed = 0;
+                }
+                tokio::time::sleep(FOLLOW_INTERVAL).await;
+                continue;
+            }
+            Err(err) => {
+                warn!("mock source: could not read {}: {}", source.path, err);
+                return futures::future::pending().await;
+            }
+        }
+        number += 1;
+        let text = std::mem::take(&mut line);
+        if text.trim().is_empty() {
+            continue;
+        }
+        let tx: MockTransaction = match serde_json::from_str(&text) {
+            Ok(tx) => tx,
+            Err(err) => {
+                warn!("mock source: line {} of {} skipped: {}", number, source.path, err);
+                continue;
+            }
+        };
+        if let (true, Some(block_time_ms)) = (source.speed > 0.0, tx.block_time_ms) {
+            let (started, first_ms) = *start.get_or_insert((Instant::now(), block_time_ms));
+            let offset = (block_time_ms - first_ms).max(0) as f64 / source.speed;
+            sleep_until(started + Duration::from_secs_f64(offset / 1000.0)).await;
+        }
+        push_fills(
+            &mut intake,
+            SourceTransaction {
+                signature: tx.signature,
+                slot: tx.slot,
+                tx_index: tx.tx_index,
+                logs: &tx.logs,
+                block_time_ms: tx.block_time_ms,
+                cost: TxCost {
+                    compute_units: tx.compute_units,
+                    priority_fee: tx.priority_fee,
+                },
+            },
+        );
+        replayed += 1;
+        // let the stages take the fills instead of overflowing the intake queue
+        tokio::task::yield_now().await;
+    }
+}
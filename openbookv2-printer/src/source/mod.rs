@@ -0,0 +1,302 @@
+use crate::alloc_audit;
+use crate::clock::now_ms;
+use crate::config::{Commitment, Config, SourceKind};
+use crate::error::{Error, SourceError};
+use crate::exit;
+use crate::idle::IdleMarkets;
+use crate::intake::{IntakeQueue, TxCost};
+use crate::metrics::{metrics, Metrics};
+use crate::postmortem;
+use crate::summary;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use geyser::GeyserSource;
+use helius::HeliusSource;
+use log::{debug, warn};
+use mock::MockSource;
+use openbookv2_decoder::{FillPosition, TradeIdScheme};
+use poll::PollSource;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+use yellowstone_grpc_proto::geyser::CommitmentLevel;
+
+pub mod geyser;
+pub mod helius;
+pub mod mock;
+pub mod poll;
+#[cfg(feature = "shredstream")]
+pub mod shredstream;
+
+// Where fills come from. A source runs until the process exits and reconnects by itself.
+pub trait Source: Send {
+    fn name(&self) -> &'static str;
+    fn run(self: Box<Self>, intake: IntakeQueue) -> BoxFuture<'static, ()>;
+    // Connects once without subscribing, for `config check`
+    fn check(&self) -> BoxFuture<'_, Result<(), String>>;
+    // Subscribes once and waits for the first update, for --self-test. Sources without a
+    // subscription only connect.
+    fn subscribe(&self) -> BoxFuture<'_, Result<String, String>> {
+        self.check().map(|result| result.map(|()| "connected".to_string())).boxed()
+    }
+}
+
+// The source selected by --source for the given markets
+pub fn from_config(config: &Config, markets: &[Pubkey], commitment: Commitment) -> Box<dyn Source> {
+    if config.prune_idle_days > 0 && !matches!(config.source, SourceKind::Geyser) {
+        warn!("--prune-idle-days is only supported by the geyser source");
+    }
+    if config.stale_feed_secs == 0 && matches!(config.source, SourceKind::Geyser) {
+        warn!("--stale-feed-secs is 0, a geyser subscription which stops delivering transactions is never rebuilt");
+    }
+    match config.source {
+        SourceKind::Geyser => {
+            // one file per subscription, the markets are split by commitment
+            let idle = (config.prune_idle_days > 0).then(|| {
+                let path = Path::new(&config.state_dir).join(format!("activity_{}.json", commitment.as_str()));
+                IdleMarkets::load(path, markets, config.prune_idle_days, now_ms() / 1000)
+            });
+            let commitment = match commitment {
+                Commitment::Processed => CommitmentLevel::Processed,
+                Commitment::Confirmed => CommitmentLevel::Confirmed,
+                Commitment::Finalized => CommitmentLevel::Finalized,
+            };
+            let mut headers = vec![("x-token".to_string(), config.x_token.clone())];
+            headers.extend(config.grpc_headers.iter().cloned());
+            let stale_after = (config.stale_feed_secs > 0).then(|| Duration::from_secs(config.stale_feed_secs));
+            Box::new(
+                GeyserSource::new(config.grpc.clone(), headers, markets, commitment, stale_after, idle)
+                    .token_refresh(config.token_refresh_command.clone())
+                    .commitment_check(
+                        (config.commitment_check_ms > 0).then(|| Duration::from_millis(config.commitment_check_ms)),
+                    ),
+            )
+        }
+        SourceKind::Poll => {
+            let commitment = match commitment {
+                Commitment::Processed => {
+                    warn!("getBlock does not support processed, polling confirmed blocks");
+                    CommitmentConfig::confirmed()
+                }
+                Commitment::Confirmed => CommitmentConfig::confirmed(),
+                Commitment::Finalized => CommitmentConfig::finalized(),
+            };
+            Box::new(PollSource::new(
+                config.rpc_url.clone(),
+                markets,
+                commitment,
+                Duration::from_millis(config.poll_interval_ms),
+            ))
+        }
+        SourceKind::Helius => {
+            if config.trade_id == TradeIdScheme::Slot {
+                warn!("the helius source has no transaction index, use --trade-id signature for unique trade ids");
+            }
+            Box::new(HeliusSource::new(config.helius_url.clone(), markets, commitment.as_str()))
+        }
+        SourceKind::Mock => {
+            let path = config
+                .mock_file
+                .clone()
+                .unwrap_or_else(|| panic!("--source mock needs --mock-file <path>"));
+            Box::new(MockSource::new(path, config.mock_speed))
+        }
+        SourceKind::Shredstream => {
+            #[cfg(feature = "shredstream")]
+            {
+                let url = config.shredstream_url.clone();
+                return Box::new(shredstream::ShredstreamSource::new(url, config.rpc_url.clone(), markets));
+            }
+            #[cfg(not(feature = "shredstream"))]
+            {
+                panic!("--source shredstream needs a binary built with the `shredstream` feature");
+            }
+        }
+    }
+}
+
+// Counts a failure of the source, which retries unless --fail-fast exits
+pub fn failed(name: &'static str, error: SourceError) {
+    let err = Error::Source { name, error };
+    err.record();
+    exit::retry_or_exit(&err);
+}
+
+// One transaction touching a watched market, as seen by any source
+pub struct SourceTransaction<'a> {
+    pub signature: String,
+    pub slot: u64,
+    pub tx_index: u64,
+    pub logs: &'a [String],
+    pub block_time_ms: Option<i64>,
+    pub cost: TxCost,
+}
+
+pub fn push_fills(intake: &mut IntakeQueue, tx: SourceTransaction) {
+    #[cfg(feature = "chaos")]
+    if crate::chaos::drop_message() {
+        debug!("dropping {} (chaos)", tx.signature);
+        return;
+    }
+    alloc_audit::measure("source", || push_transaction(intake, tx))
+}
+
+fn push_transaction(intake: &mut IntakeQueue, tx: SourceTransaction) {
+    // the same fill can show up twice in one transaction, e.g. as program data log and
+    // again as self CPI event; (market, seq_num) identifies a fill
+    let mut seen = HashSet::new();
+    let mut fills = Vec::new();
+    for (log_index, log) in tx.logs.iter().enumerate() {
+        if let Some(fill_log) = intake.log_format().decode_fill_log(log) {
+            if intake.dedup_fills() && !seen.insert((fill_log.market, fill_log.seq_num)) {
+                debug!("duplicate fill {} of market {} in {}", fill_log.seq_num, fill_log.market, tx.signature);
+                continue;
+            }
+            fills.push((log_index, fill_log));
+        } else if let Some(settle_funds_log) = intake.log_format().decode_settle_funds_log(log) {
+            if settle_funds_log.referrer.is_some() {
+                intake.push_referrer_rebate(settle_funds_log, tx.signature.clone(), log_index as u32);
+            }
+        }
+    }
+    postmortem::record_slot(tx.slot);
+    // fills are numbered over all markets of the transaction, in log order, and pushed for
+    // the markets of this source; the subscriptions of the others push theirs
+    let fill_count = fills.len() as u32;
+    let mut pushed = 0;
+    for (fill_index, (log_index, fill_log)) in fills.into_iter().enumerate() {
+        if !intake.watches(&fill_log.market) {
+            continue;
+        }
+        if !intake.first_delivery(&tx.signature, log_index as u32) {
+            debug!("fill at log {} of {} was delivered before", log_index, tx.signature);
+            metrics().redelivered_fills.inc();
+            continue;
+        }
+        pushed += 1;
+        let position = FillPosition {
+            slot: tx.slot,
+            tx_index: tx.tx_index,
+            log_index: log_index as u32,
+        };
+        let fill_index = (fill_index as u32, fill_count);
+        intake.push(fill_log, tx.signature.clone(), position, fill_index, tx.block_time_ms, tx.cost);
+    }
+    summary::record_fills(pushed, tx.block_time_ms);
+}
+
+pub fn record_lag<'a>(block_time_ms: Option<i64>, markets: impl Iterator<Item = &'a String>) {
+    let Some(block_time_ms) = block_time_ms else {
+        return;
+    };
+    let lag = (now_ms() - block_time_ms) as f64 / 1000.0;
+    metrics().source_lag_seconds.set(lag);
+    for market in markets {
+        metrics()
+            .market_source_lag_seconds
+            .get_or_create(&Metrics::market(market))
+            .set(lag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intake::{intake_queue, Update};
+    use anchor_lang::{AnchorSerialize, Discriminator};
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use openbookv2_decoder::{FillLog, LogFormat, PROGRAM_DATA_PREFIX};
+
+    fn fill_log(market: Pubkey, seq_num: u64) -> String {
+        let fill = FillLog {
+            market,
+            taker_side: 0,
+            maker_slot: 0,
+            maker_out: false,
+            timestamp: 0,
+            seq_num,
+            maker: Pubkey::default(),
+            maker_client_order_id: 0,
+            maker_fee: 0,
+            maker_timestamp: 0,
+            taker: Pubkey::default(),
+            taker_client_order_id: 0,
+            taker_fee_ceil: 0,
+            price: 1,
+            quantity: 1,
+        };
+        let mut data = FillLog::discriminator().to_vec();
+        fill.serialize(&mut data).unwrap();
+        format!("{}{}", PROGRAM_DATA_PREFIX, STANDARD.encode(data))
+    }
+
+    // (seq_num, log_index, fill_index) of the fills pushed for `logs` of one transaction
+    fn pushed(dedup_fills: bool, market: Pubkey, logs: &[String]) -> Vec<(u64, u32, (u32, u32))> {
+        let (intake, mut updates) = intake_queue(16, dedup_fills, 0, LogFormat::default(), HashSet::new());
+        let mut source = intake.split(&[market]);
+        drop(intake);
+        let tx = SourceTransaction {
+            signature: "sig".to_string(),
+            slot: 1,
+            tx_index: 0,
+            logs,
+            block_time_ms: Some(0),
+            cost: TxCost::default(),
+        };
+        push_transaction(&mut source, tx);
+        drop(source);
+        let mut fills = Vec::new();
+        while let Some(update) = updates.blocking_recv() {
+            if let Update::Fill {
+                fill_log,
+                position,
+                fill_index,
+                ..
+            } = update
+            {
+                fills.push((fill_log.seq_num, position.log_index, fill_index));
+            }
+        }
+        fills
+    }
+
+    #[test]
+    fn repeated_fills_of_a_transaction_are_pushed_once() {
+        let market = Pubkey::new_unique();
+        // the program data log and the self CPI event of the same fill
+        let logs = [fill_log(market, 7), fill_log(market, 8), fill_log(market, 7)];
+        assert_eq!(pushed(true, market, &logs), [(7, 0, (0, 2)), (8, 1, (1, 2))]);
+        assert_eq!(pushed(false, market, &logs), [(7, 0, (0, 3)), (8, 1, (1, 3)), (7, 2, (2, 3))]);
+    }
+
+    #[test]
+    fn fills_of_other_markets_count_but_are_not_pushed() {
+        let (market, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let logs = [fill_log(other, 7), fill_log(market, 7)];
+        assert_eq!(pushed(true, market, &logs), [(7, 1, (1, 2))]);
+    }
+
+    #[test]
+    fn fills_of_a_pre_confirmed_source_are_flagged() {
+        let market = Pubkey::new_unique();
+        let (intake, mut updates) = intake_queue(16, true, 0, LogFormat::default(), HashSet::new());
+        let mut source = intake.split(&[market]);
+        drop(intake);
+        source.mark_pre_confirmed();
+        let logs = [fill_log(market, 7)];
+        let tx = SourceTransaction {
+            signature: "sig".to_string(),
+            slot: 1,
+            tx_index: 0,
+            logs: &logs,
+            block_time_ms: None,
+            cost: TxCost::default(),
+        };
+        push_transaction(&mut source, tx);
+        drop(source);
+        assert!(matches!(updates.blocking_recv(), Some(Update::Fill { pre_confirmed: true, .. })));
+    }
+}
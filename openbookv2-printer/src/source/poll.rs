@@ -0,0 +1,171 @@
+use crate::block_times;
+use crate::error::SourceError;
+use crate::intake::{IntakeQueue, TxCost};
+use crate::source::{failed, push_fills, record_lag, Source, SourceTransaction};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::{debug, warn};
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcBlockConfig;
+use solana_client::rpc_request::RpcError;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::{TransactionDetails, UiConfirmedBlock, UiTransactionEncoding};
+use std::time::Duration;
+use tokio::time::interval;
+
+// JSON RPC codes for slots without a block, see solana rpc custom_error
+const SLOT_SKIPPED: i64 = -32007;
+const LONG_TERM_STORAGE_SLOT_SKIPPED: i64 = -32009;
+
+// Polls getBlock for every slot, for RPC nodes without geyser. Slower than geyser and
+// does not detect closed markets.
+pub struct PollSource {
+    rpc_url: String,
+    markets: Vec<String>,
+    commitment: CommitmentConfig,
+    poll_interval: Duration,
+    program: String,
+}
+
+impl PollSource {
+    pub fn new(
+        rpc_url: String,
+        markets: &[Pubkey],
+        commitment: CommitmentConfig,
+        poll_interval: Duration,
+    ) -> Self {
+        PollSource {
+            rpc_url,
+            markets: markets.iter().map(|market| market.to_string()).collect(),
+            commitment,
+            poll_interval,
+            program: openbookv2_generated::id().to_string(),
+        }
+    }
+}
+
+impl Source for PollSource {
+    fn name(&self) -> &'static str {
+        "poll"
+    }
+
+    fn run(self: Box<Self>, intake: IntakeQueue) -> BoxFuture<'static, ()> {
+        run(*self, intake).boxed()
+    }
+
+    fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+        async move {
+            let client = RpcClient::new_with_commitment(self.rpc_url.clone(), self.commitment);
+            client.get_slot().await.map_err(|err| err.to_string())?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+fn is_skipped(err: &ClientError) -> bool {
+    matches!(
+        err.kind(),
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. })
+            if *code == SLOT_SKIPPED || *code == LONG_TERM_STORAGE_SLOT_SKIPPED
+    )
+}
+
+async fn run(source: PollSource, mut intake: IntakeQueue) {
+    let client = RpcClient::new_with_commitment(source.rpc_url.clone(), source.commitment);
+    let block_config = RpcBlockConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        transaction_details: Some(TransactionDetails::Full),
+        rewards: Some(false),
+        commitment: Some(source.commitment),
+        max_supported_transaction_version: Some(0),
+    };
+    let mut next_slot = None;
+    let mut ticker = interval(source.poll_interval);
+    loop {
+        ticker.tick().await;
+        let latest = match client.get_slot().await {
+            Ok(slot) => slot,
+            Err(err) => {
+                warn!("getSlot failed: {}", err);
+                failed("poll", SourceError::Rpc(format!("getSlot: {}", err)));
+                continue;
+            }
+        };
+        let mut slot = next_slot.unwrap_or(latest);
+        while slot <= latest {
+            #[cfg(feature = "chaos")]
+            crate::chaos::rpc_delay().await;
+            match client.get_block_with_config(slot, block_config).await {
+                Ok(block) => scan_block(&source, &mut intake, slot, block),
+                Err(err) if is_skipped(&err) => debug!("slot {} was skipped", slot),
+                Err(err) => {
+                    // retried on the next poll
+                    warn!("getBlock for slot {} failed: {}", slot, err);
+                    failed("poll", SourceError::Rpc(format!("getBlock: {}", err)));
+                    break;
+                }
+            }
+            slot += 1;
+        }
+        next_slot = Some(slot);
+    }
+}
+
+fn scan_block(source: &PollSource, intake: &mut IntakeQueue, slot: u64, block: UiConfirmedBlock) {
+    let block_time_ms = block.block_time.map(|secs| secs * 1000);
+    if let Some(block_time_ms) = block_time_ms {
+        block_times::observe(slot, block_time_ms);
+    }
+    for (tx_index, tx) in block.transactions.unwrap_or_default().iter().enumerate() {
+        let Some(meta) = tx.meta.as_ref() else {
+            continue;
+        };
+        if meta.err.is_some() {
+            continue;
+        }
+        let Some(decoded) = tx.transaction.decode() else {
+            continue;
+        };
+        let mut keys: Vec<String> = decoded
+            .message
+            .static_account_keys()
+            .iter()
+            .map(|key| key.to_string())
+            .collect();
+        if let OptionSerializer::Some(loaded) = &meta.loaded_addresses {
+            keys.extend(loaded.writable.iter().cloned());
+            keys.extend(loaded.readonly.iter().cloned());
+        }
+        let touched: Vec<&String> = source
+            .markets
+            .iter()
+            .filter(|market| keys.contains(market))
+            .collect();
+        if touched.is_empty() || !keys.contains(&source.program) {
+            continue;
+        }
+        record_lag(block_time_ms, touched.into_iter());
+        let OptionSerializer::Some(logs) = &meta.log_messages else {
+            continue;
+        };
+        push_fills(
+            intake,
+            SourceTransaction {
+                signature: decoded.signatures[0].to_string(),
+                slot,
+                tx_index: tx_index as u64,
+                logs,
+                block_time_ms,
+                cost: TxCost::new(
+                    meta.compute_units_consumed.clone().into(),
+                    Some(meta.fee),
+                    Some(decoded.signatures.len()),
+                ),
+            },
+        );
+    }
+}
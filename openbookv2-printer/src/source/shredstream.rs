@@ -0,0 +1,216 @@
+use crate::error::SourceError;
+use crate::intake::{IntakeQueue, TxCost};
+use crate::source::{failed, push_fills, Source, SourceTransaction};
+use crate::summary;
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use log::{debug, error, info, warn};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcSimulateTransactionConfig;
+use solana_entry::entry::Entry;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::transaction::VersionedTransaction;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::{Channel, Endpoint};
+use tonic::Request;
+
+const SUBSCRIBE_ENTRIES: &str = "/shredstream.ShredstreamProxy/SubscribeEntries";
+// simulations in flight per batch of entries, their fills are pushed in entry order
+const SIMULATIONS: usize = 8;
+
+// shredstream.proto of the jito shredstream proxy
+#[derive(Clone, PartialEq, prost::Message)]
+struct SubscribeEntriesRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct EntriesMessage {
+    #[prost(uint64, tag = "1")]
+    slot: u64,
+    // bincode of the Vec<solana_entry::entry::Entry> of the slot
+    #[prost(bytes = "vec", tag = "2")]
+    entries: Vec<u8>,
+}
+
+// Entries of a jito shredstream proxy (experimental). Entries carry the transactions of a
+// slot as the leader produces them, before they are executed anywhere, so there are no
+// logs: every transaction naming the program and a watched market in its static keys is
+// simulated at processed commitment over --rpc-url and the fills of the simulated logs are
+// pushed, published with preConfirmed. The simulation runs against the state the RPC node
+// has, which can differ from the state the transaction meets in its slot.
+pub struct ShredstreamSource {
+    url: String,
+    rpc_url: String,
+    markets: HashSet<Pubkey>,
+}
+
+impl ShredstreamSource {
+    pub fn new(url: String, rpc_url: String, markets: &[Pubkey]) -> Self {
+        ShredstreamSource {
+            url,
+            rpc_url,
+            markets: markets.iter().copied().collect(),
+        }
+    }
+
+    async fn connect(&self) -> Result<Channel, String> {
+        Endpoint::from_shared(self.url.clone())
+            .map_err(|err| err.to_string())?
+            .connect()
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    fn is_candidate(&self, tx: &VersionedTransaction) -> bool {
+        let keys = tx.message.static_account_keys();
+        keys.contains(&openbookv2_generated::id()) && keys.iter().any(|key| self.markets.contains(key))
+    }
+}
+
+impl Source for ShredstreamSource {
+    fn name(&self) -> &'static str {
+        "shredstream"
+    }
+
+    fn run(self: Box<Self>, intake: IntakeQueue) -> BoxFuture<'static, ()> {
+        run(*self, intake).boxed()
+    }
+
+    fn check(&self) -> BoxFuture<'_, Result<(), String>> {
+        async move { self.connect().await.map(|_| ()) }.boxed()
+    }
+}
+
+async fn run(source: ShredstreamSource, mut intake: IntakeQueue) {
+    intake.mark_pre_confirmed();
+    let rpc = Arc::new(RpcClient::new_with_commitment(
+        source.rpc_url.clone(),
+        CommitmentConfig::processed(),
+    ));
+    let mut connected_before = false;
+    loop {
+        if connected_before {
+            summary::record_reconnect();
+        }
+        connected_before = true;
+        let channel = match source.connect().await {
+            Ok(channel) => channel,
+            Err(err) => {
+                error!("Failed to connect to {}: {}. Retrying in 5 seconds...", source.url, err);
+                failed("shredstream", SourceError::Connect(err));
+                sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        let mut grpc = tonic::client::Grpc::new(channel);
+        let codec: ProstCodec<SubscribeEntriesRequest, EntriesMessage> = ProstCodec::default();
+        let subscribed = match grpc.ready().await {
+            Ok(()) => {
+                let path = PathAndQuery::from_static(SUBSCRIBE_ENTRIES);
+                grpc.server_streaming(Request::new(SubscribeEntriesRequest {}), path, codec)
+                    .await
+                    .map_err(|status| status.to_string())
+            }
+            Err(err) => Err(err.to_string()),
+        };
+        let mut stream = match subscribed {
+            Ok(response) => response.into_inner(),
+            Err(err) => {
+                error!("SubscribeEntries failed: {}. Reconnecting...", err);
+                failed("shredstream", SourceError::Subscribe(err));
+                sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        info!("subscribed to the entries of {}", source.url);
+        // position of the next transaction in its slot, entries arrive in slot order
+        let mut slot = 0;
+        let mut tx_index = 0;
+        loop {
+            match stream.next().await {
+                Some(Ok(message)) => {
+                    if message.slot != slot {
+                        (slot, tx_index) = (message.slot, 0);
+                    }
+                    let entries: Vec<Entry> = match bincode::deserialize(&message.entries) {
+                        Ok(entries) => entries,
+                        Err(err) => {
+                            warn!("entries of slot {} skipped: {}", message.slot, err);
+                            continue;
+                        }
+                    };
+                    let mut candidates = Vec::new();
+                    for tx in entries.into_iter().flat_map(|entry| entry.transactions) {
+                        if source.is_candidate(&tx) {
+                            candidates.push((tx_index, tx));
+                        }
+                        tx_index += 1;
+                    }
+                    let simulated: Vec<_> = futures::stream::iter(candidates)
+                        .map(|(tx_index, tx)| simulate(rpc.clone(), tx_index, tx))
+                        .buffered(SIMULATIONS)
+                        .collect()
+                        .await;
+                    for (tx_index, signature, logs, compute_units) in simulated.into_iter().flatten() {
+                        push_fills(
+                            &mut intake,
+                            SourceTransaction {
+                                signature,
+                                slot,
+                                tx_index,
+                                logs: &logs,
+                                block_time_ms: None,
+                                cost: TxCost::new(compute_units, None, None),
+                            },
+                        );
+                    }
+                }
+                Some(Err(status)) => {
+                    error!("shredstream error: {}. Reconnecting...", status);
+                    failed("shredstream", SourceError::Stream(status.to_string()));
+                    break;
+                }
+                None => {
+                    warn!("shredstream closed. Reconnecting...");
+                    failed("shredstream", SourceError::Ended);
+                    break;
+                }
+            }
+        }
+        sleep(Duration::from_secs(5)).await;
+    }
+}
+
+// (tx_index, signature, logs, compute units) of a transaction which would succeed, None
+// for one which fails or was processed already
+async fn simulate(
+    rpc: Arc<RpcClient>,
+    tx_index: u64,
+    tx: VersionedTransaction,
+) -> Option<(u64, String, Vec<String>, Option<u64>)> {
+    let signature = tx.signatures.first()?.to_string();
+    let config = RpcSimulateTransactionConfig {
+        sig_verify: false,
+        replace_recent_blockhash: false,
+        commitment: Some(CommitmentConfig::processed()),
+        ..RpcSimulateTransactionConfig::default()
+    };
+    match rpc.simulate_transaction_with_config(&tx, config).await {
+        Ok(response) => match (response.value.err, response.value.logs) {
+            (None, Some(logs)) => Some((tx_index, signature, logs, response.value.units_consumed)),
+            (err, _) => {
+                debug!("simulation of {} yields no fills: {:?}", signature, err);
+                None
+            }
+        },
+        Err(err) => {
+            failed("shredstream", SourceError::Rpc(err.to_string()));
+            None
+        }
+    }
+}
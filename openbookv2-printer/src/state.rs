@@ -0,0 +1,156 @@
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+
+// Layout version of the files in the state dir, written next to the state as
+// {"version": n, "state": ...}. Every bump adds the step from the previous version to
+// MIGRATIONS, so a file written by an older release is migrated when it is loaded.
+pub const STATE_VERSION: u32 = 1;
+
+// Rewrites the state of a file of version `version - 1` into `version`, the file name says
+// which state it is
+type Migration = fn(&Path, &mut Value);
+
+// (version produced, step), oldest first
+const MIGRATIONS: &[(u32, Migration)] = &[
+    // files of releases before the envelope, their state is unchanged
+    (1, |_, _| {}),
+];
+
+#[derive(Serialize)]
+struct Versioned<'a, T> {
+    version: u32,
+    state: &'a T,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Saved {
+    version: u32,
+    state: Value,
+}
+
+// Loads json state from the state dir, migrated to STATE_VERSION, starting from default when
+// it is missing or unreadable
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> T {
+    let Some(value) = read::<Value>(path) else {
+        return T::default();
+    };
+    // files without the envelope predate versioning
+    let (version, mut state) = match serde_json::from_value::<Saved>(value.clone()) {
+        Ok(saved) => (saved.version, saved.state),
+        Err(_) => (0, value),
+    };
+    if version > STATE_VERSION {
+        warn!(
+            "{} was written by a newer release (state version {} > {}), loading it as is",
+            path.display(),
+            version,
+            STATE_VERSION
+        );
+    }
+    for (produced, migrate) in MIGRATIONS.iter().filter(|(produced, _)| *produced > version) {
+        migrate(path, &mut state);
+        info!("migrated {} to state version {}", path.display(), produced);
+    }
+    match serde_json::from_value(state) {
+        Ok(state) => {
+            info!("loaded {}", path.display());
+            state
+        }
+        Err(err) => {
+            warn!("could not parse {}: {}, starting from scratch", path.display(), err);
+            T::default()
+        }
+    }
+}
+
+// Written to a temporary file first so a crash never leaves half written state
+pub fn save<T: Serialize>(path: &Path, state: &T) {
+    write(
+        path,
+        &Versioned {
+            version: STATE_VERSION,
+            state,
+        },
+    )
+}
+
+// Plain json without the version, for the files of the archive which other programs read
+// (manifest.json, owner snapshots)
+pub fn load_plain<T: DeserializeOwned + Default>(path: &Path) -> T {
+    match read(path) {
+        Some(state) => {
+            info!("loaded {}", path.display());
+            state
+        }
+        None => T::default(),
+    }
+}
+
+pub fn save_plain<T: Serialize>(path: &Path, state: &T) {
+    write(path, state)
+}
+
+fn read<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let data = std::fs::read(path).ok()?;
+    match serde_json::from_slice(&data) {
+        Ok(state) => Some(state),
+        Err(err) => {
+            warn!("could not parse {}: {}, starting from scratch", path.display(), err);
+            None
+        }
+    }
+}
+
+fn write<T: Serialize>(path: &Path, state: &T) {
+    let tmp = path.with_extension("tmp");
+    let result = serde_json::to_vec(state)
+        .map_err(std::io::Error::from)
+        .and_then(|data| std::fs::write(&tmp, data))
+        .and_then(|_| std::fs::rename(&tmp, path));
+    if let Err(err) = result {
+        warn!("could not persist {}: {}", path.display(), err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("openbookv2-state-{}-{}.json", std::process::id(), name))
+    }
+
+    #[test]
+    fn saved_state_is_versioned_and_loads_back() {
+        let path = path("versioned");
+        let state = BTreeMap::from([("a".to_string(), 1u64)]);
+        save(&path, &state);
+        let saved: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(saved, serde_json::json!({"version": STATE_VERSION, "state": {"a": 1}}));
+        assert_eq!(load::<BTreeMap<String, u64>>(&path), state);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn files_before_versioning_are_migrated() {
+        let path = path("unversioned");
+        std::fs::write(&path, r#"{"a": 1, "version": 2}"#).unwrap();
+        let state: BTreeMap<String, u64> = load(&path);
+        assert_eq!(state, BTreeMap::from([("a".to_string(), 1), ("version".to_string(), 2)]));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unreadable_state_starts_from_default() {
+        let path = path("broken");
+        std::fs::write(&path, "{").unwrap();
+        assert!(load::<BTreeMap<String, u64>>(&path).is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
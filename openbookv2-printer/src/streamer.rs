@@ -0,0 +1,878 @@
+use crate::admin;
+use crate::affinity;
+use crate::alert_state::AlertState;
+use crate::archive::{ArchivePolicy, ArchiveSink};
+use crate::auth::ApiKeys;
+use crate::bars::BarAggregator;
+use crate::checksums::ChecksumTracker;
+use crate::bootstrap;
+use crate::bus::Bus;
+use crate::check;
+use crate::clock::{self, now_ms};
+use crate::config::{Commitment, Config, DecimalsCheck, EnricherKind, SourceKind};
+use crate::consumers::ConsumerRegistry;
+use crate::counters::CounterStore;
+use crate::decimals;
+use crate::encode::EncodeStage;
+use crate::enrich::EnrichStage;
+use crate::enricher;
+use crate::features::FeatureFlags;
+use crate::fees::FeeTracker;
+use crate::feed_crypto::FeedCipher;
+use crate::error::{Error, SinkError, SourceError};
+use crate::fill_rate::FillRate;
+use crate::intake::{intake_queue, RecentFills};
+use crate::inventory::InventoryTracker;
+#[cfg(feature = "kafka")]
+use crate::kafka::KafkaSink;
+use crate::market_info::MarketInfoTracker;
+use crate::listings::{self, ListingFilter};
+use crate::metrics::{metrics, Metrics};
+use crate::oracle::Oracles;
+use crate::pairs::PairFeed;
+use crate::pipeline::{self, stage_channel, Outgoing, StageSender};
+use crate::postmortem;
+use crate::replay::{self, ReplayBuffer};
+use crate::sequences::SequenceGuard;
+use crate::sessions::SessionTracker;
+use crate::shadow;
+use crate::sink::{
+    HealthRegistry, QuoteFeedSink, Sink, SinkHealth, SinkPolicy, Sinks, StdoutSink, WsSink, ZmqSink,
+};
+use crate::slo::{HealthPolicy, LagSlo, SloRegistry};
+use crate::source;
+use crate::summary;
+use crate::upgrades::{self, UpgradeGuard};
+use crate::utils::OoaOwner;
+use crate::volume::VolumeTracker;
+use crate::ws::{self, WsPolicy};
+use anchor_lang::AnchorDeserialize;
+use log::{error, info, warn};
+use openbookv2_decoder::schema::{oldest_schema_version, supports_schema_version};
+use openbookv2_decoder::{parse_name, LogFormat, FEED_SCHEMA_VERSION};
+use openbookv2_generated::state::Market;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+
+// Embeds the whole pipeline in another service:
+//
+//   let handle = TradesStreamer::builder()
+//       .markets([market])
+//       .source(SourceKind::Geyser)
+//       .sink(my_sink)
+//       .spawn()
+//       .await?;
+//
+// Everything the command line can set is reachable with configure(). Unlike the binary
+// the builder opens no ZMQ socket unless configured to.
+pub struct TradesStreamer;
+
+impl TradesStreamer {
+    pub fn builder() -> StreamerBuilder {
+        StreamerBuilder {
+            config: Config {
+                no_zmq: true,
+                ..Config::defaults()
+            },
+            sinks: Vec::new(),
+            bus: None,
+        }
+    }
+}
+
+pub struct StreamerBuilder {
+    config: Config,
+    sinks: Vec<Box<dyn Sink>>,
+    bus: Option<Bus>,
+}
+
+impl StreamerBuilder {
+    pub fn markets(mut self, markets: impl IntoIterator<Item = Pubkey>) -> Self {
+        self.config.market_keys = markets.into_iter().collect();
+        self
+    }
+
+    pub fn source(mut self, source: SourceKind) -> Self {
+        self.config.source = source;
+        self
+    }
+
+    pub fn commitment(mut self, commitment: Commitment) -> Self {
+        self.config.commitment = commitment;
+        self
+    }
+
+    pub fn rpc_url(mut self, rpc_url: impl Into<String>) -> Self {
+        self.config.rpc_url = rpc_url.into();
+        self
+    }
+
+    pub fn grpc(mut self, endpoint: impl Into<String>, x_token: impl Into<String>) -> Self {
+        self.config.grpc = endpoint.into();
+        self.config.x_token = x_token.into();
+        self
+    }
+
+    // Gets every published message like the built in sinks, with their retries and breaker
+    pub fn sink(mut self, sink: impl Sink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    // Publishes every message to in-process consumers as well, see bus.rs
+    pub fn bus(mut self, bus: Bus) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    pub fn configure(mut self, configure: impl FnOnce(&mut Config)) -> Self {
+        configure(&mut self.config);
+        self
+    }
+
+    pub async fn spawn(self) -> Result<StreamerHandle, Error> {
+        start(self.config, self.sinks, self.bus).await
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StageStats {
+    pub processed: u64,
+    pub queued: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct StreamerStats {
+    pub markets: usize,
+    // by the stage reading the queue: enrich, encode and publish
+    pub stages: BTreeMap<&'static str, StageStats>,
+    pub sinks: BTreeMap<String, SinkHealth>,
+}
+
+// A running pipeline. Dropping it leaves the pipeline running.
+pub struct StreamerHandle {
+    stop: watch::Sender<bool>,
+    pipeline: JoinHandle<()>,
+    // servers and background tasks, aborted on shutdown
+    tasks: Vec<JoinHandle<()>>,
+    markets: usize,
+    sinks: HealthRegistry,
+}
+
+impl StreamerHandle {
+    pub fn stats(&self) -> StreamerStats {
+        let stages = ["enrich", "encode", "publish"]
+            .into_iter()
+            .map(|stage| {
+                let labels = Metrics::stage(stage);
+                let stats = StageStats {
+                    processed: metrics().stage_processed.get_or_create(&labels).get(),
+                    queued: metrics().stage_queue.get_or_create(&labels).get(),
+                };
+                (stage, stats)
+            })
+            .collect();
+        StreamerStats {
+            markets: self.markets,
+            stages,
+            sinks: self.sinks.lock().unwrap().clone(),
+        }
+    }
+
+    // Stops the sources, the stages publish what they have queued and save their state
+    pub async fn shutdown(self) {
+        let _ = self.stop.send(true);
+        if let Err(err) = self.pipeline.await {
+            error!("pipeline stopped with {}", err);
+        }
+        for task in self.tasks {
+            task.abort();
+        }
+        info!("pipeline shut down");
+    }
+
+    // Waits for the pipeline, the sources run until shutdown
+    pub async fn join(self) {
+        if let Err(err) = self.pipeline.await {
+            error!("pipeline stopped with {}", err);
+        }
+    }
+}
+
+// Loads the markets, opens every sink (the built in ones of the config and `extra_sinks`)
+// and starts the sources last
+pub async fn start(
+    config: Config,
+    extra_sinks: Vec<Box<dyn Sink>>,
+    bus: Option<Bus>,
+) -> Result<StreamerHandle, Error> {
+    #[cfg(feature = "chaos")]
+    crate::chaos::from_env();
+    let mut tasks = Vec::new();
+    if config.summary_interval > 0 {
+        tasks.push(spawn(summary::run(Duration::from_secs(config.summary_interval))));
+    }
+
+    let sink_health = HealthRegistry::default();
+    postmortem::watch_sinks(sink_health.clone());
+    let lag_slos = SloRegistry::default();
+    let startup = Arc::new(Mutex::new(None));
+    let sink_control = Arc::new(Mutex::new(None));
+    let api_keys_cell = Arc::new(Mutex::new(None));
+    let consumers = config
+        .consumer_heartbeats
+        .then(|| ConsumerRegistry::new(config.consumer_stale_secs));
+    let features = FeatureFlags::load(&Path::new(&config.state_dir).join("features.json"), &config.disable_features);
+    if let Some(addr) = config.admin.clone() {
+        let state = admin::AdminState {
+            profiling: config.profiling,
+            sinks: sink_health.clone(),
+            lag_slos: lag_slos.clone(),
+            min_score: config.health_min_score,
+            startup: startup.clone(),
+            features: features.clone(),
+            consumers: consumers.clone(),
+            sink_control: sink_control.clone(),
+            api_keys: config.api_keys.is_some().then(|| api_keys_cell.clone()),
+        };
+        tasks.push(spawn(admin::serve(addr, state)));
+    } else if config.profiling {
+        warn!("--profiling has no effect without --admin <host:port>");
+    }
+    if config.profiling && cfg!(not(feature = "profiling")) {
+        warn!("--profiling requested but the binary was built without the `profiling` feature");
+    }
+    if let Some(legacy) = config.legacy_schema() {
+        if legacy.version >= FEED_SCHEMA_VERSION || !supports_schema_version(legacy.version) {
+            return Err(Error::Config(format!(
+                "--legacy-schema-version {} is not an older schema version this printer can publish (oldest {}, current {})",
+                legacy.version,
+                oldest_schema_version(),
+                FEED_SCHEMA_VERSION
+            )));
+        }
+    }
+    if (config.legacy_schema().is_some() || config.pair_feed) && !config.no_zmq && !config.zmq_topics {
+        warn!("without --zmq-topics ZMQ consumers cannot tell the legacy schema and pair copies from the trades");
+    }
+
+    let processed_commitment = CommitmentConfig::processed();
+    let client = RpcClient::new_with_commitment(config.rpc_url.clone(), processed_commitment);
+    clock::calibrate(config.clock_sync, &config.ntp_server, &client).await;
+
+    let accounts: Vec<Option<Vec<u8>>> = match config.market_accounts.as_deref() {
+        Some(path) => bootstrap::load_accounts(Path::new(path), &config.market_keys).map_err(Error::Config)?,
+        None => bootstrap::fetch_accounts(&client, &config.market_keys, config.bootstrap_concurrency, "markets")
+            .await
+            .map_err(|err| Error::Source {
+                name: "rpc",
+                error: SourceError::Rpc(format!("could not load the markets: {}", err)),
+            })?
+            .into_iter()
+            .map(|account| account.map(|account| account.data))
+            .collect(),
+    };
+    let mut market_names = BTreeMap::new();
+    let mut markets = BTreeMap::new();
+    let mut quarantined = HashSet::new();
+    let mut priority = HashSet::new();
+    for (idx, option) in accounts.iter().enumerate() {
+        if let Some(data) = option {
+            // an account without the discriminator can not be a market
+            let Some(mut fields) = data.get(8..) else {
+                warn!("{} has {} bytes, too few for a market, not subscribing", config.market_keys[idx], data.len());
+                continue;
+            };
+            let market = Market::deserialize(&mut fields)
+                .map_err(|err| Error::Config(format!("{} is not a market: {}", config.market_keys[idx], err)))?;
+            let market_name = parse_name(&market.name);
+            let listed = |list: &[Pubkey]| {
+                [config.market_keys[idx], market.base_mint, market.quote_mint]
+                    .iter()
+                    .any(|key| list.contains(key))
+            };
+            if listed(&config.blacklist) {
+                warn!("Market {} ({}) is blacklisted, not subscribing", market_name.replace('\0', ""), config.market_keys[idx]);
+                continue;
+            }
+            if listed(&config.quarantine) {
+                warn!("Market {} ({}) is quarantined", market_name.replace('\0', ""), config.market_keys[idx]);
+                quarantined.insert(config.market_keys[idx]);
+            }
+            if listed(&config.priority_markets) {
+                info!("Market {} ({}) has priority", market_name.replace('\0', ""), config.market_keys[idx]);
+                priority.insert(config.market_keys[idx]);
+            }
+            market_names.insert(config.market_keys[idx], market_name.clone());
+            markets.insert(config.market_keys[idx], market);
+            info!("Subscribing for fills for market: {:<30} Pubkey: {:<10}", market_name.as_str(), &config.market_keys[idx].to_string()[..5]);
+        } else {
+            warn!("Market account not found for pubkey: {}", config.market_keys[idx]);
+        }
+    }
+    if markets.is_empty() {
+        return Err(Error::Config("none of the markets exists or is not blacklisted".to_string()));
+    }
+
+    if config.decimals_check != DecimalsCheck::Off {
+        match decimals::verify(&client, &markets, config.bootstrap_concurrency).await {
+            Ok(mismatches) => {
+                for mismatch in mismatches.iter() {
+                    error!("{}", mismatch);
+                }
+                if !mismatches.is_empty() && config.decimals_check == DecimalsCheck::Fail {
+                    return Err(Error::Config(
+                        "prices and sizes would be converted wrong (--decimals-check warn to continue)".to_string(),
+                    ));
+                }
+            }
+            Err(err) => warn!("could not verify market decimals: {}", err),
+        }
+    }
+
+    *startup.lock().unwrap() = Some(serde_json::json!({
+        "config": check::effective(&config),
+        "markets": check::market_table(&config, &markets),
+    }));
+
+    let market_keys: Vec<Pubkey> = markets.keys().copied().collect();
+
+    let counters = config.persist_metrics.then(|| {
+        if let Err(err) = std::fs::create_dir_all(&config.state_dir) {
+            warn!("could not create state dir {}: {}", config.state_dir, err);
+        }
+        CounterStore::restore(
+            Path::new(&config.state_dir).join("metrics.json"),
+            market_names.values().map(|name| name.replace('\0', "")),
+            sink_health.clone(),
+        )
+    });
+
+    let ctx = zmq::Context::new();
+    let socket = if config.no_zmq {
+        None
+    } else {
+        let zero_url = format!("tcp://{}:{}", config.host, config.port);
+        let socket = ctx.socket(zmq::PUB).unwrap();
+        let result = if config.connect {
+            socket.connect(&zero_url)
+        } else {
+            socket.bind(&zero_url)
+        };
+        result.map_err(|err| Error::Sink {
+            name: "zmq".to_string(),
+            error: SinkError::Open {
+                target: zero_url,
+                reason: err.to_string(),
+            },
+        })?;
+        Some(socket)
+    };
+
+    // latency critical consumers, no retries or filters
+    let binary_socket = match config.zmq_binary.as_ref() {
+        Some(addr) => {
+            let socket = ctx.socket(zmq::PUB).unwrap();
+            socket.bind(addr).map_err(|err| Error::Sink {
+                name: "zmq_binary".to_string(),
+                error: SinkError::Open {
+                    target: addr.clone(),
+                    reason: err.to_string(),
+                },
+            })?;
+            Some(socket)
+        }
+        None => None,
+    };
+
+    let replay_buffer = Arc::new(Mutex::new(ReplayBuffer::new(config.replay_buffer)));
+    if let Some(addr) = config.control.clone() {
+        replay::serve(&ctx, addr, replay_buffer.clone(), consumers.clone());
+    }
+
+    let mut sinks = Sinks::new(
+        SinkPolicy {
+            retries: config.sink_retries,
+            backoff: Duration::from_millis(config.sink_backoff_ms),
+            backoff_max: Duration::from_millis(config.sink_backoff_max_ms),
+            failure_threshold: config.breaker_threshold,
+            open_for: Duration::from_secs(config.breaker_open_secs),
+        },
+        sink_health.clone(),
+    );
+    if let Some(socket) = socket {
+        sinks.add(Box::new(ZmqSink::new(socket, config.zmq_topics)), config.zmq_filter.clone());
+    }
+    for (quote_mint, addr) in config.quote_feeds.iter() {
+        let names: Vec<String> = markets
+            .iter()
+            .filter(|(_, market)| market.quote_mint == *quote_mint)
+            .map(|(key, _)| market_names[key].replace('\0', ""))
+            .collect();
+        if names.is_empty() {
+            warn!("no subscribed market is quoted in {}, its feed on {} stays empty", quote_mint, addr);
+        }
+        let socket = ctx.socket(zmq::PUB).unwrap();
+        socket.bind(addr).map_err(|err| Error::Sink {
+            name: format!("quote.{}", quote_mint),
+            error: SinkError::Open {
+                target: addr.clone(),
+                reason: err.to_string(),
+            },
+        })?;
+        info!("{} markets quoted in {} published on {}", names.len(), quote_mint, addr);
+        let sink = QuoteFeedSink::new(quote_mint, socket, config.zmq_topics, &names);
+        sinks.add(Box::new(sink), config.zmq_filter.clone());
+    }
+    if config.stdout {
+        sinks.add(Box::new(StdoutSink::new(config.stdout_topics.clone())), config.stdout_filter.clone());
+    }
+    if let Some(url) = config.shadow.clone() {
+        let (shadow_sink, published) = shadow::channel();
+        sinks.add(Box::new(shadow_sink), None);
+        let watched = market_keys.iter().map(|market| market.to_string()).collect();
+        let window = Duration::from_secs(config.shadow_window_secs);
+        tasks.push(spawn(shadow::run(url, watched, window, published)));
+    }
+    if let Some(brokers) = config.kafka_brokers.as_deref() {
+        #[cfg(feature = "kafka")]
+        {
+            let registry = config.schema_registry.as_deref();
+            let kafka = KafkaSink::new(brokers, config.kafka_topic.clone(), registry).map_err(|error| Error::Sink {
+                name: "kafka".to_string(),
+                error,
+            })?;
+            info!("trades published to kafka topic {} on {}", config.kafka_topic, brokers);
+            sinks.add(Box::new(kafka), None);
+        }
+        #[cfg(not(feature = "kafka"))]
+        {
+            return Err(Error::Config(format!(
+                "--kafka-brokers {} requested but the binary was built without the `kafka` feature",
+                brokers
+            )));
+        }
+    } else if config.schema_registry.is_some() {
+        warn!("--schema-registry has no effect without --kafka-brokers");
+    }
+    // for the websocket feed and the changing admin endpoints
+    let api_keys = match config.api_keys.as_ref() {
+        Some(path) => {
+            // names as published, without the padding of the on-chain name
+            let names: Vec<(String, String)> = market_names
+                .iter()
+                .map(|(key, name)| (key.to_string(), name.replace('\0', "")))
+                .collect();
+            let groups: Vec<String> = config.feed_keys.iter().map(|key| key.group.clone()).collect();
+            let keys = ApiKeys::load(path, &names, &groups).map_err(Error::Config)?;
+            info!("loaded {} api keys", keys.len());
+            Some(Arc::new(keys))
+        }
+        None => None,
+    };
+    *api_keys_cell.lock().unwrap() = api_keys.clone();
+    if let Some(addr) = config.ws.clone() {
+        let (feed, _) = broadcast::channel(1024);
+        let policy = WsPolicy {
+            client_rate: config.ws_client_rate,
+            send_timeout: Duration::from_millis(config.ws_send_timeout_ms),
+            slow_client: config.ws_slow_client,
+            replay_limit: config.ws_replay_limit,
+            legacy_schema: config.legacy_schema(),
+        };
+        let listener = std::net::TcpListener::bind(&addr)
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+            .and_then(tokio::net::TcpListener::from_std)
+            .map_err(|err| Error::Sink {
+                name: "ws".to_string(),
+                error: SinkError::Open {
+                    target: addr.clone(),
+                    reason: err.to_string(),
+                },
+            })?;
+        info!("websocket server listening on {}", addr);
+        let archive = config.archive_dir.clone().map(|dir| Arc::new(PathBuf::from(dir)));
+        tasks.push(spawn(ws::serve(listener, feed.clone(), api_keys, archive, consumers.clone(), policy)));
+        sinks.add(Box::new(WsSink::new(feed)), config.ws_filter.clone());
+    }
+    if let Some(dir) = config.archive_dir.clone() {
+        let policy = ArchivePolicy {
+            batch: config.archive_batch.max(1),
+            flush_after: Duration::from_millis(config.archive_flush_ms),
+            retention: config.archive_retention(),
+        };
+        let archive = ArchiveSink::new(PathBuf::from(dir), policy).map_err(|error| Error::Sink {
+            name: "archive".to_string(),
+            error,
+        })?;
+        sinks.add(Box::new(archive), None);
+    }
+    for sink in extra_sinks {
+        sinks.add(sink, None);
+    }
+    let unknown = sinks.set_fast(&config.fast_sinks);
+    if !unknown.is_empty() {
+        return Err(Error::Config(format!(
+            "--fast-sinks names sinks which are not enabled: {}",
+            unknown.join(", ")
+        )));
+    }
+    if !config.encrypted_sinks.is_empty() {
+        if config.feed_keys.is_empty() {
+            return Err(Error::Config("--encrypted-sinks needs at least one --feed-key".to_string()));
+        }
+        if let Some((group, _)) =
+            config.feed_group_markets.iter().find(|(group, _)| config.feed_keys.iter().all(|key| key.group != *group))
+        {
+            return Err(Error::Config(format!("--feed-group-market names group {} which has no --feed-key", group)));
+        }
+        let cipher = FeedCipher::new(&config.feed_keys, &config.feed_group_markets);
+        let unknown = sinks.set_encrypted(&config.encrypted_sinks, cipher);
+        if !unknown.is_empty() {
+            return Err(Error::Config(format!(
+                "--encrypted-sinks names sinks which are not enabled: {}",
+                unknown.join(", ")
+            )));
+        }
+    }
+    let unknown = sinks.set_workers(&config.sink_workers, config.queue_size);
+    if !unknown.is_empty() {
+        return Err(Error::Config(format!(
+            "--sink-workers names sinks which are not enabled: {}",
+            unknown.join(", ")
+        )));
+    }
+
+    *sink_control.lock().unwrap() = Some(sinks.control(config.capture_dir.as_ref().map(PathBuf::from)));
+
+    let owners = if config.prewarm_owners && config.enrichers.contains(&EnricherKind::Owners) {
+        bootstrap::prewarm_owners(&client, &market_keys, config.bootstrap_concurrency).await
+    } else {
+        HashMap::new()
+    };
+    let client = Arc::new(client);
+    let market_count = markets.len();
+    // intake -> enrich -> encode per shard -> publish, see pipeline.rs
+    let (outgoing, outgoing_receiver) = stage_channel("publish", config.queue_size);
+    let publish_core = config.publish_core;
+    let publisher = std::thread::Builder::new()
+        .name("publish".to_string())
+        .spawn(move || {
+            if let Some(core) = publish_core {
+                affinity::pin_current_thread("publish", core);
+            }
+            pipeline::publish(sinks, binary_socket, bus, outgoing_receiver)
+        })
+        .unwrap();
+
+    let (stop, stopped) = watch::channel(false);
+    if let Some(counters) = counters.clone() {
+        tasks.push(spawn(counters.run(Duration::from_secs(config.state_interval.max(1)), stopped.clone())));
+    }
+    if config.listing_interval > 0 {
+        let filter = ListingFilter {
+            mints: config.listing_mints.clone(),
+            names: config.listing_names.clone(),
+        };
+        tasks.push(spawn(listings::run(
+            client.clone(),
+            filter,
+            Duration::from_secs(config.listing_interval),
+            Path::new(&config.state_dir).join("listings.json"),
+            outgoing.clone(),
+            stopped.clone(),
+            config.canonical_json,
+        )));
+    }
+    let upgrades = UpgradeGuard::default();
+    // feed_seq starts at 1 again with every start, the epoch tells consumers so
+    let feed_epoch = now_ms() as u64;
+    if config.upgrade_check_secs > 0 {
+        tasks.push(spawn(upgrades::run(
+            client.clone(),
+            upgrades.clone(),
+            markets.clone(),
+            Duration::from_secs(config.upgrade_check_secs),
+            Path::new(&config.state_dir).join("program.json"),
+            outgoing.clone(),
+            stopped.clone(),
+            config.canonical_json,
+        )));
+    }
+    let shards = config.shards.max(1);
+    let mut shard_markets: Vec<BTreeMap<Pubkey, Market>> = (0..shards).map(|_| BTreeMap::new()).collect();
+    for (key, market) in markets {
+        shard_markets[shard_of(&key, &market, shards, config.pair_feed)].insert(key, market);
+    }
+    let mut enrich_stages = Vec::new();
+    for (index, markets) in shard_markets.into_iter().enumerate() {
+        if markets.is_empty() {
+            warn!("shard {} of {} has no markets", index, shards);
+            continue;
+        }
+        let shard = Shard {
+            index,
+            count: shards,
+            market_names: markets.keys().map(|key| (*key, market_names[key].clone())).collect(),
+            owners: owners
+                .iter()
+                .filter(|(_, owner)| markets.contains_key(&owner.market))
+                .map(|(key, owner)| (*key, *owner))
+                .collect(),
+            markets,
+        };
+        let shared = Shared {
+            client: client.clone(),
+            quarantined: &quarantined,
+            priority: &priority,
+            replay_buffer: replay_buffer.clone(),
+            lag_slos: lag_slos.clone(),
+            outgoing: outgoing.clone(),
+            features: features.clone(),
+            consumers: consumers.clone(),
+            upgrades: upgrades.clone(),
+            feed_epoch,
+        };
+        enrich_stages.push(start_shard(&config, shard, shared, &stopped));
+    }
+    // the publish thread ends once every shard dropped its sender
+    drop(outgoing);
+    let pipeline = spawn(async move {
+        for stage in enrich_stages {
+            if let Err(err) = stage.await {
+                error!("enrich stage stopped with {}", err);
+            }
+        }
+        let _ = tokio::task::spawn_blocking(move || publisher.join()).await;
+        if let Some(counters) = counters {
+            counters.save();
+        }
+    });
+    Ok(StreamerHandle {
+        stop,
+        pipeline,
+        tasks,
+        markets: market_count,
+        sinks: sink_health,
+    })
+}
+
+// The markets of one pipeline shard
+struct Shard {
+    index: usize,
+    count: usize,
+    markets: BTreeMap<Pubkey, Market>,
+    market_names: BTreeMap<Pubkey, String>,
+    // prewarmed owners of OpenOrders accounts of these markets
+    owners: HashMap<Pubkey, OoaOwner>,
+}
+
+// What the shards of a process share
+struct Shared<'a> {
+    client: Arc<RpcClient>,
+    quarantined: &'a HashSet<Pubkey>,
+    // --priority-market markets
+    priority: &'a HashSet<Pubkey>,
+    replay_buffer: Arc<Mutex<ReplayBuffer>>,
+    lag_slos: SloRegistry,
+    outgoing: StageSender<Outgoing>,
+    features: FeatureFlags,
+    consumers: Option<ConsumerRegistry>,
+    upgrades: UpgradeGuard,
+    feed_epoch: u64,
+}
+
+// Pubkeys are uniformly distributed, their first bytes are hash enough. With --pair-feed
+// markets of the same base and quote mint stay in one shard so their pairs are complete.
+fn shard_of(key: &Pubkey, market: &Market, shards: usize, pair_feed: bool) -> usize {
+    let mut bytes = key.to_bytes();
+    if pair_feed {
+        let (base, quote) = (market.base_mint.to_bytes(), market.quote_mint.to_bytes());
+        for (byte, (b, q)) in bytes.iter_mut().zip(base.iter().zip(quote.iter())) {
+            *byte = b ^ q;
+        }
+    }
+    (u64::from_le_bytes(bytes[..8].try_into().unwrap()) % shards as u64) as usize
+}
+
+// Starts the sources, enrich and encode stages of a shard into the shared publish queue,
+// returns the enrich stage which ends once the sources are stopped. Every shard keeps its
+// state in its own directory below --state-dir, unless there is only one.
+fn start_shard(config: &Config, shard: Shard, shared: Shared<'_>, stopped: &watch::Receiver<bool>) -> JoinHandle<()> {
+    let state_dir = match shard.count {
+        1 => PathBuf::from(&config.state_dir),
+        count => PathBuf::from(&config.state_dir).join(format!("shard-{}-of-{}", shard.index, count)),
+    };
+    if let Err(err) = std::fs::create_dir_all(&state_dir) {
+        warn!("could not create state dir {}: {}", state_dir.display(), err);
+    }
+    let mut volumes = VolumeTracker::load(&state_dir.join("volumes.json"));
+    match config.archive_dir.as_ref() {
+        Some(dir) if config.repair_from_archive => {
+            let market_ids: Vec<String> = shard.markets.keys().map(|key| key.to_string()).collect();
+            let repaired = volumes.repair(&PathBuf::from(dir), &market_ids, now_ms() / 1000);
+            info!("added {} archived trades missing from the saved volumes", repaired);
+        }
+        None if config.repair_from_archive => warn!("--repair-from-archive has no effect without --archive-dir"),
+        _ => {}
+    }
+    let Shard {
+        index,
+        count,
+        markets,
+        market_names,
+        owners,
+    } = shard;
+    let quarantined: HashSet<Pubkey> = markets
+        .keys()
+        .filter(|market| shared.quarantined.contains(market))
+        .copied()
+        .collect();
+    let priority: HashSet<Pubkey> = markets
+        .keys()
+        .filter(|market| shared.priority.contains(market))
+        .copied()
+        .collect();
+    let names: Vec<String> = market_names.values().map(|name| name.replace('\0', "")).collect();
+    let encode = EncodeStage {
+        market_names: market_names.clone(),
+        volumes,
+        volume_path: state_dir.join("volumes.json"),
+        state_interval: config.state_interval,
+        ticker_interval: config.ticker_interval,
+        fill_rate: FillRate::new(names.iter()),
+        bars: config.bars.then(BarAggregator::default),
+        inventory: InventoryTracker::load(&state_dir.join("inventory.json"), now_ms() / 1000),
+        inventory_path: state_dir.join("inventory.json"),
+        inventory_interval: config.inventory_interval,
+        checksums: ChecksumTracker::load(&state_dir.join("checksums.json")),
+        checksums_path: state_dir.join("checksums.json"),
+        checksum_interval: config.checksum_interval,
+        fees: FeeTracker::load(&state_dir.join("fees.json")),
+        fees_path: state_dir.join("fees.json"),
+        fees_interval: config.fees_interval,
+        sessions: SessionTracker::load(&state_dir.join("sessions.json"), config.sessions.clone()),
+        sessions_path: state_dir.join("sessions.json"),
+        pair_feed: config.pair_feed.then(|| PairFeed::new(&markets, &market_names)),
+        replay_buffer: config.control.is_some().then_some(shared.replay_buffer),
+        alerts: config.alerts.clone(),
+        alert_state: AlertState::load(&state_dir.join("alerts.json"), config.alert_cooldown_secs),
+        alert_state_path: state_dir.join("alerts.json"),
+        embed_lag: config.embed_lag,
+        canonical: config.canonical_json,
+        legacy_schema: config.legacy_schema(),
+        features: shared.features.clone(),
+        consumers: shared.consumers,
+        quarantined: quarantined.iter().map(|market| market.to_string()).collect(),
+        priority: priority.iter().map(|market| market.to_string()).collect(),
+        lag_slo: LagSlo::new(
+            market_names
+                .iter()
+                .filter_map(|(market, name)| {
+                    let limit_ms = config.lag_slo(market)?;
+                    Some((market.to_string(), name.replace('\0', ""), limit_ms))
+                })
+                .collect(),
+            HealthPolicy {
+                min_interval_ms: config.health_min_interval_ms,
+                max_interval_ms: config.health_max_interval_ms,
+                min_score: config.health_min_score,
+            },
+            shared.lag_slos,
+        ),
+        yield_every: config.yield_every,
+    };
+    let market_keys: Vec<Pubkey> = markets.keys().copied().collect();
+    let commitments = market_keys
+        .iter()
+        .map(|market| (*market, config.market_commitment(market).as_str()))
+        .collect();
+    let (enriched, enriched_receiver) = stage_channel("encode", config.queue_size);
+    let enrich = EnrichStage {
+        commitments,
+        oracles: Oracles::new(&markets, &config.quote_conversions),
+        enrichers: enricher::from_config(
+            config,
+            shared.client.clone(),
+            &markets,
+            owners,
+            (count > 1).then(|| format!("shard-{}-of-{}", index, count)).as_deref(),
+            shared.features.clone(),
+        ),
+        client: shared.client,
+        markets,
+        market_names,
+        market_info: MarketInfoTracker::load(&state_dir.join("market_info.json")),
+        market_info_path: state_dir.join("market_info.json"),
+        market_info_interval: config.market_info_interval,
+        oracle_interval: config.oracle_interval,
+        features: shared.features.clone(),
+        trade_id: config.trade_id,
+        yield_every: config.yield_every,
+        sequences: SequenceGuard::load(&state_dir.join("sequences.json")),
+        sequences_path: state_dir.join("sequences.json"),
+        seq_guard: config.seq_guard,
+        state_interval: config.state_interval,
+        fast: (!config.fast_sinks.is_empty()).then(|| shared.outgoing.clone()),
+        quarantined,
+        canonical: config.canonical_json,
+        priority: priority.iter().map(|market| market.to_string()).collect(),
+        catch_up: config.catch_up_signatures,
+        log_format: LogFormat::new(config.log_prefixes.clone()),
+        upgrades: shared.upgrades,
+        feed_epoch: shared.feed_epoch,
+        rebates: RecentFills::new(config.signature_dedup),
+        rebate_markets: HashMap::new(),
+    };
+
+    // Fills arriving while the stages start up wait in the intake queue
+    let (intake, updates) = intake_queue(
+        config.queue_size,
+        config.fill_dedup,
+        config.signature_dedup,
+        LogFormat::new(config.log_prefixes.clone()),
+        priority,
+    );
+    // one subscription per commitment, all into the same intake channel
+    for (commitment, keys) in config.commitment_groups(&market_keys) {
+        let source = source::from_config(config, &keys, commitment);
+        info!(
+            "reading fills of {} markets from the {} source at {} commitment (shard {} of {})",
+            keys.len(),
+            source.name(),
+            commitment.as_str(),
+            index,
+            count
+        );
+        let intake = intake.split(&keys);
+        // dropping the intake queue on shutdown lets the stages behind it finish
+        let mut stopped = stopped.clone();
+        let run = async move {
+            tokio::select! {
+                _ = source.run(intake) => {}
+                _ = stopped.changed() => {}
+            }
+        };
+        if config.intake_runtime {
+            let name = match count {
+                1 => format!("intake-{}", commitment.as_str()),
+                _ => format!("intake-{}-{}", index, commitment.as_str()),
+            };
+            affinity::spawn_dedicated(&name, config.intake_core, run);
+        } else {
+            spawn(run);
+        }
+    }
+    drop(intake);
+    spawn(encode.run(enriched_receiver, shared.outgoing));
+    spawn(enrich.run(updates, enriched))
+}
+
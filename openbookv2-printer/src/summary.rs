@@ -0,0 +1,69 @@
+use crate::clock::now_ms;
+use crate::metrics::metrics;
+use log::info;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::interval;
+
+// Totals since the last summary, filled by the sources
+struct Period {
+    fills: u64,
+    // fills with a block time, the lag average is over these
+    timed_fills: u64,
+    lag_sum_ms: i64,
+    lag_max_ms: Option<i64>,
+    reconnects: u64,
+}
+
+const EMPTY: Period = Period {
+    fills: 0,
+    timed_fills: 0,
+    lag_sum_ms: 0,
+    lag_max_ms: None,
+    reconnects: 0,
+};
+
+static PERIOD: Mutex<Period> = Mutex::new(EMPTY);
+
+pub fn record_fills(count: usize, block_time_ms: Option<i64>) {
+    if count == 0 {
+        return;
+    }
+    let mut period = PERIOD.lock().unwrap();
+    period.fills += count as u64;
+    if let Some(block_time_ms) = block_time_ms {
+        let lag_ms = now_ms() - block_time_ms;
+        period.timed_fills += count as u64;
+        period.lag_sum_ms += lag_ms * count as i64;
+        period.lag_max_ms = Some(period.lag_max_ms.map_or(lag_ms, |max| max.max(lag_ms)));
+    }
+}
+
+pub fn record_reconnect() {
+    PERIOD.lock().unwrap().reconnects += 1;
+    metrics().source_reconnects.inc();
+}
+
+// Logs one line per period and sets the openbook_summary_* gauges, also without
+// fills, so a quiet market still shows the feed is alive
+pub async fn run(every: Duration) {
+    let mut ticker = interval(every);
+    // the first tick is immediate and would summarize nothing
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let period = std::mem::replace(&mut *PERIOD.lock().unwrap(), EMPTY);
+        let lag_avg_ms = (period.timed_fills > 0).then(|| period.lag_sum_ms / period.timed_fills as i64);
+        metrics().summary_fills.set(period.fills as i64);
+        metrics().summary_lag_avg_seconds.set(lag_avg_ms.unwrap_or(0) as f64 / 1000.0);
+        metrics().summary_lag_max_seconds.set(period.lag_max_ms.unwrap_or(0) as f64 / 1000.0);
+        info!(
+            "last {} s: {} fills, lag avg {} ms, max {} ms, {} reconnects",
+            every.as_secs(),
+            period.fills,
+            lag_avg_ms.map_or("-".to_string(), |lag| lag.to_string()),
+            period.lag_max_ms.map_or("-".to_string(), |lag| lag.to_string()),
+            period.reconnects
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use crate::config::{Config, TailFormat};
+use crate::exit;
+use chrono::{DateTime, Utc};
+use openbookv2_trades_client::{ClientEvent, ControlMessage, Trade, TradesClient};
+use std::io::{IsTerminal, Write};
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+// How `tail` prints what it receives
+struct Printer {
+    format: TailFormat,
+    // markets by name or id, empty for all
+    markets: Vec<String>,
+    // control messages, gaps and reconnects as well, to stderr
+    control: bool,
+    colors: bool,
+}
+
+impl Printer {
+    fn wants(&self, market_id: &str, market_name: &str) -> bool {
+        self.markets.is_empty() || self.markets.iter().any(|market| market == market_id || market == market_name)
+    }
+
+    fn header(&self) -> Option<&'static str> {
+        match self.format {
+            TailFormat::Csv => Some("time,market,side,price,size,feedSeq,signature"),
+            _ => None,
+        }
+    }
+
+    fn trade(&self, trade: &Trade) -> String {
+        let side = if trade.taker_side == 0 { "buy" } else { "sell" };
+        // block time where the publisher sends it, the on-chain clock otherwise
+        let time = trade
+            .block_time_ms
+            .and_then(DateTime::<Utc>::from_timestamp_millis)
+            .or_else(|| DateTime::<Utc>::from_timestamp(trade.time_stamp as i64, 0))
+            .unwrap_or_default();
+        match self.format {
+            TailFormat::Json => serde_json::to_string(trade).unwrap(),
+            TailFormat::Csv => format!(
+                "{},{},{},{},{},{},{}",
+                time.to_rfc3339(),
+                trade.market_name,
+                side,
+                trade.price_double,
+                trade.quantity_double,
+                trade.feed_seq,
+                trade.signature
+            ),
+            TailFormat::Pretty => {
+                let (color, dim, reset) = match (self.colors, trade.taker_side) {
+                    (false, _) => ("", "", ""),
+                    (true, 0) => (GREEN, DIM, RESET),
+                    (true, _) => (RED, DIM, RESET),
+                };
+                format!(
+                    "{} {:<14} {}{:<4} {:>14} @ {:<14}{} {}#{} {}{}",
+                    time.format("%H:%M:%S%.3f"),
+                    trade.market_name,
+                    color,
+                    side,
+                    trade.quantity_double,
+                    trade.price_double,
+                    reset,
+                    dim,
+                    trade.feed_seq,
+                    trade.signature.get(..16).unwrap_or(&trade.signature),
+                    reset
+                )
+            }
+        }
+    }
+
+    // What goes to stderr with --control, None for events of other markets
+    fn event(&self, event: &ClientEvent) -> Option<String> {
+        let market_id = match event {
+            ClientEvent::Gap { market_id, .. }
+            | ClientEvent::Reset { market_id }
+            | ClientEvent::DataLoss { market_id, .. } => Some(market_id.as_str()),
+            _ => None,
+        };
+        if market_id.is_some_and(|market_id| !self.wants(market_id, "")) {
+            return None;
+        }
+        match event {
+            ClientEvent::Connected => Some("connected".to_string()),
+            ClientEvent::Disconnected(reason) => Some(format!("disconnected: {}", reason)),
+            ClientEvent::Gap { market_id, from, to } => Some(format!("missed {}..={} of {}", from, to, market_id)),
+            ClientEvent::Reset { market_id } => Some(format!("publisher restarted, {} numbered anew", market_id)),
+            ClientEvent::DataLoss { market_id, from, to } => {
+                Some(format!("publisher dropped {}..={} of {}", from, to, market_id))
+            }
+            ClientEvent::Control(message) => Some(serde_json::to_string(message).unwrap()),
+            ClientEvent::Trade(_) => None,
+        }
+    }
+}
+
+// `tail`: connects to the feed of a running printer like any consumer (the ZMQ endpoint of
+// the configuration unless --url names another one or a ws:// server), and prints the trades
+// of --markets to stdout until interrupted. Serves as reference consumer of the client crate.
+pub async fn run(config: &Config, url: Option<&str>, markets: &[String], format: TailFormat, control: bool) -> i32 {
+    let url = url
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("tcp://{}:{}", config.host.replace("0.0.0.0", "127.0.0.1"), config.port));
+    let client = match url.starts_with("ws://") || url.starts_with("wss://") {
+        true => TradesClient::websocket(url.clone()),
+        false => TradesClient::zmq(url.clone()),
+    };
+    let printer = Printer {
+        format,
+        markets: markets.to_vec(),
+        control,
+        colors: std::io::stdout().is_terminal(),
+    };
+    eprintln!("tailing {}", url);
+    let mut events = client.spawn();
+    let mut stdout = std::io::stdout().lock();
+    if let Some(header) = printer.header() {
+        let _ = writeln!(stdout, "{}", header);
+    }
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = tokio::signal::ctrl_c() => return exit::OK,
+        };
+        let Some(event) = event else {
+            return exit::OK;
+        };
+        match &event {
+            ClientEvent::Trade(trade) if printer.wants(&trade.market_id, &trade.market_name) => {
+                // a closed pipe, e.g. `tail | head`, ends the command
+                if writeln!(stdout, "{}", printer.trade(trade)).is_err() {
+                    return exit::OK;
+                }
+            }
+            ClientEvent::Trade(_) => {}
+            ClientEvent::Control(ControlMessage::Encrypted { .. }) => {}
+            event if printer.control => {
+                if let Some(line) = printer.event(event) {
+                    eprintln!("{}", line);
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use crate::bootstrap;
+use crate::encode::to_json;
+use crate::metrics::metrics;
+use crate::pipeline::{Outgoing, StageSender};
+use crate::sink::TOPIC_DIAGNOSTICS;
+use crate::state;
+use anchor_lang::{AnchorDeserialize, AnchorSerialize, Discriminator};
+use log::{error, info, warn};
+use openbookv2_decoder::ControlMessage;
+use openbookv2_generated::state::Market;
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_program::bpf_loader_upgradeable::get_program_data_address;
+use solana_program::pubkey::Pubkey;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::interval;
+
+// UpgradeableLoaderState::ProgramData: u32 variant 3, then the slot of the last deployment
+const PROGRAM_DATA_TAG: u32 = 3;
+
+// The market account still has the layout this printer was built for: same discriminator
+// and size, and the fields which can not change on-chain still hold what they held at
+// startup. A moved field fails the comparison even when its bytes deserialize.
+pub fn validate(data: &[u8], known: &Market) -> Result<Market, String> {
+    if data.get(..8) != Some(&Market::discriminator()[..]) {
+        return Err("discriminator changed".to_string());
+    }
+    let market = Market::deserialize(&mut &data[8..]).map_err(|err| format!("does not deserialize: {}", err))?;
+    let size = 8 + market.try_to_vec().map_err(|err| err.to_string())?.len();
+    if data.len() != size {
+        return Err(format!("{} bytes instead of {}", data.len(), size));
+    }
+    let identity = |market: &Market| {
+        (
+            market.base_mint,
+            market.quote_mint,
+            market.bids,
+            market.asks,
+            market.event_heap,
+            market.base_decimals,
+            market.quote_decimals,
+        )
+    };
+    if identity(&market) != identity(known) {
+        return Err("mints, decimals or book accounts differ from the ones at startup".to_string());
+    }
+    if market.base_lot_size <= 0 || market.quote_lot_size <= 0 {
+        return Err("lot sizes are not positive".to_string());
+    }
+    Ok(market)
+}
+
+// Markets whose account no longer has the expected layout, shared by the enrich stages.
+// Their fills are not decoded into trades until the printer is updated and restarted.
+#[derive(Clone)]
+pub struct UpgradeGuard {
+    incompatible: Arc<RwLock<HashMap<Pubkey, String>>>,
+    // slot of the last deployment seen, changes once per upgrade after the markets are checked
+    deployed: Arc<watch::Sender<u64>>,
+}
+
+impl Default for UpgradeGuard {
+    fn default() -> Self {
+        UpgradeGuard {
+            incompatible: Arc::default(),
+            deployed: Arc::new(watch::channel(0).0),
+        }
+    }
+}
+
+impl UpgradeGuard {
+    pub fn is_incompatible(&self, market: &Pubkey) -> bool {
+        self.incompatible.read().unwrap().contains_key(market)
+    }
+
+    // The market of `data`, None when its layout changed
+    pub fn check(&self, key: &Pubkey, data: &[u8], known: &Market) -> Option<Market> {
+        match validate(data, known) {
+            Ok(market) => Some(market),
+            Err(reason) => {
+                let mut incompatible = self.incompatible.write().unwrap();
+                if !incompatible.contains_key(key) {
+                    error!("market {} has an incompatible layout ({}), its fills are skipped", key, reason);
+                    incompatible.insert(*key, reason);
+                    metrics().incompatible_markets.set(incompatible.len() as i64);
+                }
+                None
+            }
+        }
+    }
+
+    // Changes after every upgrade, the enrich stages re-read their markets then
+    pub fn upgrades(&self) -> watch::Receiver<u64> {
+        self.deployed.subscribe()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct ProgramState {
+    // slot of the last deployment of the program the markets were checked against
+    deployed_slot: u64,
+}
+
+async fn deployed_slot(client: &RpcClient, program_data: &Pubkey) -> Result<u64, String> {
+    let account = client.get_account(program_data).await.map_err(|err| err.to_string())?;
+    let tag = account.data.get(..4).map(|tag| u32::from_le_bytes(tag.try_into().unwrap()));
+    match (tag, account.data.get(4..12)) {
+        (Some(PROGRAM_DATA_TAG), Some(slot)) => Ok(u64::from_le_bytes(slot.try_into().unwrap())),
+        _ => Err(format!("{} is not a program data account", program_data)),
+    }
+}
+
+// Polls the program data account of OpenBook every `every`. When the program was deployed
+// again since the last check, also across restarts, the market accounts are read and
+// validated, incompatible ones are skipped from then on, the enrich stages re-derive the
+// parameters of the others, and a program_upgrade message goes out on the diagnostics
+// topic. Ends with the sources.
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    client: Arc<RpcClient>,
+    guard: UpgradeGuard,
+    markets: BTreeMap<Pubkey, Market>,
+    every: Duration,
+    path: PathBuf,
+    output: StageSender<Outgoing>,
+    mut stopped: watch::Receiver<bool>,
+    canonical: bool,
+) {
+    let program_id = openbookv2_generated::id();
+    let program_data = get_program_data_address(&program_id);
+    let mut state: ProgramState = state::load(&path);
+    let keys: Vec<Pubkey> = markets.keys().copied().collect();
+    let mut ticker = interval(every);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = stopped.changed() => break,
+        }
+        let slot = match deployed_slot(&client, &program_data).await {
+            Ok(slot) => slot,
+            Err(err) => {
+                warn!("could not read the deployment slot of the program: {}", err);
+                continue;
+            }
+        };
+        if slot == state.deployed_slot {
+            continue;
+        }
+        if state.deployed_slot == 0 {
+            info!("program {} was last deployed at slot {}", program_id, slot);
+            state.deployed_slot = slot;
+            state::save(&path, &state);
+            continue;
+        }
+        warn!("program {} was upgraded at slot {}, checking the market accounts", program_id, slot);
+        metrics().program_upgrades.inc();
+        let accounts = match bootstrap::fetch_accounts(&client, &keys, 1, "markets").await {
+            Ok(accounts) => accounts,
+            // checked again at the next tick
+            Err(err) => {
+                warn!("could not read the markets after the upgrade: {}", err);
+                continue;
+            }
+        };
+        for (key, account) in keys.iter().zip(accounts) {
+            let data = account.map(|account| account.data).unwrap_or_default();
+            guard.check(key, &data, &markets[key]);
+        }
+        let incompatible: BTreeMap<String, String> = guard
+            .incompatible
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, reason)| (key.to_string(), reason.clone()))
+            .collect();
+        if incompatible.is_empty() {
+            info!("the layout of all {} markets is unchanged", keys.len());
+        }
+        let message = ControlMessage::ProgramUpgrade {
+            program_id: program_id.to_string(),
+            slot,
+            previous_slot: state.deployed_slot,
+            markets: keys.len() as u32,
+            incompatible,
+        };
+        state.deployed_slot = slot;
+        state::save(&path, &state);
+        guard.deployed.send_replace(slot);
+        let outgoing = Outgoing {
+            topic: TOPIC_DIAGNOSTICS.to_string(),
+            payload: to_json(&message, canonical),
+            trade: None,
+            fast: false,
+        };
+        if !output.send(outgoing).await {
+            return;
+        }
+    }
+}
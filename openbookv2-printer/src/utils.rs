@@ -1,47 +1,71 @@
 use anchor_lang::Discriminator;
-use openbookv2_generated::{Market, OpenOrdersAccount};
+use openbookv2_generated::OpenOrdersAccount;
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_program::pubkey::Pubkey;
-use std::collections::BTreeMap;
 
-pub fn to_native(value: f64, decimals: f64) -> f64 {
-    let d = 10_f64.powf(decimals);
-    value * d
+#[derive(Clone, Copy)]
+pub struct OoaOwner {
+    pub owner: Pubkey,
+    // set with setDelegate, may place and cancel orders for the owner
+    pub delegate: Option<Pubkey>,
+    // the market the account was opened for
+    pub market: Pubkey,
 }
 
-pub fn to_ui_decimals(value: f64, decimals: f64) -> f64 {
-    let d = 10_f64.powf(decimals);
-    value / d
+// Why an OpenOrders account has no owner
+#[derive(Clone, Debug)]
+pub enum LookupFailure {
+    // the account does not exist (anymore)
+    Closed,
+    // the account is not an OpenOrders account
+    NotOpenOrders,
+    // an OpenOrders account of another market than the fill's
+    OtherMarket,
+    // the RPC call failed, a later lookup may succeed
+    Rpc(String),
 }
 
-pub fn price_lots_to_ui(price: i64, market: &Market) -> f64 {
-    let coeff = (10_f64.powf((market.base_decimals as i8 - market.quote_decimals as i8) as f64)
-        * market.quote_lot_size as f64)
-        / market.base_lot_size as f64;
-    price as f64 * coeff
-}
+impl LookupFailure {
+    // retrying does not help
+    pub fn permanent(&self) -> bool {
+        !matches!(self, LookupFailure::Rpc(_))
+    }
 
-pub async fn get_owner_account_for_ooa(
-    client: &RpcClient,
-    ooa2owner: &BTreeMap<Pubkey, Pubkey>,
-    key: &Pubkey,
-) -> Option<Pubkey> {
-    if !ooa2owner.contains_key(key) {
-        let mut raw_data = client.get_account_data(key).await;
-        match raw_data {
-            Ok(mut data) => {
-                if data.len() > 8 && data[0..8] == OpenOrdersAccount::discriminator() {
-                    let pubkey_data: [u8; 32] =
-                        data.drain(8..40).collect::<Vec<u8>>().try_into().unwrap();
-                    return Some(Pubkey::from(pubkey_data));
-                } else {
-                    return None;
-                }
-            }
-            Err(_) => {}
+    pub fn reason(&self) -> &'static str {
+        match self {
+            LookupFailure::Closed => "closed",
+            LookupFailure::NotOpenOrders => "not_open_orders",
+            LookupFailure::OtherMarket => "other_market",
+            LookupFailure::Rpc(_) => "rpc",
         }
-    } else {
-        return Some(*ooa2owner.get(key).unwrap());
     }
-    None
+}
+
+pub async fn get_owner_account_for_ooa(client: &RpcClient, key: &Pubkey) -> Result<OoaOwner, LookupFailure> {
+    #[cfg(feature = "chaos")]
+    crate::chaos::rpc_delay().await;
+    let account = client
+        .get_account_with_commitment(key, client.commitment())
+        .await
+        .map_err(|err| LookupFailure::Rpc(err.to_string()))?
+        .value
+        .ok_or(LookupFailure::Closed)?;
+    parse_ooa_owner(&account.data).ok_or(LookupFailure::NotOpenOrders)
+}
+
+// None when the data is not (the start of) an OpenOrders account
+pub fn parse_ooa_owner(data: &[u8]) -> Option<OoaOwner> {
+    if data.len() < 136 || data[0..8] != OpenOrdersAccount::discriminator() {
+        return None;
+    }
+    // owner, market, name, delegate (zeroed when unset)
+    let delegate_data: [u8; 32] = data[104..136].try_into().unwrap();
+    let pubkey_data: [u8; 32] = data[8..40].try_into().unwrap();
+    let market_data: [u8; 32] = data[40..72].try_into().unwrap();
+    let delegate = Pubkey::from(delegate_data);
+    Some(OoaOwner {
+        owner: Pubkey::from(pubkey_data),
+        market: Pubkey::from(market_data),
+        delegate: (delegate != Pubkey::default()).then_some(delegate),
+    })
 }
@@ -0,0 +1,178 @@
+use crate::archive;
+use crate::state;
+use openbookv2_decoder::feed::Volume;
+use openbookv2_decoder::Trade;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+
+const DAY_SECS: i64 = 86_400;
+const MINUTES_24H: i64 = 1440;
+
+#[derive(Serialize, Deserialize, Default)]
+struct MarketVolume {
+    // utc day number (days since epoch) of day_volume
+    day: i64,
+    day_volume: Volume,
+    // per minute volume for the rolling 24h window, oldest first
+    minutes: VecDeque<(i64, Volume)>,
+    last_price: Option<f64>,
+    // slot of the last recorded trade, archived trades after it are added by repair
+    #[serde(default)]
+    last_slot: u64,
+}
+
+pub struct VolumeSnapshot {
+    pub last_price: Option<f64>,
+    pub volume_24h: Volume,
+    pub volume_day: Volume,
+}
+
+// Cumulative base/quote volume per market, persisted so restarts don't reset the day
+#[derive(Serialize, Deserialize, Default)]
+pub struct VolumeTracker {
+    markets: BTreeMap<String, MarketVolume>,
+}
+
+impl VolumeTracker {
+    pub fn load(path: &Path) -> Self {
+        state::load(path)
+    }
+
+    pub fn save(&self, path: &Path) {
+        state::save(path, self)
+    }
+
+    // Adds the archived trades of the last 24h which are newer than the last recorded
+    // trade of their market: those of a crash between two saves, or of the time an
+    // instance sharing the archive dir ran while this one was down. Only the partitions of
+    // `markets` (ids), the markets of this shard, are read.
+    pub fn repair(&mut self, archive_dir: &Path, markets: &[String], now: i64) -> usize {
+        if markets.is_empty() {
+            return 0;
+        }
+        let last_slots: BTreeMap<String, u64> = self
+            .markets
+            .iter()
+            .map(|(market_id, market)| (market_id.clone(), market.last_slot))
+            .collect();
+        let mut repaired = 0;
+        archive::replay(archive_dir, markets, (now - DAY_SECS).max(0) as u64, |trade| {
+            if trade.slot > last_slots.get(&trade.market_id).copied().unwrap_or(0) {
+                self.record(&trade);
+                repaired += 1;
+            }
+        });
+        repaired
+    }
+
+    pub fn record(&mut self, trade: &Trade) {
+        let timestamp = trade.time_stamp as i64;
+        let price = trade.price_double;
+        let volume = Volume {
+            base: trade.quantity_double,
+            quote: trade.quantity_double * price,
+        };
+        let market = self.markets.entry(trade.market_id.clone()).or_default();
+        let day = timestamp / DAY_SECS;
+        if day > market.day {
+            market.day = day;
+            market.day_volume = Volume::default();
+        }
+        if day == market.day {
+            market.day_volume.add(&volume);
+        }
+        let minute = timestamp / 60;
+        match market.minutes.back_mut() {
+            Some((last, bucket)) if *last == minute => bucket.add(&volume),
+            _ => market.minutes.push_back((minute, volume)),
+        }
+        market.last_price = Some(price);
+        market.last_slot = market.last_slot.max(trade.slot);
+    }
+
+    pub fn snapshot(&mut self, market_id: &str, now: i64) -> VolumeSnapshot {
+        let market = self.markets.entry(market_id.to_string()).or_default();
+        let day = now / DAY_SECS;
+        if day > market.day {
+            market.day = day;
+            market.day_volume = Volume::default();
+        }
+        let oldest = now / 60 - MINUTES_24H;
+        while market.minutes.front().is_some_and(|(minute, _)| *minute <= oldest) {
+            market.minutes.pop_front();
+        }
+        let mut volume_24h = Volume::default();
+        for (_, volume) in market.minutes.iter() {
+            volume_24h.add(volume);
+        }
+        VolumeSnapshot {
+            last_price: market.last_price,
+            volume_24h,
+            volume_day: market.day_volume,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // 2023-11-14 22:00:00 UTC, two hours before a day boundary
+    const NOW: i64 = 1_700_000_000 - 1_700_000_000 % 3600;
+
+    fn trade(market_id: &str, time_stamp: i64, price: f64, quantity: f64) -> Trade {
+        serde_json::from_value(json!({
+            "timeStamp": time_stamp,
+            "makerOwner": "maker",
+            "takerOwner": "taker",
+            "priceDouble": price,
+            "quantityDouble": quantity,
+            "marketId": market_id,
+            "takerSide": 0,
+            "marketName": "SOL-USDC",
+            "signature": "signature",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn adds_base_and_quote_volume_per_market() {
+        let mut volumes = VolumeTracker::default();
+        volumes.record(&trade("a", NOW, 2.0, 3.0));
+        volumes.record(&trade("a", NOW + 30, 4.0, 1.0));
+        volumes.record(&trade("b", NOW, 10.0, 1.0));
+        let a = volumes.snapshot("a", NOW + 60);
+        assert_eq!((a.volume_24h.base, a.volume_24h.quote), (4.0, 10.0));
+        assert_eq!((a.volume_day.base, a.volume_day.quote), (4.0, 10.0));
+        assert_eq!(a.last_price, Some(4.0));
+        assert_eq!(volumes.snapshot("b", NOW + 60).volume_24h.quote, 10.0);
+        assert_eq!(volumes.snapshot("c", NOW + 60).last_price, None);
+    }
+
+    #[test]
+    fn the_day_resets_at_utc_midnight_and_the_window_rolls() {
+        let mut volumes = VolumeTracker::default();
+        let midnight = NOW - NOW % DAY_SECS + DAY_SECS;
+        volumes.record(&trade("a", midnight - 60, 1.0, 1.0));
+        volumes.record(&trade("a", midnight + 60, 1.0, 2.0));
+        let snapshot = volumes.snapshot("a", midnight + 120);
+        assert_eq!(snapshot.volume_day.base, 2.0);
+        assert_eq!(snapshot.volume_24h.base, 3.0);
+        // the minute before midnight leaves the 24h window a day later
+        let snapshot = volumes.snapshot("a", midnight - 60 + DAY_SECS);
+        assert_eq!(snapshot.volume_24h.base, 2.0);
+        // a new day without trades has no day volume
+        let snapshot = volumes.snapshot("a", midnight + DAY_SECS + 60);
+        assert_eq!(snapshot.volume_day.base, 0.0);
+    }
+
+    #[test]
+    fn trades_of_a_past_day_do_not_count_for_today() {
+        let mut volumes = VolumeTracker::default();
+        volumes.record(&trade("a", NOW, 1.0, 5.0));
+        volumes.record(&trade("a", NOW - DAY_SECS, 1.0, 1.0));
+        assert_eq!(volumes.snapshot("a", NOW).volume_day.base, 5.0);
+    }
+}
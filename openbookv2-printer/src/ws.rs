@@ -0,0 +1,531 @@
+use crate::archive;
+use crate::auth::{ApiKey, ApiKeys, TokenBucket};
+use crate::clock::now_ms;
+use crate::config::SlowClient;
+use crate::consumers::ConsumerRegistry;
+use crate::legacy_schema::LegacySchema;
+use crate::metrics::metrics;
+use crate::sink::{
+    topic_matches, TOPIC_BARS, TOPIC_CHECKSUMS, TOPIC_CONTROL, TOPIC_DIAGNOSTICS, TOPIC_LISTINGS, TOPIC_PAIRS,
+    TOPIC_PRICES, TOPIC_QUARANTINE, TOPIC_TRADES,
+};
+use futures::{SinkExt, StreamExt};
+use log::{debug, info, warn};
+use openbookv2_decoder::{
+    ClientHeartbeat, ClientHello, ClientReplay, ControlMessage, Trade, TradeFrame, FEED_SCHEMA_VERSION,
+    FLAG_REPLAYED,
+};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::spawn;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Sender;
+use tokio::select;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, timeout, Instant};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+
+pub struct WsMessage {
+    pub topic: String,
+    pub market_id: Option<String>,
+    // `type` of control messages, None for trades
+    pub kind: Option<String>,
+    // consumer group of encrypted envelopes
+    pub group: Option<String>,
+    pub payload: String,
+    // binary encoding of trades
+    pub frame: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Copy)]
+pub struct WsPolicy {
+    // messages per second per connection, on top of the api key limit
+    pub client_rate: Option<u32>,
+    pub send_timeout: Duration,
+    pub slow_client: SlowClient,
+    // archived trades sent per replay request at most
+    pub replay_limit: usize,
+    // older trade schema version clients can still negotiate during a migration
+    pub legacy_schema: Option<LegacySchema>,
+}
+
+#[derive(Deserialize, Default)]
+struct Routing {
+    #[serde(rename = "marketId")]
+    market_id: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    group: Option<String>,
+}
+
+impl WsMessage {
+    pub fn new(topic: &str, payload: &str) -> Self {
+        // parsed once here instead of once per client
+        let routing = serde_json::from_str::<Routing>(payload).unwrap_or_default();
+        let frame = topic_matches(TOPIC_TRADES, topic)
+            .then(|| serde_json::from_str::<Trade>(payload).ok())
+            .flatten()
+            .and_then(|trade| TradeFrame::from_trade(&trade))
+            .map(|frame| frame.encode().to_vec());
+        WsMessage {
+            topic: topic.to_string(),
+            market_id: routing.market_id,
+            kind: routing.kind,
+            group: routing.group,
+            payload: payload.to_string(),
+            frame,
+        }
+    }
+}
+
+const ENCODINGS: [&str; 2] = ["json", "binary"];
+const TOPICS: [&str; 9] = [
+    TOPIC_TRADES,
+    TOPIC_BARS,
+    TOPIC_PAIRS,
+    TOPIC_PRICES,
+    TOPIC_CONTROL,
+    TOPIC_QUARANTINE,
+    TOPIC_DIAGNOSTICS,
+    TOPIC_CHECKSUMS,
+    TOPIC_LISTINGS,
+];
+
+// What a connection was negotiated to, everything as JSON until the client says otherwise
+struct Session {
+    binary: bool,
+    topics: Vec<String>,
+    schema_version: u32,
+    legacy_schema: Option<LegacySchema>,
+}
+
+impl Session {
+    // the legacy schema while its overlap lasts
+    fn legacy(&self) -> Option<LegacySchema> {
+        self.legacy_schema.filter(|legacy| legacy.active((now_ms() / 1000) as u64))
+    }
+
+    fn hello(&self) -> ControlMessage {
+        let legacy = self.legacy().map(|legacy| legacy.version);
+        ControlMessage::Hello {
+            schema_versions: legacy.into_iter().chain([FEED_SCHEMA_VERSION]).collect(),
+            encodings: ENCODINGS.iter().map(|encoding| encoding.to_string()).collect(),
+            topics: TOPICS.iter().map(|topic| topic.to_string()).collect(),
+        }
+    }
+
+    fn negotiate(&mut self, hello: ClientHello) -> Result<ControlMessage, String> {
+        let schema_version = hello.schema_version.unwrap_or(FEED_SCHEMA_VERSION);
+        let legacy = self.legacy().is_some_and(|legacy| legacy.version == schema_version);
+        if schema_version != FEED_SCHEMA_VERSION && !legacy {
+            return Err(format!("unsupported schema version {}", schema_version));
+        }
+        let encoding = hello.encoding.unwrap_or_else(|| "json".to_string());
+        if !ENCODINGS.contains(&encoding.as_str()) {
+            return Err(format!("unsupported encoding {}", encoding));
+        }
+        // patterns must start below one of the roots, e.g. trades.*.USDC or bars.1s.SOL
+        let known = |pattern: &String| {
+            TOPICS
+                .iter()
+                .any(|root| topic_matches(pattern, root) || topic_matches(root, pattern))
+        };
+        if let Some(topic) = hello.topics.iter().find(|topic| !known(topic)) {
+            return Err(format!("unknown topic {}", topic));
+        }
+        self.binary = encoding == "binary";
+        self.topics = hello.topics.clone();
+        self.schema_version = schema_version;
+        Ok(ControlMessage::Welcome {
+            schema_version,
+            encoding,
+            topics: hello.topics,
+        })
+    }
+
+    // The topic the message has for this session, None when it is a trade of the other
+    // schema version. JSON sessions on the legacy version get its copies of the trades
+    // in place of the current ones, binary frames have no schema version.
+    fn topic<'a>(&self, message: &'a WsMessage) -> Option<&'a str> {
+        let Some(legacy) = self.legacy() else {
+            return Some(&message.topic);
+        };
+        let on_legacy = !self.binary && self.schema_version == legacy.version;
+        match legacy.strip(&message.topic) {
+            Some(topic) => on_legacy.then_some(topic),
+            None if on_legacy && topic_matches(TOPIC_TRADES, &message.topic) => None,
+            None => Some(&message.topic),
+        }
+    }
+
+    // None when the message is not for this session
+    fn encode(&self, message: &WsMessage) -> Option<Message> {
+        let topic = self.topic(message)?;
+        if !self.topics.is_empty() && !self.topics.iter().any(|pattern| topic_matches(pattern, topic)) {
+            return None;
+        }
+        // only for clients which asked for it by name
+        if self.topics.is_empty() && topic_matches(TOPIC_QUARANTINE, topic) {
+            return None;
+        }
+        match (&message.frame, self.binary) {
+            (Some(frame), true) => Some(Message::Binary(frame.clone())),
+            _ => Some(Message::Text(message.payload.clone())),
+        }
+    }
+
+    fn encode_replayed(&self, trade: Trade) -> Message {
+        if self.binary {
+            if let Some(mut frame) = TradeFrame::from_trade(&trade) {
+                frame.flags |= FLAG_REPLAYED;
+                return Message::Binary(frame.encode().to_vec());
+            }
+        }
+        let replayed = ControlMessage::Replayed { trade: Box::new(trade) };
+        Message::Text(serde_json::to_string(&replayed).unwrap())
+    }
+}
+
+// What a client can send, a hello at any time, a replay request or a heartbeat
+enum Incoming {
+    Hello(ClientHello),
+    Replay(ClientReplay),
+    Heartbeat(ClientHeartbeat),
+}
+
+fn parse_incoming(text: &str) -> Result<Incoming, String> {
+    let value: serde_json::Value = serde_json::from_str(text).map_err(|err| format!("invalid message: {}", err))?;
+    if value["type"] == "replay" {
+        serde_json::from_value(value)
+            .map(Incoming::Replay)
+            .map_err(|err| format!("invalid replay: {}", err))
+    } else if value["type"] == "heartbeat" {
+        serde_json::from_value(value)
+            .map(Incoming::Heartbeat)
+            .map_err(|err| format!("invalid heartbeat: {}", err))
+    } else {
+        serde_json::from_value(value)
+            .map(Incoming::Hello)
+            .map_err(|err| format!("invalid hello: {}", err))
+    }
+}
+
+// A replay request of the connection, archived trades are interleaved with the live feed
+struct Replay {
+    request: ClientReplay,
+    trades: VecDeque<Trade>,
+    sent: u64,
+    truncated: bool,
+    // wall clock and archive time the pacing starts from
+    started: Instant,
+    first_time: u64,
+}
+
+impl Replay {
+    fn due(&self) -> Instant {
+        let speed = self.request.speed.unwrap_or(0.0);
+        match self.trades.front() {
+            Some(trade) if speed > 0.0 => {
+                let offset = trade.time_stamp.saturating_sub(self.first_time) as f64 / speed;
+                self.started + Duration::from_secs_f64(offset)
+            }
+            _ => Instant::now(),
+        }
+    }
+
+    fn end(&self) -> ControlMessage {
+        ControlMessage::ReplayEnd {
+            market: self.request.market.clone(),
+            from: self.request.from,
+            to: self.request.to,
+            trades: self.sent,
+            truncated: self.truncated,
+        }
+    }
+}
+
+// Reads the archived trades of the request off the runtime, at most `limit` and only
+// of markets the api key is entitled to
+fn load_replay(dir: Arc<PathBuf>, request: ClientReplay, limit: usize, key: Option<Arc<ApiKey>>) -> JoinHandle<Replay> {
+    tokio::task::spawn_blocking(move || {
+        let mut trades = Vec::new();
+        let mut truncated = false;
+        // request.market may be a name, the partitions are by id
+        archive::replay(&dir, &[], request.from, |trade| {
+            let market = trade.market_id == request.market || trade.market_name.replace('\0', "") == request.market;
+            let entitled = key.as_ref().is_none_or(|key| key.entitled(Some(&trade.market_id), None, None));
+            if !market || !entitled || trade.time_stamp > request.to {
+                return;
+            }
+            if trades.len() < limit {
+                trades.push(trade);
+            } else {
+                truncated = true;
+            }
+        });
+        trades.sort_by_key(|trade| (trade.time_stamp, trade.slot, trade.feed_seq));
+        let first_time = trades.first().map(|trade| trade.time_stamp).unwrap_or(request.from);
+        Replay {
+            request,
+            trades: trades.into(),
+            sent: 0,
+            truncated,
+            started: Instant::now(),
+            first_time,
+        }
+    })
+}
+
+// Every connected client receives the same JSON payloads as the ZMQ socket,
+// limited to its markets when api keys are configured. Connections start with a
+// hello from the server, a client hello can narrow topics or switch trades to
+// binary frames. With an archive, clients can ask for the trades of a market in a
+// time range, which only their connection gets.
+// The listener is bound by main before the source subscribes
+pub async fn serve(
+    listener: TcpListener,
+    feed: Sender<Arc<WsMessage>>,
+    keys: Option<Arc<ApiKeys>>,
+    archive: Option<Arc<PathBuf>>,
+    consumers: Option<ConsumerRegistry>,
+    policy: WsPolicy,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let feed = feed.clone();
+                let keys = keys.clone();
+                let archive = archive.clone();
+                let consumers = consumers.clone();
+                spawn(async move {
+                    info!("websocket client connected: {}", peer);
+                    metrics().ws_clients.inc();
+                    handle_client(stream, feed, keys, archive, consumers, policy).await;
+                    metrics().ws_clients.dec();
+                    info!("websocket client disconnected: {}", peer);
+                });
+            }
+            Err(err) => {
+                warn!("websocket accept failed: {}", err);
+            }
+        }
+    }
+}
+
+fn presented_key(request: &Request) -> Option<String> {
+    if let Some(key) = request.headers().get("x-api-key").and_then(|value| value.to_str().ok()) {
+        return Some(key.to_string());
+    }
+    request.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(name, _)| *name == "api_key")
+            .map(|(_, value)| value.to_string())
+    })
+}
+
+async fn handle_client(
+    stream: TcpStream,
+    feed: Sender<Arc<WsMessage>>,
+    keys: Option<Arc<ApiKeys>>,
+    archive: Option<Arc<PathBuf>>,
+    consumers: Option<ConsumerRegistry>,
+    policy: WsPolicy,
+) {
+    let mut api_key: Option<Arc<ApiKey>> = None;
+    // the error type is fixed by tungstenite's handshake callback
+    #[allow(clippy::result_large_err)]
+    let authorize = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+        let Some(keys) = keys.as_ref() else {
+            return Ok(response);
+        };
+        match presented_key(request).and_then(|key| keys.lookup(&key)) {
+            Some(key) => {
+                api_key = Some(key);
+                Ok(response)
+            }
+            None => {
+                let mut error = ErrorResponse::new(Some("missing or unknown api key".to_string()));
+                *error.status_mut() = StatusCode::UNAUTHORIZED;
+                Err(error)
+            }
+        }
+    };
+    let mut ws = match tokio_tungstenite::accept_hdr_async(stream, authorize).await {
+        Ok(ws) => ws,
+        Err(err) => {
+            warn!("websocket handshake failed: {}", err);
+            return;
+        }
+    };
+    if let Some(key) = api_key.as_ref() {
+        info!("websocket client authenticated as {}", key.name);
+    }
+    let mut session = Session {
+        binary: false,
+        topics: Vec::new(),
+        schema_version: FEED_SCHEMA_VERSION,
+        legacy_schema: policy.legacy_schema,
+    };
+    let hello = serde_json::to_string(&session.hello()).unwrap();
+    if ws.send(Message::Text(hello)).await.is_err() {
+        return;
+    }
+    // the broadcast buffer is the only queue per client, a stalled client
+    // either skips what it missed or is disconnected
+    let mut receiver = feed.subscribe();
+    let mut bucket = policy.client_rate.map(TokenBucket::new);
+    let mut rate_limited = 0u64;
+    let mut loading: Option<JoinHandle<Replay>> = None;
+    let mut replay: Option<Replay> = None;
+    loop {
+        let due = replay.as_ref().map(Replay::due);
+        let received = select! {
+            received = receiver.recv() => received,
+            loaded = async { loading.as_mut().unwrap().await }, if loading.is_some() => {
+                loading = None;
+                match loaded {
+                    Ok(loaded) => {
+                        info!("websocket replay of {} with {} trades", loaded.request.market, loaded.trades.len());
+                        replay = Some(loaded);
+                    }
+                    Err(err) => {
+                        warn!("websocket replay failed: {}", err);
+                        let message = ControlMessage::Error { message: "replay failed".to_string() };
+                        if ws.send(Message::Text(serde_json::to_string(&message).unwrap())).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                continue;
+            }
+            _ = sleep_until(due.unwrap_or_else(Instant::now)), if due.is_some() => {
+                let current = replay.as_mut().unwrap();
+                let outgoing = match current.trades.pop_front() {
+                    Some(trade) => {
+                        current.sent += 1;
+                        session.encode_replayed(trade)
+                    }
+                    None => {
+                        let end = serde_json::to_string(&current.end()).unwrap();
+                        replay = None;
+                        Message::Text(end)
+                    }
+                };
+                // replays are paced by the client, no send timeout
+                if ws.send(outgoing).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            incoming = ws.next() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    let (reply, close) = match parse_incoming(&text) {
+                        Ok(Incoming::Hello(hello)) => match session.negotiate(hello) {
+                            Ok(welcome) => (Some(welcome), false),
+                            Err(message) => (Some(ControlMessage::Error { message }), true),
+                        },
+                        Ok(Incoming::Replay(request)) => {
+                            let rejected = if loading.is_some() || replay.is_some() {
+                                Some("a replay is already running on this connection".to_string())
+                            } else if request.from > request.to {
+                                Some("replay from is after to".to_string())
+                            } else {
+                                match archive.clone() {
+                                    Some(dir) => {
+                                        let key = api_key.clone();
+                                        loading = Some(load_replay(dir, request, policy.replay_limit, key));
+                                        None
+                                    }
+                                    None => Some("replay needs --archive-dir on the server".to_string()),
+                                }
+                            };
+                            (rejected.map(|message| ControlMessage::Error { message }), false)
+                        }
+                        Ok(Incoming::Heartbeat(mut heartbeat)) => {
+                            // an api key is the consumer, whatever name it sends
+                            if let Some(key) = api_key.as_ref() {
+                                heartbeat.consumer = key.name.clone();
+                            }
+                            let recorded = match consumers.as_ref() {
+                                Some(consumers) => consumers.heartbeat("ws", heartbeat).map(|_| ()),
+                                None => Err("consumer heartbeats are off on the server".to_string()),
+                            };
+                            (recorded.err().map(|message| ControlMessage::Error { message }), false)
+                        }
+                        Err(message) => (Some(ControlMessage::Error { message }), true),
+                    };
+                    if let Some(reply) = reply {
+                        if ws.send(Message::Text(serde_json::to_string(&reply).unwrap())).await.is_err() {
+                            break;
+                        }
+                    }
+                    if close {
+                        break;
+                    }
+                    continue;
+                }
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                Some(Ok(other)) => {
+                    debug!("ignoring websocket message {:?}", other);
+                    continue;
+                }
+            },
+        };
+        match received {
+            Ok(message) => {
+                let Some(outgoing) = session.encode(&message) else {
+                    continue;
+                };
+                if let Some(key) = api_key.as_ref() {
+                    let (market_id, group) = (message.market_id.as_deref(), message.group.as_deref());
+                    if !key.entitled(market_id, message.kind.as_deref(), group) {
+                        continue;
+                    }
+                    if !key.allow() {
+                        rate_limited += 1;
+                        metrics().ws_rate_limited.inc();
+                        continue;
+                    }
+                }
+                if let Some(bucket) = bucket.as_mut() {
+                    if !bucket.take() {
+                        rate_limited += 1;
+                        metrics().ws_rate_limited.inc();
+                        continue;
+                    }
+                }
+                match timeout(policy.send_timeout, ws.send(outgoing)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(_)) => break,
+                    Err(_) => {
+                        warn!("websocket send timed out after {:?}", policy.send_timeout);
+                        if let SlowClient::Disconnect = policy.slow_client {
+                            metrics().ws_slow_disconnects.inc();
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("websocket client lagging, skipped {} messages", skipped);
+                if let SlowClient::Disconnect = policy.slow_client {
+                    metrics().ws_slow_disconnects.inc();
+                    break;
+                }
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+    if rate_limited > 0 {
+        warn!("websocket client dropped {} messages over its rate limits", rate_limited);
+    }
+    let _ = ws.close(None).await;
+}
@@ -0,0 +1,222 @@
+use openbookv2_printer::chaos::{self, Faults};
+use openbookv2_printer::config::{ClockSync, DecimalsCheck, SourceKind};
+use openbookv2_printer::sink::SinkState;
+use openbookv2_printer::{StreamerHandle, TradesStreamer};
+use openbookv2_trades_client::request_replay;
+use serde_json::Value;
+use solana_program::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod common;
+
+// Recovery of the pipeline under injected faults, on the mock source with the chaos hooks:
+// dropped source transactions, a stalled enrich stage which overflows the intake queue and
+// a failing ZMQ sink. Checks that what survives is published in order, that every feed_seq
+// is either published or reported by a data_loss, that the breaker opens and closes again
+// and that the trades the sink missed can be replayed from the control socket.
+// cargo test -p openbookv2-printer --features chaos --test chaos
+
+const FILLS: u64 = 100;
+// fraction of the transactions dropped in the first phase
+const DROP: f64 = 0.3;
+const SEED: u64 = 7;
+// fills written at once while the enrich stage is stalled, more than the queues of
+// QUEUE_SIZE hold, which take the FILLS of the first phase
+const BURST: u64 = 200;
+const QUEUE_SIZE: usize = 128;
+const ENRICH_DELAY: Duration = Duration::from_millis(20);
+const TIMEOUT: Duration = Duration::from_secs(10);
+// breaker settings of the run, small so the breaker phase is quick
+const BREAKER_THRESHOLD: u32 = 3;
+const BREAKER_OPEN_SECS: u64 = 1;
+
+// What the ZMQ consumer received: trades by feed_seq and the data_loss ranges
+#[derive(Default)]
+struct Received {
+    trades: BTreeMap<u64, Value>,
+    losses: Vec<(u64, u64)>,
+}
+
+type Shared = Arc<Mutex<Received>>;
+
+fn zmq_consumer(url: String, received: Shared) {
+    std::thread::spawn(move || {
+        let ctx = zmq::Context::new();
+        let socket = ctx.socket(zmq::SUB).unwrap();
+        socket.set_subscribe(b"").unwrap();
+        socket.connect(&url).unwrap();
+        while let Ok(frames) = socket.recv_multipart(0) {
+            let Some(value) = frames.last().and_then(|payload| serde_json::from_slice::<Value>(payload).ok()) else {
+                continue;
+            };
+            let mut received = received.lock().unwrap();
+            match value["type"].as_str() {
+                None => {
+                    if let Some(feed_seq) = value["feedSeq"].as_u64() {
+                        received.trades.insert(feed_seq, value);
+                    }
+                }
+                Some("data_loss") => {
+                    let range = (value["fromSeq"].as_u64().unwrap(), value["toSeq"].as_u64().unwrap());
+                    received.losses.push(range);
+                }
+                // other control messages are not counted
+                Some(_) => {}
+            }
+        }
+    });
+}
+
+fn zmq_state(handle: &StreamerHandle) -> Option<SinkState> {
+    handle.stats().sinks.get("zmq").map(|health| health.state)
+}
+
+fn zmq_failures(handle: &StreamerHandle) -> u64 {
+    handle.stats().sinks.get("zmq").map_or(0, |health| health.consecutive_failures as u64)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pipeline_recovers_from_injected_faults() {
+    let market = Pubkey::new_unique();
+    let dir = std::env::temp_dir().join(format!("openbookv2-chaos-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let market_accounts = common::market_accounts(&dir, market);
+    let mock_file = dir.join("transactions.jsonl");
+    File::create(&mock_file).unwrap();
+
+    let zmq_addr = common::free_addr();
+    let control_url = format!("tcp://{}", common::free_addr());
+    let (host, port) = zmq_addr.split_once(':').unwrap();
+    let (host, port) = (host.to_string(), port.to_string());
+    let handle = TradesStreamer::builder()
+        .markets([market])
+        .source(SourceKind::Mock)
+        .configure(|config| {
+            config.mock_file = Some(mock_file.display().to_string());
+            config.market_accounts = Some(market_accounts.display().to_string());
+            config.state_dir = dir.join("state").display().to_string();
+            config.enrichers = vec![];
+            config.decimals_check = DecimalsCheck::Off;
+            config.clock_sync = ClockSync::Off;
+            config.no_zmq = false;
+            config.host = host;
+            config.port = port;
+            config.control = Some(control_url.clone());
+            config.queue_size = QUEUE_SIZE;
+            config.sink_retries = 0;
+            config.breaker_threshold = BREAKER_THRESHOLD;
+            config.breaker_open_secs = BREAKER_OPEN_SECS;
+        })
+        .spawn()
+        .await
+        .unwrap_or_else(|err| panic!("pipeline did not start: {}", err));
+
+    let received = Shared::default();
+    zmq_consumer(format!("tcp://{}", zmq_addr), received.clone());
+    // ZMQ subscriptions take a moment to reach the publisher
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let mut file = std::fs::OpenOptions::new().append(true).open(&mock_file).unwrap();
+    let mut seq_num = 0;
+    let mut write_fills = |count: u64| {
+        for _ in 0..count {
+            seq_num += 1;
+            writeln!(file, "{}", common::mock_transaction(market, seq_num)).unwrap();
+        }
+    };
+    let trades = |received: &Shared| received.lock().unwrap().trades.len() as u64;
+
+    // Dropped transactions never get a feed_seq, the rest is published contiguously
+    chaos::seed(SEED);
+    chaos::set(Some(Faults {
+        drop_messages: DROP,
+        ..Faults::default()
+    }));
+    write_fills(FILLS);
+    // the mock source reads the file on its own, the count of dropped ones is final once
+    // every fill is either dropped or received
+    common::wait_for(TIMEOUT, || trades(&received) + chaos::dropped() >= FILLS).await;
+    chaos::set(None);
+    let published = trades(&received);
+    assert!(chaos::dropped() > 0, "no transaction was dropped");
+    assert_eq!(published + chaos::dropped(), FILLS, "fills less dropped ones are not all published");
+    assert!(
+        received.lock().unwrap().trades.keys().copied().eq(1..=published),
+        "feedSeq of the published trades is not contiguous"
+    );
+    assert!(received.lock().unwrap().losses.is_empty(), "data_loss for transactions dropped by the source");
+
+    // A stalled enrich stage overflows the intake queue, the dropped fills are reported by
+    // data_loss ahead of the next fill which fits
+    chaos::set(Some(Faults {
+        enrich_delay: ENRICH_DELAY,
+        ..Faults::default()
+    }));
+    write_fills(BURST);
+    // the mock source reads the burst within its follow interval, the stalled stage takes a
+    // few fills of it meanwhile
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    chaos::set(None);
+    write_fills(1);
+    let last = published + BURST + 1;
+    common::wait_for(TIMEOUT, || received.lock().unwrap().trades.contains_key(&last)).await;
+    {
+        let received = received.lock().unwrap();
+        assert!(!received.losses.is_empty(), "no data_loss for a burst of {} fills", BURST);
+        // every feed_seq of the burst is published or in exactly one data_loss range
+        let mut accounted: Vec<u64> =
+            received.trades.keys().copied().filter(|feed_seq| *feed_seq > published).collect();
+        accounted.extend(received.losses.iter().flat_map(|&(from_seq, to_seq)| from_seq..=to_seq));
+        accounted.sort_unstable();
+        assert_eq!(
+            accounted,
+            (published + 1..=last).collect::<Vec<_>>(),
+            "published trades and data_loss ranges do not cover the burst exactly once"
+        );
+    }
+    let published = last;
+
+    // A failing sink opens its breaker, the trades sent meanwhile are lost to it
+    chaos::set(Some(Faults {
+        sink_failures: 1.0,
+        failing_sinks: vec!["zmq".to_string()],
+        ..Faults::default()
+    }));
+    let mut lost = 0;
+    while zmq_state(&handle) != Some(SinkState::Open) && lost < FILLS {
+        lost += 1;
+        write_fills(1);
+        common::wait_for(TIMEOUT, || zmq_failures(&handle) >= lost.min(BREAKER_THRESHOLD as u64)).await;
+    }
+    assert_eq!(zmq_state(&handle), Some(SinkState::Open), "breaker still closed after {} failed trades", lost);
+    chaos::set(None);
+    tokio::time::sleep(Duration::from_secs(BREAKER_OPEN_SECS) + Duration::from_millis(200)).await;
+
+    // The first trade after open_for probes the sink, which closes the breaker, and the
+    // feed_seq gap of the sink is exactly the lost trades
+    let recovered = 10;
+    write_fills(recovered);
+    let total = published - received.lock().unwrap().losses.iter().map(|(from, to)| to - from + 1).sum::<u64>();
+    common::wait_for(TIMEOUT, || trades(&received) >= total + recovered).await;
+    assert_eq!(zmq_state(&handle), Some(SinkState::Healthy), "zmq sink did not recover");
+    let first = published + lost + 1;
+    let after: Vec<u64> =
+        received.lock().unwrap().trades.keys().copied().filter(|feed_seq| *feed_seq > published).collect();
+    assert_eq!(after, (first..first + recovered).collect::<Vec<_>>(), "feedSeq after the breaker closed");
+
+    // The trades the sink missed are still in the replay buffer of the control socket
+    let (from, to, market_id) = (published + 1, published + lost, market.to_string());
+    let replay = tokio::task::spawn_blocking(move || request_replay(&control_url, &market_id, from, to, TIMEOUT))
+        .await
+        .unwrap()
+        .unwrap_or_else(|err| panic!("replay failed: {}", err));
+    assert!(replay.missing.is_empty(), "replay misses {:?}", replay.missing);
+    let replayed: Vec<u64> = replay.trades.iter().map(|trade| trade.feed_seq).collect();
+    assert_eq!(replayed, (from..=to).collect::<Vec<_>>(), "replayed feedSeq");
+
+    handle.shutdown().await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
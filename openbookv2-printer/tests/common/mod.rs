@@ -0,0 +1,80 @@
+use anchor_lang::{AnchorDeserialize, AnchorSerialize, Discriminator};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use openbookv2_decoder::{FillLog, PROGRAM_DATA_PREFIX};
+use openbookv2_generated::state::Market;
+use serde_json::{json, Value};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Helpers of the tests which run the pipeline on the mock source
+
+pub fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64
+}
+
+// A SOL-USDC market with lot sizes of 1, written to a --market-accounts file in `dir`
+pub fn market_accounts(dir: &Path, key: Pubkey) -> PathBuf {
+    let mut market = Market::deserialize(&mut &[0u8; 1024][..]).unwrap();
+    market.name[..8].copy_from_slice(b"SOL-USDC");
+    (market.base_decimals, market.quote_decimals) = (9, 6);
+    (market.base_lot_size, market.quote_lot_size) = (1, 1);
+    (market.base_mint, market.quote_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    (market.bids, market.asks, market.event_heap) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let mut data = Market::discriminator().to_vec();
+    market.serialize(&mut data).unwrap();
+    let path = dir.join("markets.json");
+    let accounts = json!({ key.to_string(): STANDARD.encode(data) });
+    std::fs::write(&path, accounts.to_string()).unwrap();
+    path
+}
+
+// One mock transaction with one fill
+pub fn mock_transaction(market: Pubkey, seq_num: u64) -> Value {
+    let fill = FillLog {
+        market,
+        taker_side: (seq_num % 2) as u8,
+        maker_slot: 0,
+        maker_out: false,
+        timestamp: (now_ms() / 1000) as u64,
+        seq_num,
+        maker: Pubkey::new_unique(),
+        maker_client_order_id: seq_num,
+        maker_fee: 0,
+        maker_timestamp: (now_ms() / 1000) as u64,
+        taker: Pubkey::new_unique(),
+        taker_client_order_id: seq_num,
+        taker_fee_ceil: 0,
+        price: 1000 + seq_num as i64,
+        quantity: 1 + seq_num as i64,
+    };
+    let mut data = FillLog::discriminator().to_vec();
+    fill.serialize(&mut data).unwrap();
+    json!({
+        "signature": Signature::new_unique().to_string(),
+        "slot": 1000 + seq_num,
+        "txIndex": 0,
+        "blockTimeMs": now_ms(),
+        "logs": [format!("{}{}", PROGRAM_DATA_PREFIX, STANDARD.encode(data))],
+    })
+}
+
+// Waits until `done` holds or the timeout passes, returns whether it held
+pub async fn wait_for(timeout: Duration, mut done: impl FnMut() -> bool) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    while tokio::time::Instant::now() < deadline {
+        if done() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    done()
+}
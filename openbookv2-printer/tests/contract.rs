@@ -0,0 +1,245 @@
+use futures::{SinkExt, StreamExt};
+use openbookv2_decoder::{ClientHello, TradeFrame, FEED_SCHEMA_VERSION};
+use openbookv2_printer::config::{ClockSync, DecimalsCheck, SourceKind};
+use openbookv2_printer::TradesStreamer;
+use serde_json::Value;
+use solana_program::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+mod common;
+
+// Contract test of the published feed: runs the pipeline on the mock source, publishes
+// generated fills and checks what every transport and encoding delivers against
+// schemas/trade.avsc and the binary frame layout.
+
+const FILLS: u64 = 20;
+// how long to wait for every consumer to see every fill
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const TRADE_SCHEMA: &str = include_str!("../../openbookv2-decoder/schemas/trade.avsc");
+
+// What one consumer received, by feed_seq
+#[derive(Default)]
+struct Received {
+    trades: BTreeMap<u64, Value>,
+    frames: BTreeMap<u64, TradeFrame>,
+    violations: Vec<String>,
+}
+
+type Shared = Arc<Mutex<Received>>;
+
+// Violations of the avro schema by a JSON trade: missing fields without a default, wrong
+// types and fields the schema does not document
+fn check_trade(schema: &Value, trade: &Value) -> Vec<String> {
+    let mut violations = Vec::new();
+    let fields = schema["fields"].as_array().unwrap();
+    let object = trade.as_object().unwrap();
+    for field in fields {
+        let name = field["name"].as_str().unwrap();
+        match object.get(name) {
+            None if field.get("default").is_none() => violations.push(format!("{} is missing", name)),
+            None => {}
+            Some(value) if !matches_type(&field["type"], value) => {
+                violations.push(format!("{} is {} but the schema says {}", name, value, field["type"]))
+            }
+            Some(_) => {}
+        }
+    }
+    for name in object.keys() {
+        if !fields.iter().any(|field| field["name"] == name.as_str()) {
+            violations.push(format!("{} is not in the schema", name));
+        }
+    }
+    violations
+}
+
+fn matches_type(avro: &Value, value: &Value) -> bool {
+    match avro {
+        Value::String(name) => match name.as_str() {
+            "null" => value.is_null(),
+            "boolean" => value.is_boolean(),
+            "int" | "long" => value.is_i64() || value.is_u64(),
+            "float" | "double" => value.is_number(),
+            "string" => value.is_string(),
+            _ => false,
+        },
+        // union
+        Value::Array(types) => types.iter().any(|avro| matches_type(avro, value)),
+        Value::Object(complex) if complex["type"] == "array" => value
+            .as_array()
+            .is_some_and(|items| items.iter().all(|item| matches_type(&complex["items"], item))),
+        _ => false,
+    }
+}
+
+fn record_json(received: &Shared, schema: &Value, payload: &[u8]) {
+    let mut received = received.lock().unwrap();
+    let value: Value = match serde_json::from_slice(payload) {
+        Ok(value) => value,
+        Err(err) => {
+            received.violations.push(format!("payload is not JSON: {}", err));
+            return;
+        }
+    };
+    // control messages only have to be tagged
+    if value.get("type").is_some() {
+        if !value["type"].is_string() {
+            received.violations.push(format!("control message with a type which is not a string: {}", value));
+        }
+        return;
+    }
+    for violation in check_trade(schema, &value) {
+        received.violations.push(format!("trade {}: {}", value["tradeId"], violation));
+    }
+    if let Some(feed_seq) = value["feedSeq"].as_u64() {
+        received.trades.insert(feed_seq, value);
+    }
+}
+
+fn record_frame(received: &Shared, frame: &[u8]) {
+    let mut received = received.lock().unwrap();
+    match TradeFrame::decode(frame) {
+        Some(frame) => {
+            received.frames.insert(frame.feed_seq, frame);
+        }
+        None => received.violations.push(format!("binary frame of {} bytes does not decode", frame.len())),
+    }
+}
+
+fn zmq_consumer(url: String, binary: bool, received: Shared, schema: Arc<Value>) {
+    std::thread::spawn(move || {
+        let ctx = zmq::Context::new();
+        let socket = ctx.socket(zmq::SUB).unwrap();
+        socket.set_subscribe(b"").unwrap();
+        socket.connect(&url).unwrap();
+        while let Ok(frames) = socket.recv_multipart(0) {
+            let Some(payload) = frames.last() else {
+                continue;
+            };
+            if binary {
+                record_frame(&received, payload);
+            } else {
+                record_json(&received, &schema, payload);
+            }
+        }
+    });
+}
+
+async fn ws_consumer(url: String, encoding: &str, received: Shared, schema: Arc<Value>) {
+    let (mut ws, _) = loop {
+        match tokio_tungstenite::connect_async(url.as_str()).await {
+            Ok(connection) => break connection,
+            Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+        }
+    };
+    let hello = ClientHello {
+        schema_version: Some(FEED_SCHEMA_VERSION),
+        encoding: Some(encoding.to_string()),
+        topics: vec!["trades".to_string()],
+    };
+    ws.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+    tokio::spawn(async move {
+        while let Some(Ok(message)) = ws.next().await {
+            match message {
+                Message::Text(text) => record_json(&received, &schema, text.as_bytes()),
+                Message::Binary(frame) => record_frame(&received, &frame),
+                _ => {}
+            }
+        }
+    });
+}
+
+// Every fill arrived at every consumer and the frames agree with the JSON trades
+fn compare(name: &str, received: &Received, fills: u64, json: &Received) -> Vec<String> {
+    let mut violations: Vec<String> = received.violations.iter().map(|v| format!("{}: {}", name, v)).collect();
+    let count = received.trades.len().max(received.frames.len()) as u64;
+    if count != fills {
+        violations.push(format!("{}: {} of {} trades received", name, count, fills));
+    }
+    for (feed_seq, frame) in received.frames.iter() {
+        let Some(trade) = json.trades.get(feed_seq) else {
+            violations.push(format!("{}: frame {} has no JSON trade", name, feed_seq));
+            continue;
+        };
+        if trade["priceDouble"].as_f64() != Some(frame.price_double)
+            || trade["quantityDouble"].as_f64() != Some(frame.quantity_double)
+            || trade["slot"].as_u64() != Some(frame.slot)
+            || trade["takerSide"].as_u64() != Some(frame.taker_side as u64)
+        {
+            violations.push(format!("{}: frame {} differs from its JSON trade", name, feed_seq));
+        }
+    }
+    violations
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn every_consumer_gets_every_trade_in_the_documented_schema() {
+    let market = Pubkey::new_unique();
+    let schema: Arc<Value> = Arc::new(serde_json::from_str(TRADE_SCHEMA).unwrap());
+    let dir = std::env::temp_dir().join(format!("openbookv2-contract-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let market_accounts = common::market_accounts(&dir, market);
+    let mock_file = dir.join("transactions.jsonl");
+    std::fs::File::create(&mock_file).unwrap();
+
+    let zmq_addr = common::free_addr();
+    let binary_addr = format!("tcp://{}", common::free_addr());
+    let ws_addr = common::free_addr();
+    let (host, port) = zmq_addr.split_once(':').unwrap();
+    let (host, port) = (host.to_string(), port.to_string());
+    let handle = TradesStreamer::builder()
+        .markets([market])
+        .source(SourceKind::Mock)
+        .configure(|config| {
+            config.mock_file = Some(mock_file.display().to_string());
+            config.market_accounts = Some(market_accounts.display().to_string());
+            config.state_dir = dir.join("state").display().to_string();
+            config.enrichers = vec![];
+            config.decimals_check = DecimalsCheck::Off;
+            config.clock_sync = ClockSync::Off;
+            config.no_zmq = false;
+            config.host = host;
+            config.port = port;
+            config.zmq_binary = Some(binary_addr.clone());
+            config.ws = Some(ws_addr.clone());
+        })
+        .spawn()
+        .await
+        .unwrap_or_else(|err| panic!("pipeline did not start: {}", err));
+
+    let consumers: Vec<(&str, Shared)> = ["zmq json", "zmq binary", "ws json", "ws binary"]
+        .into_iter()
+        .map(|name| (name, Shared::default()))
+        .collect();
+    zmq_consumer(format!("tcp://{}", zmq_addr), false, consumers[0].1.clone(), schema.clone());
+    zmq_consumer(binary_addr, true, consumers[1].1.clone(), schema.clone());
+    let ws_url = format!("ws://{}", ws_addr);
+    ws_consumer(ws_url.clone(), "json", consumers[2].1.clone(), schema.clone()).await;
+    ws_consumer(ws_url, "binary", consumers[3].1.clone(), schema.clone()).await;
+    // ZMQ subscriptions take a moment to reach the publisher
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let mut file = std::fs::OpenOptions::new().append(true).open(&mock_file).unwrap();
+    for seq_num in 1..=FILLS {
+        writeln!(file, "{}", common::mock_transaction(market, seq_num)).unwrap();
+    }
+    let complete = |received: &Shared| {
+        let received = received.lock().unwrap();
+        received.trades.len().max(received.frames.len()) as u64 >= FILLS
+    };
+    common::wait_for(TIMEOUT, || consumers.iter().all(|(_, received)| complete(received))).await;
+    handle.shutdown().await;
+
+    let json = consumers[0].1.lock().unwrap();
+    let mut violations = Vec::new();
+    for (name, received) in consumers.iter() {
+        let received = if *name == "zmq json" { &*json } else { &*received.lock().unwrap() };
+        violations.extend(compare(name, received, FILLS, &json));
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(violations.is_empty(), "contract violations:\n{}", violations.join("\n"));
+}
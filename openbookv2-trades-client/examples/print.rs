@@ -0,0 +1,26 @@
+use openbookv2_trades_client::{ClientEvent, TradesClient};
+
+// cargo run -p openbookv2-trades-client --example print -- tcp://127.0.0.1:8585
+// cargo run -p openbookv2-trades-client --example print -- ws://127.0.0.1:8686
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let url = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "tcp://127.0.0.1:8585".to_string());
+    let client = if url.starts_with("ws") {
+        TradesClient::websocket(url)
+    } else {
+        TradesClient::zmq(url)
+    };
+    let mut events = client.spawn();
+    while let Some(event) = events.recv().await {
+        match event {
+            ClientEvent::Trade(trade) => println!(
+                "{} {} price: {} quantity: {} seq: {}",
+                trade.market_name, trade.signature, trade.price_double, trade.quantity_double, trade.feed_seq
+            ),
+            other => println!("{:?}", other),
+        }
+    }
+}
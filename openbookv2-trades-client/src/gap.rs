@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SeqCheck {
+    InOrder,
+    // feed_seq values that never arrived, inclusive
+    Gap { from: u64, to: u64 },
+    Duplicate,
+    // the printer was restarted: its feed_epoch changed or the sequence went backwards
+    Reset,
+}
+
+// Tracks the last feed_seq and feed_epoch per market
+#[derive(Default)]
+pub struct GapDetector {
+    last: HashMap<String, u64>,
+    epochs: HashMap<String, u64>,
+}
+
+impl GapDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check(&mut self, market_id: &str, seq: u64) -> SeqCheck {
+        let result = match self.last.get(market_id) {
+            None => SeqCheck::InOrder,
+            Some(&last) if seq == last + 1 => SeqCheck::InOrder,
+            Some(&last) if seq == last => return SeqCheck::Duplicate,
+            Some(&last) if seq > last => SeqCheck::Gap {
+                from: last + 1,
+                to: seq - 1,
+            },
+            Some(_) => SeqCheck::Reset,
+        };
+        self.last.insert(market_id.to_string(), seq);
+        result
+    }
+
+    // Like check, but a feed_epoch other than the last one of the market starts its sequence
+    // again, whatever seq it starts at. Epoch 0 comes from publishers without epochs.
+    pub fn check_epoch(&mut self, market_id: &str, epoch: u64, seq: u64) -> SeqCheck {
+        if epoch != 0 {
+            let last_epoch = self.epochs.insert(market_id.to_string(), epoch);
+            if last_epoch.is_some_and(|last_epoch| last_epoch != epoch) {
+                self.last.insert(market_id.to_string(), seq);
+                return SeqCheck::Reset;
+            }
+        }
+        self.check(market_id, seq)
+    }
+
+    pub fn last_seq(&self, market_id: &str) -> Option<u64> {
+        self.last.get(market_id).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_missing_duplicate_and_reset_sequences() {
+        let mut detector = GapDetector::new();
+        assert_eq!(detector.check("a", 5), SeqCheck::InOrder);
+        assert_eq!(detector.check("a", 6), SeqCheck::InOrder);
+        assert_eq!(detector.check("a", 6), SeqCheck::Duplicate);
+        assert_eq!(detector.check("a", 10), SeqCheck::Gap { from: 7, to: 9 });
+        assert_eq!(detector.last_seq("a"), Some(10));
+        assert_eq!(detector.check("a", 2), SeqCheck::Reset);
+        assert_eq!(detector.check("a", 3), SeqCheck::InOrder);
+    }
+
+    #[test]
+    fn tracks_markets_separately() {
+        let mut detector = GapDetector::new();
+        assert_eq!(detector.check("a", 1), SeqCheck::InOrder);
+        assert_eq!(detector.check("b", 100), SeqCheck::InOrder);
+        assert_eq!(detector.check("a", 2), SeqCheck::InOrder);
+        assert_eq!(detector.check("b", 102), SeqCheck::Gap { from: 101, to: 101 });
+        assert_eq!(detector.last_seq("c"), None);
+    }
+
+    #[test]
+    fn a_new_epoch_resets_at_any_seq() {
+        let mut detector = GapDetector::new();
+        assert_eq!(detector.check_epoch("a", 100, 1), SeqCheck::InOrder);
+        // restarted before the second trade, its first trade is 1 again
+        assert_eq!(detector.check_epoch("a", 200, 1), SeqCheck::Reset);
+        assert_eq!(detector.check_epoch("a", 200, 2), SeqCheck::InOrder);
+        assert_eq!(detector.check_epoch("a", 200, 2), SeqCheck::Duplicate);
+        // restarted with the first trades of the new epoch missed
+        assert_eq!(detector.check_epoch("a", 300, 5), SeqCheck::Reset);
+        assert_eq!(detector.last_seq("a"), Some(5));
+        assert_eq!(detector.check_epoch("a", 300, 7), SeqCheck::Gap { from: 6, to: 6 });
+        // restarted past the last seq
+        assert_eq!(detector.check_epoch("a", 400, 8), SeqCheck::Reset);
+        assert_eq!(detector.check_epoch("a", 400, 9), SeqCheck::InOrder);
+    }
+
+    #[test]
+    fn publishers_without_epochs_reset_on_lower_seqs_only() {
+        let mut detector = GapDetector::new();
+        assert_eq!(detector.check_epoch("a", 0, 3), SeqCheck::InOrder);
+        assert_eq!(detector.check_epoch("a", 0, 3), SeqCheck::Duplicate);
+        assert_eq!(detector.check_epoch("a", 0, 1), SeqCheck::Reset);
+        // the first epoch seen after an upgraded publisher starts
+        assert_eq!(detector.check_epoch("a", 100, 2), SeqCheck::InOrder);
+        assert_eq!(detector.check_epoch("b", 100, 1), SeqCheck::InOrder);
+        assert_eq!(detector.check_epoch("b", 100, 1), SeqCheck::Duplicate);
+    }
+}
@@ -0,0 +1,46 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use std::collections::HashMap;
+
+// Keys of the consumer groups this client belongs to, by (group, key id). Keep the
+// previous key of a group next to the new one while the publisher rotates.
+#[derive(Clone, Default)]
+pub struct FeedKeys {
+    keys: HashMap<(String, String), XChaCha20Poly1305>,
+}
+
+impl FeedKeys {
+    pub fn insert(&mut self, group: &str, key_id: &str, key: [u8; 32]) {
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(&key));
+        self.keys.insert((group.to_string(), key_id.to_string()), cipher);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    // The payload of an `encrypted` message, None for groups or key ids without a key
+    pub fn open(&self, group: &str, key_id: &str, nonce: &str, data: &str) -> Option<Result<Vec<u8>, String>> {
+        let cipher = self.keys.get(&(group.to_string(), key_id.to_string()))?;
+        let open = || -> Result<Vec<u8>, String> {
+            let nonce = STANDARD.decode(nonce).map_err(|err| err.to_string())?;
+            if nonce.len() != 24 {
+                return Err(format!("nonce has {} bytes, expected 24", nonce.len()));
+            }
+            let data = STANDARD.decode(data).map_err(|err| err.to_string())?;
+            let aad = format!("{}:{}", group, key_id);
+            cipher
+                .decrypt(
+                    XNonce::from_slice(&nonce),
+                    Payload {
+                        msg: &data,
+                        aad: aad.as_bytes(),
+                    },
+                )
+                .map_err(|_| format!("payload of group {} does not match key {}", group, key_id))
+        };
+        Some(open())
+    }
+}
@@ -0,0 +1,377 @@
+// Consumer side of the openbookv2-printer feed: typed trades, reconnects and gap
+// detection over the ZMQ or WebSocket transport.
+pub mod gap;
+pub mod keys;
+
+use futures::{SinkExt, StreamExt};
+use gap::{GapDetector, SeqCheck};
+use keys::FeedKeys;
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio_tungstenite::tungstenite::Message;
+
+pub use openbookv2_decoder::{
+    ClientHeartbeat, ClientHello, ControlMessage, ReplayResponse, Trade, FEED_SCHEMA_VERSION,
+};
+
+#[derive(Clone, Debug)]
+pub enum Transport {
+    // e.g. tcp://127.0.0.1:8585
+    Zmq(String),
+    // e.g. ws://127.0.0.1:8686
+    WebSocket(String),
+}
+
+#[derive(Debug)]
+pub enum ClientEvent {
+    Connected,
+    Disconnected(String),
+    Trade(Box<Trade>),
+    // trades with these feed_seq values were not received for the market, or these pair_seq
+    // values for market_id "pair:<pair>" when subscribed to the pair feed
+    Gap { market_id: String, from: u64, to: u64 },
+    // the publisher restarted (a new feedEpoch) and its sequence numbering began again
+    Reset { market_id: String },
+    // the publisher dropped these trades itself, they have to be backfilled
+    DataLoss { market_id: String, from: u64, to: u64 },
+    // any other control message, e.g. market_closed
+    Control(ControlMessage),
+}
+
+// Topics of a new client: the trades in the current schema and the control messages. The
+// copies on other topics (pairs, v<n>.trades of a schema migration) repeat the feed_seq of
+// their trade.
+pub const DEFAULT_TOPICS: [&str; 2] = ["trades", "control"];
+
+pub struct TradesClient {
+    transport: Transport,
+    reconnect_delay: Duration,
+    keys: FeedKeys,
+    topics: Vec<String>,
+}
+
+impl TradesClient {
+    pub fn new(transport: Transport) -> Self {
+        TradesClient {
+            transport,
+            reconnect_delay: Duration::from_secs(1),
+            keys: FeedKeys::default(),
+            topics: DEFAULT_TOPICS.iter().map(|topic| topic.to_string()).collect(),
+        }
+    }
+
+    pub fn zmq(url: impl Into<String>) -> Self {
+        Self::new(Transport::Zmq(url.into()))
+    }
+
+    pub fn websocket(url: impl Into<String>) -> Self {
+        Self::new(Transport::WebSocket(url.into()))
+    }
+
+    pub fn reconnect_delay(mut self, delay: Duration) -> Self {
+        self.reconnect_delay = delay;
+        self
+    }
+
+    // Topics to subscribe to instead of DEFAULT_TOPICS, e.g. "bars.1s" or "trades.SOL". ZMQ
+    // matches them as prefixes, so only publishers with --zmq-topics are filtered; the
+    // messages of one without come as single frames and are all received.
+    pub fn topics(mut self, topics: &[&str]) -> Self {
+        self.topics = topics.iter().map(|topic| topic.to_string()).collect();
+        self
+    }
+
+    // Key of a consumer group of a publisher with --encrypted-sinks, repeat it for the new
+    // key id of a rotation. Encrypted messages of other groups are skipped.
+    pub fn feed_key(mut self, group: &str, key_id: &str, key: [u8; 32]) -> Self {
+        self.keys.insert(group, key_id, key);
+        self
+    }
+
+    // Runs until the returned receiver is dropped. Must be called inside a tokio runtime.
+    pub fn spawn(self) -> UnboundedReceiver<ClientEvent> {
+        let (tx, rx) = unbounded_channel();
+        match self.transport {
+            Transport::Zmq(url) => {
+                let delay = self.reconnect_delay;
+                let keys = self.keys;
+                let topics = self.topics;
+                std::thread::spawn(move || run_zmq(url, delay, keys, topics, tx));
+            }
+            Transport::WebSocket(url) => {
+                tokio::spawn(run_websocket(url, self.reconnect_delay, self.keys, self.topics, tx));
+            }
+        }
+        rx
+    }
+}
+
+// Asks the publisher's control socket (--control) for trades feed_seq from..=to of a market,
+// e.g. after a Gap event. Blocking, call it from a blocking context.
+pub fn request_replay(
+    control_url: &str,
+    market_id: &str,
+    from: u64,
+    to: u64,
+    timeout: Duration,
+) -> Result<ReplayResponse, String> {
+    let ctx = zmq::Context::new();
+    let socket = ctx.socket(zmq::REQ).map_err(|err| err.to_string())?;
+    socket
+        .set_rcvtimeo(timeout.as_millis() as i32)
+        .map_err(|err| err.to_string())?;
+    socket.set_linger(0).map_err(|err| err.to_string())?;
+    socket.connect(control_url).map_err(|err| err.to_string())?;
+    socket
+        .send(format!("replay {} {} {}", market_id, from, to).as_str(), 0)
+        .map_err(|err| err.to_string())?;
+    let reply = socket.recv_bytes(0).map_err(|err| err.to_string())?;
+    serde_json::from_slice(&reply).map_err(|_| String::from_utf8_lossy(&reply).to_string())
+}
+
+// Tells the publisher's control socket the last feed_seq `consumer` processed per market id
+// (GapDetector::last_seq), every few seconds. Returns the consumer's lag in trades over all
+// markets. Blocking like request_replay.
+pub fn send_heartbeat(
+    control_url: &str,
+    consumer: &str,
+    feed_seqs: &BTreeMap<String, u64>,
+    timeout: Duration,
+) -> Result<u64, String> {
+    let ctx = zmq::Context::new();
+    let socket = ctx.socket(zmq::REQ).map_err(|err| err.to_string())?;
+    socket
+        .set_rcvtimeo(timeout.as_millis() as i32)
+        .map_err(|err| err.to_string())?;
+    socket.set_linger(0).map_err(|err| err.to_string())?;
+    socket.connect(control_url).map_err(|err| err.to_string())?;
+    let markets: Vec<String> = feed_seqs
+        .iter()
+        .map(|(market_id, feed_seq)| format!("{}={}", market_id, feed_seq))
+        .collect();
+    socket
+        .send(format!("heartbeat {} {}", consumer, markets.join(" ")).as_str(), 0)
+        .map_err(|err| err.to_string())?;
+    let reply = socket.recv_bytes(0).map_err(|err| err.to_string())?;
+    let reply: serde_json::Value =
+        serde_json::from_slice(&reply).map_err(|_| String::from_utf8_lossy(&reply).to_string())?;
+    match reply["lag"].as_u64() {
+        Some(lag) => Ok(lag),
+        None => Err(reply["error"].as_str().unwrap_or("invalid reply").to_string()),
+    }
+}
+
+// Returns false once the consumer went away
+fn handle_payload(
+    payload: &[u8],
+    gaps: &mut GapDetector,
+    keys: &FeedKeys,
+    tx: &UnboundedSender<ClientEvent>,
+) -> bool {
+    let trade: Trade = match serde_json::from_slice(payload) {
+        Ok(trade) => trade,
+        Err(err) => {
+            return match serde_json::from_slice::<ControlMessage>(payload) {
+                Ok(ControlMessage::Encrypted {
+                    group,
+                    key_id,
+                    nonce,
+                    data,
+                }) => match keys.open(&group, &key_id, &nonce, &data) {
+                    Some(Ok(payload)) => handle_payload(&payload, gaps, keys, tx),
+                    Some(Err(err)) => {
+                        warn!("skipping encrypted payload: {}", err);
+                        !tx.is_closed()
+                    }
+                    // another group's copy, or a key id this client does not have yet
+                    None => {
+                        if !keys.is_empty() {
+                            debug!("no key for group {} key id {}", group, key_id);
+                        }
+                        !tx.is_closed()
+                    }
+                },
+                Ok(ControlMessage::DataLoss {
+                    market_id,
+                    from_seq,
+                    to_seq,
+                }) => {
+                    warn!("publisher dropped trades {}..={} for market {}", from_seq, to_seq, market_id);
+                    // the trades after the loss continue the sequence, no extra gap event
+                    gaps.check(&market_id, to_seq);
+                    tx.send(ClientEvent::DataLoss {
+                        market_id,
+                        from: from_seq,
+                        to: to_seq,
+                    })
+                    .is_ok()
+                }
+                // the trade was filtered out by the publisher, its feed_seq is no gap
+                Ok(ControlMessage::TradeFiltered {
+                    ref market_id,
+                    feed_seq,
+                    ..
+                }) => {
+                    gaps.check(market_id, feed_seq);
+                    !tx.is_closed()
+                }
+                // with --seq-guard drop the trade of this feed_seq is not published either
+                Ok(message @ ControlMessage::SeqRegression { dropped: true, .. }) => {
+                    if let ControlMessage::SeqRegression { market_id, feed_seq, .. } = &message {
+                        gaps.check(market_id, *feed_seq);
+                    }
+                    tx.send(ClientEvent::Control(message)).is_ok()
+                }
+                Ok(message) => tx.send(ClientEvent::Control(message)).is_ok(),
+                Err(_) => {
+                    debug!("skipping payload which is not a trade: {}", err);
+                    !tx.is_closed()
+                }
+            };
+        }
+    };
+    // feed_seq 0 comes from publishers which do not number their trades, the copies of the
+    // pair feed are numbered by pair_seq
+    let seq = match (trade.pair.as_ref(), trade.pair_seq) {
+        (Some(pair), Some(pair_seq)) => Some((format!("pair:{}", pair), pair_seq)),
+        _ => (trade.feed_seq != 0).then(|| (trade.market_id.clone(), trade.feed_seq)),
+    };
+    if let Some((stream, seq)) = seq {
+        match gaps.check_epoch(&stream, trade.feed_epoch, seq) {
+            SeqCheck::InOrder => {}
+            SeqCheck::Duplicate => return !tx.is_closed(),
+            SeqCheck::Gap { from, to } => {
+                warn!("missed trades {}..={} for market {}", from, to, stream);
+                let gap = ClientEvent::Gap {
+                    market_id: stream,
+                    from,
+                    to,
+                };
+                if tx.send(gap).is_err() {
+                    return false;
+                }
+            }
+            SeqCheck::Reset => {
+                let reset = ClientEvent::Reset { market_id: stream };
+                if tx.send(reset).is_err() {
+                    return false;
+                }
+            }
+        }
+    }
+    tx.send(ClientEvent::Trade(Box::new(trade))).is_ok()
+}
+
+fn run_zmq(url: String, delay: Duration, keys: FeedKeys, topics: Vec<String>, tx: UnboundedSender<ClientEvent>) {
+    let ctx = zmq::Context::new();
+    let mut gaps = GapDetector::new();
+    loop {
+        let result = (|| -> Result<zmq::Socket, zmq::Error> {
+            let socket = ctx.socket(zmq::SUB)?;
+            for topic in topics.iter() {
+                socket.set_subscribe(topic.as_bytes())?;
+            }
+            // the single frames of publishers without --zmq-topics, JSON objects
+            socket.set_subscribe(b"{")?;
+            // wake up periodically so a dropped receiver is noticed
+            socket.set_rcvtimeo(1000)?;
+            socket.connect(&url)?;
+            Ok(socket)
+        })();
+        let socket = match result {
+            Ok(socket) => socket,
+            Err(err) => {
+                if tx.send(ClientEvent::Disconnected(err.to_string())).is_err() {
+                    return;
+                }
+                std::thread::sleep(delay);
+                continue;
+            }
+        };
+        if tx.send(ClientEvent::Connected).is_err() {
+            return;
+        }
+        // libzmq reconnects the underlying tcp connection by itself
+        loop {
+            // publishers started with --zmq-topics send [topic, payload]
+            match socket.recv_multipart(0) {
+                Ok(frames) => {
+                    let Some(payload) = frames.last() else {
+                        continue;
+                    };
+                    if !handle_payload(payload, &mut gaps, &keys, &tx) {
+                        return;
+                    }
+                }
+                Err(zmq::Error::EAGAIN) => {
+                    if tx.is_closed() {
+                        return;
+                    }
+                }
+                Err(err) => {
+                    if tx.send(ClientEvent::Disconnected(err.to_string())).is_err() {
+                        return;
+                    }
+                    break;
+                }
+            }
+        }
+        std::thread::sleep(delay);
+    }
+}
+
+async fn run_websocket(
+    url: String,
+    delay: Duration,
+    keys: FeedKeys,
+    topics: Vec<String>,
+    tx: UnboundedSender<ClientEvent>,
+) {
+    let mut gaps = GapDetector::new();
+    loop {
+        match tokio_tungstenite::connect_async(url.as_str()).await {
+            Ok((mut ws, _)) => {
+                if tx.send(ClientEvent::Connected).is_err() {
+                    return;
+                }
+                // pin the schema this client was built against, older servers ignore it
+                let hello = ClientHello {
+                    schema_version: Some(FEED_SCHEMA_VERSION),
+                    encoding: Some("json".to_string()),
+                    topics: topics.clone(),
+                };
+                if let Err(err) = ws.send(Message::Text(serde_json::to_string(&hello).unwrap())).await {
+                    warn!("sending hello failed: {}", err);
+                }
+                let reason = loop {
+                    match ws.next().await {
+                        Some(Ok(Message::Text(text))) => {
+                            if !handle_payload(text.as_bytes(), &mut gaps, &keys, &tx) {
+                                return;
+                            }
+                        }
+                        Some(Ok(Message::Binary(data))) => {
+                            if !handle_payload(&data, &mut gaps, &keys, &tx) {
+                                return;
+                            }
+                        }
+                        Some(Ok(Message::Close(_))) | None => break "closed by server".to_string(),
+                        Some(Ok(_)) => {}
+                        Some(Err(err)) => break err.to_string(),
+                    }
+                };
+                if tx.send(ClientEvent::Disconnected(reason)).is_err() {
+                    return;
+                }
+            }
+            Err(err) => {
+                if tx.send(ClientEvent::Disconnected(err.to_string())).is_err() {
+                    return;
+                }
+            }
+        }
+        tokio::time::sleep(delay).await;
+    }
+}